@@ -0,0 +1,100 @@
+/* Runtime library of programs compiled to C
+ *
+ * The C backend copies this file to the top of every program it emits, so that the output is a
+ * single C99 file depending only on the standard library. Arithmetic on ints wraps around, which
+ * signed arithmetic in C does not promise, so it is done on unsigned integers here.
+ */
+#include <math.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* MAX_CALL_DEPTH, the deepest the call stack may grow before the program is stopped, is defined
+ * by the C backend ahead of this file from the limit of the interpreter */
+
+typedef struct {
+    int64_t length;
+    const char *bytes;
+} string;
+
+static int call_depth = 0;
+
+static void runtime_error(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "%s\n", message);
+    exit(1);
+}
+
+static void *checked_malloc(size_t length) {
+    void *p = malloc(length);
+    if (p == NULL) {
+        runtime_error("out of memory");
+    }
+    return p;
+}
+
+static void enter_call(void) {
+    if (call_depth >= MAX_CALL_DEPTH) {
+        runtime_error("stack overflow");
+    }
+    call_depth++;
+}
+
+static void leave_call(void) {
+    call_depth--;
+}
+
+static void print_int(int64_t x) {
+    printf("%lld\n", (long long)x);
+}
+
+static void print_float(double x) {
+    if (isnan(x)) {
+        printf("NaN\n");
+    } else if (isinf(x)) {
+        printf(x > 0 ? "inf\n" : "-inf\n");
+    } else {
+        printf("%.6f\n", x);
+    }
+}
+
+static void print_bool(bool b) {
+    printf(b ? "True\n" : "False\n");
+}
+
+static void print_string(string s) {
+    fwrite(s.bytes, 1, (size_t)s.length, stdout);
+    putchar('\n');
+}
+
+static string string_concat(string a, string b) {
+    char *bytes = checked_malloc((size_t)(a.length + b.length) + 1);
+    string s;
+    memcpy(bytes, a.bytes, (size_t)a.length);
+    memcpy(bytes + a.length, b.bytes, (size_t)b.length);
+    s.length = a.length + b.length;
+    s.bytes = bytes;
+    return s;
+}
+
+static bool string_equal(string a, string b) {
+    return a.length == b.length && memcmp(a.bytes, b.bytes, (size_t)a.length) == 0;
+}
+
+static int64_t int_add(int64_t a, int64_t b) { return (int64_t)((uint64_t)a + (uint64_t)b); }
+static int64_t int_sub(int64_t a, int64_t b) { return (int64_t)((uint64_t)a - (uint64_t)b); }
+static int64_t int_mul(int64_t a, int64_t b) { return (int64_t)((uint64_t)a * (uint64_t)b); }
+static int64_t int_neg(int64_t a) { return (int64_t)(0 - (uint64_t)a); }
+
+static int64_t int_div(int64_t a, int64_t b) {
+    if (b == 0) {
+        runtime_error("division by zero");
+    }
+    /* The one quotient that overflows wraps around instead */
+    if (b == -1) {
+        return int_neg(a);
+    }
+    return a / b;
+}
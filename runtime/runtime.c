@@ -0,0 +1,100 @@
+/* Runtime library for compiled programs
+ *
+ * Compiled code calls these functions for everything the generated instructions do not do
+ * themselves. Floats are passed and returned as their bits in an integer register, and a string
+ * is the address of a word holding its length followed by its bytes.
+ */
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef struct {
+    int64_t length;
+    char bytes[];
+} string;
+
+void program_main(void);
+
+static double to_float(int64_t bits) {
+    double x;
+    memcpy(&x, &bits, sizeof x);
+    return x;
+}
+
+static int64_t to_bits(double x) {
+    int64_t bits;
+    memcpy(&bits, &x, sizeof bits);
+    return bits;
+}
+
+void print_int(int64_t x) {
+    printf("%lld\n", (long long)x);
+}
+
+void print_float(int64_t bits) {
+    double x = to_float(bits);
+    if (isnan(x)) {
+        printf("NaN\n");
+    } else if (isinf(x)) {
+        printf(x > 0 ? "inf\n" : "-inf\n");
+    } else {
+        printf("%.6f\n", x);
+    }
+}
+
+void print_bool(int64_t b) {
+    printf(b ? "True\n" : "False\n");
+}
+
+void print_string(const string *s) {
+    fwrite(s->bytes, 1, (size_t)s->length, stdout);
+    putchar('\n');
+}
+
+const string *string_concat(const string *a, const string *b) {
+    string *s = malloc(sizeof(string) + (size_t)(a->length + b->length));
+    if (s == NULL) {
+        fflush(stdout);
+        fprintf(stderr, "out of memory\n");
+        exit(1);
+    }
+    s->length = a->length + b->length;
+    memcpy(s->bytes, a->bytes, (size_t)a->length);
+    memcpy(s->bytes + a->length, b->bytes, (size_t)b->length);
+    return s;
+}
+
+int64_t string_equal(const string *a, const string *b) {
+    return a->length == b->length && memcmp(a->bytes, b->bytes, (size_t)a->length) == 0;
+}
+
+int64_t float_add(int64_t a, int64_t b) { return to_bits(to_float(a) + to_float(b)); }
+int64_t float_sub(int64_t a, int64_t b) { return to_bits(to_float(a) - to_float(b)); }
+int64_t float_mul(int64_t a, int64_t b) { return to_bits(to_float(a) * to_float(b)); }
+int64_t float_div(int64_t a, int64_t b) { return to_bits(to_float(a) / to_float(b)); }
+int64_t float_neg(int64_t a) { return to_bits(-to_float(a)); }
+int64_t float_eq(int64_t a, int64_t b) { return to_float(a) == to_float(b); }
+int64_t float_ne(int64_t a, int64_t b) { return to_float(a) != to_float(b); }
+int64_t float_lt(int64_t a, int64_t b) { return to_float(a) < to_float(b); }
+int64_t float_le(int64_t a, int64_t b) { return to_float(a) <= to_float(b); }
+int64_t float_gt(int64_t a, int64_t b) { return to_float(a) > to_float(b); }
+int64_t float_ge(int64_t a, int64_t b) { return to_float(a) >= to_float(b); }
+
+void div_by_zero(void) {
+    fflush(stdout);
+    fprintf(stderr, "division by zero\n");
+    exit(1);
+}
+
+void stack_overflow(void) {
+    fflush(stdout);
+    fprintf(stderr, "stack overflow\n");
+    exit(1);
+}
+
+int main(void) {
+    program_main();
+    return 0;
+}
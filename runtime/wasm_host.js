@@ -0,0 +1,84 @@
+// Runs a program compiled to WebAssembly under Node.js: node wasm_host.js program.wasm
+//
+// The module imports its printing and error functions from "env" and exports "main" and its
+// "memory". A string is the address of a 32-bit length followed by the bytes.
+"use strict";
+const fs = require("fs");
+
+class ProgramError extends Error {}
+
+let memory;
+
+function print(text) {
+    process.stdout.write(text + "\n");
+}
+
+function readString(address) {
+    const length = new DataView(memory.buffer).getInt32(address, true);
+    return Buffer.from(memory.buffer, address + 4, length);
+}
+
+// Format a float like Rust's `{:.6}`: the exact value rounded half to even
+function formatFloat(x) {
+    if (Number.isNaN(x)) {
+        return "NaN";
+    }
+    if (!Number.isFinite(x)) {
+        return x > 0 ? "inf" : "-inf";
+    }
+    const negative = x < 0 || Object.is(x, -0);
+    const view = new DataView(new ArrayBuffer(8));
+    view.setFloat64(0, Math.abs(x));
+    const bits = view.getBigUint64(0);
+    let exponent = Number((bits >> 52n) & 0x7ffn);
+    let mantissa = bits & ((1n << 52n) - 1n);
+    if (exponent === 0) {
+        exponent = 1;
+    } else {
+        mantissa |= 1n << 52n;
+    }
+    exponent -= 1075;
+    let numerator = mantissa * 1000000n;
+    let denominator = 1n;
+    if (exponent >= 0) {
+        numerator <<= BigInt(exponent);
+    } else {
+        denominator <<= BigInt(-exponent);
+    }
+    let quotient = numerator / denominator;
+    const twice = 2n * (numerator % denominator);
+    if (twice > denominator || (twice === denominator && quotient % 2n === 1n)) {
+        quotient += 1n;
+    }
+    const digits = quotient.toString().padStart(7, "0");
+    return (negative ? "-" : "") + digits.slice(0, -6) + "." + digits.slice(-6);
+}
+
+const env = {
+    print_int: (x) => print(x.toString()),
+    print_float: (x) => print(formatFloat(x)),
+    print_bool: (b) => print(b ? "True" : "False"),
+    print_string: (address) => {
+        process.stdout.write(readString(address));
+        process.stdout.write("\n");
+    },
+    div_by_zero: () => {
+        throw new ProgramError("division by zero");
+    },
+    stack_overflow: () => {
+        throw new ProgramError("stack overflow");
+    },
+};
+
+WebAssembly.instantiate(fs.readFileSync(process.argv[2]), { env }).then(({ instance }) => {
+    memory = instance.exports.memory;
+    try {
+        instance.exports.main();
+    } catch (e) {
+        if (!(e instanceof ProgramError)) {
+            throw e;
+        }
+        process.stderr.write(e.message + "\n");
+        process.exitCode = 1;
+    }
+});
@@ -0,0 +1,1058 @@
+/// A stack based bytecode for type checked programs, with its compiler and virtual machine
+///
+/// Compiling resolves every name ahead of time, so the virtual machine runs without the
+/// environments the reference interpreter looks names up in:
+///
+/// * A `Bytecode` has a pool of constants and a table of functions. Function 0 is the program.
+/// * Each function has numbered slots for its parameters and variables. A call pushes a frame
+///   whose slots start at the arguments on the operand stack.
+/// * Variables of enclosing functions are reached through static links, by the number of links
+///   to follow and the slot.
+/// * Every expression leaves exactly one value on the stack, `()` when it has none.
+///
+/// Bytecode can be written to bytes and read back, to run a program without compiling it again.
+use std::io::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::chapter_4::*;
+use crate::interpreter::{interpret, RuntimeError, Value, MAX_CALL_DEPTH};
+use crate::test_programs::load;
+
+/// The start of every file of bytecode, followed by the format version
+pub const MAGIC: &[u8; 4] = b"MCIB";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Push a value from the constant pool
+    Const(u32),
+    Unit,
+    Pop,
+    /// Push the value of a slot of the current frame
+    Load(u32),
+    /// Pop a value into a slot of the current frame
+    Store(u32),
+    /// Push the value of a slot of the frame reached by following static links
+    LoadOuter {
+        depth: u32,
+        slot: u32,
+    },
+    StoreOuter {
+        depth: u32,
+        slot: u32,
+    },
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    Eq,
+    Neq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Jump(u32),
+    /// Pop a bool and jump if it is false
+    JumpIfFalse(u32),
+    /// Call a function with the arguments on the stack. Its static link is the frame reached
+    /// by following `depth` static links from the caller
+    Call {
+        function: u32,
+        depth: u32,
+    },
+    /// Return the value on top of the stack
+    Return,
+    /// Pop and print values, the deepest first
+    Print(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: u32,
+    /// Slots of the frame, the parameters first
+    pub slots: u32,
+    pub code: Vec<Op>,
+    /// The position in the source of each instruction, for runtime errors
+    pub positions: Vec<Pos>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bytecode {
+    pub constants: Vec<Value>,
+    pub functions: Vec<Function>,
+}
+
+/// What a name of the program is in the virtual machine: a slot of a frame on its stack, or an
+/// index into the table of functions
+#[derive(Clone, Copy)]
+enum Binding {
+    /// A slot of the frame of the function at a nesting level
+    Var { level: usize, slot: u32 },
+    /// A function, declared in the function at a nesting level
+    Fn { index: u32, level: usize },
+}
+
+struct Compiler {
+    env: Vec<(String, Binding)>,
+    /// The functions being compiled, innermost last
+    fns: Vec<Function>,
+    functions: Vec<Option<Function>>,
+    constants: Vec<Value>,
+}
+
+/// Compile a type checked program to bytecode
+pub fn compile(program: &Program) -> Bytecode {
+    let mut compiler = Compiler {
+        env: Vec::new(),
+        fns: Vec::new(),
+        functions: vec![None],
+        constants: Vec::new(),
+    };
+    compiler.begin_fn("main", 0);
+    compiler.compile_stmts(&program.stmts);
+    compiler.emit(Op::Unit, 0);
+    compiler.emit(Op::Return, 0);
+    compiler.functions[0] = compiler.fns.pop();
+    return Bytecode {
+        constants: compiler.constants,
+        functions: compiler.functions.into_iter().map(Option::unwrap).collect(),
+    };
+}
+
+impl Compiler {
+    fn level(&self) -> usize {
+        return self.fns.len() - 1;
+    }
+
+    fn current(&mut self) -> &mut Function {
+        return self.fns.last_mut().unwrap();
+    }
+
+    fn begin_fn(&mut self, name: &str, params: u32) {
+        self.fns.push(Function {
+            name: name.to_string(),
+            params,
+            slots: 0,
+            code: Vec::new(),
+            positions: Vec::new(),
+        });
+    }
+
+    /// Append an instruction, returning its address
+    fn emit(&mut self, op: Op, pos: Pos) -> u32 {
+        let function = self.current();
+        function.code.push(op);
+        function.positions.push(pos);
+        return function.code.len() as u32 - 1;
+    }
+
+    fn here(&mut self) -> u32 {
+        return self.current().code.len() as u32;
+    }
+
+    /// Point the jump at `address` to the next instruction
+    fn patch(&mut self, address: u32) {
+        let target = self.here();
+        match &mut self.current().code[address as usize] {
+            Op::Jump(to) | Op::JumpIfFalse(to) => *to = target,
+            op => panic!("cannot patch {:?}", op),
+        }
+    }
+
+    fn constant(&mut self, value: Value, pos: Pos) {
+        let index = match self.constants.iter().position(|c| *c == value) {
+            Some(index) => index,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        };
+        self.emit(Op::Const(index as u32), pos);
+    }
+
+    /// Give a variable a new slot, without making it visible yet
+    fn new_slot(&mut self) -> Binding {
+        let level = self.level();
+        let function = self.current();
+        function.slots += 1;
+        return Binding::Var {
+            level,
+            slot: function.slots - 1,
+        };
+    }
+
+    fn lookup(&self, name: &str) -> Binding {
+        for (n, binding) in self.env.iter().rev() {
+            if n == name {
+                return *binding;
+            }
+        }
+        panic!("`{}` is not declared", name);
+    }
+
+    fn load(&mut self, binding: Binding, pos: Pos) {
+        if let Binding::Var { level, slot } = binding {
+            let depth = (self.level() - level) as u32;
+            match depth {
+                0 => self.emit(Op::Load(slot), pos),
+                _ => self.emit(Op::LoadOuter { depth, slot }, pos),
+            };
+        }
+    }
+
+    fn store(&mut self, binding: Binding, pos: Pos) {
+        if let Binding::Var { level, slot } = binding {
+            let depth = (self.level() - level) as u32;
+            match depth {
+                0 => self.emit(Op::Store(slot), pos),
+                _ => self.emit(Op::StoreOuter { depth, slot }, pos),
+            };
+        }
+    }
+
+    fn compile_stmts(&mut self, stmts: &[Stmt]) {
+        let mut i = 0;
+        while i < stmts.len() {
+            let mut decls = Vec::new();
+            while let Some(Stmt {
+                kind: StmtKind::Fn(decl),
+                ..
+            }) = stmts.get(i)
+            {
+                decls.push(decl);
+                i += 1;
+            }
+            if !decls.is_empty() {
+                self.compile_fn_group(&decls);
+            } else {
+                self.compile_stmt(&stmts[i]);
+                i += 1;
+            }
+        }
+    }
+
+    /// Compile consecutive function declarations, reserving all their indices in the function table
+    /// first so that each body can call any of them
+    fn compile_fn_group(&mut self, decls: &[&FnDecl]) {
+        let level = self.level();
+        let mut indices = Vec::new();
+        for decl in decls {
+            let index = self.functions.len() as u32;
+            self.functions.push(None);
+            self.env
+                .push((decl.name.clone(), Binding::Fn { index, level }));
+            indices.push(index);
+        }
+        for (decl, index) in decls.iter().zip(indices) {
+            self.begin_fn(&decl.name, decl.params.len() as u32);
+            let scope = self.env.len();
+            for param in &decl.params {
+                let binding = self.new_slot();
+                self.env.push((param.name.clone(), binding));
+            }
+            self.compile_block(&decl.body);
+            self.emit(Op::Return, decl.body.pos);
+            self.env.truncate(scope);
+            self.functions[index as usize] = self.fns.pop();
+        }
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, init, .. } => {
+                self.compile_expr(init);
+                let binding = self.new_slot();
+                self.store(binding, stmt.pos);
+                self.env.push((name.clone(), binding));
+            }
+            StmtKind::Assign { name, value } => {
+                self.compile_expr(value);
+                let binding = self.lookup(name);
+                self.store(binding, stmt.pos);
+            }
+            StmtKind::While { cond, body } => {
+                let start = self.here();
+                self.compile_expr(cond);
+                let exit = self.emit(Op::JumpIfFalse(0), cond.pos);
+                self.compile_body(body);
+                self.emit(Op::Jump(start), stmt.pos);
+                self.patch(exit);
+            }
+            StmtKind::For { var, lo, hi, body } => {
+                let (var_slot, limit) = (self.new_slot(), self.new_slot());
+                self.compile_expr(lo);
+                self.store(var_slot, lo.pos);
+                self.compile_expr(hi);
+                self.store(limit, hi.pos);
+                let start = self.here();
+                self.load(var_slot, stmt.pos);
+                self.load(limit, stmt.pos);
+                self.emit(Op::Lt, stmt.pos);
+                let exit = self.emit(Op::JumpIfFalse(0), stmt.pos);
+                let scope = self.env.len();
+                self.env.push((var.clone(), var_slot));
+                self.compile_body(body);
+                self.env.truncate(scope);
+                self.load(var_slot, stmt.pos);
+                self.constant(Value::Int(1), stmt.pos);
+                self.emit(Op::Add, stmt.pos);
+                self.store(var_slot, stmt.pos);
+                self.emit(Op::Jump(start), stmt.pos);
+                self.patch(exit);
+            }
+            StmtKind::Fn(_) => unreachable!("function declarations are compiled in groups"),
+            StmtKind::Error => unreachable!("the type checker rejects syntax errors"),
+            StmtKind::Expr(expr) => {
+                self.compile_expr(expr);
+                self.emit(Op::Pop, stmt.pos);
+            }
+        }
+    }
+
+    fn compile_block(&mut self, block: &Block) {
+        let scope = self.env.len();
+        self.compile_stmts(&block.stmts);
+        match &block.result {
+            Some(result) => self.compile_expr(result),
+            None => {
+                self.emit(Op::Unit, block.pos);
+            }
+        }
+        self.env.truncate(scope);
+    }
+
+    /// Compile a block whose value is not used, such as the body of a loop
+    fn compile_body(&mut self, block: &Block) {
+        let scope = self.env.len();
+        self.compile_stmts(&block.stmts);
+        if let Some(result) = &block.result {
+            self.compile_expr(result);
+            self.emit(Op::Pop, result.pos);
+        }
+        self.env.truncate(scope);
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) {
+        let pos = expr.pos;
+        match &expr.kind {
+            ExprKind::Int(i) => self.constant(Value::Int(*i), pos),
+            ExprKind::Float(x) => self.constant(Value::Float(*x), pos),
+            ExprKind::Bool(b) => self.constant(Value::Bool(*b), pos),
+            ExprKind::Str(s) => self.constant(Value::Str(Rc::from(s.as_str())), pos),
+            ExprKind::Var(name) => {
+                let binding = self.lookup(name);
+                self.load(binding, pos);
+            }
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                self.compile_expr(operand);
+                self.emit(Op::Neg, pos);
+            }
+            ExprKind::Binary(left, BinOp::And, right) => {
+                self.compile_expr(left);
+                let skip = self.emit(Op::JumpIfFalse(0), pos);
+                self.compile_expr(right);
+                let end = self.emit(Op::Jump(0), pos);
+                self.patch(skip);
+                self.constant(Value::Bool(false), pos);
+                self.patch(end);
+            }
+            ExprKind::Binary(left, BinOp::Or, right) => {
+                self.compile_expr(left);
+                let next = self.emit(Op::JumpIfFalse(0), pos);
+                self.constant(Value::Bool(true), pos);
+                let end = self.emit(Op::Jump(0), pos);
+                self.patch(next);
+                self.compile_expr(right);
+                self.patch(end);
+            }
+            ExprKind::Binary(left, op, right) => {
+                self.compile_expr(left);
+                self.compile_expr(right);
+                let op = match op {
+                    BinOp::Add => Op::Add,
+                    BinOp::Sub => Op::Sub,
+                    BinOp::Mul => Op::Mul,
+                    BinOp::Div => Op::Div,
+                    BinOp::Eq => Op::Eq,
+                    BinOp::Neq => Op::Neq,
+                    BinOp::Lt => Op::Lt,
+                    BinOp::Le => Op::Le,
+                    BinOp::Gt => Op::Gt,
+                    BinOp::Ge => Op::Ge,
+                    BinOp::And | BinOp::Or => unreachable!(),
+                };
+                self.emit(op, pos);
+            }
+            ExprKind::Error => unreachable!("the type checker rejects syntax errors"),
+            ExprKind::Call(name, args) => {
+                for arg in args {
+                    self.compile_expr(arg);
+                }
+                let binding = self.env.iter().rev().find(|(n, _)| n == name);
+                match binding.map(|(_, binding)| *binding) {
+                    Some(Binding::Fn { index, level }) => {
+                        let depth = (self.level() - level) as u32;
+                        self.emit(
+                            Op::Call {
+                                function: index,
+                                depth,
+                            },
+                            pos,
+                        );
+                    }
+                    _ => {
+                        self.emit(Op::Print(args.len() as u32), pos);
+                        self.emit(Op::Unit, pos);
+                    }
+                }
+            }
+            ExprKind::If {
+                branches,
+                else_block,
+            } => {
+                let mut ends = Vec::new();
+                for (cond, block) in branches {
+                    self.compile_expr(cond);
+                    let next = self.emit(Op::JumpIfFalse(0), cond.pos);
+                    self.compile_block(block);
+                    ends.push(self.emit(Op::Jump(0), pos));
+                    self.patch(next);
+                }
+                match else_block {
+                    Some(block) => self.compile_block(block),
+                    None => {
+                        self.emit(Op::Unit, pos);
+                    }
+                }
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+        }
+    }
+}
+
+struct Frame {
+    function: usize,
+    ip: usize,
+    /// Where the slots of the frame start on the stack
+    base: usize,
+    /// The index of the frame of the enclosing function
+    link: usize,
+}
+
+fn error<T>(message: &str, pos: Pos) -> Result<T, RuntimeError> {
+    return Err(RuntimeError {
+        message: message.to_string(),
+        pos,
+    });
+}
+
+/// Run bytecode, writing anything it prints to `out`
+pub fn run(code: &Bytecode, out: &mut dyn Write) -> Result<(), RuntimeError> {
+    let mut stack: Vec<Value> = Vec::new();
+    let mut frames = vec![Frame {
+        function: 0,
+        ip: 0,
+        base: 0,
+        link: 0,
+    }];
+    stack.resize(code.functions[0].slots as usize, Value::Unit);
+    let mut function = &code.functions[0];
+    let mut ip = 0;
+    let mut base = 0;
+    loop {
+        let op = function.code[ip];
+        ip += 1;
+        match op {
+            Op::Const(index) => stack.push(code.constants[index as usize].clone()),
+            Op::Unit => stack.push(Value::Unit),
+            Op::Pop => {
+                stack.pop();
+            }
+            Op::Load(slot) => {
+                let value = match &stack[base + slot as usize] {
+                    Value::Int(i) => Value::Int(*i),
+                    value => value.clone(),
+                };
+                stack.push(value);
+            }
+            Op::Store(slot) => stack[base + slot as usize] = stack.pop().unwrap(),
+            Op::LoadOuter { depth, slot } => {
+                let frame = outer(&frames, depth);
+                stack.push(stack[frames[frame].base + slot as usize].clone());
+            }
+            Op::StoreOuter { depth, slot } => {
+                let frame = outer(&frames, depth);
+                let value = stack.pop().unwrap();
+                stack[frames[frame].base + slot as usize] = value;
+            }
+            Op::Neg => {
+                let value = match stack.pop().unwrap() {
+                    Value::Int(i) => Value::Int(i.wrapping_neg()),
+                    Value::Float(x) => Value::Float(-x),
+                    other => {
+                        let pos = function.positions[ip - 1];
+                        return error(&format!("cannot negate {:?}", other), pos);
+                    }
+                };
+                stack.push(value);
+            }
+            Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Div
+            | Op::Eq
+            | Op::Neq
+            | Op::Lt
+            | Op::Le
+            | Op::Gt
+            | Op::Ge => {
+                let right = stack.pop().unwrap();
+                let left = stack.last_mut().unwrap();
+                // Operations on ints are the common case, computed in place
+                *left = match (&*left, &right, op) {
+                    (Value::Int(l), Value::Int(r), Op::Add) => Value::Int(l.wrapping_add(*r)),
+                    (Value::Int(l), Value::Int(r), Op::Sub) => Value::Int(l.wrapping_sub(*r)),
+                    (Value::Int(l), Value::Int(r), Op::Mul) => Value::Int(l.wrapping_mul(*r)),
+                    (Value::Int(l), Value::Int(r), Op::Lt) => Value::Bool(l < r),
+                    (Value::Int(l), Value::Int(r), Op::Le) => Value::Bool(l <= r),
+                    (Value::Int(l), Value::Int(r), Op::Gt) => Value::Bool(l > r),
+                    (Value::Int(l), Value::Int(r), Op::Ge) => Value::Bool(l >= r),
+                    _ => {
+                        let left = std::mem::replace(left, Value::Unit);
+                        binary(op, left, right, function.positions[ip - 1])?
+                    }
+                };
+            }
+            Op::Jump(target) => ip = target as usize,
+            Op::JumpIfFalse(target) => {
+                if let Some(Value::Bool(false)) = stack.pop() {
+                    ip = target as usize;
+                }
+            }
+            Op::Call {
+                function: index,
+                depth,
+            } => {
+                if frames.len() > MAX_CALL_DEPTH {
+                    return error("stack overflow", function.positions[ip - 1]);
+                }
+                let link = outer(&frames, depth);
+                frames.last_mut().unwrap().ip = ip;
+                function = &code.functions[index as usize];
+                base = stack.len() - function.params as usize;
+                stack.resize(base + function.slots as usize, Value::Unit);
+                frames.push(Frame {
+                    function: index as usize,
+                    ip: 0,
+                    base,
+                    link,
+                });
+                ip = 0;
+            }
+            Op::Return => {
+                let result = stack.pop().unwrap();
+                stack.truncate(base);
+                frames.pop();
+                let Some(caller) = frames.last() else {
+                    return Ok(());
+                };
+                function = &code.functions[caller.function];
+                ip = caller.ip;
+                base = caller.base;
+                stack.push(result);
+            }
+            Op::Print(count) => {
+                let values = stack.split_off(stack.len() - count as usize);
+                for value in values {
+                    if let Err(e) = writeln!(out, "{}", value) {
+                        let pos = function.positions[ip - 1];
+                        return error(&format!("could not print: {}", e), pos);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Run bytecode and collect everything it prints
+#[cfg(test)]
+pub fn run_to_string(code: &Bytecode) -> Result<String, RuntimeError> {
+    let mut out = Vec::new();
+    run(code, &mut out)?;
+    return Ok(String::from_utf8(out).unwrap());
+}
+
+/// Programs whose time goes into running loops and calls rather than printing
+const BENCHMARKS: &[(&str, &str)] = &[
+    (
+        "loop",
+        "let mut total = 0; let mut i = 0;
+         while i < 100000 { total = total + i * 2; i = i + 1; }
+         print(total);",
+    ),
+    (
+        "calls",
+        "fn fib(n: int): int { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }
+         print(fib(20));",
+    ),
+];
+
+/// How many times each program is run, the table shows the fastest run
+const RUNS: usize = 5;
+
+/// Time the virtual machine against the reference interpreter, as a table with a row for each
+/// program. Both print to a buffer, which the row checks they filled alike
+pub fn benchmark() -> String {
+    let mut table = format!(
+        "{:<24} {:>10} {:>12} {:>8}\n",
+        "program", "bytecode", "interpreter", "speedup"
+    );
+    for (name, source) in BENCHMARKS {
+        let program = load(source);
+        let code = compile(&program);
+        let fastest = |f: &dyn Fn(&mut Vec<u8>)| -> (Duration, Vec<u8>) {
+            let mut best = Duration::MAX;
+            let mut out = Vec::new();
+            for _ in 0..RUNS {
+                out.clear();
+                let start = Instant::now();
+                f(&mut out);
+                best = best.min(start.elapsed());
+            }
+            return (best, out);
+        };
+        let (vm, vm_out) = fastest(&|out| run(&code, out).unwrap());
+        let (interpreter, interpreter_out) = fastest(&|out| interpret(&program, out).unwrap());
+        assert_eq!(vm_out, interpreter_out, "`{}` printed differently", name);
+        table.push_str(&format!(
+            "{:<24} {:>10.3?} {:>12.3?} {:>7.1}x\n",
+            name,
+            vm,
+            interpreter,
+            interpreter.as_secs_f64() / vm.as_secs_f64()
+        ));
+    }
+    return table;
+}
+
+/// The index of the frame `depth` static links away from the current one
+fn outer(frames: &[Frame], depth: u32) -> usize {
+    let mut frame = frames.len() - 1;
+    for _ in 0..depth {
+        frame = frames[frame].link;
+    }
+    return frame;
+}
+
+/// Apply an arithmetic or comparison instruction to two values
+fn binary(op: Op, left: Value, right: Value, pos: Pos) -> Result<Value, RuntimeError> {
+    match (op, &left, &right) {
+        (Op::Div, Value::Int(_), Value::Int(0)) => return error("division by zero", pos),
+        (Op::Div, Value::Int(l), Value::Int(r)) => return Ok(Value::Int(l.wrapping_div(*r))),
+        (Op::Add, Value::Float(l), Value::Float(r)) => return Ok(Value::Float(l + r)),
+        (Op::Sub, Value::Float(l), Value::Float(r)) => return Ok(Value::Float(l - r)),
+        (Op::Mul, Value::Float(l), Value::Float(r)) => return Ok(Value::Float(l * r)),
+        (Op::Div, Value::Float(l), Value::Float(r)) => return Ok(Value::Float(l / r)),
+        (Op::Add, Value::Str(l), Value::Str(r)) => {
+            return Ok(Value::Str(Rc::from(format!("{}{}", l, r).as_str())))
+        }
+        _ => {}
+    }
+    let ordering = match (&left, &right) {
+        (Value::Int(l), Value::Int(r)) => l.partial_cmp(r),
+        (Value::Float(l), Value::Float(r)) => l.partial_cmp(r),
+        (Value::Bool(_), Value::Bool(_)) | (Value::Str(_), Value::Str(_))
+            if matches!(op, Op::Eq | Op::Neq) =>
+        {
+            None
+        }
+        (l, r) => {
+            return error(
+                &format!("cannot apply {:?} to {:?} and {:?}", op, l, r),
+                pos,
+            )
+        }
+    };
+    let result = match op {
+        Op::Eq => left == right,
+        Op::Neq => left != right,
+        Op::Lt => ordering.is_some_and(|o| o.is_lt()),
+        Op::Le => ordering.is_some_and(|o| o.is_le()),
+        Op::Gt => ordering.is_some_and(|o| o.is_gt()),
+        Op::Ge => ordering.is_some_and(|o| o.is_ge()),
+        _ => {
+            return error(
+                &format!("cannot apply {:?} to {:?} and {:?}", op, left, right),
+                pos,
+            )
+        }
+    };
+    return Ok(Value::Bool(result));
+}
+
+impl Op {
+    /// The number of the instruction in the serialized format, and its operands
+    fn encode(self) -> (u8, [u32; 2]) {
+        match self {
+            Op::Const(i) => return (0, [i, 0]),
+            Op::Unit => return (1, [0, 0]),
+            Op::Pop => return (2, [0, 0]),
+            Op::Load(slot) => return (3, [slot, 0]),
+            Op::Store(slot) => return (4, [slot, 0]),
+            Op::LoadOuter { depth, slot } => return (5, [depth, slot]),
+            Op::StoreOuter { depth, slot } => return (6, [depth, slot]),
+            Op::Add => return (7, [0, 0]),
+            Op::Sub => return (8, [0, 0]),
+            Op::Mul => return (9, [0, 0]),
+            Op::Div => return (10, [0, 0]),
+            Op::Neg => return (11, [0, 0]),
+            Op::Eq => return (12, [0, 0]),
+            Op::Neq => return (13, [0, 0]),
+            Op::Lt => return (14, [0, 0]),
+            Op::Le => return (15, [0, 0]),
+            Op::Gt => return (16, [0, 0]),
+            Op::Ge => return (17, [0, 0]),
+            Op::Jump(target) => return (18, [target, 0]),
+            Op::JumpIfFalse(target) => return (19, [target, 0]),
+            Op::Call { function, depth } => return (20, [function, depth]),
+            Op::Return => return (21, [0, 0]),
+            Op::Print(count) => return (22, [count, 0]),
+        }
+    }
+
+    fn decode(number: u8, [a, b]: [u32; 2]) -> Option<Op> {
+        let op = match number {
+            0 => Op::Const(a),
+            1 => Op::Unit,
+            2 => Op::Pop,
+            3 => Op::Load(a),
+            4 => Op::Store(a),
+            5 => Op::LoadOuter { depth: a, slot: b },
+            6 => Op::StoreOuter { depth: a, slot: b },
+            7 => Op::Add,
+            8 => Op::Sub,
+            9 => Op::Mul,
+            10 => Op::Div,
+            11 => Op::Neg,
+            12 => Op::Eq,
+            13 => Op::Neq,
+            14 => Op::Lt,
+            15 => Op::Le,
+            16 => Op::Gt,
+            17 => Op::Ge,
+            18 => Op::Jump(a),
+            19 => Op::JumpIfFalse(a),
+            20 => Op::Call {
+                function: a,
+                depth: b,
+            },
+            21 => Op::Return,
+            22 => Op::Print(a),
+            _ => return None,
+        };
+        return Some(op);
+    }
+}
+
+/// Reads the serialized format, failing on anything truncated
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        if self.bytes.len() < n {
+            return Err("unexpected end of bytecode".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        return Ok(taken);
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        return Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()));
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        return Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()));
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()? as usize;
+        let bytes = self.take(length)?.to_vec();
+        return String::from_utf8(bytes).map_err(|_| "string is not UTF-8".to_string());
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend((s.len() as u32).to_le_bytes());
+    out.extend(s.as_bytes());
+}
+
+impl Bytecode {
+    /// Serialize the bytecode. Numbers are little endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend((self.constants.len() as u32).to_le_bytes());
+        for constant in &self.constants {
+            match constant {
+                Value::Int(i) => {
+                    out.push(0);
+                    out.extend(i.to_le_bytes());
+                }
+                Value::Float(x) => {
+                    out.push(1);
+                    out.extend(x.to_bits().to_le_bytes());
+                }
+                Value::Bool(b) => out.extend([2, *b as u8]),
+                Value::Str(s) => {
+                    out.push(3);
+                    write_string(&mut out, s);
+                }
+                Value::Unit => out.push(4),
+            }
+        }
+        out.extend((self.functions.len() as u32).to_le_bytes());
+        for function in &self.functions {
+            write_string(&mut out, &function.name);
+            out.extend(function.params.to_le_bytes());
+            out.extend(function.slots.to_le_bytes());
+            out.extend((function.code.len() as u32).to_le_bytes());
+            for (op, pos) in function.code.iter().zip(&function.positions) {
+                let (number, operands) = op.encode();
+                out.push(number);
+                for operand in operands {
+                    out.extend(operand.to_le_bytes());
+                }
+                out.extend((*pos as u64).to_le_bytes());
+            }
+        }
+        return out;
+    }
+
+    /// Read serialized bytecode, checking the indices its instructions use
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, String> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err("not a bytecode file".to_string());
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("unsupported bytecode version {}", version));
+        }
+        let mut constants = Vec::new();
+        for _ in 0..reader.u32()? {
+            let constant = match reader.u8()? {
+                0 => Value::Int(reader.u64()? as i64),
+                1 => Value::Float(f64::from_bits(reader.u64()?)),
+                2 => Value::Bool(reader.u8()? != 0),
+                3 => Value::Str(Rc::from(reader.string()?.as_str())),
+                4 => Value::Unit,
+                tag => return Err(format!("unknown constant tag {}", tag)),
+            };
+            constants.push(constant);
+        }
+        let mut functions = Vec::new();
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let params = reader.u32()?;
+            let slots = reader.u32()?;
+            let mut code = Vec::new();
+            let mut positions = Vec::new();
+            for _ in 0..reader.u32()? {
+                let number = reader.u8()?;
+                let operands = [reader.u32()?, reader.u32()?];
+                let op = Op::decode(number, operands)
+                    .ok_or_else(|| format!("unknown instruction {}", number))?;
+                code.push(op);
+                positions.push(reader.u64()? as Pos);
+            }
+            functions.push(Function {
+                name,
+                params,
+                slots,
+                code,
+                positions,
+            });
+        }
+        if !reader.bytes.is_empty() {
+            return Err("trailing bytes after bytecode".to_string());
+        }
+        let bytecode = Bytecode {
+            constants,
+            functions,
+        };
+        bytecode.validate()?;
+        return Ok(bytecode);
+    }
+
+    /// Check the indices in instructions. Types and stack depths are trusted to be as the
+    /// compiler leaves them
+    fn validate(&self) -> Result<(), String> {
+        if self.functions.is_empty() {
+            return Err("bytecode has no functions".to_string());
+        }
+        for function in &self.functions {
+            let invalid = |op: &Op| format!("invalid instruction {:?} in `{}`", op, function.name);
+            if function.params > function.slots || function.code.last() != Some(&Op::Return) {
+                return Err(format!("invalid function `{}`", function.name));
+            }
+            for op in &function.code {
+                let valid = match *op {
+                    Op::Const(i) => (i as usize) < self.constants.len(),
+                    Op::Load(slot) | Op::Store(slot) => slot < function.slots,
+                    Op::Jump(target) | Op::JumpIfFalse(target) => {
+                        (target as usize) < function.code.len()
+                    }
+                    Op::Call { function, .. } => (function as usize) < self.functions.len(),
+                    _ => true,
+                };
+                if !valid {
+                    return Err(invalid(op));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // Kept beside `to_bytes` for embedders, the driver works on bytes to support stdin and stdout
+    #[allow(dead_code)]
+    pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+        return std::fs::write(path, self.to_bytes());
+    }
+
+    #[allow(dead_code)]
+    pub fn load(path: &std::path::Path) -> std::io::Result<Bytecode> {
+        let bytes = std::fs::read(path)?;
+        return Bytecode::from_bytes(&bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+    }
+}
+
+/// A listing of the constants and the code of every function, one instruction to a line
+impl std::fmt::Display for Bytecode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "constants:")?;
+        for (i, value) in self.constants.iter().enumerate() {
+            writeln!(f, "{:>6}  {:?}", i, value)?;
+        }
+        for (i, function) in self.functions.iter().enumerate() {
+            writeln!(
+                f,
+                "function {} {} (params {}, slots {}):",
+                i, function.name, function.params, function.slots
+            )?;
+            for (pc, op) in function.code.iter().enumerate() {
+                writeln!(f, "{:>6}  {:?}", pc, op)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::*;
+
+    #[test]
+    fn test_bytecode_programs_match_interpreter() {
+        for (name, source) in PROGRAMS {
+            let code = compile(&load(source));
+            assert_eq!(
+                run_to_string(&code).unwrap(),
+                expected_output(source),
+                "program {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_bytecode_runtime_errors() {
+        for (name, source) in FAILING_PROGRAMS {
+            let error = run_to_string(&compile(&load(source))).unwrap_err();
+            assert_eq!(error.message, expected_error(source), "program {}", name);
+        }
+        let error = run_to_string(&compile(&load("let x = 0;\nprint(1 / x);"))).unwrap_err();
+        assert_eq!(error.pos, 19);
+    }
+
+    #[test]
+    fn test_bytecode_compiles_names_to_slots() {
+        let code = compile(&load(
+            "let x = 1; fn f(y: int): int { fn g(): int { x + y } g() } print(f(2));",
+        ));
+        assert_eq!(code.functions.len(), 3);
+        let g = &code.functions[2];
+        assert_eq!(g.name, "g");
+        assert_eq!(
+            g.code[..3],
+            [
+                Op::LoadOuter { depth: 2, slot: 0 },
+                Op::LoadOuter { depth: 1, slot: 0 },
+                Op::Add
+            ]
+        );
+        let f = &code.functions[1];
+        assert!(f.code.contains(&Op::Call {
+            function: 2,
+            depth: 0
+        }));
+    }
+
+    #[test]
+    fn test_bytecode_round_trip() {
+        for (name, source) in PROGRAMS {
+            let code = compile(&load(source));
+            let bytes = code.to_bytes();
+            assert!(bytes.starts_with(MAGIC));
+            assert_eq!(
+                Bytecode::from_bytes(&bytes).unwrap(),
+                code,
+                "program {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_bytecode_save_and_load() {
+        let source = "let s = \"saved\"; for i = 0 : 2 { print(s, i, 0.5) }";
+        let path = std::env::temp_dir().join(format!("program_{}.mcib", std::process::id()));
+        compile(&load(source)).save(&path).unwrap();
+        let code = Bytecode::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(run_to_string(&code).unwrap(), expected_output(source));
+    }
+
+    #[test]
+    fn test_bytecode_rejects_bad_files() {
+        let code = compile(&load("print(1);"));
+        let bytes = code.to_bytes();
+        assert_eq!(
+            Bytecode::from_bytes(b"nope").unwrap_err(),
+            "not a bytecode file"
+        );
+        assert_eq!(
+            Bytecode::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            "unexpected end of bytecode"
+        );
+        let mut bad = code.clone();
+        bad.functions[0].code[0] = Op::Const(99);
+        assert_eq!(
+            Bytecode::from_bytes(&bad.to_bytes()).unwrap_err(),
+            "invalid instruction Const(99) in `main`"
+        );
+    }
+}
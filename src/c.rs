@@ -0,0 +1,693 @@
+/// A backend translating type checked programs to a single self-contained C99 file
+///
+/// The output starts with the call depth limit of the interpreter and the runtime library in
+/// `runtime/c_runtime.c`, and compiles with any C compiler:
+///
+/// * `int` is `int64_t`, `float` is `double`, `bool` is `bool` and `string` is a length and a
+///   pointer to the bytes, passed by value.
+/// * Every function of the program is a `static` C function. C has no nested functions, so each
+///   function keeps the variables its nested functions use in a `frame` struct, and the nested
+///   functions take a pointer to it as their static link.
+/// * Expressions become statements computing temporaries `tN` wherever the order of evaluation
+///   matters, since C leaves the order of operands unspecified.
+use crate::chapter_4::*;
+use crate::chapter_6::{find_escape, Escapes};
+use crate::interpreter::MAX_CALL_DEPTH;
+
+const RUNTIME: &str = include_str!("../runtime/c_runtime.c");
+
+fn c_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Int => return "int64_t",
+        Type::Float => return "double",
+        Type::Bool => return "bool",
+        Type::String => return "string",
+        Type::Unit => return "void",
+    }
+}
+
+/// A C string literal with the given bytes
+fn c_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            // `?` is escaped so that no trigraph is formed
+            b'"' | b'\\' | b'?' => {
+                out.push('\\');
+                out.push(b as char);
+            }
+            b' '..=b'~' => out.push(b as char),
+            _ => out.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+fn is_temp(value: &str) -> bool {
+    return value.starts_with('t') && value[1..].bytes().all(|b| b.is_ascii_digit());
+}
+
+/// What a name of the program is in C: a local variable, a field of a frame struct, or a static
+/// function
+#[derive(Clone)]
+enum Binding {
+    /// A C local variable
+    Local(String),
+    /// A field of the frame of the function at a nesting level
+    Field { level: usize, name: String },
+    /// A C function, declared in the function at a nesting level
+    Fn { name: String, level: usize },
+}
+
+/// A function being translated
+struct FnContext {
+    level: usize,
+    /// Declarations of the fields of its frame
+    fields: String,
+    indent: usize,
+}
+
+struct Compiler {
+    /// The declarations that escape, found in Chapter 6
+    escapes: Escapes,
+    env: Vec<(String, Binding)>,
+    fns: Vec<FnContext>,
+    /// Frame struct names by nesting level of the functions being translated
+    frames: Vec<String>,
+    structs: String,
+    prototypes: String,
+    functions: String,
+    next_id: usize,
+}
+
+/// Translate a type checked program to C
+pub fn compile(program: &Program) -> String {
+    let mut compiler = Compiler {
+        escapes: find_escape(program),
+        env: Vec::new(),
+        fns: vec![FnContext {
+            level: 0,
+            fields: "    void *link;\n".to_string(),
+            indent: 1,
+        }],
+        frames: vec!["frame_main".to_string()],
+        structs: String::new(),
+        prototypes: String::new(),
+        functions: String::new(),
+        next_id: 0,
+    };
+    let mut body = String::new();
+    compiler.compile_stmts(&program.stmts, &mut body);
+    let main = compiler.fns.pop().unwrap();
+    compiler.add_struct("frame_main", &main.fields);
+
+    let mut out = format!("#define MAX_CALL_DEPTH {}\n\n", MAX_CALL_DEPTH);
+    out.push_str(RUNTIME);
+    out.push('\n');
+    out.push_str(&compiler.structs);
+    out.push_str(&compiler.prototypes);
+    out.push_str(&compiler.functions);
+    out.push_str("int main(void)\n{\n");
+    out.push_str("    struct frame_main frame;\n");
+    out.push_str("    frame.link = NULL;\n");
+    out.push_str("    (void)frame;\n");
+    out.push_str(&body);
+    out.push_str("    return 0;\n}\n");
+    return out;
+}
+
+impl Compiler {
+    fn current(&self) -> &FnContext {
+        return self.fns.last().unwrap();
+    }
+
+    fn fresh(&mut self, name: &str) -> String {
+        self.next_id += 1;
+        return format!("{}_{}", name, self.next_id);
+    }
+
+    fn line(&self, out: &mut String, text: &str) {
+        for _ in 0..self.current().indent {
+            out.push_str("    ");
+        }
+        out.push_str(text);
+        out.push('\n');
+    }
+
+    fn indent(&mut self, by: isize) {
+        let context = self.fns.last_mut().unwrap();
+        context.indent = (context.indent as isize + by) as usize;
+    }
+
+    /// Compute a value into a new temporary
+    fn temp(&mut self, ty: Type, value: &str, out: &mut String) -> String {
+        self.next_id += 1;
+        let name = format!("t{}", self.next_id);
+        self.line(out, &format!("{} {} = {};", c_type(ty), name, value));
+        return name;
+    }
+
+    /// Declare a temporary the branches of an `if` assign to
+    fn declare_temp(&mut self, ty: Type, out: &mut String) -> String {
+        self.next_id += 1;
+        let name = format!("t{}", self.next_id);
+        self.line(out, &format!("{} {};", c_type(ty), name));
+        return name;
+    }
+
+    /// Mark a value as unused, so that the C compiler does not warn about its temporary
+    fn discard(&self, value: &str, out: &mut String) {
+        if is_temp(value) {
+            self.line(out, &format!("(void){};", value));
+        }
+    }
+
+    fn add_struct(&mut self, name: &str, fields: &str) {
+        // Declare the tag first, since functions may mention it before the definition
+        self.prototypes = format!("struct {};\n{}", name, self.prototypes);
+        self.structs
+            .push_str(&format!("struct {} {{\n{}}};\n\n", name, fields));
+    }
+
+    /// Declare a variable as a C local, or as a field of the frame struct of the current function
+    /// if a nested function reaches it through the static link
+    fn declare(&mut self, name: &str, ty: Type, escape: bool) -> Binding {
+        let c_name = self.fresh(name);
+        if escape {
+            let context = self.fns.last_mut().unwrap();
+            context
+                .fields
+                .push_str(&format!("    {} {};\n", c_type(ty), c_name));
+            return Binding::Field {
+                level: context.level,
+                name: c_name,
+            };
+        }
+        return Binding::Local(c_name);
+    }
+
+    fn lookup(&self, name: &str) -> Binding {
+        for (n, binding) in self.env.iter().rev() {
+            if n == name {
+                return binding.clone();
+            }
+        }
+        panic!("`{}` is not declared", name);
+    }
+
+    /// A pointer to the frame of the function at `level`, found by following static links
+    fn frame_pointer(&self, level: usize) -> String {
+        let current = self.current().level;
+        if level == current {
+            return "&frame".to_string();
+        }
+        let mut pointer = "frame.link".to_string();
+        for _ in level + 1..current {
+            pointer.push_str("->link");
+        }
+        return pointer;
+    }
+
+    /// The C lvalue of a variable
+    fn place(&self, binding: &Binding) -> String {
+        match binding {
+            Binding::Local(name) => return name.clone(),
+            Binding::Field { level, name } => {
+                if *level == self.current().level {
+                    return format!("frame.{}", name);
+                }
+                return format!("{}->{}", self.frame_pointer(*level), name);
+            }
+            Binding::Fn { .. } => panic!("a function is not a value"),
+        }
+    }
+
+    /// Store the value of a new variable, declaring it if it is a C local
+    fn define(&self, binding: &Binding, ty: Type, value: &str, out: &mut String) {
+        match binding {
+            Binding::Local(name) => {
+                self.line(out, &format!("{} {} = {};", c_type(ty), name, value));
+            }
+            _ => self.line(out, &format!("{} = {};", self.place(binding), value)),
+        }
+    }
+
+    fn compile_stmts(&mut self, stmts: &[Stmt], out: &mut String) {
+        let mut i = 0;
+        while i < stmts.len() {
+            let mut decls = Vec::new();
+            while let Some(Stmt {
+                kind: StmtKind::Fn(decl),
+                ..
+            }) = stmts.get(i)
+            {
+                decls.push(decl);
+                i += 1;
+            }
+            if !decls.is_empty() {
+                self.compile_fn_group(&decls);
+            } else {
+                self.compile_stmt(&stmts[i], out);
+                i += 1;
+            }
+        }
+    }
+
+    /// Compile consecutive function declarations to static C functions. Each gets a prototype, so
+    /// the bodies may call one another whatever their order
+    fn compile_fn_group(&mut self, decls: &[&FnDecl]) {
+        let level = self.current().level;
+        let mut names = Vec::new();
+        for decl in decls {
+            let name = self.fresh(&decl.name);
+            self.env.push((
+                decl.name.clone(),
+                Binding::Fn {
+                    name: name.clone(),
+                    level,
+                },
+            ));
+            names.push(name);
+        }
+        for (decl, name) in decls.iter().zip(names) {
+            self.compile_fn(decl, &name, level + 1);
+        }
+    }
+
+    fn compile_fn(&mut self, decl: &FnDecl, name: &str, level: usize) {
+        let frame = format!("frame_{}", name);
+        let mut signature = format!(
+            "static {} {}(struct {} *link",
+            c_type(decl.result),
+            name,
+            self.frames[level - 1]
+        );
+        self.fns.push(FnContext {
+            level,
+            fields: format!("    struct {} *link;\n", self.frames[level - 1]),
+            indent: 1,
+        });
+        self.frames.push(frame.clone());
+
+        let scope = self.env.len();
+        let mut body = String::new();
+        for param in &decl.params {
+            let binding = self.declare(&param.name, param.ty, self.escapes.escapes(param.pos));
+            match &binding {
+                Binding::Local(c_name) => {
+                    signature.push_str(&format!(", {} {}", c_type(param.ty), c_name));
+                }
+                Binding::Field { name: c_name, .. } => {
+                    signature.push_str(&format!(", {} {}", c_type(param.ty), c_name));
+                    self.line(&mut body, &format!("frame.{} = {};", c_name, c_name));
+                }
+                Binding::Fn { .. } => unreachable!(),
+            }
+            self.env.push((param.name.clone(), binding));
+        }
+        signature.push(')');
+        let value = self.compile_block(&decl.body, &mut body);
+        self.env.truncate(scope);
+        if decl.result != Type::Unit {
+            self.line(
+                &mut body,
+                &format!("{} result = {};", c_type(decl.result), value),
+            );
+        }
+        self.line(&mut body, "leave_call();");
+        if decl.result != Type::Unit {
+            self.line(&mut body, "return result;");
+        }
+        self.frames.pop();
+        let context = self.fns.pop().unwrap();
+        self.add_struct(&frame, &context.fields);
+
+        self.prototypes.push_str(&format!("{};\n", signature));
+        self.functions.push_str(&format!("{}\n{{\n", signature));
+        self.functions
+            .push_str(&format!("    struct {} frame;\n", frame));
+        self.functions.push_str("    enter_call();\n");
+        self.functions.push_str("    frame.link = link;\n");
+        self.functions.push_str("    (void)frame;\n");
+        self.functions.push_str(&body);
+        self.functions.push_str("}\n\n");
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt, out: &mut String) {
+        match &stmt.kind {
+            StmtKind::Let { name, init, .. } => {
+                let value = self.compile_expr(init, out);
+                let binding = self.declare(name, init.ty(), self.escapes.escapes(stmt.pos));
+                self.define(&binding, init.ty(), &value, out);
+                self.env.push((name.clone(), binding));
+            }
+            StmtKind::Assign { name, value } => {
+                let value = self.compile_expr(value, out);
+                let place = self.place(&self.lookup(name));
+                self.line(out, &format!("{} = {};", place, value));
+            }
+            StmtKind::While { cond, body } => {
+                let mut code = String::new();
+                self.indent(1);
+                let cond = self.compile_expr(cond, &mut code);
+                if code.is_empty() {
+                    self.indent(-1);
+                    self.line(out, &format!("while ({}) {{", cond));
+                    self.indent(1);
+                } else {
+                    self.indent(-1);
+                    self.line(out, "while (true) {");
+                    self.indent(1);
+                    out.push_str(&code);
+                    self.line(out, &format!("if (!{}) {{", cond));
+                    self.line(out, "    break;");
+                    self.line(out, "}");
+                }
+                let value = self.compile_block(body, out);
+                self.discard(&value, out);
+                self.indent(-1);
+                self.line(out, "}");
+            }
+            StmtKind::For { var, lo, hi, body } => {
+                let bounds = self.compile_operands(&[lo, hi], out);
+                self.next_id += 1;
+                let counter = format!("t{}", self.next_id);
+                let hi = match is_temp(&bounds[1]) {
+                    true => bounds[1].clone(),
+                    false => self.temp(Type::Int, &bounds[1], out),
+                };
+                self.line(
+                    out,
+                    &format!(
+                        "for (int64_t {} = {}; {} < {}; {}++) {{",
+                        counter, bounds[0], counter, hi, counter
+                    ),
+                );
+                self.indent(1);
+                let scope = self.env.len();
+                let binding = self.declare(var, Type::Int, self.escapes.escapes(stmt.pos));
+                self.define(&binding, Type::Int, &counter, out);
+                self.env.push((var.clone(), binding));
+                let value = self.compile_block(body, out);
+                self.discard(&value, out);
+                self.env.truncate(scope);
+                self.indent(-1);
+                self.line(out, "}");
+            }
+            StmtKind::Fn(_) => unreachable!("function declarations are compiled in groups"),
+            StmtKind::Error => unreachable!("the type checker rejects syntax errors"),
+            StmtKind::Expr(expr) => {
+                let value = self.compile_expr(expr, out);
+                self.discard(&value, out);
+            }
+        }
+    }
+
+    /// Compile a block, returning its value or an empty string if it has none
+    fn compile_block(&mut self, block: &Block, out: &mut String) -> String {
+        let scope = self.env.len();
+        self.compile_stmts(&block.stmts, out);
+        let value = match &block.result {
+            Some(result) => self.compile_expr(result, out),
+            None => String::new(),
+        };
+        self.env.truncate(scope);
+        return value;
+    }
+
+    /// Compile expressions evaluated left to right. Values read before an operand with effects
+    /// are saved in temporaries first
+    fn compile_operands(&mut self, exprs: &[&Expr], out: &mut String) -> Vec<String> {
+        let mut values: Vec<String> = Vec::new();
+        for expr in exprs {
+            let mut code = String::new();
+            let value = self.compile_expr(expr, &mut code);
+            if !code.is_empty() {
+                for i in 0..values.len() {
+                    let constant = matches!(
+                        exprs[i].kind,
+                        ExprKind::Int(_)
+                            | ExprKind::Float(_)
+                            | ExprKind::Bool(_)
+                            | ExprKind::Str(_)
+                    );
+                    if !constant && !is_temp(&values[i]) {
+                        values[i] = self.temp(exprs[i].ty(), &values[i], out);
+                    }
+                }
+            }
+            out.push_str(&code);
+            values.push(value);
+        }
+        return values;
+    }
+
+    /// Compile an expression into statements appended to `out`, returning a C expression for its
+    /// value, or an empty string if it has none
+    fn compile_expr(&mut self, expr: &Expr, out: &mut String) -> String {
+        match &expr.kind {
+            ExprKind::Int(i) => return i.to_string(),
+            ExprKind::Float(x) => return format!("{:?}", x),
+            ExprKind::Bool(b) => return b.to_string(),
+            ExprKind::Str(s) => return format!("(string){{{}, {}}}", s.len(), c_string(s)),
+            ExprKind::Var(name) => return self.place(&self.lookup(name)),
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let value = self.compile_expr(operand, out);
+                if operand.ty() == Type::Float {
+                    return format!("(-{})", value);
+                }
+                return format!("int_neg({})", value);
+            }
+            ExprKind::Binary(left, op, right) => return self.compile_binary(left, *op, right, out),
+            ExprKind::Error => unreachable!("the type checker rejects syntax errors"),
+            ExprKind::Call(name, args) => return self.compile_call(name, args, expr.ty(), out),
+            ExprKind::If {
+                branches,
+                else_block,
+            } => {
+                let result = match expr.ty() {
+                    Type::Unit => String::new(),
+                    ty => self.declare_temp(ty, out),
+                };
+                self.compile_if(branches, else_block.as_ref(), &result, out);
+                return result;
+            }
+        }
+    }
+
+    fn compile_binary(&mut self, left: &Expr, op: BinOp, right: &Expr, out: &mut String) -> String {
+        if op == BinOp::And || op == BinOp::Or {
+            let left = self.compile_expr(left, out);
+            let mut code = String::new();
+            self.indent(1);
+            let right = self.compile_expr(right, &mut code);
+            self.indent(-1);
+            let c_op = if op == BinOp::And { "&&" } else { "||" };
+            if code.is_empty() {
+                return format!("({} {} {})", left, c_op, right);
+            }
+            // The right operand is only computed when it decides the result
+            let result = self.temp(Type::Bool, &left, out);
+            let test = if op == BinOp::And {
+                result.clone()
+            } else {
+                format!("!{}", result)
+            };
+            self.line(out, &format!("if ({}) {{", test));
+            out.push_str(&code);
+            self.line(out, &format!("    {} = {};", result, right));
+            self.line(out, "}");
+            return result;
+        }
+        let values = self.compile_operands(&[left, right], out);
+        let (l, r) = (&values[0], &values[1]);
+        let c_op = match op {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Eq => "==",
+            BinOp::Neq => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And | BinOp::Or => unreachable!(),
+        };
+        match (left.ty(), op) {
+            // Division may stop the program, so it happens in its place among the statements
+            (Type::Int, BinOp::Div) => {
+                return self.temp(Type::Int, &format!("int_div({}, {})", l, r), out);
+            }
+            (Type::Int, BinOp::Add) => return format!("int_add({}, {})", l, r),
+            (Type::Int, BinOp::Sub) => return format!("int_sub({}, {})", l, r),
+            (Type::Int, BinOp::Mul) => return format!("int_mul({}, {})", l, r),
+            (Type::String, BinOp::Add) => return format!("string_concat({}, {})", l, r),
+            (Type::String, BinOp::Eq) => return format!("string_equal({}, {})", l, r),
+            (Type::String, BinOp::Neq) => return format!("!string_equal({}, {})", l, r),
+            _ => return format!("({} {} {})", l, c_op, r),
+        }
+    }
+
+    fn compile_call(&mut self, name: &str, args: &[Expr], ty: Type, out: &mut String) -> String {
+        let exprs: Vec<&Expr> = args.iter().collect();
+        let binding = self.env.iter().rev().find(|(n, _)| n == name);
+        if let Some((_, Binding::Fn { name, level })) = binding {
+            let name = name.clone();
+            let mut arguments = vec![self.frame_pointer(*level)];
+            arguments.extend(self.compile_operands(&exprs, out));
+            let call = format!("{}({})", name, arguments.join(", "));
+            if ty == Type::Unit {
+                self.line(out, &format!("{};", call));
+                return String::new();
+            }
+            return self.temp(ty, &call, out);
+        }
+        // `print` computes every argument before printing any of them
+        let values = self.compile_operands(&exprs, out);
+        for (arg, value) in args.iter().zip(values) {
+            let print = match arg.ty() {
+                Type::Int => "print_int",
+                Type::Float => "print_float",
+                Type::Bool => "print_bool",
+                Type::String => "print_string",
+                Type::Unit => unreachable!(),
+            };
+            self.line(out, &format!("{}({});", print, value));
+        }
+        return String::new();
+    }
+
+    fn compile_if(
+        &mut self,
+        branches: &[(Expr, Block)],
+        else_block: Option<&Block>,
+        result: &str,
+        out: &mut String,
+    ) {
+        let (cond, then) = &branches[0];
+        let cond = self.compile_expr(cond, out);
+        self.line(out, &format!("if ({}) {{", cond));
+        self.compile_branch(then, result, out);
+        if branches.len() > 1 {
+            self.line(out, "} else {");
+            self.indent(1);
+            self.compile_if(&branches[1..], else_block, result, out);
+            self.indent(-1);
+        } else if let Some(block) = else_block {
+            self.line(out, "} else {");
+            self.compile_branch(block, result, out);
+        }
+        self.line(out, "}");
+    }
+
+    fn compile_branch(&mut self, block: &Block, result: &str, out: &mut String) {
+        self.indent(1);
+        let value = self.compile_block(block, out);
+        if result.is_empty() {
+            self.discard(&value, out);
+        } else {
+            self.line(out, &format!("{} = {};", result, value));
+        }
+        self.indent(-1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::*;
+    use std::process::{Command, Output};
+
+    /// Compile a program with strict warnings and run it, from a directory of its own. The
+    /// program may well declare variables and functions it does not use
+    fn run(dir: &std::path::Path, name: &str, source: &str) -> Output {
+        let path = dir.join(format!("{}.c", name));
+        let exe = dir.join(name);
+        std::fs::write(&path, compile(&load(source))).unwrap();
+        let output = Command::new("cc")
+            .args(["-std=c99", "-pedantic", "-Wall", "-Werror"])
+            .args(["-Wno-unused-variable", "-Wno-unused-function"])
+            .arg("-o")
+            .arg(&exe)
+            .arg(&path)
+            .arg("-lm")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "program {}: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Command::new(&exe).output().unwrap();
+    }
+
+    #[test]
+    fn test_c_string_escapes() {
+        assert_eq!(c_string("a \"b\" \\ ??="), "\"a \\\"b\\\" \\\\ \\?\\?=\"");
+        assert_eq!(c_string("\n\u{e9}"), "\"\\012\\303\\251\"");
+    }
+
+    #[test]
+    fn test_c_nested_function_uses_static_link() {
+        let c = compile(&load(
+            "fn f(x: int): int { fn g(): int { fn h(): int { x } h() } g() } print(f(1));",
+        ));
+        assert!(
+            c.contains("struct frame_f_1 {\n    struct frame_main *link;\n    int64_t x_2;\n};")
+        );
+        assert!(c.contains("static int64_t f_1(struct frame_main *link, int64_t x_2);"));
+        assert!(c.contains("    frame.x_2 = x_2;\n"));
+        assert!(c.contains("int64_t result = frame.link->link->x_2;"));
+    }
+
+    #[test]
+    fn test_c_keeps_order_of_evaluation() {
+        let c = compile(&load(
+            "let mut x = 1; fn f(): int { x = 2; 3 } print(x + f());",
+        ));
+        let read = c.find(" = frame.x_1;").unwrap();
+        assert!(read < c.find(" = f_2(&frame);").unwrap());
+        assert!(c.contains("print_int(int_add(t4, t3));"));
+    }
+
+    #[test]
+    #[ignore = "needs `cc`, run with `cargo test -- --ignored`"]
+    fn test_c_programs_compile_and_run() {
+        require_tool("cc");
+        let dir = std::env::temp_dir().join(format!("c_run_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in PROGRAMS {
+            let output = run(&dir, name, source);
+            assert!(output.status.success(), "program {}", name);
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                expected_output(source),
+                "program {}",
+                name
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs `cc`, run with `cargo test -- --ignored`"]
+    fn test_c_runtime_errors() {
+        require_tool("cc");
+        let dir = std::env::temp_dir().join(format!("c_fail_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in FAILING_PROGRAMS {
+            let output = run(&dir, name, source);
+            assert_eq!(output.status.code(), Some(1), "program {}", name);
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap().trim_end(),
+                expected_error(source),
+                "program {}",
+                name
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
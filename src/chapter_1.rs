@@ -1,8 +1,12 @@
+// The straight-line programs of Chapter 1 are a warm-up that only its tests run
+#![allow(dead_code)]
+
 /// Implementations described in Chapter 1
 use std::cmp;
 use std::collections::HashMap;
 
 /// Variant for binary operators
+#[derive(Debug, PartialEq)]
 enum BinOp {
     Add,
     Sub,
@@ -27,6 +31,7 @@ where
 }
 
 /// A statement in the program. Statements do not return anything
+#[derive(Debug, PartialEq)]
 enum Statement {
     Compound(Box<Statement>, Box<Statement>),
     Assignment { id: String, expr: Box<Expression> },
@@ -34,6 +39,7 @@ enum Statement {
 }
 
 /// An expression in the program. Expressions currently always return an integer
+#[derive(Debug, PartialEq)]
 enum Expression {
     Id(String),
     Num(i32),
@@ -41,28 +47,209 @@ enum Expression {
     Eseq(Statement, Box<Expression>),
 }
 
-/// Returns the maximum number of arguments of any print statement within any subexpression of a given statement
-fn max_args(stm: &Statement) -> i32 {
+/// Walks a statement and the expressions inside it without changing them. Each method defaults to
+/// visiting the children, so a pass overrides only the nodes it cares about and calls the `walk_`
+/// function to keep descending
+trait Visitor {
+    fn visit_stm(&mut self, stm: &Statement) {
+        walk_stm(self, stm);
+    }
+
+    fn visit_expr(&mut self, expr: &Expression) {
+        walk_expr(self, expr);
+    }
+}
+
+/// Visits the children of a statement, in the order they are evaluated
+fn walk_stm<V: Visitor + ?Sized>(visitor: &mut V, stm: &Statement) {
+    match stm {
+        Statement::Compound(l, r) => {
+            visitor.visit_stm(l);
+            visitor.visit_stm(r);
+        }
+        Statement::Assignment { id: _, expr } => visitor.visit_expr(expr),
+        Statement::Print(exprs) => {
+            for expr in exprs {
+                visitor.visit_expr(expr);
+            }
+        }
+    }
+}
+
+/// Visits the children of an expression, in the order they are evaluated
+fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expression) {
+    match expr {
+        Expression::Id(_) | Expression::Num(_) => {}
+        Expression::Op(l, _, r) => {
+            visitor.visit_expr(l);
+            visitor.visit_expr(r);
+        }
+        Expression::Eseq(s, e) => {
+            visitor.visit_stm(s);
+            visitor.visit_expr(e);
+        }
+    }
+}
+
+/// Like `Visitor`, but may change the nodes in place
+trait VisitorMut {
+    fn visit_stm_mut(&mut self, stm: &mut Statement) {
+        walk_stm_mut(self, stm);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expression) {
+        walk_expr_mut(self, expr);
+    }
+}
+
+/// Visits the children of a statement mutably
+fn walk_stm_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stm: &mut Statement) {
     match stm {
-        Statement::Compound(l, r) => return cmp::max(max_args(l), max_args(r)),
-        Statement::Assignment { id: _, expr } => return max_args_expr(expr),
+        Statement::Compound(l, r) => {
+            visitor.visit_stm_mut(l);
+            visitor.visit_stm_mut(r);
+        }
+        Statement::Assignment { id: _, expr } => visitor.visit_expr_mut(expr),
         Statement::Print(exprs) => {
-            let mut current_max = exprs.len() as i32;
             for expr in exprs {
-                current_max = cmp::max(current_max, max_args_expr(expr));
+                visitor.visit_expr_mut(expr);
             }
-            return current_max;
         }
     }
 }
 
+/// Visits the children of an expression mutably
+fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expression) {
+    match expr {
+        Expression::Id(_) | Expression::Num(_) => {}
+        Expression::Op(l, _, r) => {
+            visitor.visit_expr_mut(l);
+            visitor.visit_expr_mut(r);
+        }
+        Expression::Eseq(s, e) => {
+            visitor.visit_stm_mut(s);
+            visitor.visit_expr_mut(e);
+        }
+    }
+}
+
+/// Rebuilds a tree from its parts. The defaults fold the children and put the node back together
+/// unchanged, so a pass that rewrites one kind of node can replace it with anything
+trait Fold {
+    fn fold_stm(&mut self, stm: Statement) -> Statement {
+        return fold_stm(self, stm);
+    }
+
+    fn fold_expr(&mut self, expr: Expression) -> Expression {
+        return fold_expr(self, expr);
+    }
+}
+
+/// Folds the children of a statement
+fn fold_stm<F: Fold + ?Sized>(folder: &mut F, stm: Statement) -> Statement {
+    match stm {
+        Statement::Compound(l, r) => {
+            let l = folder.fold_stm(*l);
+            let r = folder.fold_stm(*r);
+            return Statement::Compound(Box::new(l), Box::new(r));
+        }
+        Statement::Assignment { id, expr } => {
+            let expr = Box::new(folder.fold_expr(*expr));
+            return Statement::Assignment { id, expr };
+        }
+        Statement::Print(exprs) => {
+            return Statement::Print(exprs.into_iter().map(|e| folder.fold_expr(e)).collect())
+        }
+    }
+}
+
+/// Folds the children of an expression
+fn fold_expr<F: Fold + ?Sized>(folder: &mut F, expr: Expression) -> Expression {
+    match expr {
+        Expression::Id(_) | Expression::Num(_) => return expr,
+        Expression::Op(l, op, r) => {
+            let l = folder.fold_expr(*l);
+            let r = folder.fold_expr(*r);
+            return Expression::Op(Box::new(l), op, Box::new(r));
+        }
+        Expression::Eseq(s, e) => {
+            let s = folder.fold_stm(s);
+            let e = folder.fold_expr(*e);
+            return Expression::Eseq(s, Box::new(e));
+        }
+    }
+}
+
+/// Tracks the most arguments seen in a print statement
+struct MaxArgs(i32);
+
+impl Visitor for MaxArgs {
+    fn visit_stm(&mut self, stm: &Statement) {
+        if let Statement::Print(exprs) = stm {
+            self.0 = cmp::max(self.0, exprs.len() as i32);
+        }
+        walk_stm(self, stm);
+    }
+}
+
+/// Returns the maximum number of arguments of any print statement within any subexpression of a given statement
+fn max_args(stm: &Statement) -> i32 {
+    let mut max = MaxArgs(0);
+    max.visit_stm(stm);
+    return max.0;
+}
+
 /// Helper function for max_args to do the same functionality on an expression
 fn max_args_expr(eseq: &Expression) -> i32 {
-    match eseq {
-        Expression::Id(_) => return 0,
-        Expression::Num(_) => return 0,
-        Expression::Op(l, _, r) => return cmp::max(max_args_expr(l), max_args_expr(r)),
-        Expression::Eseq(s, e) => return cmp::max(max_args(s), max_args_expr(e)),
+    let mut max = MaxArgs(0);
+    max.visit_expr(eseq);
+    return max.0;
+}
+
+/// Interprets the program as it is visited. Every expression leaves its value on `values`, which
+/// the node above it then takes off again
+struct Interp<'a> {
+    context: &'a mut HashMap<String, i32>,
+    values: Vec<i32>,
+}
+
+impl Interp<'_> {
+    fn pop(&mut self) -> i32 {
+        return self.values.pop().expect("every expression leaves a value");
+    }
+}
+
+impl Visitor for Interp<'_> {
+    fn visit_stm(&mut self, stm: &Statement) {
+        match stm {
+            Statement::Compound(..) => walk_stm(self, stm),
+            Statement::Assignment { id, expr } => {
+                self.visit_expr(expr);
+                let value = self.pop();
+                self.context.insert(id.clone(), value);
+            }
+            Statement::Print(exprs) => {
+                for expr in exprs {
+                    self.visit_expr(expr);
+                    println!("{}", self.pop());
+                }
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Id(s) => self.values.push(self.context[s]),
+            Expression::Num(i) => self.values.push(*i),
+            Expression::Op(_, bin_op, _) => {
+                walk_expr(self, expr);
+                let right = self.pop();
+                let left = self.pop();
+                self.values.push(calc_bin_op(left, bin_op, right));
+            }
+            // The statement leaves nothing behind, so the value of the expression is the result
+            Expression::Eseq(..) => walk_expr(self, expr),
+        }
     }
 }
 
@@ -74,34 +261,62 @@ fn interp(stm: &Statement) {
 
 /// Helper function for interp() which interperets a statement
 fn interp_stm(stm: &Statement, context: &mut HashMap<String, i32>) {
-    match stm {
-        Statement::Compound(l, r) => {
-            interp_stm(l, context);
-            interp_stm(r, context);
-        }
-        Statement::Assignment { id: s, expr } => {
-            let expr_val = interp_expr(expr, context);
-            context.insert(s.clone(), expr_val);
+    let mut interp = Interp {
+        context,
+        values: Vec::new(),
+    };
+    interp.visit_stm(stm);
+}
+
+/// Helper function for interp() which interperets an expression
+fn interp_expr(expr: &Expression, context: &mut HashMap<String, i32>) -> i32 {
+    let mut interp = Interp {
+        context,
+        values: Vec::new(),
+    };
+    interp.visit_expr(expr);
+    return interp.pop();
+}
+
+/// Renames every use and assignment of a variable
+struct Rename<'a> {
+    from: &'a str,
+    to: &'a str,
+}
+
+impl VisitorMut for Rename<'_> {
+    fn visit_stm_mut(&mut self, stm: &mut Statement) {
+        if let Statement::Assignment { id, expr: _ } = stm {
+            if id == self.from {
+                *id = self.to.to_string();
+            }
         }
-        Statement::Print(exprs) => {
-            for expr in exprs {
-                println!("{}", interp_expr(expr, context));
+        walk_stm_mut(self, stm);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expression) {
+        if let Expression::Id(id) = expr {
+            if id == self.from {
+                *id = self.to.to_string();
             }
         }
+        walk_expr_mut(self, expr);
     }
 }
 
-/// Helper function for interp() which interperets an expression
-fn interp_expr(expr: &Expression, context: &mut HashMap<String, i32>) -> i32 {
-    match expr {
-        Expression::Id(s) => return context[s],
-        Expression::Num(i) => return *i,
-        Expression::Op(l, bin_op, r) => {
-            return calc_bin_op(interp_expr(l, context), bin_op, interp_expr(r, context))
-        }
-        Expression::Eseq(s, e) => {
-            interp_stm(s, context);
-            return interp_expr(e, context);
+/// Replaces operators on two numbers by their result, leaving division by zero for run time
+struct ConstantFold;
+
+impl Fold for ConstantFold {
+    fn fold_expr(&mut self, expr: Expression) -> Expression {
+        match fold_expr(self, expr) {
+            Expression::Op(l, op, r) => match (*l, *r) {
+                (Expression::Num(l), Expression::Num(r)) if !matches!(op, BinOp::Div) || r != 0 => {
+                    return Expression::Num(calc_bin_op(l, &op, r));
+                }
+                (l, r) => return Expression::Op(Box::new(l), op, Box::new(r)),
+            },
+            expr => return expr,
         }
     }
 }
@@ -278,4 +493,49 @@ mod tests {
 
         assert_eq!(interp_expr(&eseq, &mut context), 10);
     }
+
+    #[test]
+    fn test_rename() {
+        let mut stm = Statement::Compound(
+            Box::new(Statement::Assignment {
+                id: "a".to_string(),
+                expr: Box::new(Expression::Id("b".to_string())),
+            }),
+            Box::new(Statement::Print(vec![Expression::Eseq(
+                Statement::Print(vec![Expression::Id("a".to_string())]),
+                Box::new(Expression::Id("a".to_string())),
+            )])),
+        );
+        Rename { from: "a", to: "c" }.visit_stm_mut(&mut stm);
+
+        let mut context = HashMap::new();
+        context.insert("b".to_string(), 4);
+        interp_stm(&stm, &mut context);
+        assert_eq!(context.get("a"), None);
+        assert_eq!(context["c"], 4);
+    }
+
+    #[test]
+    fn test_constant_fold() {
+        let op = |l, op, r| Expression::Op(Box::new(l), op, Box::new(r));
+        let expr = op(
+            op(Expression::Num(2), BinOp::Mul, Expression::Num(3)),
+            BinOp::Add,
+            Expression::Id("a".to_string()),
+        );
+        assert_eq!(
+            ConstantFold.fold_expr(expr),
+            op(
+                Expression::Num(6),
+                BinOp::Add,
+                Expression::Id("a".to_string())
+            )
+        );
+
+        let stm = Statement::Print(vec![op(Expression::Num(1), BinOp::Div, Expression::Num(0))]);
+        assert_eq!(
+            ConstantFold.fold_stm(stm),
+            Statement::Print(vec![op(Expression::Num(1), BinOp::Div, Expression::Num(0))])
+        );
+    }
 }
@@ -0,0 +1,515 @@
+/// Implementations described in Chapter 10: flow graphs, liveness and interference graphs
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::chapter_6::{Label, Temp};
+use crate::chapter_9::Instr;
+
+/// A set of small integers, one bit each
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn new() -> BitSet {
+        return BitSet::default();
+    }
+
+    pub fn insert(&mut self, i: usize) {
+        let word = i / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (i % 64);
+    }
+
+    #[cfg(test)]
+    pub fn remove(&mut self, i: usize) {
+        if let Some(word) = self.words.get_mut(i / 64) {
+            *word &= !(1 << (i % 64));
+        }
+    }
+
+    #[cfg(test)]
+    pub fn contains(&self, i: usize) -> bool {
+        return self
+            .words
+            .get(i / 64)
+            .is_some_and(|word| word & (1 << (i % 64)) != 0);
+    }
+
+    /// Add every element of `other`, returning whether the set grew
+    pub fn union_with(&mut self, other: &BitSet) -> bool {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        let mut changed = false;
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            let union = *word | other;
+            changed |= union != *word;
+            *word = union;
+        }
+        return changed;
+    }
+
+    /// Remove every element of `other`
+    pub fn difference_with(&mut self, other: &BitSet) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= !other;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        return self.words.iter().enumerate().flat_map(|(i, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| i * 64 + bit)
+        });
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        return self
+            .words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum();
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        return self.words.iter().all(|word| *word == 0);
+    }
+}
+
+/// The control flow graph of a function body, with one node per instruction
+///
+/// Temporaries are numbered densely in the order they first appear, so that sets of them are
+/// bitsets.
+#[derive(Debug, Clone)]
+pub struct FlowGraph {
+    /// The temporary each number stands for
+    pub temps: Vec<Temp>,
+    pub numbers: HashMap<Temp, usize>,
+    pub defs: Vec<BitSet>,
+    pub uses: Vec<BitSet>,
+    /// Whether the instruction is a move, whose source and destination need not interfere
+    pub is_move: Vec<bool>,
+    pub succs: Vec<Vec<usize>>,
+    pub preds: Vec<Vec<usize>>,
+}
+
+impl FlowGraph {
+    /// Build the flow graph of a list of instructions. Control falls through to the next
+    /// instruction unless it is a jump; a jump to a label outside the list leaves the function
+    pub fn new(instrs: &[Instr]) -> FlowGraph {
+        let mut graph = FlowGraph {
+            temps: Vec::new(),
+            numbers: HashMap::new(),
+            defs: Vec::new(),
+            uses: Vec::new(),
+            is_move: Vec::new(),
+            succs: Vec::new(),
+            preds: vec![Vec::new(); instrs.len()],
+        };
+        let mut labels: HashMap<&Label, usize> = HashMap::new();
+        for (i, instr) in instrs.iter().enumerate() {
+            if let Instr::Label { label, .. } = instr {
+                labels.insert(label, i);
+            }
+        }
+        for (i, instr) in instrs.iter().enumerate() {
+            let defs = graph.number_all(&instr.defs());
+            let uses = graph.number_all(&instr.uses());
+            graph.defs.push(defs);
+            graph.uses.push(uses);
+            graph.is_move.push(matches!(instr, Instr::Move { .. }));
+            let succs: Vec<usize> = match instr {
+                Instr::Oper {
+                    jump: Some(targets),
+                    ..
+                } => targets
+                    .iter()
+                    .filter_map(|t| labels.get(t).copied())
+                    .collect(),
+                _ if i + 1 < instrs.len() => vec![i + 1],
+                _ => Vec::new(),
+            };
+            for succ in &succs {
+                if !graph.preds[*succ].contains(&i) {
+                    graph.preds[*succ].push(i);
+                }
+            }
+            graph.succs.push(succs);
+        }
+        return graph;
+    }
+
+    fn number(&mut self, t: Temp) -> usize {
+        if let Some(n) = self.numbers.get(&t) {
+            return *n;
+        }
+        self.temps.push(t);
+        self.numbers.insert(t, self.temps.len() - 1);
+        return self.temps.len() - 1;
+    }
+
+    fn number_all(&mut self, temps: &[Temp]) -> BitSet {
+        let mut set = BitSet::new();
+        for t in temps {
+            set.insert(self.number(*t));
+        }
+        return set;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.succs.len();
+    }
+
+    /// The order to visit nodes in for a backward analysis: reverse postorder of the reversed
+    /// graph, starting from the exits. Nodes that cannot reach an exit, such as those of an
+    /// infinite loop, come last
+    pub fn backward_order(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.len()];
+        let mut postorder = Vec::new();
+        let exits = (0..self.len()).filter(|n| self.succs[*n].is_empty());
+        for root in exits.chain((0..self.len()).rev()) {
+            if visited[root] {
+                continue;
+            }
+            // Depth first search over predecessors without recursion
+            visited[root] = true;
+            let mut stack = vec![(root, 0)];
+            while let Some((node, next)) = stack.last_mut() {
+                if let Some(&pred) = self.preds[*node].get(*next) {
+                    *next += 1;
+                    if !visited[pred] {
+                        visited[pred] = true;
+                        stack.push((pred, 0));
+                    }
+                } else {
+                    postorder.push(*node);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        return postorder;
+    }
+}
+
+/// The temporaries live on entry to and exit from each node of a flow graph
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    pub live_in: Vec<BitSet>,
+    pub live_out: Vec<BitSet>,
+}
+
+/// Solve the liveness equations
+///
+/// `in[n] = use[n] ∪ (out[n] − def[n])` and `out[n]` is the union of `in` over the successors
+/// of `n`. Nodes wait on a worklist ordered by `backward_order`, so each is usually visited after
+/// its successors and a loop body settles in a few passes.
+pub fn liveness(graph: &FlowGraph) -> Liveness {
+    let mut live = Liveness {
+        live_in: vec![BitSet::new(); graph.len()],
+        live_out: vec![BitSet::new(); graph.len()],
+    };
+    let order = graph.backward_order();
+    let mut rank = vec![0; graph.len()];
+    for (i, node) in order.iter().enumerate() {
+        rank[*node] = i;
+    }
+    let mut worklist: BTreeSet<(usize, usize)> = order.iter().map(|n| (rank[*n], *n)).collect();
+    while let Some((_, node)) = worklist.pop_first() {
+        let mut out = BitSet::new();
+        for succ in &graph.succs[node] {
+            out.union_with(&live.live_in[*succ]);
+        }
+        let mut new_in = out.clone();
+        new_in.difference_with(&graph.defs[node]);
+        new_in.union_with(&graph.uses[node]);
+        live.live_out[node] = out;
+        if new_in != live.live_in[node] {
+            live.live_in[node] = new_in;
+            for pred in &graph.preds[node] {
+                worklist.insert((rank[*pred], *pred));
+            }
+        }
+    }
+    return live;
+}
+
+/// Which temporaries may not share a register
+#[derive(Debug, Clone)]
+pub struct InterferenceGraph {
+    /// The temporary of each node, numbered as in the flow graph
+    pub temps: Vec<Temp>,
+    pub numbers: HashMap<Temp, usize>,
+    pub adj_set: HashSet<(usize, usize)>,
+    pub adj_list: Vec<Vec<usize>>,
+    /// The destination and source of every move, which coalescing tries to put in one register
+    pub moves: Vec<(usize, usize)>,
+}
+
+impl InterferenceGraph {
+    /// Build the interference graph: every temporary an instruction defines interferes with each
+    /// one live after it, except that the destination of a move does not interfere with its
+    /// source
+    pub fn new(graph: &FlowGraph, live: &Liveness) -> InterferenceGraph {
+        let mut interference = InterferenceGraph {
+            temps: graph.temps.clone(),
+            numbers: graph.numbers.clone(),
+            adj_set: HashSet::new(),
+            adj_list: vec![Vec::new(); graph.temps.len()],
+            moves: Vec::new(),
+        };
+        for node in 0..graph.len() {
+            let source = match graph.is_move[node] {
+                true => graph.uses[node].iter().next(),
+                false => None,
+            };
+            for def in graph.defs[node].iter() {
+                if let Some(source) = source {
+                    interference.moves.push((def, source));
+                }
+                for out in live.live_out[node].iter() {
+                    if Some(out) != source {
+                        interference.add_edge(def, out);
+                    }
+                }
+            }
+        }
+        return interference;
+    }
+
+    pub fn add_edge(&mut self, a: usize, b: usize) {
+        if a == b || self.adj_set.contains(&(a, b)) {
+            return;
+        }
+        self.adj_set.insert((a, b));
+        self.adj_set.insert((b, a));
+        self.adj_list[a].push(b);
+        self.adj_list[b].push(a);
+    }
+
+    /// Whether two temporaries may not share a register
+    #[allow(dead_code)]
+    pub fn interferes(&self, a: Temp, b: Temp) -> bool {
+        match (self.numbers.get(&a), self.numbers.get(&b)) {
+            (Some(a), Some(b)) => return self.adj_set.contains(&(*a, *b)),
+            _ => return false,
+        }
+    }
+
+    /// One line per temporary listing its neighbours, then the moves
+    pub fn dump(&self, name: &dyn Fn(Temp) -> String) -> String {
+        let mut out = String::new();
+        for (node, t) in self.temps.iter().enumerate() {
+            let mut neighbours: Vec<Temp> =
+                self.adj_list[node].iter().map(|n| self.temps[*n]).collect();
+            neighbours.sort();
+            let names: Vec<String> = neighbours.into_iter().map(name).collect();
+            writeln!(out, "{}: {}", name(*t), names.join(" ")).unwrap();
+        }
+        for (dst, src) in &self.moves {
+            writeln!(
+                out,
+                "move {} <- {}",
+                name(self.temps[*dst]),
+                name(self.temps[*src])
+            )
+            .unwrap();
+        }
+        return out;
+    }
+}
+
+/// Each instruction with the temporaries live before and after it, for debugging
+pub fn dump_liveness(
+    instrs: &[Instr],
+    graph: &FlowGraph,
+    live: &Liveness,
+    name: &dyn Fn(Temp) -> String,
+) -> String {
+    let set = |bits: &BitSet| -> String {
+        let mut temps: Vec<Temp> = bits.iter().map(|n| graph.temps[n]).collect();
+        temps.sort();
+        let names: Vec<String> = temps.into_iter().map(name).collect();
+        return format!("{{{}}}", names.join(" "));
+    };
+    let mut out = String::new();
+    for (i, instr) in instrs.iter().enumerate() {
+        writeln!(
+            out,
+            "{:<32} in: {} out: {}",
+            instr.format(name),
+            set(&live.live_in[i]),
+            set(&live.live_out[i])
+        )
+        .unwrap();
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_6::X86_64Frame;
+    use crate::chapter_7::{translate, Frag};
+    use crate::chapter_8::canonicalize;
+    use crate::chapter_9::{codegen, x86_64_name};
+    use crate::test_programs::*;
+
+    const A: Temp = Temp(100);
+    const B: Temp = Temp(101);
+    const C: Temp = Temp(102);
+
+    fn jump(assem: &str, src: Vec<Temp>, targets: &[&str]) -> Instr {
+        return Instr::Oper {
+            assem: assem.to_string(),
+            dst: vec![],
+            src,
+            jump: Some(targets.iter().map(|t| Label::named(t)).collect()),
+        };
+    }
+
+    fn label(name: &str) -> Instr {
+        return Instr::Label {
+            assem: format!("{}:", name),
+            label: Label::named(name),
+        };
+    }
+
+    /// The loop of Graph 10.1 in the book, returning `c`
+    fn book_example() -> Vec<Instr> {
+        return vec![
+            Instr::oper("movq $0, `d0", vec![A], vec![]),
+            label("L1"),
+            Instr::oper("leaq 1(`s0), `d0", vec![B], vec![A]),
+            Instr::oper("addq `s0, `d0", vec![C], vec![B, C]),
+            Instr::oper("leaq (`s0,`s0), `d0", vec![A], vec![B]),
+            Instr::oper("cmpq $10, `s0", vec![], vec![A]),
+            jump("jl `j0", vec![], &["L1", "L2"]),
+            label("L2"),
+            Instr::Move {
+                assem: "movq `s0, `d0".to_string(),
+                dst: X86_64Frame::RAX,
+                src: C,
+            },
+            jump("ret", vec![X86_64Frame::RAX], &[]),
+        ];
+    }
+
+    fn names(graph: &FlowGraph, set: &BitSet) -> Vec<String> {
+        let mut temps: Vec<Temp> = set.iter().map(|n| graph.temps[n]).collect();
+        temps.sort();
+        return temps.into_iter().map(x86_64_name).collect();
+    }
+
+    #[test]
+    fn test_bitset() {
+        let mut a = BitSet::new();
+        a.insert(3);
+        a.insert(130);
+        assert!(a.contains(130) && !a.contains(4) && !a.contains(1000));
+        let mut b = BitSet::new();
+        b.insert(3);
+        assert!(!b.union_with(&BitSet::new()));
+        assert!(b.union_with(&a));
+        assert_eq!(b.iter().collect::<Vec<_>>(), [3, 130]);
+        b.difference_with(&a);
+        assert!(b.is_empty());
+        a.remove(3);
+        assert_eq!(a.len(), 1);
+    }
+
+    #[test]
+    fn test_flow_graph_edges() {
+        let graph = FlowGraph::new(&book_example());
+        assert_eq!(graph.succs[0], [1]);
+        assert_eq!(graph.succs[6], [1, 7]);
+        assert!(graph.succs[9].is_empty());
+        assert_eq!(graph.preds[1], [0, 6]);
+        assert!(graph.is_move[8] && !graph.is_move[3]);
+        assert_eq!(graph.temps[..3], [A, B, C]);
+    }
+
+    #[test]
+    fn test_backward_order_starts_at_exit() {
+        let graph = FlowGraph::new(&book_example());
+        let order = graph.backward_order();
+        assert_eq!(order.len(), graph.len());
+        assert_eq!(order[..4], [9, 8, 7, 6]);
+        // A node that cannot reach the exit is still visited
+        let spin = vec![label("L1"), jump("jmp `j0", vec![], &["L1"])];
+        assert_eq!(FlowGraph::new(&spin).backward_order().len(), 2);
+    }
+
+    #[test]
+    fn test_liveness_of_book_example() {
+        let graph = FlowGraph::new(&book_example());
+        let live = liveness(&graph);
+        assert_eq!(names(&graph, &live.live_in[0]), ["t102"]);
+        assert_eq!(names(&graph, &live.live_out[0]), ["t100", "t102"]);
+        assert_eq!(names(&graph, &live.live_out[2]), ["t101", "t102"]);
+        assert_eq!(names(&graph, &live.live_out[4]), ["t100", "t102"]);
+        assert_eq!(names(&graph, &live.live_out[6]), ["t100", "t102"]);
+        assert_eq!(names(&graph, &live.live_in[8]), ["t102"]);
+        assert_eq!(names(&graph, &live.live_out[8]), ["%rax"]);
+    }
+
+    #[test]
+    fn test_interference_of_book_example() {
+        let graph = FlowGraph::new(&book_example());
+        let interference = InterferenceGraph::new(&graph, &liveness(&graph));
+        assert!(interference.interferes(A, C));
+        assert!(interference.interferes(B, C));
+        assert!(!interference.interferes(A, B));
+        // The move from `c` to `%rax` adds no edge between them
+        assert!(!interference.interferes(C, X86_64Frame::RAX));
+        assert_eq!(
+            interference.dump(&x86_64_name),
+            "t100: t102\nt101: t102\nt102: t100 t101\n%rax: \nmove %rax <- t102\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_liveness() {
+        let instrs = book_example();
+        let graph = FlowGraph::new(&instrs);
+        let dump = dump_liveness(&instrs, &graph, &liveness(&graph), &x86_64_name);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), instrs.len());
+        assert_eq!(
+            lines[2],
+            "leaq 1(t100), t101               in: {t100 t102} out: {t101 t102}"
+        );
+    }
+
+    /// The solution satisfies the dataflow equations for the code of every test program
+    #[test]
+    fn test_liveness_equations_hold() {
+        for (name, source) in PROGRAMS {
+            for frag in translate::<X86_64Frame>(&load(source)) {
+                if let Frag::Proc { body, .. } = frag {
+                    let code = codegen(&canonicalize(body));
+                    let graph = FlowGraph::new(&code);
+                    let live = liveness(&graph);
+                    for n in 0..graph.len() {
+                        let mut out = BitSet::new();
+                        for succ in &graph.succs[n] {
+                            out.union_with(&live.live_in[*succ]);
+                        }
+                        assert_eq!(live.live_out[n], out, "program {}", name);
+                        let mut live_in = out;
+                        live_in.difference_with(&graph.defs[n]);
+                        live_in.union_with(&graph.uses[n]);
+                        assert_eq!(live.live_in[n], live_in, "program {}", name);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,669 @@
+/// Implementations described in Chapter 11: register allocation by iterated register coalescing
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::chapter_10::{liveness, FlowGraph, InterferenceGraph};
+use crate::chapter_6::{Frame, Temp};
+use crate::chapter_7::{access_exp, Exp, Stm};
+use crate::chapter_9::Instr;
+use crate::linear_scan;
+
+/// What the register allocator needs to know about a target machine
+#[derive(Clone, Copy)]
+pub struct Machine<'a> {
+    /// The registers a temporary may be given, most preferred first
+    pub registers: &'a [Temp],
+    /// Every temporary that is a machine register, allocatable or not
+    pub precolored: &'a [Temp],
+    /// Instruction selection, used to load and store spilled temporaries
+    pub codegen: fn(&[Stm]) -> Vec<Instr>,
+}
+
+/// The result of register allocation
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    /// The instructions with spill code added
+    pub instrs: Vec<Instr>,
+    /// The register given to every temporary of `instrs`
+    pub colors: HashMap<Temp, Temp>,
+}
+
+impl Allocation {
+    /// Whether an instruction is a move from a register to itself, which need not be emitted
+    pub fn is_redundant(&self, instr: &Instr) -> bool {
+        match instr {
+            Instr::Move { dst, src, .. } => return self.colors[dst] == self.colors[src],
+            _ => return false,
+        }
+    }
+}
+
+/// The register allocators a backend can be built with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Allocator {
+    /// Iterated register coalescing, which makes the best code
+    #[default]
+    GraphColoring,
+    /// Linear scan, which is much faster and suits debug builds
+    LinearScan,
+}
+
+impl Allocator {
+    pub fn allocate<F: Frame>(
+        self,
+        instrs: Vec<Instr>,
+        frame: &mut F,
+        machine: &Machine,
+    ) -> Allocation {
+        match self {
+            Allocator::GraphColoring => return allocate(instrs, frame, machine),
+            Allocator::LinearScan => return linear_scan::allocate(instrs, frame, machine),
+        }
+    }
+}
+
+/// Give every temporary of a function body a register of `machine`
+///
+/// Temporaries that cannot be coloured are spilled to new locals of `frame` and the body is
+/// rewritten to load them before each use and store them after each definition, until colouring
+/// succeeds.
+pub fn allocate<F: Frame>(instrs: Vec<Instr>, frame: &mut F, machine: &Machine) -> Allocation {
+    let mut instrs = instrs;
+    // Temporaries made by spill code, whose live ranges are too short to be worth spilling
+    let mut spill_temps: HashSet<Temp> = HashSet::new();
+    loop {
+        let graph = FlowGraph::new(&instrs);
+        let interference = InterferenceGraph::new(&graph, &liveness(&graph));
+        let mut occurrences = vec![0; graph.temps.len()];
+        for node in 0..graph.len() {
+            for t in graph.defs[node].iter().chain(graph.uses[node].iter()) {
+                occurrences[t] += 1;
+            }
+        }
+        let mut coloring = Coloring::new(&interference, machine, &occurrences, &spill_temps);
+        coloring.run();
+        if coloring.spilled.is_empty() {
+            let colors: HashMap<Temp, Temp> = (0..graph.temps.len())
+                .map(|n| (graph.temps[n], coloring.color[n].unwrap()))
+                .collect();
+            return Allocation { instrs, colors };
+        }
+        let spilled: Vec<Temp> = coloring.spilled.iter().map(|n| graph.temps[*n]).collect();
+        instrs = rewrite_program(instrs, &spilled, frame, machine, &mut spill_temps);
+    }
+}
+
+/// Put each spilled temporary in a new frame slot, loading it into a fresh temporary before
+/// every instruction that reads it and storing one after every instruction that writes it
+fn rewrite_program<F: Frame>(
+    instrs: Vec<Instr>,
+    spilled: &[Temp],
+    frame: &mut F,
+    machine: &Machine,
+    spill_temps: &mut HashSet<Temp>,
+) -> Vec<Instr> {
+    let slots: HashMap<Temp, Exp> = spilled
+        .iter()
+        .map(|t| (*t, access_exp(frame.alloc_local(true), Exp::Temp(F::fp()))))
+        .collect();
+    let mut result = Vec::new();
+    for mut instr in instrs {
+        let mut stores = Vec::new();
+        for t in spilled {
+            let used = instr.uses().contains(t);
+            let defined = instr.defs().contains(t);
+            if !used && !defined {
+                continue;
+            }
+            let fresh = Temp::new();
+            rename(&mut instr, *t, fresh);
+            let slot = Box::new(slots[t].clone());
+            if used {
+                let load = Stm::Move(Box::new(Exp::Temp(fresh)), slot.clone());
+                result.extend(spill_code(&[load], machine, spill_temps));
+            }
+            if defined {
+                let store = Stm::Move(slot, Box::new(Exp::Temp(fresh)));
+                stores.extend(spill_code(&[store], machine, spill_temps));
+            }
+            spill_temps.insert(fresh);
+        }
+        result.push(instr);
+        result.extend(stores);
+    }
+    return result;
+}
+
+/// Select instructions for a load or store, marking the temporaries they make as spill temporaries
+fn spill_code(stms: &[Stm], machine: &Machine, spill_temps: &mut HashSet<Temp>) -> Vec<Instr> {
+    let code = (machine.codegen)(stms);
+    for instr in &code {
+        spill_temps.extend(instr.defs());
+    }
+    return code;
+}
+
+/// Replace a temporary wherever an instruction reads or writes it
+pub fn rename(instr: &mut Instr, from: Temp, to: Temp) {
+    let swap = |t: &mut Temp| {
+        if *t == from {
+            *t = to;
+        }
+    };
+    match instr {
+        Instr::Oper { dst, src, .. } => dst.iter_mut().chain(src.iter_mut()).for_each(swap),
+        Instr::Label { .. } => {}
+        Instr::Move { dst, src, .. } => {
+            swap(dst);
+            swap(src);
+        }
+    }
+}
+
+/// The worklist or set each node of the interference graph is in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NodeState {
+    Precolored,
+    Simplify,
+    Freeze,
+    Spill,
+    Spilled,
+    Coalesced,
+    Colored,
+    Selected,
+}
+
+/// The set each move is in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MoveState {
+    Worklist,
+    Active,
+    Coalesced,
+    Constrained,
+    Frozen,
+}
+
+/// The state of colouring one interference graph, named after the sets of Appel and George
+struct Coloring<'a> {
+    machine: &'a Machine<'a>,
+    k: usize,
+    state: Vec<NodeState>,
+    adj_set: HashSet<(usize, usize)>,
+    /// Neighbours of each node that is not precoloured
+    adj_list: Vec<Vec<usize>>,
+    degree: Vec<usize>,
+    moves: Vec<(usize, usize)>,
+    move_state: Vec<MoveState>,
+    move_list: Vec<Vec<usize>>,
+    alias: Vec<usize>,
+    color: Vec<Option<Temp>>,
+    /// Estimated cost of spilling each node, infinite for spill temporaries
+    spill_cost: Vec<f64>,
+    simplify_worklist: BTreeSet<usize>,
+    freeze_worklist: BTreeSet<usize>,
+    spill_worklist: BTreeSet<usize>,
+    worklist_moves: BTreeSet<usize>,
+    select_stack: Vec<usize>,
+    spilled: Vec<usize>,
+}
+
+impl<'a> Coloring<'a> {
+    fn new(
+        interference: &InterferenceGraph,
+        machine: &'a Machine<'a>,
+        occurrences: &[usize],
+        spill_temps: &HashSet<Temp>,
+    ) -> Coloring<'a> {
+        let n = interference.temps.len();
+        let mut coloring = Coloring {
+            machine,
+            k: machine.registers.len(),
+            state: vec![NodeState::Simplify; n],
+            adj_set: HashSet::new(),
+            adj_list: vec![Vec::new(); n],
+            degree: vec![0; n],
+            moves: interference.moves.clone(),
+            move_state: vec![MoveState::Worklist; interference.moves.len()],
+            move_list: vec![Vec::new(); n],
+            alias: (0..n).collect(),
+            color: vec![None; n],
+            spill_cost: vec![0.0; n],
+            simplify_worklist: BTreeSet::new(),
+            freeze_worklist: BTreeSet::new(),
+            spill_worklist: BTreeSet::new(),
+            worklist_moves: (0..interference.moves.len()).collect(),
+            select_stack: Vec::new(),
+            spilled: Vec::new(),
+        };
+        for (node, t) in interference.temps.iter().enumerate() {
+            if machine.precolored.contains(t) {
+                coloring.state[node] = NodeState::Precolored;
+                coloring.color[node] = Some(*t);
+            }
+            coloring.spill_cost[node] = match spill_temps.contains(t) {
+                true => f64::INFINITY,
+                false => occurrences[node] as f64,
+            };
+        }
+        for (u, neighbours) in interference.adj_list.iter().enumerate() {
+            for v in neighbours {
+                coloring.add_edge(u, *v);
+            }
+        }
+        for (i, (dst, src)) in interference.moves.iter().enumerate() {
+            coloring.move_list[*dst].push(i);
+            if src != dst {
+                coloring.move_list[*src].push(i);
+            }
+        }
+        for node in 0..n {
+            if coloring.is_precolored(node) {
+                continue;
+            }
+            if coloring.degree[node] >= coloring.k {
+                coloring.spill_worklist.insert(node);
+                coloring.state[node] = NodeState::Spill;
+            } else if coloring.move_related(node) {
+                coloring.freeze_worklist.insert(node);
+                coloring.state[node] = NodeState::Freeze;
+            } else {
+                coloring.simplify_worklist.insert(node);
+            }
+        }
+        return coloring;
+    }
+
+    fn run(&mut self) {
+        loop {
+            if let Some(node) = self.simplify_worklist.pop_first() {
+                self.simplify(node);
+            } else if let Some(m) = self.worklist_moves.pop_first() {
+                self.coalesce(m);
+            } else if let Some(node) = self.freeze_worklist.pop_first() {
+                self.freeze(node);
+            } else if !self.spill_worklist.is_empty() {
+                self.select_spill();
+            } else {
+                break;
+            }
+        }
+        self.assign_colors();
+    }
+
+    fn is_precolored(&self, node: usize) -> bool {
+        return self.state[node] == NodeState::Precolored;
+    }
+
+    /// Whether a precoloured node is a register temporaries may be given
+    fn is_allocatable(&self, node: usize) -> bool {
+        return self
+            .color
+            .get(node)
+            .and_then(|c| *c)
+            .is_some_and(|c| self.machine.registers.contains(&c));
+    }
+
+    fn add_edge(&mut self, u: usize, v: usize) {
+        if u == v || self.adj_set.contains(&(u, v)) {
+            return;
+        }
+        self.adj_set.insert((u, v));
+        self.adj_set.insert((v, u));
+        for (a, b) in [(u, v), (v, u)] {
+            if !self.is_precolored(a) {
+                self.adj_list[a].push(b);
+                self.degree[a] += 1;
+            }
+        }
+    }
+
+    /// The neighbours of a node still in the graph
+    fn adjacent(&self, node: usize) -> Vec<usize> {
+        return self.adj_list[node]
+            .iter()
+            .copied()
+            .filter(|n| !matches!(self.state[*n], NodeState::Selected | NodeState::Coalesced))
+            .collect();
+    }
+
+    /// The moves of a node that may still be coalesced
+    fn node_moves(&self, node: usize) -> Vec<usize> {
+        return self.move_list[node]
+            .iter()
+            .copied()
+            .filter(|m| matches!(self.move_state[*m], MoveState::Worklist | MoveState::Active))
+            .collect();
+    }
+
+    fn move_related(&self, node: usize) -> bool {
+        return !self.node_moves(node).is_empty();
+    }
+
+    fn simplify(&mut self, node: usize) {
+        self.state[node] = NodeState::Selected;
+        self.select_stack.push(node);
+        for m in self.adjacent(node) {
+            self.decrement_degree(m);
+        }
+    }
+
+    fn decrement_degree(&mut self, node: usize) {
+        if self.is_precolored(node) {
+            return;
+        }
+        self.degree[node] -= 1;
+        if self.degree[node] + 1 != self.k {
+            return;
+        }
+        let mut nodes = self.adjacent(node);
+        nodes.push(node);
+        self.enable_moves(&nodes);
+        self.spill_worklist.remove(&node);
+        if self.move_related(node) {
+            self.freeze_worklist.insert(node);
+            self.state[node] = NodeState::Freeze;
+        } else {
+            self.simplify_worklist.insert(node);
+            self.state[node] = NodeState::Simplify;
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[usize]) {
+        for node in nodes {
+            for m in self.node_moves(*node) {
+                if self.move_state[m] == MoveState::Active {
+                    self.move_state[m] = MoveState::Worklist;
+                    self.worklist_moves.insert(m);
+                }
+            }
+        }
+    }
+
+    fn get_alias(&self, node: usize) -> usize {
+        let mut node = node;
+        while self.state[node] == NodeState::Coalesced {
+            node = self.alias[node];
+        }
+        return node;
+    }
+
+    fn coalesce(&mut self, m: usize) {
+        let (x, y) = (
+            self.get_alias(self.moves[m].0),
+            self.get_alias(self.moves[m].1),
+        );
+        let (u, v) = match self.is_precolored(y) {
+            true => (y, x),
+            false => (x, y),
+        };
+        if u == v {
+            self.move_state[m] = MoveState::Coalesced;
+            self.add_work_list(u);
+        } else if self.is_precolored(v)
+            || self.adj_set.contains(&(u, v))
+            || (self.is_precolored(u) && !self.is_allocatable(u))
+        {
+            self.move_state[m] = MoveState::Constrained;
+            self.add_work_list(u);
+            self.add_work_list(v);
+        } else if (self.is_precolored(u) && self.adjacent(v).iter().all(|t| self.ok(*t, u)))
+            || (!self.is_precolored(u) && self.conservative(u, v))
+        {
+            self.move_state[m] = MoveState::Coalesced;
+            self.combine(u, v);
+            self.add_work_list(u);
+        } else {
+            self.move_state[m] = MoveState::Active;
+        }
+    }
+
+    fn add_work_list(&mut self, node: usize) {
+        if !self.is_precolored(node) && !self.move_related(node) && self.degree[node] < self.k {
+            self.freeze_worklist.remove(&node);
+            self.simplify_worklist.insert(node);
+            self.state[node] = NodeState::Simplify;
+        }
+    }
+
+    /// George's test: a neighbour of a node joining precoloured `r` is harmless if it has few
+    /// neighbours or already interferes with `r`
+    fn ok(&self, t: usize, r: usize) -> bool {
+        return self.degree[t] < self.k || self.is_precolored(t) || self.adj_set.contains(&(t, r));
+    }
+
+    /// Briggs's test: joining `u` and `v` is safe if the result has fewer than `k` neighbours of
+    /// significant degree
+    fn conservative(&self, u: usize, v: usize) -> bool {
+        let nodes: BTreeSet<usize> = self
+            .adjacent(u)
+            .into_iter()
+            .chain(self.adjacent(v))
+            .collect();
+        let significant = nodes
+            .iter()
+            .filter(|n| self.is_precolored(**n) || self.degree[**n] >= self.k)
+            .count();
+        return significant < self.k;
+    }
+
+    fn combine(&mut self, u: usize, v: usize) {
+        self.freeze_worklist.remove(&v);
+        self.spill_worklist.remove(&v);
+        self.state[v] = NodeState::Coalesced;
+        self.alias[v] = u;
+        let moves = self.move_list[v].clone();
+        self.move_list[u].extend(moves);
+        self.enable_moves(&[v]);
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+        if self.degree[u] >= self.k && self.state[u] == NodeState::Freeze {
+            self.freeze_worklist.remove(&u);
+            self.spill_worklist.insert(u);
+            self.state[u] = NodeState::Spill;
+        }
+    }
+
+    fn freeze(&mut self, node: usize) {
+        self.simplify_worklist.insert(node);
+        self.state[node] = NodeState::Simplify;
+        self.freeze_moves(node);
+    }
+
+    /// Give up coalescing the moves of a node
+    fn freeze_moves(&mut self, u: usize) {
+        for m in self.node_moves(u) {
+            let (x, y) = self.moves[m];
+            let v = match self.get_alias(y) == self.get_alias(u) {
+                true => self.get_alias(x),
+                false => self.get_alias(y),
+            };
+            self.move_state[m] = MoveState::Frozen;
+            if self.state[v] == NodeState::Freeze
+                && !self.move_related(v)
+                && self.degree[v] < self.k
+            {
+                self.freeze_worklist.remove(&v);
+                self.simplify_worklist.insert(v);
+                self.state[v] = NodeState::Simplify;
+            }
+        }
+    }
+
+    /// Choose the node with the lowest cost of spilling per neighbour as a potential spill
+    fn select_spill(&mut self) {
+        let priority = |n: usize| self.spill_cost[n] / self.degree[n] as f64;
+        let mut best = *self.spill_worklist.first().unwrap();
+        for node in &self.spill_worklist {
+            if priority(*node) < priority(best) {
+                best = *node;
+            }
+        }
+        self.spill_worklist.remove(&best);
+        self.simplify_worklist.insert(best);
+        self.state[best] = NodeState::Simplify;
+        self.freeze_moves(best);
+    }
+
+    fn assign_colors(&mut self) {
+        while let Some(node) = self.select_stack.pop() {
+            let mut ok_colors: Vec<Temp> = self.machine.registers.to_vec();
+            for w in &self.adj_list[node] {
+                let a = self.get_alias(*w);
+                if matches!(self.state[a], NodeState::Colored | NodeState::Precolored) {
+                    ok_colors.retain(|c| Some(*c) != self.color[a]);
+                }
+            }
+            match ok_colors.first() {
+                Some(c) => {
+                    self.state[node] = NodeState::Colored;
+                    self.color[node] = Some(*c);
+                }
+                None => {
+                    self.state[node] = NodeState::Spilled;
+                    self.spilled.push(node);
+                }
+            }
+        }
+        for node in 0..self.state.len() {
+            if self.state[node] == NodeState::Coalesced {
+                self.color[node] = self.color[self.get_alias(node)];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_6::{Access, Label, X86_64Frame};
+    use crate::chapter_7::{translate, Frag};
+    use crate::chapter_8::canonicalize;
+    use crate::chapter_9::codegen;
+    use crate::riscv::{self, RiscVFrame};
+    use crate::test_programs::*;
+
+    fn x86_64_machine(registers: &[Temp]) -> Machine<'_> {
+        return Machine {
+            registers,
+            precolored: &X86_64Frame::PRECOLORED,
+            codegen,
+        };
+    }
+
+    fn add(dst: Temp, src: Temp) -> Instr {
+        return Instr::oper("addq `s0, `d0", vec![dst], vec![src, dst]);
+    }
+
+    fn move_instr(dst: Temp, src: Temp) -> Instr {
+        return Instr::Move {
+            assem: "movq `s0, `d0".to_string(),
+            dst,
+            src,
+        };
+    }
+
+    /// No two temporaries that interfere share a register, and every register is a real one
+    fn assert_valid(allocation: &Allocation, machine: &Machine) {
+        let graph = FlowGraph::new(&allocation.instrs);
+        let interference = InterferenceGraph::new(&graph, &liveness(&graph));
+        for t in &graph.temps {
+            let color = allocation.colors[t];
+            assert!(machine.registers.contains(&color) || machine.precolored.contains(t));
+        }
+        for (a, b) in &interference.adj_set {
+            let (a, b) = (graph.temps[*a], graph.temps[*b]);
+            assert_ne!(
+                allocation.colors[&a], allocation.colors[&b],
+                "{} and {}",
+                a, b
+            );
+        }
+    }
+
+    #[test]
+    fn test_allocate_coalesces_moves() {
+        let (a, b) = (Temp::new(), Temp::new());
+        let instrs = vec![
+            move_instr(a, X86_64Frame::RDI),
+            move_instr(b, a),
+            add(b, b),
+            move_instr(X86_64Frame::RAX, b),
+            Instr::oper("ret", vec![], vec![X86_64Frame::RAX]),
+        ];
+        let machine = x86_64_machine(&X86_64Frame::REGISTERS);
+        let mut frame = X86_64Frame::new(Label::named("f"), &[]);
+        let allocation = allocate(instrs, &mut frame, &machine);
+        assert_valid(&allocation, &machine);
+        // `a` joins `%rdi` and `b` joins it too, or `%rax`
+        assert_eq!(allocation.colors[&a], X86_64Frame::RDI);
+        let emitted = allocation
+            .instrs
+            .iter()
+            .filter(|i| !allocation.is_redundant(i));
+        assert_eq!(emitted.count(), 3);
+        assert_eq!(frame.locals_size(), 0);
+    }
+
+    #[test]
+    fn test_allocate_spills_to_frame() {
+        let temps: Vec<Temp> = (0..4).map(|_| Temp::new()).collect();
+        let mut instrs: Vec<Instr> = temps
+            .iter()
+            .enumerate()
+            .map(|(i, t)| Instr::oper(&format!("movq ${}, `d0", i), vec![*t], vec![]))
+            .collect();
+        for t in &temps[1..] {
+            instrs.push(add(temps[0], *t));
+        }
+        instrs.push(move_instr(X86_64Frame::RAX, temps[0]));
+        instrs.push(Instr::oper("ret", vec![], vec![X86_64Frame::RAX]));
+
+        let registers = [X86_64Frame::RAX, X86_64Frame::RBX];
+        let machine = x86_64_machine(&registers);
+        let mut frame = X86_64Frame::new(Label::named("f"), &[]);
+        let allocation = allocate(instrs, &mut frame, &machine);
+        assert_valid(&allocation, &machine);
+        assert!(frame.locals_size() > 0);
+        let text: Vec<String> = allocation.instrs.iter().map(|i| i.to_string()).collect();
+        assert!(text.iter().any(|i| i.contains("(%rbp)")), "{:?}", text);
+    }
+
+    #[test]
+    fn test_allocate_programs() {
+        for (_, source) in PROGRAMS {
+            for frag in translate::<X86_64Frame>(&load(source)) {
+                if let Frag::Proc { body, mut frame } = frag {
+                    let machine = x86_64_machine(&X86_64Frame::REGISTERS);
+                    let instrs = codegen(&canonicalize(body));
+                    let allocation = allocate(instrs, &mut frame, &machine);
+                    assert_valid(&allocation, &machine);
+                }
+            }
+            for frag in translate::<RiscVFrame>(&load(source)) {
+                if let Frag::Proc { body, mut frame } = frag {
+                    let instrs = riscv::codegen(&canonicalize(body));
+                    let allocation = allocate(instrs, &mut frame, &RiscVFrame::MACHINE);
+                    assert_valid(&allocation, &RiscVFrame::MACHINE);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rewrite_program_loads_and_stores() {
+        let t = Temp::new();
+        let instrs = vec![add(t, X86_64Frame::RBX)];
+        let mut frame = X86_64Frame::new(Label::named("f"), &[]);
+        let machine = x86_64_machine(&X86_64Frame::REGISTERS);
+        let mut spill_temps = HashSet::new();
+        let code = rewrite_program(instrs, &[t], &mut frame, &machine, &mut spill_temps);
+        assert_eq!(frame.alloc_local(true), Access::InFrame(-16));
+        let text: Vec<String> = code.iter().map(|i| i.to_string()).collect();
+        assert_eq!(text.len(), 3);
+        assert!(text[0].starts_with("movq -8(%rbp), t"));
+        assert!(text[1].starts_with("addq %rbx, t"));
+        assert!(text[2].starts_with("movq t") && text[2].ends_with(", -8(%rbp)"));
+        assert!(!code[1].uses().contains(&t) && spill_temps.len() == 1);
+    }
+}
@@ -0,0 +1,280 @@
+/// Implementations described in Chapter 12: putting it all together into x86-64 executables
+use std::path::Path;
+use std::process::Command;
+
+use crate::chapter_11::{Allocation, Allocator, Machine};
+use crate::chapter_6::{Access, Frame, Temp, X86_64Frame};
+use crate::chapter_7::{Frag, PROGRAM_MAIN};
+use crate::chapter_8::canonicalize;
+use crate::chapter_9::{codegen, x86_64_name, Instr};
+use crate::interpreter::MAX_CALL_DEPTH;
+use crate::riscv::escape_string;
+
+/// The runtime library every executable is linked with
+pub const RUNTIME: &str = include_str!("../runtime/runtime.c");
+
+/// The target description the register allocator works from
+pub const X86_64_MACHINE: Machine<'static> = Machine {
+    registers: &X86_64Frame::REGISTERS,
+    precolored: &X86_64Frame::PRECOLORED,
+    codegen,
+};
+
+fn move_instr(dst: Temp, src: Temp) -> Instr {
+    return Instr::Move {
+        assem: "movq `s0, `d0".to_string(),
+        dst,
+        src,
+    };
+}
+
+/// Move each formal from the register it arrives in to where the body expects it, and keep the
+/// callee-save registers in temporaries for the whole body, restoring them at the end. The
+/// allocator then uses those registers freely, spilling the temporaries when it needs them
+pub fn proc_entry_exit1(frame: &X86_64Frame, body: Vec<Instr>) -> Vec<Instr> {
+    let saved: Vec<Temp> = X86_64Frame::CALLEE_SAVES
+        .iter()
+        .map(|_| Temp::new())
+        .collect();
+    let mut instrs: Vec<Instr> = saved
+        .iter()
+        .zip(X86_64Frame::CALLEE_SAVES)
+        .map(|(t, reg)| move_instr(*t, reg))
+        .collect();
+    for (access, reg) in frame.formals().iter().zip(X86_64Frame::ARG_REGS) {
+        match access {
+            Access::InFrame(offset) => instrs.push(Instr::oper(
+                &format!("movq `s0, {}(`s1)", offset),
+                vec![],
+                vec![reg, X86_64Frame::RBP],
+            )),
+            Access::InReg(t) => instrs.push(move_instr(*t, reg)),
+        }
+    }
+    instrs.extend(body);
+    for (t, reg) in saved.iter().zip(X86_64Frame::CALLEE_SAVES) {
+        instrs.push(move_instr(reg, *t));
+    }
+    return instrs;
+}
+
+/// Add an instruction that emits nothing but keeps the result, the stack and frame pointers and
+/// the callee-save registers live to the end of the body
+pub fn proc_entry_exit2(mut body: Vec<Instr>) -> Vec<Instr> {
+    let mut live = vec![X86_64Frame::RAX, X86_64Frame::RSP, X86_64Frame::RBP];
+    live.extend(X86_64Frame::CALLEE_SAVES);
+    body.push(Instr::oper("", vec![], live));
+    return body;
+}
+
+/// The assembly of a whole function: the allocated body between a prologue making the frame,
+/// which keeps the stack 16-byte aligned, and an epilogue removing it
+///
+/// Every function but the main one counts itself in `call_depth`, stopping the program with a
+/// stack overflow when too many calls are active, as the interpreter does.
+pub fn proc_entry_exit3(frame: &X86_64Frame, allocation: &Allocation) -> String {
+    let size = (frame.locals_size() + 15) / 16 * 16;
+    let name = frame.name();
+    let counted = name.0 != PROGRAM_MAIN;
+    let mut out = String::new();
+    if !counted {
+        out.push_str(&format!("\t.globl {}\n", name));
+    }
+    out.push_str(&format!("\t.p2align 4\n{}:\n", name));
+    out.push_str("\tpushq %rbp\n\tmovq %rsp, %rbp\n");
+    if size > 0 {
+        out.push_str(&format!("\tsubq ${}, %rsp\n", size));
+    }
+    if counted {
+        out.push_str(&format!(
+            "\tcmpq ${}, call_depth(%rip)\n\tjl 1f\n\tcall stack_overflow\n1:\n\tincq call_depth(%rip)\n",
+            MAX_CALL_DEPTH
+        ));
+    }
+    let register = |t: Temp| x86_64_name(allocation.colors[&t]);
+    for instr in &allocation.instrs {
+        match instr {
+            Instr::Label { assem, .. } => out.push_str(&format!("{}\n", assem)),
+            Instr::Oper { assem, .. } if assem.is_empty() => {}
+            _ if allocation.is_redundant(instr) => {}
+            _ => out.push_str(&format!("\t{}\n", instr.format(&register))),
+        }
+    }
+    if counted {
+        out.push_str("\tdecq call_depth(%rip)\n");
+    }
+    out.push_str("\tmovq %rbp, %rsp\n\tpopq %rbp\n\tret\n");
+    return out;
+}
+
+/// Assembly for a whole program in GNU assembler syntax
+///
+/// String literals are a word holding the length followed by the bytes, as the runtime library
+/// expects. The result is linked with the runtime, whose `main` calls `PROGRAM_MAIN`.
+pub fn emit_program(frags: Vec<Frag<X86_64Frame>>, allocator: Allocator) -> String {
+    let mut text = String::from("\t.text\n");
+    let mut data = String::new();
+    for frag in frags {
+        match frag {
+            Frag::Proc { body, mut frame } => {
+                let instrs = proc_entry_exit1(&frame, codegen(&canonicalize(body)));
+                let instrs = proc_entry_exit2(instrs);
+                let allocation = allocator.allocate(instrs, &mut frame, &X86_64_MACHINE);
+                text.push_str(&proc_entry_exit3(&frame, &allocation));
+            }
+            Frag::Str(label, s) => {
+                data.push_str(&format!(
+                    "\t.p2align 3\n{}:\n\t.quad {}\n\t.ascii \"{}\"\n",
+                    label,
+                    s.len(),
+                    escape_string(&s)
+                ));
+            }
+        }
+    }
+    text.push_str("\t.data\n\t.p2align 3\ncall_depth:\n\t.quad 0\n");
+    if !data.is_empty() {
+        text.push_str("\t.section .rodata\n");
+        text.push_str(&data);
+    }
+    text.push_str("\t.section .note.GNU-stack,\"\",@progbits\n");
+    return text;
+}
+
+/// Assemble a program and link it with the runtime library into an executable at `output`, using
+/// the system C compiler driver
+pub fn link(assembly: &str, output: &Path) -> Result<(), String> {
+    let dir = std::env::temp_dir().join(format!(
+        "link_{}_{}",
+        std::process::id(),
+        output
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("a.out")
+    ));
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let source = dir.join("program.s");
+    let runtime = dir.join("runtime.c");
+    std::fs::write(&source, assembly).map_err(|e| e.to_string())?;
+    std::fs::write(&runtime, RUNTIME).map_err(|e| e.to_string())?;
+    let result = Command::new("cc")
+        .arg("-o")
+        .arg(output)
+        .arg(&source)
+        .arg(&runtime)
+        .arg("-lm")
+        .output();
+    std::fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+    match result {
+        Ok(out) if out.status.success() => return Ok(()),
+        Ok(out) => return Err(String::from_utf8_lossy(&out.stderr).into_owned()),
+        Err(error) => return Err(format!("cannot run cc: {}", error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_6::Label;
+    use crate::chapter_7::translate;
+    use crate::test_programs::*;
+    use std::process::Output;
+
+    /// Compile a program into an executable in a fresh directory and run it
+    fn build_and_run(name: &str, source: &str, allocator: Allocator) -> Output {
+        let dir = std::env::temp_dir().join(format!("x86_64_run_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join(format!("{}_{:?}", name, allocator));
+        let assembly = emit_program(translate::<X86_64Frame>(&load(source)), allocator);
+        if let Err(message) = link(&assembly, &exe) {
+            panic!("program {} did not link: {}", name, message);
+        }
+        let output = Command::new(&exe).output().unwrap();
+        std::fs::remove_file(&exe).unwrap();
+        return output;
+    }
+
+    #[test]
+    fn test_proc_entry_exit1_moves_formals() {
+        let frame = X86_64Frame::new(Label::named("f"), &[false, true]);
+        let instrs = proc_entry_exit1(&frame, vec![]);
+        let text: Vec<String> = instrs.iter().map(|i| i.to_string()).collect();
+        let saves = X86_64Frame::CALLEE_SAVES.len();
+        assert!(text[0].starts_with("movq %rbx, t"));
+        assert!(text[saves].starts_with("movq %rdi, t"));
+        assert_eq!(text[saves + 1], "movq %rsi, -8(%rbp)");
+        assert!(text[saves + 2].starts_with("movq t") && text[saves + 2].ends_with(", %rbx"));
+        assert_eq!(text.len(), 2 * saves + 2);
+    }
+
+    #[test]
+    fn test_emit_program_sections() {
+        let source = r#"fn f(x: int): int { x + 1 } print(f(1), "a\b");"#;
+        let assembly = emit_program(
+            translate::<X86_64Frame>(&load(source)),
+            Allocator::default(),
+        );
+        assert!(assembly.contains("\t.globl program_main\n\t.p2align 4\nprogram_main:\n"));
+        assert!(assembly.contains("\tpushq %rbp\n\tmovq %rsp, %rbp\n"));
+        assert!(assembly.contains("\tincq call_depth(%rip)\n"));
+        assert!(assembly.contains("\t.section .rodata\n\t.p2align 3\n"));
+        assert!(assembly.contains("\t.quad 3\n\t.ascii \"a\\\\b\"\n"));
+        // Every temporary has been given a register
+        assert!(!assembly.contains(" t1"), "{}", assembly);
+    }
+
+    #[test]
+    #[ignore = "needs an x86-64 host with `cc`, run with `cargo test -- --ignored`"]
+    fn test_executables_match_interpreter() {
+        require_tool("cc");
+        for allocator in [Allocator::GraphColoring, Allocator::LinearScan] {
+            for (name, source) in PROGRAMS {
+                let output = build_and_run(name, source, allocator);
+                assert!(output.status.success(), "program {}", name);
+                assert_eq!(
+                    String::from_utf8(output.stdout).unwrap(),
+                    expected_output(source),
+                    "program {} with {:?}",
+                    name,
+                    allocator
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs an x86-64 host with `cc`, run with `cargo test -- --ignored`"]
+    fn test_executables_report_runtime_errors() {
+        require_tool("cc");
+        for (name, source) in FAILING_PROGRAMS {
+            let output = build_and_run(name, source, Allocator::default());
+            assert_eq!(output.status.code(), Some(1), "program {}", name);
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap(),
+                format!("{}\n", expected_error(source)),
+                "program {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs an x86-64 host with `cc`, run with `cargo test -- --ignored`"]
+    fn test_executables_spill_and_wrap() {
+        require_tool("cc");
+        let overflow =
+            "let mut min = 1; for i = 0 : 63 { min = min * 2; } print(min / -1, min / 1);";
+        for (name, source) in [
+            ("overflow", overflow.to_string()),
+            ("pressure", pressure_program(40)),
+        ] {
+            let output = build_and_run(name, &source, Allocator::default());
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                expected_output(&source),
+                "program {}",
+                name
+            );
+        }
+    }
+}
@@ -1,14 +1,14 @@
 /// A custom lexer written for learning purposes
-use std::str::FromStr;
+use std::sync::LazyLock;
 
 use regex::Regex;
 
 /// A Token created by the lexer
-#[derive(Debug, PartialEq)]
-enum Token {
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
     Id(String),
-    Num(i32),
-    Real(f32),
+    Num(i64),
+    Real(f64),
     Boolean(bool),
 
     // Special Characters
@@ -48,80 +48,184 @@ enum Token {
     Char,
     Mut,
 
+    /// A `//` comment running to the end of its line, holding the text after the `//`. Only
+    /// `tokenize_with_comments` keeps comments, the other functions skip them like whitespace
+    Comment(String),
     Passthrough,
 }
 
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let text = match self {
+            Token::Id(s) => s.clone(),
+            Token::Num(n) => n.to_string(),
+            Token::Real(r) => format!("{:?}", r),
+            Token::Boolean(true) => "True".to_string(),
+            Token::Boolean(false) => "False".to_string(),
+            Token::StaticString(s) => format!("\"{}\"", s),
+            Token::Comma => ",".to_string(),
+            Token::Colon => ":".to_string(),
+            Token::Semicolon => ";".to_string(),
+            Token::Lparen => "(".to_string(),
+            Token::Rparen => ")".to_string(),
+            Token::Lbracket => "[".to_string(),
+            Token::Rbracket => "]".to_string(),
+            Token::Lbrace => "{".to_string(),
+            Token::Rbrace => "}".to_string(),
+            Token::Period => ".".to_string(),
+            Token::Plus => "+".to_string(),
+            Token::Minus => "-".to_string(),
+            Token::Star => "*".to_string(),
+            Token::ForwardSlash => "/".to_string(),
+            Token::Equal => "=".to_string(),
+            Token::LessThan => "<".to_string(),
+            Token::GreaterThan => ">".to_string(),
+            Token::Ampersand => "&".to_string(),
+            Token::Bar => "|".to_string(),
+            Token::If => "if".to_string(),
+            Token::Else => "else".to_string(),
+            Token::ElseIf => "elseif".to_string(),
+            Token::For => "for".to_string(),
+            Token::While => "while".to_string(),
+            Token::Function => "fn".to_string(),
+            Token::Let => "let".to_string(),
+            Token::Int => "int".to_string(),
+            Token::Bool => "bool".to_string(),
+            Token::Float => "float".to_string(),
+            Token::String => "string".to_string(),
+            Token::Char => "char".to_string(),
+            Token::Mut => "mut".to_string(),
+            Token::Comment(text) => format!("//{}", text),
+            Token::Passthrough => "".to_string(),
+        };
+        return write!(f, "{}", text);
+    }
+}
+
+/// An error found while lexing, located at a byte offset in the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub pos: usize,
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
 /// Reserved words not allowable to be used as identifiers
-static RESERVED_WORDS: &'static [&str] = &[
+pub static RESERVED_WORDS: &[&str] = &[
     "if", "else", "elseif", "for", "while", "fn", "let", "int", "bool", "float", "string", "char",
     "mut", "True", "False",
 ];
 
+/// Signature shared by all of the token matching functions
+type MatchFunction = fn(&str) -> (&str, Option<Token>);
+
+/// The token matching functions, tried in order on the rest of the input
+const MATCH_FUNCTIONS: &[MatchFunction] = &[
+    match_id,
+    match_real,
+    match_num,
+    match_if,
+    match_boolean,
+    match_static_string,
+    match_comma,
+    match_colon,
+    match_semicolon,
+    match_lparen,
+    match_rparen,
+    match_lbracket,
+    match_rbracket,
+    match_lbrace,
+    match_rbrace,
+    match_period,
+    match_plus,
+    match_minus,
+    match_star,
+    match_comment,
+    match_forwardslash,
+    match_equal,
+    match_lessthan,
+    match_greaterthan,
+    match_ampersand,
+    match_bar,
+    match_else,
+    match_elseif,
+    match_for,
+    match_while,
+    match_function,
+    match_let,
+    match_int,
+    match_bool,
+    match_float,
+    match_string,
+    match_char,
+    match_mut,
+    match_whitespace,
+];
+
 /// Tokenize a string which is of the language of this project
-fn tokenize(input: &str) -> Vec<Token> {
+pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    return Ok(tokenize_with_positions(input)?
+        .into_iter()
+        .map(|(tok, _)| tok)
+        .collect());
+}
+
+/// Tokenize a string, pairing every token with the byte offset it starts at
+pub fn tokenize_with_positions(input: &str) -> Result<Vec<(Token, usize)>, LexError> {
+    let mut tokens = tokenize_with_comments(input)?;
+    tokens.retain(|(tok, _)| !matches!(tok, Token::Comment(_)));
+    return Ok(tokens);
+}
+
+/// Tokenize a string like `tokenize_with_positions`, keeping the comments as tokens
+pub fn tokenize_with_comments(input: &str) -> Result<Vec<(Token, usize)>, LexError> {
     let mut tokens = Vec::new();
-    let mut input_string = input.to_owned();
-    let match_functions: Vec<fn(&str) -> (&str, Option<Token>)> = vec![
-        match_id,
-        match_num,
-        match_if,
-        match_real,
-        match_boolean,
-        match_static_string,
-        match_comma,
-        match_colon,
-        match_semicolon,
-        match_lparen,
-        match_rparen,
-        match_lbracket,
-        match_rbracket,
-        match_lbrace,
-        match_rbrace,
-        match_period,
-        match_plus,
-        match_minus,
-        match_star,
-        match_forwardslash,
-        match_equal,
-        match_lessthan,
-        match_greaterthan,
-        match_ampersand,
-        match_bar,
-        match_else,
-        match_elseif,
-        match_for,
-        match_while,
-        match_function,
-        match_let,
-        match_int,
-        match_bool,
-        match_float,
-        match_string,
-        match_char,
-        match_mut,
-        match_whitespace,
-        match_error,
-    ];
-
-    while input_string != "" {
-        for func in &match_functions {
-            if let (output, Some(tok)) = func(&input_string) {
-                input_string = output.clone().to_string();
+    let mut rest = input;
+
+    'input: while !rest.is_empty() {
+        let offset = input.len() - rest.len();
+        for func in MATCH_FUNCTIONS {
+            if let (output, Some(tok)) = func(rest) {
+                rest = output;
 
                 if tok != Token::Passthrough {
-                    tokens.push(tok);
+                    tokens.push((tok, offset));
                 }
 
-                break;
+                continue 'input;
             }
         }
+        return Err(lex_error(rest, offset));
     }
 
-    return tokens;
+    return Ok(tokens);
+}
+
+/// Convert a byte offset into a 1-based (line, column) pair
+pub fn line_col(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset.min(input.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    return (line, col);
 }
 
+// The patterns of the tokens that need one, compiled once
+static ID_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[_a-zA-Z][_a-zA-Z0-9]*\b\s?").unwrap());
+static NUM_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[0-9]+\b\s?").unwrap());
+static REAL_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[0-9]+\.[0-9]+\b\s?").unwrap());
+static BOOLEAN_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^((True)|(False))\b\s?").unwrap());
+static STATIC_STRING_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^"[^"]*"\s?"#).unwrap());
+static WHITESPACE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s+").unwrap());
+
 // Match a general regex. Return the rest of the input after matching and the matched string
-fn match_re(re: Regex, input: &str) -> (&str, &str) {
+fn match_re<'a>(re: &Regex, input: &'a str) -> (&'a str, &'a str) {
     if let Some(mat) = re.find(input) {
         return (&input[mat.end()..], mat.as_str().trim());
     } else {
@@ -141,11 +245,10 @@ fn match_re(re: Regex, input: &str) -> (&str, &str) {
 /// assert_eq!(Some(Token::Id("example_id")), id);
 /// ```
 fn match_id(input: &str) -> (&str, Option<Token>) {
-    let re = Regex::new(r"^[_a-zA-Z][_a-zA-Z0-9]*(\s|$)").unwrap();
-    let (rest, mat) = match_re(re, input);
+    let (rest, mat) = match_re(&ID_RE, input);
     if RESERVED_WORDS.contains(&mat) {
         return (input, None);
-    } else if mat != "" {
+    } else if !mat.is_empty() {
         return (rest, Some(Token::Id(mat.to_string())));
     } else {
         return (rest, None);
@@ -163,10 +266,12 @@ fn match_id(input: &str) -> (&str, Option<Token>) {
 /// assert_eq!(Some(Token::Num(1234)), id);
 /// ```
 fn match_num(input: &str) -> (&str, Option<Token>) {
-    let re = Regex::new(r"^[0-9]+(\s|$)").unwrap();
-    let (rest, mat) = match_re(re, input);
-    if mat != "" {
-        return (rest, Some(Token::Num(mat.parse::<i32>().unwrap())));
+    let (rest, mat) = match_re(&NUM_RE, input);
+    if !mat.is_empty() {
+        return match mat.parse::<i64>() {
+            Ok(n) => (rest, Some(Token::Num(n))),
+            Err(_) => (input, None),
+        };
     } else {
         return (rest, None);
     }
@@ -174,30 +279,26 @@ fn match_num(input: &str) -> (&str, Option<Token>) {
 
 /// Match an Real token
 fn match_real(input: &str) -> (&str, Option<Token>) {
-    let re = Regex::new(r"^[0-9]+\.[0-9]+(\s|$)").unwrap();
-    let (rest, mat) = match_re(re, input);
-    if mat != "" {
-        return (rest, Some(Token::Real(mat.parse::<f32>().unwrap())));
+    let (rest, mat) = match_re(&REAL_RE, input);
+    if !mat.is_empty() {
+        return (rest, Some(Token::Real(mat.parse::<f64>().unwrap())));
     } else {
         return (rest, None);
     }
 }
 
 fn match_boolean(input: &str) -> (&str, Option<Token>) {
-    let re = Regex::new(r"^((True)|(False))(\s|$)").unwrap();
-    let (rest, mat) = match_re(re, input);
-    if mat != "" {
-        let b = if mat == "True" { true } else { false };
-        return (rest, Some(Token::Boolean(b)));
+    let (rest, mat) = match_re(&BOOLEAN_RE, input);
+    if !mat.is_empty() {
+        return (rest, Some(Token::Boolean(mat == "True")));
     } else {
         return (rest, None);
     }
 }
 
 fn match_static_string(input: &str) -> (&str, Option<Token>) {
-    let re = Regex::new(r#"^"[[:ascii:]]+"(\s|$)"#).unwrap();
-    let (rest, mat) = match_re(re, input);
-    if mat != "" {
+    let (rest, mat) = match_re(&STATIC_STRING_RE, input);
+    if !mat.is_empty() {
         return (
             rest,
             Some(Token::StaticString(
@@ -213,15 +314,10 @@ fn match_static_string(input: &str) -> (&str, Option<Token>) {
     }
 }
 
-fn match_symbol<'a>(input: &'a str, symbol: &'a str, token: Token) -> (&'a str, Option<Token>) {
-    let mut re_str = "^".to_owned();
-    re_str.push_str(symbol);
-    let re = Regex::from_str(&re_str).unwrap();
-    let (rest, mat) = match_re(re, input);
-    if mat != "" {
-        return (rest, Some(token));
-    } else {
-        return (rest, None);
+fn match_symbol<'a>(input: &'a str, symbol: &str, token: Token) -> (&'a str, Option<Token>) {
+    match input.strip_prefix(symbol) {
+        Some(rest) => return (rest, Some(token)),
+        None => return (input, None),
     }
 }
 
@@ -238,43 +334,43 @@ fn match_semicolon(input: &str) -> (&str, Option<Token>) {
 }
 
 fn match_lparen(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\(", Token::Lparen);
+    return match_symbol(input, "(", Token::Lparen);
 }
 
 fn match_rparen(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\)", Token::Rparen);
+    return match_symbol(input, ")", Token::Rparen);
 }
 
 fn match_lbracket(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\[", Token::Lbracket);
+    return match_symbol(input, "[", Token::Lbracket);
 }
 
 fn match_rbracket(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\]", Token::Rbracket);
+    return match_symbol(input, "]", Token::Rbracket);
 }
 
 fn match_lbrace(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\{", Token::Lbrace);
+    return match_symbol(input, "{", Token::Lbrace);
 }
 
 fn match_rbrace(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\}", Token::Rbrace);
+    return match_symbol(input, "}", Token::Rbrace);
 }
 
 fn match_period(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\.", Token::Period);
+    return match_symbol(input, ".", Token::Period);
 }
 
 fn match_plus(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\+", Token::Plus);
+    return match_symbol(input, "+", Token::Plus);
 }
 
 fn match_minus(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\-", Token::Minus);
+    return match_symbol(input, "-", Token::Minus);
 }
 
 fn match_star(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\*", Token::Star);
+    return match_symbol(input, "*", Token::Star);
 }
 
 fn match_forwardslash(input: &str) -> (&str, Option<Token>) {
@@ -294,29 +390,21 @@ fn match_greaterthan(input: &str) -> (&str, Option<Token>) {
 }
 
 fn match_ampersand(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\&", Token::Ampersand);
+    return match_symbol(input, "&", Token::Ampersand);
 }
 
 fn match_bar(input: &str) -> (&str, Option<Token>) {
-    return match_symbol(input, r"\|", Token::Bar);
-}
-
-fn match_reserved_word<'a>(
-    input: &'a str,
-    word: &'a str,
-    token: Token,
-) -> (&'a str, Option<Token>) {
-    let mut re_str = "^".to_owned();
-    let re_str_suffix = r"(\s|$)";
-    re_str.push_str(word);
-    re_str.push_str(re_str_suffix);
-
-    let re = Regex::from_str(&re_str).unwrap();
-    let (rest, mat) = match_re(re, input);
-    if mat != "" {
-        return (rest.trim(), Some(token));
-    } else {
-        return (rest, None);
+    return match_symbol(input, "|", Token::Bar);
+}
+
+/// Match a reserved word, which must not run on into an identifier
+fn match_reserved_word<'a>(input: &'a str, word: &str, token: Token) -> (&'a str, Option<Token>) {
+    let Some(rest) = input.strip_prefix(word) else {
+        return (input, None);
+    };
+    match rest.chars().next() {
+        Some(c) if c.is_alphanumeric() || c == '_' => return (input, None),
+        _ => return (rest.trim_start(), Some(token)),
     }
 }
 
@@ -372,19 +460,44 @@ fn match_mut(input: &str) -> (&str, Option<Token>) {
     return match_reserved_word(input, "mut", Token::Mut);
 }
 
-fn match_whitespace(input: &str) -> (&str, Option<Token>) {
-    let re = Regex::new(r"^\s").unwrap();
-    let (rest, _) = match_re(re, input);
-    return (rest, Some(Token::Passthrough));
+fn match_comment(input: &str) -> (&str, Option<Token>) {
+    match input.strip_prefix("//") {
+        Some(rest) => {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            let text = rest[..end].trim_end().to_string();
+            return (&rest[end..], Some(Token::Comment(text)));
+        }
+        None => return (input, None),
+    }
 }
 
-fn match_error(input: &str) -> (&str, Option<Token>) {
-    panic!("Invalid token: {}", input);
+fn match_whitespace(input: &str) -> (&str, Option<Token>) {
+    if let Some(mat) = WHITESPACE_RE.find(input) {
+        return (&input[mat.end()..], Some(Token::Passthrough));
+    } else {
+        return (input, None);
+    }
 }
 
-pub fn test_exercise_2() {
-    let tokens = tokenize("57 if abcd 64.0 True False \"Hello World :)\" , { } [ ] . / + - * = > < | & if else elseif for while fn let int bool float string char mut");
-    println!("{:?}", tokens);
+/// The error for input no token matches. Digits that make a valid integer are followed by
+/// something a number cannot end in, which is the character reported
+fn lex_error(input: &str, pos: usize) -> LexError {
+    let digits = input.len() - input.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 && input[..digits].parse::<i64>().is_err() {
+        return LexError {
+            message: format!("integer literal `{}` is too large", &input[..digits]),
+            pos,
+        };
+    }
+    let message = match input[digits..].chars().next() {
+        Some('"') => "unterminated string literal".to_string(),
+        Some(c) => format!("unexpected character `{}`", c),
+        None => "unexpected end of input".to_string(),
+    };
+    return LexError {
+        message,
+        pos: pos + digits,
+    };
 }
 
 #[cfg(test)]
@@ -413,6 +526,7 @@ mod tests {
         assert_eq!(match_num("57"), ("", Some(Token::Num(57))));
         assert_eq!(match_num("64 64"), ("64", Some(Token::Num(64))));
         assert_eq!(match_num("_32"), ("_32", None));
+        assert_eq!(match_num("3000000000"), ("", Some(Token::Num(3000000000))));
     }
 
     #[test]
@@ -462,4 +576,100 @@ mod tests {
         assert_eq!(match_if("if ()"), ("()", Some(Token::If)));
         assert_eq!(match_if("if89"), ("if89", None));
     }
+
+    #[test]
+    fn test_tokenize_without_whitespace() {
+        assert_eq!(
+            tokenize("f(x,1.5)").unwrap(),
+            vec![
+                Token::Id("f".to_string()),
+                Token::Lparen,
+                Token::Id("x".to_string()),
+                Token::Comma,
+                Token::Real(1.5),
+                Token::Rparen
+            ]
+        );
+        assert_eq!(
+            tokenize(r#""a" "b""#).unwrap(),
+            vec![
+                Token::StaticString("a".to_string()),
+                Token::StaticString("b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_with_positions() {
+        assert_eq!(
+            tokenize_with_positions("let x =\n  5;").unwrap(),
+            vec![
+                (Token::Let, 0),
+                (Token::Id("x".to_string()), 4),
+                (Token::Equal, 6),
+                (Token::Num(5), 10),
+                (Token::Semicolon, 11)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        let input = "let x = 1; // one\r\n// two\nx / 2";
+        assert_eq!(
+            tokenize_with_comments(input).unwrap()[5..8],
+            [
+                (Token::Comment(" one".to_string()), 11),
+                (Token::Comment(" two".to_string()), 19),
+                (Token::Id("x".to_string()), 26)
+            ]
+        );
+        assert_eq!(
+            tokenize(input).unwrap()[5..],
+            [
+                Token::Id("x".to_string()),
+                Token::ForwardSlash,
+                Token::Num(2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_invalid() {
+        let error = |input: &str| {
+            let e = tokenize(input).unwrap_err();
+            return (e.message, e.pos);
+        };
+        assert_eq!(error("a ~"), ("unexpected character `~`".to_string(), 2));
+        assert_eq!(
+            error("x = 99999999999999999999;"),
+            (
+                "integer literal `99999999999999999999` is too large".to_string(),
+                4
+            )
+        );
+        assert_eq!(
+            error("x = 1e10;"),
+            ("unexpected character `e`".to_string(), 5)
+        );
+        assert_eq!(
+            error("x = 12abc;"),
+            ("unexpected character `a`".to_string(), 6)
+        );
+        assert_eq!(
+            error("print(\"ab);"),
+            ("unterminated string literal".to_string(), 6)
+        );
+    }
+
+    #[test]
+    fn test_line_col() {
+        assert_eq!(line_col("ab\ncd", 0), (1, 1));
+        assert_eq!(line_col("ab\ncd", 4), (2, 2));
+    }
+
+    #[test]
+    fn test_exercise_2() {
+        assert!(tokenize("57 if abcd 64.0 True False \"Hello World :)\" , { } [ ] . / + - * = > < | & if else elseif for while fn let int bool float string char mut").is_ok());
+    }
 }
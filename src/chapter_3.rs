@@ -0,0 +1,832 @@
+/// A recursive descent parser for the language lexed in Chapter 2
+///
+/// ```text
+/// program    -> item*
+/// item       -> let | fn | while | for | assign | expr ";"?
+/// let        -> "let" "mut"? ID (":" type)? "=" expr ";"
+/// fn         -> "fn" ID "(" (param ("," param)*)? ")" (":" type)? block
+/// param      -> "mut"? ID ":" type
+/// while      -> "while" expr block
+/// for        -> "for" ID "=" expr ":" expr block
+/// assign     -> ID "=" expr ";"
+/// block      -> "{" item* expr? "}"
+/// expr       -> and ("|" and)*
+/// and        -> comparison ("&" comparison)*
+/// comparison -> additive (("==" | "<>" | "<" | "<=" | ">" | ">=") additive)?
+/// additive   -> term (("+" | "-") term)*
+/// term       -> unary (("*" | "/") unary)*
+/// unary      -> "-" unary | primary
+/// primary    -> NUM | REAL | BOOLEAN | STRING | ID | ID "(" args ")" | "(" expr ")" | if
+/// if         -> "if" expr block ("elseif" expr block)* ("else" block)?
+/// ```
+use crate::chapter_2::{tokenize_with_positions, LexError, Token};
+use crate::chapter_4::*;
+
+/// An error found while parsing, located at a byte offset in the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub pos: Pos,
+    /// Other places in the source that explain the error
+    pub related: Vec<(Pos, String)>,
+}
+
+/// Lexing is the first step of parsing, so its errors are parse errors too
+impl From<LexError> for ParseError {
+    fn from(error: LexError) -> ParseError {
+        return ParseError {
+            message: error.message,
+            pos: error.pos,
+            related: vec![],
+        };
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
+/// Parse a whole program from source, stopping at the first error
+pub fn parse(input: &str) -> Result<Program, ParseError> {
+    return parse_at(input, 0);
+}
+
+/// Parse like `parse`, counting positions from `base` as if the source came after that many
+/// bytes of other text. The REPL gives each input its own positions this way, to tell which
+/// input the function an error happens in came from
+pub fn parse_at(input: &str, base: Pos) -> Result<Program, ParseError> {
+    let (program, errors) = parse_recovering_at(input, base);
+    match errors.into_iter().next() {
+        Some(error) => return Err(error),
+        None => return Ok(program),
+    }
+}
+
+/// Parse a whole program from source, reporting every syntax error instead of only the first
+///
+/// After an error the parser skips ahead to a token a statement can end or start at: past a `;`
+/// or a `}` closing a block opened while skipping, or up to the `}` of the enclosing block or a
+/// `fn`, `let`, `while` or `for`. The statement it gave up on is left in the program as a
+/// `StmtKind::Error`, and a missing operand as an `ExprKind::Error`, so the program keeps the
+/// shape of the source. A missing `;` between statements is reported and then assumed. Only the
+/// first of several errors at the same place is kept, since the later ones are its consequences
+///
+/// When the source does not lex, the tokens before the bad character are parsed and the lexing
+/// error is reported after any syntax errors found in them
+pub fn parse_recovering(input: &str) -> (Program, Vec<ParseError>) {
+    return parse_recovering_at(input, 0);
+}
+
+fn parse_recovering_at(input: &str, base: Pos) -> (Program, Vec<ParseError>) {
+    let (mut tokens, end, lex_error) = match tokenize_with_positions(input) {
+        Ok(tokens) => (tokens, input.len(), None),
+        Err(error) => {
+            let tokens = tokenize_with_positions(&input[..error.pos]).unwrap_or_default();
+            (tokens, error.pos, Some(ParseError::from(error)))
+        }
+    };
+    for (_, pos) in tokens.iter_mut() {
+        *pos += base;
+    }
+    let lex_error = lex_error.map(|error| ParseError { pos: error.pos + base, ..error });
+    let mut parser = Parser::new(tokens, end + base);
+    let program = parser.parse_program();
+    let mut errors = parser.errors;
+    if let Some(error) = lex_error {
+        // Running out of tokens at the bad character is not a syntax error of its own
+        errors.retain(|e| e.pos < error.pos);
+        errors.push(error);
+    }
+    return (program, errors);
+}
+
+/// Something that can appear inside a block: a statement or the block's trailing expression
+enum Item {
+    Stmt(Stmt),
+    Tail(Expr),
+}
+
+struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    current: usize,
+    end: Pos,
+    errors: Vec<ParseError>,
+}
+
+impl Parser {
+    fn new(tokens: Vec<(Token, Pos)>, end: Pos) -> Parser {
+        return Parser {
+            tokens,
+            current: 0,
+            end,
+            errors: Vec::new(),
+        };
+    }
+
+    fn at_end(&self) -> bool {
+        return self.current >= self.tokens.len();
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        return self.peek_at(0);
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&Token> {
+        return self.tokens.get(self.current + n).map(|(tok, _)| tok);
+    }
+
+    /// Position of the current token, or the end of the input
+    fn pos(&self) -> Pos {
+        return self.tokens.get(self.current).map_or(self.end, |(_, pos)| *pos);
+    }
+
+    /// Whether the token `n` ahead immediately follows the token before it, as in `==`
+    fn adjacent(&self, n: usize) -> bool {
+        match (
+            self.tokens.get(self.current + n - 1),
+            self.tokens.get(self.current + n),
+        ) {
+            (Some((_, a)), Some((_, b))) => return a + 1 == *b,
+            _ => return false,
+        }
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let tok = self.peek().cloned();
+        self.current += 1;
+        return tok;
+    }
+
+    fn check(&self, tok: &Token) -> bool {
+        return self.peek() == Some(tok);
+    }
+
+    fn eat(&mut self, tok: &Token) -> bool {
+        if self.check(tok) {
+            self.current += 1;
+            return true;
+        }
+        return false;
+    }
+
+    fn expect(&mut self, tok: &Token) -> Result<Pos, ParseError> {
+        let pos = self.pos();
+        if self.eat(tok) {
+            return Ok(pos);
+        }
+        return Err(self.error(&format!("expected `{}`", tok)));
+    }
+
+    fn expect_id(&mut self) -> Result<String, ParseError> {
+        if let Some(Token::Id(name)) = self.peek() {
+            let name = name.clone();
+            self.current += 1;
+            return Ok(name);
+        }
+        return Err(self.error("expected identifier"));
+    }
+
+    /// An error at the current token, mentioning what was found there
+    fn error(&self, message: &str) -> ParseError {
+        let found = match self.peek() {
+            Some(tok) => format!("`{}`", tok),
+            None => "end of input".to_string(),
+        };
+        return ParseError {
+            message: format!("{}, found {}", message, found),
+            pos: self.pos(),
+            related: vec![],
+        };
+    }
+
+    /// Record an error and carry on, unless one was already recorded at the same place
+    fn report(&mut self, error: ParseError) {
+        if self.errors.last().map(|e| e.pos) != Some(error.pos) {
+            self.errors.push(error);
+        }
+    }
+
+    /// Skip the rest of a statement that failed to parse, from the token it started at. At least
+    /// one token is skipped, so the caller always makes progress
+    fn synchronize(&mut self, start: usize) {
+        let mut depth = 0;
+        while let Some(tok) = self.peek() {
+            let skipped = self.current > start;
+            match tok {
+                Token::Lbrace => depth += 1,
+                Token::Rbrace if depth == 0 && skipped => return,
+                Token::Rbrace => {
+                    depth -= 1;
+                    if depth <= 0 {
+                        self.current += 1;
+                        return;
+                    }
+                }
+                Token::Semicolon if depth == 0 => {
+                    self.current += 1;
+                    return;
+                }
+                Token::Function | Token::Let | Token::While | Token::For
+                    if depth == 0 && skipped =>
+                {
+                    return
+                }
+                _ => {}
+            }
+            self.current += 1;
+        }
+    }
+
+    /// Parse the next item of a block or the program. An item that fails to parse is reported
+    /// and skipped, leaving an error statement in its place
+    fn recover_item(&mut self) -> Item {
+        let (start, pos) = (self.current, self.pos());
+        match self.parse_item() {
+            Ok(item) if self.current > start => return item,
+            Ok(_) => {}
+            Err(error) => self.report(error),
+        }
+        self.synchronize(start);
+        return Item::Stmt(Stmt::new(StmtKind::Error, pos));
+    }
+
+    fn parse_program(&mut self) -> Program {
+        let mut stmts = Vec::new();
+        while !self.at_end() {
+            match self.recover_item() {
+                Item::Stmt(stmt) => stmts.push(stmt),
+                Item::Tail(expr) => {
+                    if !self.at_end() {
+                        self.report(self.error("expected `;`"));
+                    }
+                    let pos = expr.pos;
+                    stmts.push(Stmt::new(StmtKind::Expr(expr), pos));
+                }
+            }
+        }
+        return Program { stmts };
+    }
+
+    fn parse_block(&mut self) -> Result<Block, ParseError> {
+        let pos = self.expect(&Token::Lbrace)?;
+        let mut stmts = Vec::new();
+        let mut result = None;
+        while !self.eat(&Token::Rbrace) {
+            if self.at_end() {
+                let mut error = self.error("expected `}`");
+                error
+                    .related
+                    .push((pos, "this `{` is never closed".to_string()));
+                self.report(error);
+                break;
+            }
+            match self.recover_item() {
+                Item::Stmt(stmt) => stmts.push(stmt),
+                Item::Tail(expr) if self.check(&Token::Rbrace) => result = Some(Box::new(expr)),
+                Item::Tail(expr) => {
+                    let mut error = self.error("expected `;` or `}`");
+                    if self.at_end() {
+                        error
+                            .related
+                            .push((pos, "this `{` is never closed".to_string()));
+                    }
+                    self.report(error);
+                    let pos = expr.pos;
+                    stmts.push(Stmt::new(StmtKind::Expr(expr), pos));
+                }
+            }
+        }
+        return Ok(Block {
+            stmts,
+            result,
+            pos,
+        });
+    }
+
+    fn parse_item(&mut self) -> Result<Item, ParseError> {
+        let pos = self.pos();
+        match self.peek() {
+            Some(Token::Let) => return Ok(Item::Stmt(self.parse_let()?)),
+            Some(Token::Function) => {
+                return Ok(Item::Stmt(Stmt::new(StmtKind::Fn(self.parse_fn()?), pos)))
+            }
+            Some(Token::While) => {
+                self.advance();
+                let cond = self.parse_expr()?;
+                let body = self.parse_block()?;
+                self.eat(&Token::Semicolon);
+                return Ok(Item::Stmt(Stmt::new(StmtKind::While { cond, body }, pos)));
+            }
+            Some(Token::For) => {
+                self.advance();
+                let var = self.expect_id()?;
+                self.expect(&Token::Equal)?;
+                let lo = self.parse_expr()?;
+                self.expect(&Token::Colon)?;
+                let hi = self.parse_expr()?;
+                let body = self.parse_block()?;
+                self.eat(&Token::Semicolon);
+                return Ok(Item::Stmt(Stmt::new(
+                    StmtKind::For { var, lo, hi, body },
+                    pos,
+                )));
+            }
+            Some(Token::Id(name))
+                if self.peek_at(1) == Some(&Token::Equal)
+                    && !(self.peek_at(2) == Some(&Token::Equal) && self.adjacent(2)) =>
+            {
+                let name = name.clone();
+                self.current += 2;
+                let value = self.parse_expr()?;
+                self.expect(&Token::Semicolon)?;
+                return Ok(Item::Stmt(Stmt::new(StmtKind::Assign { name, value }, pos)));
+            }
+            _ => {
+                let expr = self.parse_expr()?;
+                if self.eat(&Token::Semicolon) {
+                    return Ok(Item::Stmt(Stmt::new(StmtKind::Expr(expr), pos)));
+                }
+                // An `if` does not need a `;` unless it is the value of its block
+                if matches!(expr.kind, ExprKind::If { .. }) && !self.check(&Token::Rbrace) {
+                    return Ok(Item::Stmt(Stmt::new(StmtKind::Expr(expr), pos)));
+                }
+                return Ok(Item::Tail(expr));
+            }
+        }
+    }
+
+    fn parse_let(&mut self) -> Result<Stmt, ParseError> {
+        let pos = self.expect(&Token::Let)?;
+        let mutable = self.eat(&Token::Mut);
+        let name = self.expect_id()?;
+        let ty = if self.eat(&Token::Colon) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        self.expect(&Token::Equal)?;
+        let init = self.parse_expr()?;
+        self.expect(&Token::Semicolon)?;
+        return Ok(Stmt::new(
+            StmtKind::Let {
+                name,
+                mutable,
+                ty,
+                init,
+            },
+            pos,
+        ));
+    }
+
+    fn parse_fn(&mut self) -> Result<FnDecl, ParseError> {
+        let pos = self.expect(&Token::Function)?;
+        let name = self.expect_id()?;
+        self.expect(&Token::Lparen)?;
+        let mut params = Vec::new();
+        while !self.eat(&Token::Rparen) {
+            if !params.is_empty() {
+                self.expect(&Token::Comma)?;
+            }
+            let param_pos = self.pos();
+            let mutable = self.eat(&Token::Mut);
+            let param_name = self.expect_id()?;
+            self.expect(&Token::Colon)?;
+            let ty = self.parse_type()?;
+            params.push(Param {
+                name: param_name,
+                mutable,
+                ty,
+                pos: param_pos,
+            });
+        }
+        let result = if self.eat(&Token::Colon) {
+            self.parse_type()?
+        } else {
+            Type::Unit
+        };
+        let body = self.parse_block()?;
+        return Ok(FnDecl {
+            name,
+            params,
+            result,
+            body,
+            pos,
+        });
+    }
+
+    fn parse_type(&mut self) -> Result<Type, ParseError> {
+        let ty = match self.peek() {
+            Some(Token::Int) => Type::Int,
+            Some(Token::Float) => Type::Float,
+            Some(Token::Bool) => Type::Bool,
+            Some(Token::String) => Type::String,
+            Some(Token::Char) => {
+                return Err(ParseError {
+                    message: "the `char` type is not supported yet".to_string(),
+                    pos: self.pos(),
+                    related: vec![],
+                })
+            }
+            _ => return Err(self.error("expected type")),
+        };
+        self.advance();
+        return Ok(ty);
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.check(&Token::Bar) {
+            let pos = self.pos();
+            self.advance();
+            let right = self.parse_and()?;
+            left = Expr::new(
+                ExprKind::Binary(Box::new(left), BinOp::Or, Box::new(right)),
+                pos,
+            );
+        }
+        return Ok(left);
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_comparison()?;
+        while self.check(&Token::Ampersand) {
+            let pos = self.pos();
+            self.advance();
+            let right = self.parse_comparison()?;
+            left = Expr::new(
+                ExprKind::Binary(Box::new(left), BinOp::And, Box::new(right)),
+                pos,
+            );
+        }
+        return Ok(left);
+    }
+
+    /// Look for a comparison operator, returning it and how many tokens it spans
+    fn peek_comparison(&self) -> Option<(BinOp, usize)> {
+        let second = self.peek_at(1).filter(|_| self.adjacent(1));
+        match (self.peek(), second) {
+            (Some(Token::Equal), Some(Token::Equal)) => return Some((BinOp::Eq, 2)),
+            (Some(Token::LessThan), Some(Token::GreaterThan)) => return Some((BinOp::Neq, 2)),
+            (Some(Token::LessThan), Some(Token::Equal)) => return Some((BinOp::Le, 2)),
+            (Some(Token::GreaterThan), Some(Token::Equal)) => return Some((BinOp::Ge, 2)),
+            (Some(Token::LessThan), _) => return Some((BinOp::Lt, 1)),
+            (Some(Token::GreaterThan), _) => return Some((BinOp::Gt, 1)),
+            _ => return None,
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let left = self.parse_additive()?;
+        if let Some((op, len)) = self.peek_comparison() {
+            let pos = self.pos();
+            self.current += len;
+            let right = self.parse_additive()?;
+            if self.peek_comparison().is_some() {
+                return Err(self.error("comparison operators cannot be chained"));
+            }
+            return Ok(Expr::new(
+                ExprKind::Binary(Box::new(left), op, Box::new(right)),
+                pos,
+            ));
+        }
+        return Ok(left);
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => BinOp::Add,
+                Some(Token::Minus) => BinOp::Sub,
+                _ => return Ok(left),
+            };
+            let pos = self.pos();
+            self.advance();
+            let right = self.parse_term()?;
+            left = Expr::new(ExprKind::Binary(Box::new(left), op, Box::new(right)), pos);
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => BinOp::Mul,
+                Some(Token::ForwardSlash) => BinOp::Div,
+                _ => return Ok(left),
+            };
+            let pos = self.pos();
+            self.advance();
+            let right = self.parse_unary()?;
+            left = Expr::new(ExprKind::Binary(Box::new(left), op, Box::new(right)), pos);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        if self.check(&Token::Minus) {
+            let pos = self.pos();
+            self.advance();
+            let operand = self.parse_unary()?;
+            return Ok(Expr::new(
+                ExprKind::Unary(UnOp::Neg, Box::new(operand)),
+                pos,
+            ));
+        }
+        return self.parse_primary();
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let pos = self.pos();
+        let kind = match self.peek() {
+            Some(Token::Num(n)) => ExprKind::Int(*n),
+            Some(Token::Real(r)) => ExprKind::Float(*r),
+            Some(Token::Boolean(b)) => ExprKind::Bool(*b),
+            Some(Token::StaticString(s)) => ExprKind::Str(s.clone()),
+            Some(Token::Id(name)) => {
+                let name = name.clone();
+                self.advance();
+                if !self.eat(&Token::Lparen) {
+                    return Ok(Expr::new(ExprKind::Var(name), pos));
+                }
+                let mut args = Vec::new();
+                while !self.eat(&Token::Rparen) {
+                    if !args.is_empty() {
+                        self.expect(&Token::Comma)?;
+                    }
+                    args.push(self.parse_expr()?);
+                }
+                return Ok(Expr::new(ExprKind::Call(name, args), pos));
+            }
+            Some(Token::Lparen) => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect(&Token::Rparen)?;
+                return Ok(expr);
+            }
+            Some(Token::If) => return self.parse_if(),
+            _ => {
+                // Leave the token for whatever comes after the missing operand
+                self.report(self.error("expected expression"));
+                return Ok(Expr::new(ExprKind::Error, pos));
+            }
+        };
+        self.advance();
+        return Ok(Expr::new(kind, pos));
+    }
+
+    fn parse_if(&mut self) -> Result<Expr, ParseError> {
+        let pos = self.expect(&Token::If)?;
+        let mut branches = vec![(self.parse_expr()?, self.parse_block()?)];
+        while self.eat(&Token::ElseIf) {
+            branches.push((self.parse_expr()?, self.parse_block()?));
+        }
+        let else_block = if self.eat(&Token::Else) {
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
+        return Ok(Expr::new(
+            ExprKind::If {
+                branches,
+                else_block,
+            },
+            pos,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_expr(input: &str) -> Expr {
+        let program = parse(input).unwrap();
+        match &program.stmts[0].kind {
+            StmtKind::Expr(expr) => return expr.clone(),
+            other => panic!("expected expression statement, found {:?}", other),
+        }
+    }
+
+    fn int(n: i64, pos: Pos) -> Box<Expr> {
+        return Box::new(Expr::new(ExprKind::Int(n), pos));
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        assert_eq!(
+            parse_expr("1 + 2 * 3"),
+            Expr::new(
+                ExprKind::Binary(
+                    int(1, 0),
+                    BinOp::Add,
+                    Box::new(Expr::new(
+                        ExprKind::Binary(int(2, 4), BinOp::Mul, int(3, 8)),
+                        6
+                    ))
+                ),
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_left_associative() {
+        assert_eq!(
+            parse_expr("8-2-1"),
+            Expr::new(
+                ExprKind::Binary(
+                    Box::new(Expr::new(
+                        ExprKind::Binary(int(8, 0), BinOp::Sub, int(2, 2)),
+                        1
+                    )),
+                    BinOp::Sub,
+                    int(1, 4)
+                ),
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_comparisons() {
+        for (input, op) in [
+            ("a == b", BinOp::Eq),
+            ("a <> b", BinOp::Neq),
+            ("a < b", BinOp::Lt),
+            ("a <= b", BinOp::Le),
+            ("a > b", BinOp::Gt),
+            ("a >= b", BinOp::Ge),
+        ] {
+            match parse_expr(input).kind {
+                ExprKind::Binary(_, found, _) => assert_eq!(found, op, "{}", input),
+                other => panic!("{:?}", other),
+            }
+        }
+        assert!(parse("a < b < c").is_err());
+        assert!(parse("a = = b").is_err());
+    }
+
+    #[test]
+    fn test_parse_call() {
+        assert_eq!(
+            parse_expr("f(1, x)").kind,
+            ExprKind::Call(
+                "f".to_string(),
+                vec![
+                    Expr::new(ExprKind::Int(1), 2),
+                    Expr::new(ExprKind::Var("x".to_string()), 5)
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_let_and_assign() {
+        let program = parse("let mut x: int = 1; x = x + 1;").unwrap();
+        assert_eq!(program.stmts.len(), 2);
+        match &program.stmts[0].kind {
+            StmtKind::Let {
+                name, mutable, ty, ..
+            } => {
+                assert_eq!(name, "x");
+                assert!(*mutable);
+                assert_eq!(*ty, Some(Type::Int));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(program.stmts[1].kind, StmtKind::Assign { .. }));
+    }
+
+    #[test]
+    fn test_parse_fn() {
+        let program = parse("fn add(a: int, mut b: int): int { b = b + a; b }").unwrap();
+        match &program.stmts[0].kind {
+            StmtKind::Fn(decl) => {
+                assert_eq!(decl.name, "add");
+                assert_eq!(decl.params.len(), 2);
+                assert!(decl.params[1].mutable);
+                assert_eq!(decl.result, Type::Int);
+                assert_eq!(decl.body.stmts.len(), 1);
+                assert!(decl.body.result.is_some());
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_if_chain() {
+        match parse_expr("if a { 1 } elseif b { 2 } else { 3 }").kind {
+            ExprKind::If {
+                branches,
+                else_block,
+            } => {
+                assert_eq!(branches.len(), 2);
+                assert!(else_block.is_some());
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_if_statement_without_semicolon() {
+        let program = parse("if a { print(1); } print(2);").unwrap();
+        assert_eq!(program.stmts.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_loops() {
+        let program = parse("while x < 3 { x = x + 1; } for i = 0 : 10 { print(i); }").unwrap();
+        assert!(matches!(program.stmts[0].kind, StmtKind::While { .. }));
+        assert!(matches!(program.stmts[1].kind, StmtKind::For { .. }));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse("let x = ;"),
+            Err(ParseError {
+                message: "expected expression, found `;`".to_string(),
+                pos: 8,
+                related: vec![]
+            })
+        );
+        assert_eq!(
+            parse("fn f() { 1 2 }").unwrap_err().message,
+            "expected `;` or `}`, found `2`"
+        );
+        assert_eq!(
+            parse("let x: char = 1;").unwrap_err().message,
+            "the `char` type is not supported yet"
+        );
+        assert_eq!(
+            parse("{").unwrap_err().message,
+            "expected expression, found `{`"
+        );
+        assert_eq!(
+            parse("fn f() {\n  1").unwrap_err(),
+            ParseError {
+                message: "expected `;` or `}`, found end of input".to_string(),
+                pos: 12,
+                related: vec![(7, "this `{` is never closed".to_string())]
+            }
+        );
+        let unclosed = parse("while x { x = 1;").unwrap_err();
+        assert_eq!(unclosed.message, "expected `}`, found end of input");
+        assert_eq!(
+            unclosed.related,
+            [(8, "this `{` is never closed".to_string())]
+        );
+        assert_eq!(
+            parse("x = 1 ~").unwrap_err().message,
+            "unexpected character `~`"
+        );
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let source = "let x = ;\nfn f(a int) { print(a); }\nwhile x { print(x) 1 }\nlet y = 2;\n}";
+        let (program, errors) = parse_recovering(source);
+        let messages: Vec<(&str, Pos)> =
+            errors.iter().map(|e| (e.message.as_str(), e.pos)).collect();
+        assert_eq!(
+            messages,
+            [
+                ("expected expression, found `;`", 8),
+                ("expected `:`, found `int`", 17),
+                ("expected `;` or `}`, found `1`", 55),
+                ("expected expression, found `}`", 70),
+            ]
+        );
+        let kinds: Vec<&StmtKind> = program.stmts.iter().map(|stmt| &stmt.kind).collect();
+        match kinds[..] {
+            [StmtKind::Let { init, .. }, StmtKind::Error, StmtKind::While { body, .. }, StmtKind::Let { .. }, StmtKind::Error] =>
+            {
+                assert_eq!(init.kind, ExprKind::Error);
+                assert_eq!(body.stmts.len(), 1);
+                assert!(body.result.is_some());
+            }
+            _ => panic!("{:?}", kinds),
+        }
+        assert_eq!(parse(source).unwrap_err().pos, 8);
+
+        // Errors the parser runs into at the end of a block are reported once
+        let (_, errors) = parse_recovering("fn f() {\n  if x {");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].related,
+            [(16, "this `{` is never closed".to_string())]
+        );
+        let (_, errors) = parse_recovering("print(1) print(2 +);\nx = 1 ~");
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "expected `;`, found `print`",
+                "expected expression, found `)`",
+                "unexpected character `~`"
+            ]
+        );
+    }
+}
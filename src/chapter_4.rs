@@ -0,0 +1,555 @@
+/// Abstract syntax for the language lexed in Chapter 2
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Byte offset into the source a node was parsed from
+pub type Pos = usize;
+
+/// The types a value of the language can have
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    String,
+    Unit,
+}
+
+/// Variant for binary operators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Neq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+/// Variant for unary operators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg,
+}
+
+/// A whole program: the statements of the implicit main function
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+}
+
+/// A `{ ... }` block. The value of the block is its trailing expression, if any
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub result: Option<Box<Expr>>,
+    pub pos: Pos,
+}
+
+/// A statement in the program. Statements do not produce a value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Let {
+        name: String,
+        mutable: bool,
+        ty: Option<Type>,
+        init: Expr,
+    },
+    Assign {
+        name: String,
+        value: Expr,
+    },
+    While {
+        cond: Expr,
+        body: Block,
+    },
+    /// `for var = lo : hi { body }` runs body for lo <= var < hi
+    For {
+        var: String,
+        lo: Expr,
+        hi: Expr,
+        body: Block,
+    },
+    Fn(FnDecl),
+    Expr(Expr),
+    /// A statement that failed to parse, left by `parse_recovering` where it gave up
+    Error,
+}
+
+/// A function declaration. Consecutive declarations in a block may call each other
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnDecl {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub result: Type,
+    pub body: Block,
+    pub pos: Pos,
+}
+
+/// A formal parameter of a function
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub mutable: bool,
+    pub ty: Type,
+    pub pos: Pos,
+}
+
+/// An expression in the program. Its type is filled in by the type checker in Chapter 5
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub pos: Pos,
+    pub ty: OnceLock<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Var(String),
+    Unary(UnOp, Box<Expr>),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+    Call(String, Vec<Expr>),
+    /// `if c { } elseif c { } else { }`, one branch per `if`/`elseif`
+    If {
+        branches: Vec<(Expr, Block)>,
+        else_block: Option<Block>,
+    },
+    /// An expression that failed to parse, left by `parse_recovering` where one was expected
+    Error,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, pos: Pos) -> Expr {
+        return Expr {
+            kind,
+            pos,
+            ty: OnceLock::new(),
+        };
+    }
+
+    /// The type of the expression, which must have been type checked
+    pub fn ty(&self) -> Type {
+        return *self.ty.get().expect("expression has not been type checked");
+    }
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, pos: Pos) -> Stmt {
+        return Stmt { kind, pos };
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+            Type::String => "string",
+            Type::Unit => "()",
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Eq => "==",
+            BinOp::Neq => "<>",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&",
+            BinOp::Or => "|",
+        };
+        return write!(f, "{}", symbol);
+    }
+}
+
+/// Walks a program without changing it. Each method defaults to visiting the children, so a pass
+/// overrides only the nodes it cares about and calls the `walk_` function to keep descending. The
+/// lifetime lets a pass keep references into the tree, such as the names in scope
+pub trait Visitor<'a> {
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_fn_decl(&mut self, decl: &'a FnDecl) {
+        walk_fn_decl(self, decl);
+    }
+
+    fn visit_block(&mut self, block: &'a Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+/// Visits the statements of a program, which are not in a block of their own
+pub fn walk_program<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, program: &'a Program) {
+    for stmt in &program.stmts {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+/// Visits the children of a statement, in the order they are evaluated
+pub fn walk_stmt<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, stmt: &'a Stmt) {
+    match &stmt.kind {
+        StmtKind::Let { init, .. } => visitor.visit_expr(init),
+        StmtKind::Assign { value, .. } => visitor.visit_expr(value),
+        StmtKind::While { cond, body } => {
+            visitor.visit_expr(cond);
+            visitor.visit_block(body);
+        }
+        StmtKind::For { lo, hi, body, .. } => {
+            visitor.visit_expr(lo);
+            visitor.visit_expr(hi);
+            visitor.visit_block(body);
+        }
+        StmtKind::Fn(decl) => visitor.visit_fn_decl(decl),
+        StmtKind::Expr(expr) => visitor.visit_expr(expr),
+        StmtKind::Error => {}
+    }
+}
+
+/// Visits the body of a function
+pub fn walk_fn_decl<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, decl: &'a FnDecl) {
+    visitor.visit_block(&decl.body);
+}
+
+/// Visits the statements of a block, then its result
+pub fn walk_block<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, block: &'a Block) {
+    for stmt in &block.stmts {
+        visitor.visit_stmt(stmt);
+    }
+    if let Some(result) = &block.result {
+        visitor.visit_expr(result);
+    }
+}
+
+/// Visits the children of an expression, in the order they are evaluated
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: &'a Expr) {
+    match &expr.kind {
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Str(_)
+        | ExprKind::Var(_)
+        | ExprKind::Error => {}
+        ExprKind::Unary(_, operand) => visitor.visit_expr(operand),
+        ExprKind::Binary(left, _, right) => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        ExprKind::Call(_, args) => {
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        ExprKind::If {
+            branches,
+            else_block,
+        } => {
+            for (cond, block) in branches {
+                visitor.visit_expr(cond);
+                visitor.visit_block(block);
+            }
+            if let Some(block) = else_block {
+                visitor.visit_block(block);
+            }
+        }
+    }
+}
+
+/// Like `Visitor`, but may change the nodes in place
+pub trait VisitorMut {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_fn_decl_mut(&mut self, decl: &mut FnDecl) {
+        walk_fn_decl_mut(self, decl);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+}
+
+/// Visits the statements of a program mutably
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for stmt in &mut program.stmts {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+/// Visits the children of a statement mutably
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::Let { init, .. } => visitor.visit_expr_mut(init),
+        StmtKind::Assign { value, .. } => visitor.visit_expr_mut(value),
+        StmtKind::While { cond, body } => {
+            visitor.visit_expr_mut(cond);
+            visitor.visit_block_mut(body);
+        }
+        StmtKind::For { lo, hi, body, .. } => {
+            visitor.visit_expr_mut(lo);
+            visitor.visit_expr_mut(hi);
+            visitor.visit_block_mut(body);
+        }
+        StmtKind::Fn(decl) => visitor.visit_fn_decl_mut(decl),
+        StmtKind::Expr(expr) => visitor.visit_expr_mut(expr),
+        StmtKind::Error => {}
+    }
+}
+
+/// Visits the body of a function mutably
+pub fn walk_fn_decl_mut<V: VisitorMut + ?Sized>(visitor: &mut V, decl: &mut FnDecl) {
+    visitor.visit_block_mut(&mut decl.body);
+}
+
+/// Visits the statements of a block mutably, then its result
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    for stmt in &mut block.stmts {
+        visitor.visit_stmt_mut(stmt);
+    }
+    if let Some(result) = &mut block.result {
+        visitor.visit_expr_mut(result);
+    }
+}
+
+/// Visits the children of an expression mutably
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Str(_)
+        | ExprKind::Var(_)
+        | ExprKind::Error => {}
+        ExprKind::Unary(_, operand) => visitor.visit_expr_mut(operand),
+        ExprKind::Binary(left, _, right) => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_expr_mut(right);
+        }
+        ExprKind::Call(_, args) => {
+            for arg in args {
+                visitor.visit_expr_mut(arg);
+            }
+        }
+        ExprKind::If {
+            branches,
+            else_block,
+        } => {
+            for (cond, block) in branches {
+                visitor.visit_expr_mut(cond);
+                visitor.visit_block_mut(block);
+            }
+            if let Some(block) = else_block {
+                visitor.visit_block_mut(block);
+            }
+        }
+    }
+}
+
+/// Rebuilds a program from its parts. The defaults fold the children and put the node back
+/// together unchanged, keeping its position and type, so a pass that rewrites one kind of node
+/// can replace it with anything. No pass of the compiler rewrites the tree yet
+#[allow(dead_code)]
+pub trait Fold {
+    fn fold_program(&mut self, program: Program) -> Program {
+        return fold_program(self, program);
+    }
+
+    fn fold_stmt(&mut self, stmt: Stmt) -> Stmt {
+        return fold_stmt(self, stmt);
+    }
+
+    fn fold_fn_decl(&mut self, decl: FnDecl) -> FnDecl {
+        return fold_fn_decl(self, decl);
+    }
+
+    fn fold_block(&mut self, block: Block) -> Block {
+        return fold_block(self, block);
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        return fold_expr(self, expr);
+    }
+}
+
+/// Folds the statements of a program
+pub fn fold_program<F: Fold + ?Sized>(folder: &mut F, program: Program) -> Program {
+    let stmts = program
+        .stmts
+        .into_iter()
+        .map(|s| folder.fold_stmt(s))
+        .collect();
+    return Program { stmts };
+}
+
+/// Folds the children of a statement
+pub fn fold_stmt<F: Fold + ?Sized>(folder: &mut F, stmt: Stmt) -> Stmt {
+    let kind = match stmt.kind {
+        StmtKind::Let {
+            name,
+            mutable,
+            ty,
+            init,
+        } => StmtKind::Let {
+            name,
+            mutable,
+            ty,
+            init: folder.fold_expr(init),
+        },
+        StmtKind::Assign { name, value } => StmtKind::Assign {
+            name,
+            value: folder.fold_expr(value),
+        },
+        StmtKind::While { cond, body } => {
+            let cond = folder.fold_expr(cond);
+            let body = folder.fold_block(body);
+            StmtKind::While { cond, body }
+        }
+        StmtKind::For { var, lo, hi, body } => {
+            let lo = folder.fold_expr(lo);
+            let hi = folder.fold_expr(hi);
+            let body = folder.fold_block(body);
+            StmtKind::For { var, lo, hi, body }
+        }
+        StmtKind::Fn(decl) => StmtKind::Fn(folder.fold_fn_decl(decl)),
+        StmtKind::Expr(expr) => StmtKind::Expr(folder.fold_expr(expr)),
+        StmtKind::Error => StmtKind::Error,
+    };
+    return Stmt::new(kind, stmt.pos);
+}
+
+/// Folds the body of a function
+pub fn fold_fn_decl<F: Fold + ?Sized>(folder: &mut F, decl: FnDecl) -> FnDecl {
+    let body = folder.fold_block(decl.body);
+    return FnDecl { body, ..decl };
+}
+
+/// Folds the statements of a block, then its result
+pub fn fold_block<F: Fold + ?Sized>(folder: &mut F, block: Block) -> Block {
+    let stmts = block
+        .stmts
+        .into_iter()
+        .map(|s| folder.fold_stmt(s))
+        .collect();
+    let result = block.result.map(|r| Box::new(folder.fold_expr(*r)));
+    return Block {
+        stmts,
+        result,
+        pos: block.pos,
+    };
+}
+
+/// Folds the children of an expression
+pub fn fold_expr<F: Fold + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    let kind = match expr.kind {
+        ExprKind::Unary(op, operand) => ExprKind::Unary(op, Box::new(folder.fold_expr(*operand))),
+        ExprKind::Binary(left, op, right) => {
+            let left = folder.fold_expr(*left);
+            let right = folder.fold_expr(*right);
+            ExprKind::Binary(Box::new(left), op, Box::new(right))
+        }
+        ExprKind::Call(name, args) => ExprKind::Call(
+            name,
+            args.into_iter().map(|a| folder.fold_expr(a)).collect(),
+        ),
+        ExprKind::If {
+            branches,
+            else_block,
+        } => {
+            let branches = branches
+                .into_iter()
+                .map(|(cond, block)| (folder.fold_expr(cond), folder.fold_block(block)))
+                .collect();
+            let else_block = else_block.map(|block| folder.fold_block(block));
+            ExprKind::If {
+                branches,
+                else_block,
+            }
+        }
+        kind => kind,
+    };
+    return Expr { kind, ..expr };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_3::parse;
+
+    /// Renames a variable where it is declared and where it is used
+    struct Rename {
+        from: &'static str,
+        to: &'static str,
+    }
+
+    impl Fold for Rename {
+        fn fold_stmt(&mut self, stmt: Stmt) -> Stmt {
+            let mut stmt = fold_stmt(self, stmt);
+            match &mut stmt.kind {
+                StmtKind::Let { name, .. }
+                | StmtKind::Assign { name, .. }
+                | StmtKind::For { var: name, .. }
+                    if name == self.from =>
+                {
+                    *name = self.to.to_string()
+                }
+                _ => {}
+            }
+            return stmt;
+        }
+
+        fn fold_expr(&mut self, expr: Expr) -> Expr {
+            let mut expr = fold_expr(self, expr);
+            if let ExprKind::Var(name) = &mut expr.kind {
+                if name == self.from {
+                    *name = self.to.to_string();
+                }
+            }
+            return expr;
+        }
+    }
+
+    #[test]
+    fn test_fold_rebuilds_the_tree() {
+        let source = "let x = 1; fn f(n: int): int { x + n } for i = 0 : x { x = x * f(i); } \
+                      print(if x > 2 { -x } else { x });";
+        let mut rename = Rename { from: "x", to: "y" };
+        let renamed = rename.fold_program(parse(source).unwrap());
+        // The names are as long as each other, so even the positions match
+        assert_eq!(renamed, parse(&source.replace('x', "y")).unwrap());
+    }
+}
@@ -0,0 +1,758 @@
+/// Implementations described in Chapter 5: semantic analysis
+use std::fmt;
+
+use crate::chapter_4::*;
+
+/// An error found while type checking, located at a byte offset in the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub message: String,
+    pub pos: Pos,
+    /// Other places in the source that explain the error
+    pub related: Vec<(Pos, String)>,
+    /// A suggestion for fixing the error
+    pub help: Option<String>,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
+/// Something that type checks but is likely a mistake, located at a byte offset in the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeWarning {
+    pub message: String,
+    pub pos: Pos,
+    /// How to silence the warning when the code is meant that way
+    pub help: String,
+}
+
+fn error<T>(message: String, pos: Pos) -> Result<T, TypeError> {
+    return Err(TypeError {
+        message,
+        pos,
+        related: vec![],
+        help: None,
+    });
+}
+
+/// An error explained by another place in the source
+fn related_error<T>(message: String, pos: Pos, related: (Pos, String)) -> Result<T, TypeError> {
+    return Err(TypeError {
+        message,
+        pos,
+        related: vec![related],
+        help: None,
+    });
+}
+
+/// An error for a name that is not declared, suggesting a declared name it may be a typo of
+fn undefined<'a>(
+    what: &str,
+    name: &str,
+    pos: Pos,
+    declared: impl Iterator<Item = &'a str>,
+) -> TypeError {
+    let mut best: Option<(usize, &str)> = None;
+    for candidate in declared {
+        let distance = edit_distance(name, candidate);
+        if distance <= name.len().max(2) / 3 && best.is_none_or(|(d, _)| distance < d) {
+            best = Some((distance, candidate));
+        }
+    }
+    return TypeError {
+        message: format!("undefined {} `{}`", what, name),
+        pos,
+        related: vec![],
+        help: best.map(|(_, candidate)| {
+            format!("a {} with a similar name exists: `{}`", what, candidate)
+        }),
+    };
+}
+
+/// The number of single character insertions, deletions, substitutions and swaps of adjacent
+/// characters turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // d[i][j] is the distance between the first i characters of a and the first j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitute = d[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = substitute.min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    return d[a.len()][b.len()];
+}
+
+/// The signature of a function
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnType {
+    pub params: Vec<Type>,
+    pub result: Type,
+}
+
+impl fmt::Display for FnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<String> = self.params.iter().map(|ty| ty.to_string()).collect();
+        return write!(f, "fn({}): {}", params.join(", "), self.result);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct VarEntry {
+    name: String,
+    ty: Type,
+    mutable: bool,
+    pos: Pos,
+    /// Loop variables can never be made mutable
+    loop_var: bool,
+    /// Whether the variable is read anywhere, for the warning about those that are not
+    read: bool,
+}
+
+/// Type checks programs, recording the type of every expression in the syntax tree
+///
+/// Bindings made at the top level of a program are kept, so one checker can check a sequence of
+/// programs that build on each other. The variables no one reads are warned about when their
+/// scope ends, which for those at the top level is only when the whole program is checked.
+#[derive(Debug, Clone, Default)]
+pub struct TypeChecker {
+    vars: Vec<VarEntry>,
+    fns: Vec<(String, FnType)>,
+    warnings: Vec<TypeWarning>,
+}
+
+/// Type check a whole program
+pub fn check(program: &Program) -> Result<(), TypeError> {
+    return check_with_warnings(program).map(|_| ());
+}
+
+/// Type check a whole program, returning the warnings when it has no errors
+pub fn check_with_warnings(program: &Program) -> Result<Vec<TypeWarning>, TypeError> {
+    let mut checker = TypeChecker::new();
+    checker.check_program(program)?;
+    checker.end_scope(0);
+    let mut warnings = checker.take_warnings();
+    warnings.sort_by_key(|warning| warning.pos);
+    return Ok(warnings);
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        return TypeChecker::default();
+    }
+
+    pub fn check_program(&mut self, program: &Program) -> Result<(), TypeError> {
+        return self.check_stmts(&program.stmts);
+    }
+
+    /// The type of a variable visible at the top level
+    pub fn var_type(&self, name: &str) -> Option<Type> {
+        return self.lookup_var(name).map(|entry| entry.ty);
+    }
+
+    /// The signature of a function visible at the top level
+    pub fn fn_type(&self, name: &str) -> Option<&FnType> {
+        return self.lookup_fn(name);
+    }
+
+    /// The warnings found since the last call, in the order of the scopes they end with
+    pub fn take_warnings(&mut self) -> Vec<TypeWarning> {
+        return std::mem::take(&mut self.warnings);
+    }
+
+    fn undefined_var(&self, name: &str, pos: Pos) -> TypeError {
+        let declared = self.vars.iter().map(|entry| entry.name.as_str());
+        return undefined("variable", name, pos, declared);
+    }
+
+    fn lookup_var(&self, name: &str) -> Option<&VarEntry> {
+        return self.vars.iter().rev().find(|entry| entry.name == name);
+    }
+
+    fn lookup_fn(&self, name: &str) -> Option<&FnType> {
+        return self
+            .fns
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, ty)| ty);
+    }
+
+    /// Look up a variable whose value is used, marking it read
+    fn read_var(&mut self, name: &str) -> Option<&VarEntry> {
+        let entry = self
+            .vars
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.name == name)?;
+        entry.read = true;
+        return Some(entry);
+    }
+
+    fn declare_var(&mut self, name: &str, ty: Type, mutable: bool, pos: Pos) {
+        self.vars.push(VarEntry {
+            name: name.to_string(),
+            ty,
+            mutable,
+            pos,
+            loop_var: false,
+            read: false,
+        });
+    }
+
+    /// Forget the variables declared after the first `vars`, warning about those never read.
+    /// Names starting with `_` are meant to be unused
+    fn end_scope(&mut self, vars: usize) {
+        for entry in self.vars.drain(vars..) {
+            if !entry.read && !entry.name.starts_with('_') {
+                self.warnings.push(TypeWarning {
+                    message: format!("unused variable `{}`", entry.name),
+                    pos: entry.pos,
+                    help: format!("if this is intentional, name it `_{}`", entry.name),
+                });
+            }
+        }
+    }
+
+    fn check_stmts(&mut self, stmts: &[Stmt]) -> Result<(), TypeError> {
+        let mut i = 0;
+        while i < stmts.len() {
+            // Consecutive function declarations can see each other, so declare them all first
+            let group_start = i;
+            while let Some(Stmt {
+                kind: StmtKind::Fn(decl),
+                ..
+            }) = stmts.get(i)
+            {
+                for stmt in &stmts[group_start..i] {
+                    if let StmtKind::Fn(other) = &stmt.kind {
+                        if other.name == decl.name {
+                            return related_error(
+                                format!("`{}` is declared twice in the same group", decl.name),
+                                decl.pos,
+                                (other.pos, "first declared here".to_string()),
+                            );
+                        }
+                    }
+                }
+                let ty = FnType {
+                    params: decl.params.iter().map(|param| param.ty).collect(),
+                    result: decl.result,
+                };
+                self.fns.push((decl.name.clone(), ty));
+                i += 1;
+            }
+            if i > group_start {
+                for stmt in &stmts[group_start..i] {
+                    if let StmtKind::Fn(decl) = &stmt.kind {
+                        self.check_fn(decl)?;
+                    }
+                }
+            } else {
+                self.check_stmt(&stmts[i])?;
+                i += 1;
+            }
+        }
+        return Ok(());
+    }
+
+    fn check_fn(&mut self, decl: &FnDecl) -> Result<(), TypeError> {
+        let (vars, fns) = (self.vars.len(), self.fns.len());
+        for (i, param) in decl.params.iter().enumerate() {
+            if let Some(other) = decl.params[..i].iter().find(|p| p.name == param.name) {
+                return related_error(
+                    format!("parameter `{}` is declared twice", param.name),
+                    param.pos,
+                    (other.pos, "first declared here".to_string()),
+                );
+            }
+            self.declare_var(&param.name, param.ty, param.mutable, param.pos);
+        }
+        let body = self.check_block(&decl.body)?;
+        self.end_scope(vars);
+        self.fns.truncate(fns);
+        if body != decl.result {
+            let pos = decl.body.result.as_ref().map_or(decl.body.pos, |e| e.pos);
+            return related_error(
+                format!(
+                    "`{}` should return {} but its body has type {}",
+                    decl.name, decl.result, body
+                ),
+                pos,
+                (decl.pos, format!("`{}` is declared here", decl.name)),
+            );
+        }
+        return Ok(());
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) -> Result<(), TypeError> {
+        match &stmt.kind {
+            StmtKind::Let {
+                name,
+                mutable,
+                ty,
+                init,
+                ..
+            } => {
+                let init_ty = self.check_expr(init)?;
+                if init_ty == Type::Unit {
+                    return error(
+                        format!("`{}` cannot hold a value of type ()", name),
+                        init.pos,
+                    );
+                }
+                if let Some(ty) = ty {
+                    if *ty != init_ty {
+                        return error(
+                            format!("`{}` is declared {} but given {}", name, ty, init_ty),
+                            init.pos,
+                        );
+                    }
+                }
+                self.declare_var(name, init_ty, *mutable, stmt.pos);
+            }
+            StmtKind::Assign { name, value } => {
+                let value_ty = self.check_expr(value)?;
+                let entry = match self.lookup_var(name) {
+                    Some(entry) => entry,
+                    None => return Err(self.undefined_var(name, stmt.pos)),
+                };
+                if !entry.mutable {
+                    return Err(TypeError {
+                        message: format!("cannot assign twice to immutable variable `{}`", name),
+                        pos: stmt.pos,
+                        related: vec![(entry.pos, format!("`{}` is declared here", name))],
+                        help: (!entry.loop_var)
+                            .then(|| format!("make `{}` mutable by declaring it with `mut`", name)),
+                    });
+                }
+                if entry.ty != value_ty {
+                    return error(
+                        format!(
+                            "`{}` has type {} but is assigned {}",
+                            name, entry.ty, value_ty
+                        ),
+                        value.pos,
+                    );
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.expect_type(cond, Type::Bool)?;
+                self.check_block(body)?;
+            }
+            StmtKind::For { var, lo, hi, body } => {
+                self.expect_type(lo, Type::Int)?;
+                self.expect_type(hi, Type::Int)?;
+                let vars = self.vars.len();
+                self.declare_var(var, Type::Int, false, stmt.pos);
+                self.vars.last_mut().unwrap().loop_var = true;
+                self.check_block(body)?;
+                self.end_scope(vars);
+            }
+            StmtKind::Fn(_) => return self.check_stmts(std::slice::from_ref(stmt)),
+            StmtKind::Expr(expr) => {
+                self.check_expr(expr)?;
+            }
+            StmtKind::Error => {
+                return error("this statement failed to parse".to_string(), stmt.pos)
+            }
+        }
+        return Ok(());
+    }
+
+    fn check_block(&mut self, block: &Block) -> Result<Type, TypeError> {
+        let (vars, fns) = (self.vars.len(), self.fns.len());
+        self.check_stmts(&block.stmts)?;
+        let ty = match &block.result {
+            Some(expr) => self.check_expr(expr)?,
+            None => Type::Unit,
+        };
+        self.end_scope(vars);
+        self.fns.truncate(fns);
+        return Ok(ty);
+    }
+
+    fn expect_type(&mut self, expr: &Expr, expected: Type) -> Result<(), TypeError> {
+        let found = self.check_expr(expr)?;
+        if found != expected {
+            return error(format!("expected {}, found {}", expected, found), expr.pos);
+        }
+        return Ok(());
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Result<Type, TypeError> {
+        let ty = match &expr.kind {
+            ExprKind::Int(_) => Type::Int,
+            ExprKind::Float(_) => Type::Float,
+            ExprKind::Bool(_) => Type::Bool,
+            ExprKind::Str(_) => Type::String,
+            ExprKind::Error => {
+                return error("this expression failed to parse".to_string(), expr.pos)
+            }
+            ExprKind::Var(name) => match self.read_var(name) {
+                Some(entry) => entry.ty,
+                None => return Err(self.undefined_var(name, expr.pos)),
+            },
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let ty = self.check_expr(operand)?;
+                if ty != Type::Int && ty != Type::Float {
+                    return error(format!("cannot negate {}", ty), expr.pos);
+                }
+                ty
+            }
+            ExprKind::Binary(left, op, right) => {
+                let left = self.check_expr(left)?;
+                let right = self.check_expr(right)?;
+                match bin_op_type(left, *op, right) {
+                    Some(ty) => ty,
+                    None => {
+                        return error(
+                            format!("cannot apply `{}` to {} and {}", op, left, right),
+                            expr.pos,
+                        )
+                    }
+                }
+            }
+            ExprKind::Call(name, args) => self.check_call(name, args, expr.pos)?,
+            ExprKind::If {
+                branches,
+                else_block,
+            } => {
+                let mut first = None;
+                for (cond, block) in branches {
+                    self.expect_type(cond, Type::Bool)?;
+                    let block_ty = self.check_block(block)?;
+                    self.unify_branch(&mut first, block_ty, block)?;
+                }
+                let (ty, _) = first.unwrap();
+                match else_block {
+                    Some(block) => {
+                        let block_ty = self.check_block(block)?;
+                        self.unify_branch(&mut first, block_ty, block)?;
+                        ty
+                    }
+                    None if ty == Type::Unit => Type::Unit,
+                    None => {
+                        return Err(TypeError {
+                            message: format!("`if` without `else` must have type (), found {}", ty),
+                            pos: expr.pos,
+                            related: vec![],
+                            help: Some(
+                                "add an `else` branch with a value of the same type".to_string(),
+                            ),
+                        })
+                    }
+                }
+            }
+        };
+        // Every parse gives new expressions, which are checked once
+        let _ = expr.ty.set(ty);
+        return Ok(ty);
+    }
+
+    /// Check that every branch of an `if` has the type of the first, given with where its value
+    /// comes from
+    fn unify_branch(
+        &self,
+        first: &mut Option<(Type, Pos)>,
+        block_ty: Type,
+        block: &Block,
+    ) -> Result<(), TypeError> {
+        let pos = block.result.as_ref().map_or(block.pos, |e| e.pos);
+        match first {
+            Some((ty, first_pos)) if *ty != block_ty => {
+                return related_error(
+                    format!("`if` branches have types {} and {}", ty, block_ty),
+                    pos,
+                    (*first_pos, format!("the first branch has type {}", ty)),
+                );
+            }
+            Some(_) => {}
+            None => *first = Some((block_ty, pos)),
+        }
+        return Ok(());
+    }
+
+    fn check_call(&mut self, name: &str, args: &[Expr], pos: Pos) -> Result<Type, TypeError> {
+        let mut arg_types = Vec::new();
+        for arg in args {
+            arg_types.push(self.check_expr(arg)?);
+        }
+        let fn_type = match self.lookup_fn(name) {
+            Some(fn_type) => fn_type,
+            None if name == "print" => {
+                if let Some(i) = arg_types.iter().position(|ty| *ty == Type::Unit) {
+                    return error("cannot print a value of type ()".to_string(), args[i].pos);
+                }
+                return Ok(Type::Unit);
+            }
+            None => {
+                let declared = self.fns.iter().map(|(n, _)| n.as_str());
+                return Err(undefined("function", name, pos, declared));
+            }
+        };
+        if fn_type.params.len() != args.len() {
+            return error(
+                format!(
+                    "`{}` takes {} arguments but {} were given",
+                    name,
+                    fn_type.params.len(),
+                    args.len()
+                ),
+                pos,
+            );
+        }
+        for ((param, arg_ty), arg) in fn_type.params.iter().zip(&arg_types).zip(args) {
+            if param != arg_ty {
+                return error(format!("expected {}, found {}", param, arg_ty), arg.pos);
+            }
+        }
+        return Ok(fn_type.result);
+    }
+}
+
+/// The type of applying a binary operator to operands of the given types, if it is allowed
+pub fn bin_op_type(left: Type, op: BinOp, right: Type) -> Option<Type> {
+    if left != right {
+        return None;
+    }
+    match (op, left) {
+        (BinOp::Add, Type::Int | Type::Float | Type::String) => return Some(left),
+        (BinOp::Sub | BinOp::Mul | BinOp::Div, Type::Int | Type::Float) => return Some(left),
+        (BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge, Type::Int | Type::Float) => {
+            return Some(Type::Bool)
+        }
+        (BinOp::Eq | BinOp::Neq, Type::Int | Type::Float | Type::Bool | Type::String) => {
+            return Some(Type::Bool)
+        }
+        (BinOp::And | BinOp::Or, Type::Bool) => return Some(Type::Bool),
+        _ => return None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_3::parse;
+
+    fn check_str(input: &str) -> Result<(), String> {
+        return check(&parse(input).unwrap()).map_err(|e| e.message);
+    }
+
+    #[test]
+    fn test_check_records_expression_types() {
+        let program = parse("let x = 1.5 * 2.0; print(x < 1.0);").unwrap();
+        check(&program).unwrap();
+        match &program.stmts[0].kind {
+            StmtKind::Let { init, .. } => assert_eq!(init.ty(), Type::Float),
+            other => panic!("{:?}", other),
+        }
+        match &program.stmts[1].kind {
+            StmtKind::Expr(expr) => assert_eq!(expr.ty(), Type::Unit),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_valid_programs() {
+        assert_eq!(
+            check_str(
+                "fn fact(n: int): int { if n == 0 { 1 } else { n * fact(n - 1) } }
+                 fn even(n: int): bool { if n == 0 { True } else { odd(n - 1) } }
+                 fn odd(n: int): bool { if n == 0 { False } else { even(n - 1) } }
+                 let mut s = \"a\" + \"b\";
+                 for i = 0 : fact(3) { s = s + \"c\"; }
+                 while even(2) & s <> \"\" { s = \"\"; }
+                 print(fact(5), even(4), s, 1.5);"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_check_binary_operators() {
+        assert_eq!(
+            bin_op_type(Type::Int, BinOp::Add, Type::Int),
+            Some(Type::Int)
+        );
+        assert_eq!(
+            bin_op_type(Type::String, BinOp::Add, Type::String),
+            Some(Type::String)
+        );
+        assert_eq!(bin_op_type(Type::String, BinOp::Sub, Type::String), None);
+        assert_eq!(bin_op_type(Type::Int, BinOp::Add, Type::Float), None);
+        assert_eq!(bin_op_type(Type::Bool, BinOp::Lt, Type::Bool), None);
+        assert_eq!(
+            bin_op_type(Type::Bool, BinOp::Eq, Type::Bool),
+            Some(Type::Bool)
+        );
+        assert_eq!(bin_op_type(Type::Int, BinOp::And, Type::Int), None);
+    }
+
+    #[test]
+    fn test_check_errors() {
+        assert_eq!(
+            check_str("let x = 1; x = 2;"),
+            Err("cannot assign twice to immutable variable `x`".to_string())
+        );
+        assert_eq!(
+            check_str("let mut x = 1; x = 2.0;"),
+            Err("`x` has type int but is assigned float".to_string())
+        );
+        assert_eq!(
+            check_str("let x: bool = 1;"),
+            Err("`x` is declared bool but given int".to_string())
+        );
+        assert_eq!(
+            check_str("print(1 + 2.0);"),
+            Err("cannot apply `+` to int and float".to_string())
+        );
+        assert_eq!(
+            check_str("if 1 { }"),
+            Err("expected bool, found int".to_string())
+        );
+        assert_eq!(
+            check_str("let x = if True { 1 } else { False };"),
+            Err("`if` branches have types int and bool".to_string())
+        );
+        assert_eq!(
+            check_str("let x = if True { 1 };"),
+            Err("`if` without `else` must have type (), found int".to_string())
+        );
+        assert_eq!(
+            check_str("fn f(): int { True }"),
+            Err("`f` should return int but its body has type bool".to_string())
+        );
+        assert_eq!(
+            check_str("fn f(a: int) { } f(1, 2);"),
+            Err("`f` takes 1 arguments but 2 were given".to_string())
+        );
+        assert_eq!(
+            check_str("fn f(a: int) { } f(True);"),
+            Err("expected int, found bool".to_string())
+        );
+        assert_eq!(
+            check_str("print(y);"),
+            Err("undefined variable `y`".to_string())
+        );
+        assert_eq!(
+            check_str("fn f() { } let x = f();"),
+            Err("`x` cannot hold a value of type ()".to_string())
+        );
+        assert_eq!(
+            check_str("for i = 0 : 3 { i = 1; }"),
+            Err("cannot assign twice to immutable variable `i`".to_string())
+        );
+        assert_eq!(
+            check_str("fn f() { } fn f() { }"),
+            Err("`f` is declared twice in the same group".to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_errors_explain_themselves() {
+        let error = |input: &str| check(&parse(input).unwrap()).unwrap_err();
+        let immutable = error("let x = 1; x = 2;");
+        assert_eq!(immutable.pos, 11);
+        assert_eq!(immutable.related, [(0, "`x` is declared here".to_string())]);
+        assert_eq!(
+            immutable.help.as_deref(),
+            Some("make `x` mutable by declaring it with `mut`")
+        );
+        assert_eq!(error("for i = 0 : 2 { i = 1; }").help, None);
+        let branches = error("let x = if True { 1 } else { False };");
+        assert_eq!(
+            branches.related,
+            [(18, "the first branch has type int".to_string())]
+        );
+        assert_eq!(
+            error("fn f(a: int, a: int): int { a }").related,
+            [(5, "first declared here".to_string())]
+        );
+        assert_eq!(
+            error("let count = 1; print(cuont);").help.as_deref(),
+            Some("a variable with a similar name exists: `count`")
+        );
+        assert_eq!(
+            error("fn square(n: int): int { n * n } print(sqare(2));")
+                .help
+                .as_deref(),
+            Some("a function with a similar name exists: `square`")
+        );
+        assert_eq!(error("let count = 1; print(x);").help, None);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("cuont", "count"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    #[test]
+    fn test_check_scoping() {
+        assert_eq!(
+            check_str("fn f() { let x = 1; } print(x);"),
+            Err("undefined variable `x`".to_string())
+        );
+        assert_eq!(
+            check_str("fn f() { fn g() { } } g();"),
+            Err("undefined function `g`".to_string())
+        );
+        assert_eq!(
+            check_str("let x = 1; fn f(): bool { let x = True; x }"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_check_warns_about_unused_variables() {
+        let source = "fn f(a: int, _b: int): int { let c = a; for i = 0 : 3 { } a }
+let x = 1; let y = f(x, 2); let _z = 3;";
+        let warnings: Vec<(String, Pos)> = check_with_warnings(&parse(source).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|warning| (warning.message, warning.pos))
+            .collect();
+        let at = |piece: &str| source.find(piece).unwrap();
+        assert_eq!(
+            warnings,
+            [
+                ("unused variable `c`".to_string(), at("let c")),
+                ("unused variable `i`".to_string(), at("for i")),
+                ("unused variable `y`".to_string(), at("let y")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_checker_keeps_top_level_bindings() {
+        let mut checker = TypeChecker::new();
+        checker
+            .check_program(&parse("let x = 1; fn f(a: int): float { 1.0 }").unwrap())
+            .unwrap();
+        assert_eq!(checker.var_type("x"), Some(Type::Int));
+        assert_eq!(
+            checker.fn_type("f"),
+            Some(&FnType {
+                params: vec![Type::Int],
+                result: Type::Float
+            })
+        );
+        assert!(checker
+            .check_program(&parse("print(x + 1, f(2));").unwrap())
+            .is_ok());
+    }
+}
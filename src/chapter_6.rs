@@ -0,0 +1,605 @@
+/// Implementations described in Chapter 6: escape analysis, frames and nesting levels
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::chapter_4::*;
+
+/// The declarations that escape, by the position of the `let` or `for` statement or parameter
+/// that declares them
+#[derive(Debug, Default)]
+pub struct Escapes(HashSet<Pos>);
+
+impl Escapes {
+    /// Whether the variable declared at `pos` escapes
+    pub fn escapes(&self, pos: Pos) -> bool {
+        return self.0.contains(&pos);
+    }
+}
+
+/// A variable in scope during escape analysis: its name, the function nesting depth it was
+/// declared at, and the position of its declaration
+struct EscapeEntry<'a> {
+    name: &'a str,
+    depth: usize,
+    pos: Pos,
+}
+
+/// Find which variables escape, as a table of the declarations in the program that do
+///
+/// A variable escapes when a function nested inside the one declaring it uses it, since the
+/// nested function reaches it through the static link and so it has to live in the frame. The
+/// language has no address-of operator, so that is the only way for a variable to escape.
+pub fn find_escape(program: &Program) -> Escapes {
+    let mut finder = EscapeFinder {
+        env: Vec::new(),
+        depth: 0,
+        escapes: Escapes::default(),
+    };
+    walk_program(&mut finder, program);
+    return finder.escapes;
+}
+
+/// The variables in scope while walking the program, and the escapes found so far
+struct EscapeFinder<'a> {
+    env: Vec<EscapeEntry<'a>>,
+    /// How many functions deep the walk is
+    depth: usize,
+    escapes: Escapes,
+}
+
+impl<'a> EscapeFinder<'a> {
+    /// Mark `name` as escaping if it is used deeper than it was declared
+    fn use_var(&mut self, name: &str) {
+        if let Some(entry) = self.env.iter().rev().find(|entry| entry.name == name) {
+            if entry.depth < self.depth {
+                self.escapes.0.insert(entry.pos);
+            }
+        }
+    }
+
+    fn declare_var(&mut self, name: &'a str, pos: Pos) {
+        let depth = self.depth;
+        self.env.push(EscapeEntry { name, depth, pos });
+    }
+}
+
+impl<'a> Visitor<'a> for EscapeFinder<'a> {
+    fn visit_stmt(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { name, init, .. } => {
+                self.visit_expr(init);
+                self.declare_var(name, stmt.pos);
+            }
+            StmtKind::Assign { name, .. } => {
+                self.use_var(name);
+                walk_stmt(self, stmt);
+            }
+            StmtKind::For { var, lo, hi, body } => {
+                self.visit_expr(lo);
+                self.visit_expr(hi);
+                let scope = self.env.len();
+                self.declare_var(var, stmt.pos);
+                self.visit_block(body);
+                self.env.truncate(scope);
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_fn_decl(&mut self, decl: &'a FnDecl) {
+        let scope = self.env.len();
+        self.depth += 1;
+        for param in &decl.params {
+            self.declare_var(&param.name, param.pos);
+        }
+        walk_fn_decl(self, decl);
+        self.depth -= 1;
+        self.env.truncate(scope);
+    }
+
+    fn visit_block(&mut self, block: &'a Block) {
+        let scope = self.env.len();
+        walk_block(self, block);
+        self.env.truncate(scope);
+    }
+
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        if let ExprKind::Var(name) = &expr.kind {
+            self.use_var(name);
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// The first number handed out for a new temporary. Lower numbers name machine registers
+pub const FIRST_TEMP: usize = 100;
+
+thread_local! {
+    static NEXT_TEMP: Cell<usize> = const { Cell::new(FIRST_TEMP) };
+    static NEXT_LABEL: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A value held in a register. Temporaries are unlimited until register allocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Temp(pub usize);
+
+impl Temp {
+    /// Create a temporary that has never been used before
+    pub fn new() -> Temp {
+        return Temp(NEXT_TEMP.with(|next| next.replace(next.get() + 1)));
+    }
+}
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "t{}", self.0);
+    }
+}
+
+/// A machine-language location whose address is yet to be decided
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label(pub String);
+
+impl Label {
+    /// Create a label that has never been used before
+    pub fn new() -> Label {
+        return Label(format!(
+            "L{}",
+            NEXT_LABEL.with(|next| next.replace(next.get() + 1))
+        ));
+    }
+
+    /// Create a label with a given name, such as the name of a function
+    pub fn named(name: &str) -> Label {
+        return Label(name.to_string());
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.0);
+    }
+}
+
+/// Where a formal parameter or local variable lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    /// In memory at an offset from the frame pointer
+    InFrame(i64),
+    /// In a register
+    InReg(Temp),
+}
+
+/// The activation record of one function on a target machine
+pub trait Frame: Clone {
+    /// Size in bytes of a machine word
+    const WORD_SIZE: i64;
+
+    /// Create a frame for a function given whether each of its formals escapes
+    fn new(name: Label, formals: &[bool]) -> Self;
+
+    fn name(&self) -> &Label;
+
+    /// Where each formal is seen from inside the function
+    fn formals(&self) -> &[Access];
+
+    /// Allocate a new local variable, in the frame if it escapes
+    fn alloc_local(&mut self, escape: bool) -> Access;
+
+    /// The register holding the frame pointer
+    fn fp() -> Temp;
+
+    /// The register a function leaves its result in
+    fn rv() -> Temp;
+}
+
+/// A frame for x86-64 using the System V calling convention
+///
+/// The frame pointer is `%rbp`. The first six arguments arrive in registers, the rest on the
+/// stack above the return address. Locals are allocated downwards from the frame pointer.
+#[derive(Debug, Clone)]
+pub struct X86_64Frame {
+    name: Label,
+    formals: Vec<Access>,
+    locals: i64,
+}
+
+impl X86_64Frame {
+    pub const RAX: Temp = Temp(0);
+    pub const RBX: Temp = Temp(1);
+    pub const RCX: Temp = Temp(2);
+    pub const RDX: Temp = Temp(3);
+    pub const RSI: Temp = Temp(4);
+    pub const RDI: Temp = Temp(5);
+    pub const RBP: Temp = Temp(6);
+    pub const RSP: Temp = Temp(7);
+    pub const R8: Temp = Temp(8);
+    pub const R9: Temp = Temp(9);
+    pub const R10: Temp = Temp(10);
+    pub const R11: Temp = Temp(11);
+    pub const R12: Temp = Temp(12);
+    pub const R13: Temp = Temp(13);
+    pub const R14: Temp = Temp(14);
+    pub const R15: Temp = Temp(15);
+
+    /// Registers the first arguments are passed in, in order
+    pub const ARG_REGS: [Temp; 6] = [
+        X86_64Frame::RDI,
+        X86_64Frame::RSI,
+        X86_64Frame::RDX,
+        X86_64Frame::RCX,
+        X86_64Frame::R8,
+        X86_64Frame::R9,
+    ];
+
+    /// Registers a call may overwrite
+    pub const CALLER_SAVES: [Temp; 9] = [
+        X86_64Frame::RAX,
+        X86_64Frame::RCX,
+        X86_64Frame::RDX,
+        X86_64Frame::RSI,
+        X86_64Frame::RDI,
+        X86_64Frame::R8,
+        X86_64Frame::R9,
+        X86_64Frame::R10,
+        X86_64Frame::R11,
+    ];
+
+    /// Registers a function must give back to its caller unchanged
+    pub const CALLEE_SAVES: [Temp; 5] = [
+        X86_64Frame::RBX,
+        X86_64Frame::R12,
+        X86_64Frame::R13,
+        X86_64Frame::R14,
+        X86_64Frame::R15,
+    ];
+
+    /// Registers the allocator may give temporaries: all but `%rsp` and `%rbp`, caller-save ones
+    /// first
+    pub const REGISTERS: [Temp; 14] = [
+        X86_64Frame::RAX,
+        X86_64Frame::RCX,
+        X86_64Frame::RDX,
+        X86_64Frame::RSI,
+        X86_64Frame::RDI,
+        X86_64Frame::R8,
+        X86_64Frame::R9,
+        X86_64Frame::R10,
+        X86_64Frame::R11,
+        X86_64Frame::RBX,
+        X86_64Frame::R12,
+        X86_64Frame::R13,
+        X86_64Frame::R14,
+        X86_64Frame::R15,
+    ];
+
+    /// Every machine register, as precoloured temporaries
+    pub const PRECOLORED: [Temp; 16] = [
+        X86_64Frame::RAX,
+        X86_64Frame::RBX,
+        X86_64Frame::RCX,
+        X86_64Frame::RDX,
+        X86_64Frame::RSI,
+        X86_64Frame::RDI,
+        X86_64Frame::RBP,
+        X86_64Frame::RSP,
+        X86_64Frame::R8,
+        X86_64Frame::R9,
+        X86_64Frame::R10,
+        X86_64Frame::R11,
+        X86_64Frame::R12,
+        X86_64Frame::R13,
+        X86_64Frame::R14,
+        X86_64Frame::R15,
+    ];
+
+    /// Assembly names of the registers, indexed by their temporary
+    pub const REGISTER_NAMES: [&'static str; 16] = [
+        "%rax", "%rbx", "%rcx", "%rdx", "%rsi", "%rdi", "%rbp", "%rsp", "%r8", "%r9", "%r10",
+        "%r11", "%r12", "%r13", "%r14", "%r15",
+    ];
+
+    /// The assembly name of a temporary that is a machine register
+    pub fn register_name(t: Temp) -> Option<&'static str> {
+        return X86_64Frame::REGISTER_NAMES.get(t.0).copied();
+    }
+
+    /// Bytes of the frame used by locals allocated so far
+    pub fn locals_size(&self) -> i64 {
+        return self.locals * X86_64Frame::WORD_SIZE;
+    }
+
+    fn alloc_in_frame(&mut self) -> Access {
+        self.locals += 1;
+        return Access::InFrame(-self.locals * X86_64Frame::WORD_SIZE);
+    }
+}
+
+impl Frame for X86_64Frame {
+    const WORD_SIZE: i64 = 8;
+
+    fn new(name: Label, formals: &[bool]) -> X86_64Frame {
+        let mut frame = X86_64Frame {
+            name,
+            formals: Vec::new(),
+            locals: 0,
+        };
+        for (i, escape) in formals.iter().enumerate() {
+            let access = if i >= X86_64Frame::ARG_REGS.len() {
+                // Above the saved frame pointer and the return address
+                let stack_index = (i - X86_64Frame::ARG_REGS.len()) as i64;
+                Access::InFrame(2 * X86_64Frame::WORD_SIZE + stack_index * X86_64Frame::WORD_SIZE)
+            } else {
+                frame.alloc_local(*escape)
+            };
+            frame.formals.push(access);
+        }
+        return frame;
+    }
+
+    fn name(&self) -> &Label {
+        return &self.name;
+    }
+
+    fn formals(&self) -> &[Access] {
+        return &self.formals;
+    }
+
+    fn alloc_local(&mut self, escape: bool) -> Access {
+        if escape {
+            return self.alloc_in_frame();
+        }
+        return Access::InReg(Temp::new());
+    }
+
+    fn fp() -> Temp {
+        return X86_64Frame::RBP;
+    }
+
+    fn rv() -> Temp {
+        return X86_64Frame::RAX;
+    }
+}
+
+/// The nesting level of a function, wrapping its frame for the translation to intermediate code
+///
+/// Every level but the outermost receives a static link as a hidden first formal: the frame
+/// pointer of the function it is declared inside.
+pub struct Level<F: Frame> {
+    pub parent: Option<Rc<Level<F>>>,
+    pub frame: RefCell<F>,
+}
+
+/// A variable together with the level it was declared at
+pub struct LevelAccess<F: Frame> {
+    pub level: Rc<Level<F>>,
+    pub access: Access,
+}
+
+impl<F: Frame> Clone for LevelAccess<F> {
+    fn clone(&self) -> LevelAccess<F> {
+        return LevelAccess {
+            level: self.level.clone(),
+            access: self.access,
+        };
+    }
+}
+
+impl<F: Frame> Level<F> {
+    /// The level of the implicit main function, which has no static link
+    pub fn outermost(name: Label) -> Rc<Level<F>> {
+        return Rc::new(Level {
+            parent: None,
+            frame: RefCell::new(F::new(name, &[])),
+        });
+    }
+
+    /// A level for a function declared inside `parent`, given whether each formal escapes
+    pub fn new(parent: &Rc<Level<F>>, name: Label, formals: &[bool]) -> Rc<Level<F>> {
+        let mut escapes = vec![true];
+        escapes.extend_from_slice(formals);
+        return Rc::new(Level {
+            parent: Some(parent.clone()),
+            frame: RefCell::new(F::new(name, &escapes)),
+        });
+    }
+
+    /// The formals as declared in the source, without the static link
+    pub fn formals(self: &Rc<Level<F>>) -> Vec<LevelAccess<F>> {
+        let skip = if self.parent.is_some() { 1 } else { 0 };
+        return self.frame.borrow().formals()[skip..]
+            .iter()
+            .map(|access| LevelAccess {
+                level: self.clone(),
+                access: *access,
+            })
+            .collect();
+    }
+
+    /// Where this level's static link is kept
+    pub fn static_link(&self) -> Option<Access> {
+        self.parent.as_ref()?;
+        return Some(self.frame.borrow().formals()[0]);
+    }
+
+    pub fn alloc_local(self: &Rc<Level<F>>, escape: bool) -> LevelAccess<F> {
+        return LevelAccess {
+            level: self.clone(),
+            access: self.frame.borrow_mut().alloc_local(escape),
+        };
+    }
+
+    /// The static links to follow from this level to reach the frame of `target`, which must
+    /// enclose this level. Each entry is where the next link is found in the current frame
+    pub fn static_links_to(self: &Rc<Level<F>>, target: &Rc<Level<F>>) -> Vec<Access> {
+        let mut links = Vec::new();
+        let mut level = self.clone();
+        while !Rc::ptr_eq(&level, target) {
+            links.push(
+                level
+                    .static_link()
+                    .expect("target level does not enclose this level"),
+            );
+            level = level.parent.clone().unwrap();
+        }
+        return links;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_3::parse;
+
+    /// Run escape analysis and list every declaration as (name, escapes) in source order
+    fn escapes(input: &str) -> Vec<(String, bool)> {
+        let program = parse(input).unwrap();
+        let escapes = find_escape(&program);
+        let mut found = Vec::new();
+        collect_stmts(&escapes, &program.stmts, &mut found);
+        return found;
+    }
+
+    fn collect_stmts(escapes: &Escapes, stmts: &[Stmt], found: &mut Vec<(String, bool)>) {
+        for stmt in stmts {
+            match &stmt.kind {
+                StmtKind::Let { name, .. } => found.push((name.clone(), escapes.escapes(stmt.pos))),
+                StmtKind::For { var, body, .. } => {
+                    found.push((var.clone(), escapes.escapes(stmt.pos)));
+                    collect_stmts(escapes, &body.stmts, found);
+                }
+                StmtKind::While { body, .. } => collect_stmts(escapes, &body.stmts, found),
+                StmtKind::Fn(decl) => {
+                    for param in &decl.params {
+                        found.push((param.name.clone(), escapes.escapes(param.pos)));
+                    }
+                    collect_stmts(escapes, &decl.body.stmts, found);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn expect(pairs: &[(&str, bool)]) -> Vec<(String, bool)> {
+        return pairs
+            .iter()
+            .map(|(name, escape)| (name.to_string(), *escape))
+            .collect();
+    }
+
+    #[test]
+    fn test_find_escape_locals_do_not_escape() {
+        assert_eq!(
+            escapes("fn f(a: int): int { let b = a + 1; b } let c = f(1);"),
+            expect(&[("a", false), ("b", false), ("c", false)])
+        );
+    }
+
+    #[test]
+    fn test_find_escape_nested_capture() {
+        assert_eq!(
+            escapes(
+                "fn outer(a: int, b: int): int {
+                     let mut c = 0;
+                     let d = 1;
+                     fn inner() { c = a + d; }
+                     inner();
+                     c + b
+                 }"
+            ),
+            expect(&[("a", true), ("b", false), ("c", true), ("d", true)])
+        );
+    }
+
+    #[test]
+    fn test_find_escape_deeply_nested_capture() {
+        assert_eq!(
+            escapes(
+                "let x = 1;
+                 fn f() {
+                     let y = 2;
+                     fn g() { fn h() { print(x + y); } h(); }
+                     g();
+                 }"
+            ),
+            expect(&[("x", true), ("y", true)])
+        );
+    }
+
+    #[test]
+    fn test_find_escape_shadowing() {
+        assert_eq!(
+            escapes("let x = 1; fn f() { let x = 2; print(x); } print(x);"),
+            expect(&[("x", false), ("x", false)])
+        );
+    }
+
+    #[test]
+    fn test_find_escape_loop_variable() {
+        assert_eq!(
+            escapes("for i = 0 : 3 { fn show() { print(i); } show(); }"),
+            expect(&[("i", true)])
+        );
+    }
+
+    #[test]
+    fn test_new_temps_and_labels_are_fresh() {
+        let (a, b) = (Temp::new(), Temp::new());
+        assert_ne!(a, b);
+        assert!(a.0 >= FIRST_TEMP);
+        assert_ne!(Label::new(), Label::new());
+        assert_eq!(Label::named("main").to_string(), "main");
+    }
+
+    #[test]
+    fn test_x86_64_frame_formals() {
+        let frame = X86_64Frame::new(
+            Label::named("f"),
+            &[true, false, true, false, false, false, true, false],
+        );
+        let formals = frame.formals();
+        assert_eq!(formals[0], Access::InFrame(-8));
+        assert!(matches!(formals[1], Access::InReg(_)));
+        assert_eq!(formals[2], Access::InFrame(-16));
+        assert_eq!(formals[6], Access::InFrame(16));
+        assert_eq!(formals[7], Access::InFrame(24));
+        assert_eq!(frame.locals_size(), 16);
+    }
+
+    #[test]
+    fn test_x86_64_frame_alloc_local() {
+        let mut frame = X86_64Frame::new(Label::named("f"), &[true]);
+        assert_eq!(frame.alloc_local(true), Access::InFrame(-16));
+        assert!(matches!(frame.alloc_local(false), Access::InReg(_)));
+        assert_eq!(frame.alloc_local(true), Access::InFrame(-24));
+        assert_eq!(X86_64Frame::fp(), X86_64Frame::RBP);
+        assert_eq!(X86_64Frame::WORD_SIZE, 8);
+    }
+
+    #[test]
+    fn test_level_static_link_is_hidden_formal() {
+        let main = Level::<X86_64Frame>::outermost(Label::named("main"));
+        assert_eq!(main.static_link(), None);
+        let f = Level::new(&main, Label::named("f"), &[false, true]);
+        assert_eq!(f.static_link(), Some(Access::InFrame(-8)));
+        let formals = f.formals();
+        assert_eq!(formals.len(), 2);
+        assert!(matches!(formals[0].access, Access::InReg(_)));
+        assert_eq!(formals[1].access, Access::InFrame(-16));
+    }
+
+    #[test]
+    fn test_level_static_links_to_enclosing_levels() {
+        let main = Level::<X86_64Frame>::outermost(Label::named("main"));
+        let f = Level::new(&main, Label::named("f"), &[]);
+        let g = Level::new(&f, Label::named("g"), &[true]);
+        assert_eq!(g.static_links_to(&g), vec![]);
+        assert_eq!(g.static_links_to(&f), vec![Access::InFrame(-8)]);
+        assert_eq!(
+            g.static_links_to(&main),
+            vec![Access::InFrame(-8), Access::InFrame(-8)]
+        );
+    }
+}
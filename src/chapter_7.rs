@@ -0,0 +1,894 @@
+/// Implementations described in Chapter 7: translation to intermediate code
+use std::fmt;
+use std::rc::Rc;
+
+use crate::chapter_4 as ast;
+use crate::chapter_4::{Block, Expr, ExprKind, FnDecl, Program, Stmt, StmtKind, Type, UnOp};
+use crate::chapter_6::*;
+
+/// An expression of the intermediate representation tree, which computes a value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exp {
+    Const(i64),
+    Name(Label),
+    Temp(Temp),
+    BinOp(BinOp, Box<Exp>, Box<Exp>),
+    /// The word of memory at an address
+    Mem(Box<Exp>),
+    Call(Box<Exp>, Vec<Exp>),
+    /// Run the statement for its side effects, then compute the expression
+    Eseq(Box<Stm>, Box<Exp>),
+}
+
+/// A statement of the intermediate representation tree, which performs side effects
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stm {
+    /// Store into a `Temp` or a `Mem`
+    Move(Box<Exp>, Box<Exp>),
+    /// Compute an expression and throw away its value
+    Exp(Box<Exp>),
+    /// Jump to an address, which must be one of the listed labels
+    Jump(Box<Exp>, Vec<Label>),
+    /// Compare two values and jump to the first label if the comparison holds, else the second
+    CJump(RelOp, Box<Exp>, Box<Exp>, Label, Label),
+    Seq(Box<Stm>, Box<Stm>),
+    Label(Label),
+}
+
+/// Integer operators of the tree. Translation makes only the arithmetic ones, the bitwise ones
+/// are there for the instruction selectors and the IR interpreter to cover the whole tree language
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Plus,
+    Minus,
+    Mul,
+    Div,
+    And,
+    Or,
+    Xor,
+    LShift,
+    RShift,
+    ARShift,
+}
+
+/// Comparisons of the tree. The `U` variants compare as unsigned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelOp {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    ULt,
+    UGt,
+    ULe,
+    UGe,
+}
+
+impl RelOp {
+    /// The comparison that holds exactly when this one does not
+    pub fn not(self) -> RelOp {
+        match self {
+            RelOp::Eq => return RelOp::Ne,
+            RelOp::Ne => return RelOp::Eq,
+            RelOp::Lt => return RelOp::Ge,
+            RelOp::Gt => return RelOp::Le,
+            RelOp::Le => return RelOp::Gt,
+            RelOp::Ge => return RelOp::Lt,
+            RelOp::ULt => return RelOp::UGe,
+            RelOp::UGt => return RelOp::ULe,
+            RelOp::ULe => return RelOp::UGt,
+            RelOp::UGe => return RelOp::ULt,
+        }
+    }
+
+    /// The comparison that holds for swapped operands
+    #[allow(dead_code)]
+    pub fn commute(self) -> RelOp {
+        match self {
+            RelOp::Eq => return RelOp::Eq,
+            RelOp::Ne => return RelOp::Ne,
+            RelOp::Lt => return RelOp::Gt,
+            RelOp::Gt => return RelOp::Lt,
+            RelOp::Le => return RelOp::Ge,
+            RelOp::Ge => return RelOp::Le,
+            RelOp::ULt => return RelOp::UGt,
+            RelOp::UGt => return RelOp::ULt,
+            RelOp::ULe => return RelOp::UGe,
+            RelOp::UGe => return RelOp::ULe,
+        }
+    }
+}
+
+/// Join statements into a single statement which runs them in order
+pub fn seq(stms: Vec<Stm>) -> Stm {
+    let mut stms = stms.into_iter().rev();
+    let mut result = match stms.next() {
+        Some(stm) => stm,
+        None => return Stm::Exp(Box::new(Exp::Const(0))),
+    };
+    for stm in stms {
+        result = Stm::Seq(Box::new(stm), Box::new(result));
+    }
+    return result;
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", format!("{:?}", self).to_uppercase());
+    }
+}
+
+impl fmt::Display for RelOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", format!("{:?}", self).to_uppercase());
+    }
+}
+
+impl fmt::Display for Exp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exp::Const(i) => return write!(f, "CONST {}", i),
+            Exp::Name(label) => return write!(f, "NAME {}", label),
+            Exp::Temp(temp) => return write!(f, "TEMP {}", temp),
+            Exp::BinOp(op, l, r) => return write!(f, "BINOP({}, {}, {})", op, l, r),
+            Exp::Mem(e) => return write!(f, "MEM({})", e),
+            Exp::Call(func, args) => {
+                write!(f, "CALL({}", func)?;
+                for arg in args {
+                    write!(f, ", {}", arg)?;
+                }
+                return write!(f, ")");
+            }
+            Exp::Eseq(s, e) => return write!(f, "ESEQ({}, {})", s, e),
+        }
+    }
+}
+
+impl fmt::Display for Stm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stm::Move(dst, src) => return write!(f, "MOVE({}, {})", dst, src),
+            Stm::Exp(e) => return write!(f, "EXP({})", e),
+            Stm::Jump(e, _) => return write!(f, "JUMP({})", e),
+            Stm::CJump(op, l, r, t, fl) => {
+                return write!(f, "CJUMP({}, {}, {}, {}, {})", op, l, r, t, fl)
+            }
+            Stm::Seq(a, b) => return write!(f, "SEQ({}, {})", a, b),
+            Stm::Label(label) => return write!(f, "LABEL {}", label),
+        }
+    }
+}
+
+/// Label of the implicit main function holding the top level of a program
+pub const PROGRAM_MAIN: &str = "program_main";
+
+// Functions of the runtime library. Floats are passed and returned as their bits in an integer
+// register and strings as the address of a word holding the length followed by the bytes.
+pub const PRINT_INT: &str = "print_int";
+pub const PRINT_FLOAT: &str = "print_float";
+pub const PRINT_BOOL: &str = "print_bool";
+pub const PRINT_STRING: &str = "print_string";
+pub const STRING_CONCAT: &str = "string_concat";
+pub const STRING_EQUAL: &str = "string_equal";
+pub const FLOAT_ADD: &str = "float_add";
+pub const FLOAT_SUB: &str = "float_sub";
+pub const FLOAT_MUL: &str = "float_mul";
+pub const FLOAT_DIV: &str = "float_div";
+pub const FLOAT_NEG: &str = "float_neg";
+pub const FLOAT_EQ: &str = "float_eq";
+pub const FLOAT_NE: &str = "float_ne";
+pub const FLOAT_LT: &str = "float_lt";
+pub const FLOAT_LE: &str = "float_le";
+pub const FLOAT_GT: &str = "float_gt";
+pub const FLOAT_GE: &str = "float_ge";
+pub const DIV_BY_ZERO: &str = "div_by_zero";
+
+/// A piece of the translated program: a function body or a string literal
+#[derive(Debug, Clone)]
+pub enum Frag<F: Frame> {
+    Proc { body: Stm, frame: F },
+    Str(Label, String),
+}
+
+/// The translation of an expression, kept in the most useful form until its use is known
+enum TrExp {
+    /// An expression computing a value
+    Ex(Exp),
+    /// A statement computing no value
+    Nx(Stm),
+    /// A conditional, given the labels to jump to when it is true and when it is false
+    Cx(Box<dyn FnOnce(Label, Label) -> Stm>),
+}
+
+fn un_ex(exp: TrExp) -> Exp {
+    match exp {
+        TrExp::Ex(e) => return e,
+        TrExp::Nx(s) => return Exp::Eseq(Box::new(s), Box::new(Exp::Const(0))),
+        TrExp::Cx(gen_stm) => {
+            let r = Temp::new();
+            let t = Label::new();
+            let f = Label::new();
+            return Exp::Eseq(
+                Box::new(seq(vec![
+                    Stm::Move(Box::new(Exp::Temp(r)), Box::new(Exp::Const(1))),
+                    gen_stm(t.clone(), f.clone()),
+                    Stm::Label(f),
+                    Stm::Move(Box::new(Exp::Temp(r)), Box::new(Exp::Const(0))),
+                    Stm::Label(t),
+                ])),
+                Box::new(Exp::Temp(r)),
+            );
+        }
+    }
+}
+
+fn un_nx(exp: TrExp) -> Stm {
+    match exp {
+        TrExp::Ex(e) => return Stm::Exp(Box::new(e)),
+        TrExp::Nx(s) => return s,
+        TrExp::Cx(gen_stm) => {
+            let l = Label::new();
+            return seq(vec![gen_stm(l.clone(), l.clone()), Stm::Label(l)]);
+        }
+    }
+}
+
+fn un_cx(exp: TrExp) -> Box<dyn FnOnce(Label, Label) -> Stm> {
+    match exp {
+        TrExp::Ex(Exp::Const(0)) => return Box::new(|_, f| jump(f)),
+        TrExp::Ex(Exp::Const(_)) => return Box::new(|t, _| jump(t)),
+        TrExp::Ex(e) => {
+            return Box::new(|t, f| {
+                Stm::CJump(RelOp::Ne, Box::new(e), Box::new(Exp::Const(0)), t, f)
+            })
+        }
+        TrExp::Nx(_) => panic!("a statement cannot be used as a condition"),
+        TrExp::Cx(gen_stm) => return gen_stm,
+    }
+}
+
+fn jump(label: Label) -> Stm {
+    return Stm::Jump(Box::new(Exp::Name(label.clone())), vec![label]);
+}
+
+fn external_call(name: &str, args: Vec<Exp>) -> Exp {
+    return Exp::Call(Box::new(Exp::Name(Label::named(name))), args);
+}
+
+/// The expression reading an access, given the address of the frame it is in
+pub fn access_exp(access: Access, fp: Exp) -> Exp {
+    match access {
+        Access::InFrame(k) => {
+            return Exp::Mem(Box::new(Exp::BinOp(
+                BinOp::Plus,
+                Box::new(fp),
+                Box::new(Exp::Const(k)),
+            )))
+        }
+        Access::InReg(t) => return Exp::Temp(t),
+    }
+}
+
+enum EnvEntry<F: Frame> {
+    Var(String, LevelAccess<F>),
+    Fn(String, Rc<Level<F>>, Label),
+}
+
+/// Translate a type checked program into fragments for the target `F`
+///
+/// The implicit main function is the fragment labelled `PROGRAM_MAIN`.
+pub fn translate<F: Frame>(program: &Program) -> Vec<Frag<F>> {
+    let mut translator = Translator {
+        escapes: find_escape(program),
+        frags: Vec::new(),
+        env: Vec::new(),
+    };
+    let level = Level::<F>::outermost(Label::named(PROGRAM_MAIN));
+    let body = seq(translator.tr_stmts(&program.stmts, &level));
+    translator.proc_entry_exit(&level, TrExp::Nx(body), Type::Unit);
+    return translator.frags;
+}
+
+struct Translator<F: Frame> {
+    /// The declarations that escape, found in Chapter 6
+    escapes: Escapes,
+    frags: Vec<Frag<F>>,
+    env: Vec<EnvEntry<F>>,
+}
+
+impl<F: Frame> Translator<F> {
+    /// Record a finished function body as a fragment
+    fn proc_entry_exit(&mut self, level: &Rc<Level<F>>, body: TrExp, result: Type) {
+        let body = if result == Type::Unit {
+            un_nx(body)
+        } else {
+            Stm::Move(Box::new(Exp::Temp(F::rv())), Box::new(un_ex(body)))
+        };
+        self.frags.push(Frag::Proc {
+            body,
+            frame: level.frame.borrow().clone(),
+        });
+    }
+
+    fn lookup_var(&self, name: &str) -> LevelAccess<F> {
+        for entry in self.env.iter().rev() {
+            if let EnvEntry::Var(n, access) = entry {
+                if n == name {
+                    return access.clone();
+                }
+            }
+        }
+        panic!("undefined variable `{}` after type checking", name);
+    }
+
+    fn lookup_fn(&self, name: &str) -> Option<(Rc<Level<F>>, Label)> {
+        for entry in self.env.iter().rev() {
+            if let EnvEntry::Fn(n, level, label) = entry {
+                if n == name {
+                    return Some((level.clone(), label.clone()));
+                }
+            }
+        }
+        return None;
+    }
+
+    /// The address of the frame of `target`, as seen from code running at `level`
+    fn frame_address(&self, level: &Rc<Level<F>>, target: &Rc<Level<F>>) -> Exp {
+        let mut fp = Exp::Temp(F::fp());
+        for link in level.static_links_to(target) {
+            fp = access_exp(link, fp);
+        }
+        return fp;
+    }
+
+    fn simple_var(&self, var: &LevelAccess<F>, level: &Rc<Level<F>>) -> Exp {
+        return access_exp(var.access, self.frame_address(level, &var.level));
+    }
+
+    fn tr_stmts(&mut self, stmts: &[Stmt], level: &Rc<Level<F>>) -> Vec<Stm> {
+        let mut result = Vec::new();
+        let mut i = 0;
+        while i < stmts.len() {
+            let group_start = i;
+            while let Some(Stmt {
+                kind: StmtKind::Fn(_),
+                ..
+            }) = stmts.get(i)
+            {
+                i += 1;
+            }
+            if i > group_start {
+                self.tr_fn_group(&stmts[group_start..i], level);
+            } else {
+                result.push(self.tr_stmt(&stmts[i], level));
+                i += 1;
+            }
+        }
+        return result;
+    }
+
+    /// Translate consecutive function declarations, which may all call each other
+    fn tr_fn_group(&mut self, stmts: &[Stmt], level: &Rc<Level<F>>) {
+        let mut decls: Vec<(&FnDecl, Rc<Level<F>>)> = Vec::new();
+        for stmt in stmts {
+            if let StmtKind::Fn(decl) = &stmt.kind {
+                let label = Label::named(&format!("{}_{}", decl.name, Label::new().0));
+                let escapes: Vec<bool> = decl
+                    .params
+                    .iter()
+                    .map(|p| self.escapes.escapes(p.pos))
+                    .collect();
+                let fn_level = Level::new(level, label.clone(), &escapes);
+                self.env
+                    .push(EnvEntry::Fn(decl.name.clone(), fn_level.clone(), label));
+                decls.push((decl, fn_level));
+            }
+        }
+        for (decl, fn_level) in decls {
+            let scope = self.env.len();
+            for (param, access) in decl.params.iter().zip(fn_level.formals()) {
+                self.env.push(EnvEntry::Var(param.name.clone(), access));
+            }
+            let body = self.tr_block(&decl.body, &fn_level);
+            self.env.truncate(scope);
+            self.proc_entry_exit(&fn_level, body, decl.result);
+        }
+    }
+
+    fn tr_stmt(&mut self, stmt: &Stmt, level: &Rc<Level<F>>) -> Stm {
+        match &stmt.kind {
+            StmtKind::Let { name, init, .. } => {
+                let init = un_ex(self.tr_expr(init, level));
+                let access = level.alloc_local(self.escapes.escapes(stmt.pos));
+                let dst = self.simple_var(&access, level);
+                self.env.push(EnvEntry::Var(name.clone(), access));
+                return Stm::Move(Box::new(dst), Box::new(init));
+            }
+            StmtKind::Assign { name, value } => {
+                let value = un_ex(self.tr_expr(value, level));
+                let dst = self.simple_var(&self.lookup_var(name), level);
+                return Stm::Move(Box::new(dst), Box::new(value));
+            }
+            StmtKind::While { cond, body } => {
+                let test = Label::new();
+                let body_label = Label::new();
+                let done = Label::new();
+                let cond = un_cx(self.tr_expr(cond, level));
+                let body = un_nx(self.tr_block(body, level));
+                return seq(vec![
+                    Stm::Label(test.clone()),
+                    cond(body_label.clone(), done.clone()),
+                    Stm::Label(body_label),
+                    body,
+                    jump(test),
+                    Stm::Label(done),
+                ]);
+            }
+            StmtKind::For { var, lo, hi, body } => {
+                let lo = un_ex(self.tr_expr(lo, level));
+                let hi = un_ex(self.tr_expr(hi, level));
+                let limit = Temp::new();
+                let access = level.alloc_local(self.escapes.escapes(stmt.pos));
+                let i = self.simple_var(&access, level);
+                let scope = self.env.len();
+                self.env.push(EnvEntry::Var(var.clone(), access));
+                let body = un_nx(self.tr_block(body, level));
+                self.env.truncate(scope);
+                let test = Label::new();
+                let body_label = Label::new();
+                let done = Label::new();
+                return seq(vec![
+                    Stm::Move(Box::new(i.clone()), Box::new(lo)),
+                    Stm::Move(Box::new(Exp::Temp(limit)), Box::new(hi)),
+                    Stm::Label(test.clone()),
+                    Stm::CJump(
+                        RelOp::Lt,
+                        Box::new(i.clone()),
+                        Box::new(Exp::Temp(limit)),
+                        body_label.clone(),
+                        done.clone(),
+                    ),
+                    Stm::Label(body_label),
+                    body,
+                    Stm::Move(
+                        Box::new(i.clone()),
+                        Box::new(Exp::BinOp(
+                            BinOp::Plus,
+                            Box::new(i),
+                            Box::new(Exp::Const(1)),
+                        )),
+                    ),
+                    jump(test),
+                    Stm::Label(done),
+                ]);
+            }
+            StmtKind::Fn(_) => unreachable!("function declarations are translated in groups"),
+            StmtKind::Error => unreachable!("the type checker rejects syntax errors"),
+            StmtKind::Expr(expr) => return un_nx(self.tr_expr(expr, level)),
+        }
+    }
+
+    fn tr_block(&mut self, block: &Block, level: &Rc<Level<F>>) -> TrExp {
+        let scope = self.env.len();
+        let stms = self.tr_stmts(&block.stmts, level);
+        let result = match &block.result {
+            Some(expr) if expr.ty() != Type::Unit => {
+                let value = un_ex(self.tr_expr(expr, level));
+                if stms.is_empty() {
+                    TrExp::Ex(value)
+                } else {
+                    TrExp::Ex(Exp::Eseq(Box::new(seq(stms)), Box::new(value)))
+                }
+            }
+            Some(expr) => {
+                let mut stms = stms;
+                stms.push(un_nx(self.tr_expr(expr, level)));
+                TrExp::Nx(seq(stms))
+            }
+            None => TrExp::Nx(seq(stms)),
+        };
+        self.env.truncate(scope);
+        return result;
+    }
+
+    fn tr_expr(&mut self, expr: &Expr, level: &Rc<Level<F>>) -> TrExp {
+        match &expr.kind {
+            ExprKind::Int(i) => return TrExp::Ex(Exp::Const(*i)),
+            ExprKind::Float(x) => return TrExp::Ex(Exp::Const(x.to_bits() as i64)),
+            ExprKind::Bool(b) => return TrExp::Ex(Exp::Const(*b as i64)),
+            ExprKind::Str(s) => {
+                let label = Label::new();
+                self.frags.push(Frag::Str(label.clone(), s.clone()));
+                return TrExp::Ex(Exp::Name(label));
+            }
+            ExprKind::Var(name) => {
+                return TrExp::Ex(self.simple_var(&self.lookup_var(name), level))
+            }
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let value = un_ex(self.tr_expr(operand, level));
+                if operand.ty() == Type::Float {
+                    return TrExp::Ex(external_call(FLOAT_NEG, vec![value]));
+                }
+                return TrExp::Ex(Exp::BinOp(
+                    BinOp::Minus,
+                    Box::new(Exp::Const(0)),
+                    Box::new(value),
+                ));
+            }
+            ExprKind::Binary(left, op, right) => {
+                return self.tr_binary(left, *op, right, level);
+            }
+            ExprKind::Error => unreachable!("the type checker rejects syntax errors"),
+            ExprKind::Call(name, args) => {
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(un_ex(self.tr_expr(arg, level)));
+                }
+                match self.lookup_fn(name) {
+                    Some((callee, label)) => {
+                        let parent = callee.parent.clone().unwrap();
+                        values.insert(0, self.frame_address(level, &parent));
+                        let call = Exp::Call(Box::new(Exp::Name(label)), values);
+                        if expr.ty() == Type::Unit {
+                            return TrExp::Nx(Stm::Exp(Box::new(call)));
+                        }
+                        return TrExp::Ex(call);
+                    }
+                    None => {
+                        // Every argument is computed before anything is printed
+                        let mut stms = Vec::new();
+                        let mut printed = Vec::new();
+                        for value in values {
+                            if args.len() == 1 {
+                                printed.push(value);
+                                continue;
+                            }
+                            let t = Temp::new();
+                            stms.push(Stm::Move(Box::new(Exp::Temp(t)), Box::new(value)));
+                            printed.push(Exp::Temp(t));
+                        }
+                        for (arg, value) in args.iter().zip(printed) {
+                            let print = match arg.ty() {
+                                Type::Int => PRINT_INT,
+                                Type::Float => PRINT_FLOAT,
+                                Type::Bool => PRINT_BOOL,
+                                Type::String => PRINT_STRING,
+                                Type::Unit => panic!("cannot print ()"),
+                            };
+                            stms.push(Stm::Exp(Box::new(external_call(print, vec![value]))));
+                        }
+                        return TrExp::Nx(seq(stms));
+                    }
+                }
+            }
+            ExprKind::If {
+                branches,
+                else_block,
+            } => return self.tr_if(branches, else_block, expr.ty(), level),
+        }
+    }
+
+    fn tr_binary(
+        &mut self,
+        left: &Expr,
+        op: ast::BinOp,
+        right: &Expr,
+        level: &Rc<Level<F>>,
+    ) -> TrExp {
+        let operand_ty = left.ty();
+        let l = self.tr_expr(left, level);
+        let r = self.tr_expr(right, level);
+        match (op, operand_ty) {
+            (ast::BinOp::And, _) => {
+                let (l, r) = (un_cx(l), un_cx(r));
+                return TrExp::Cx(Box::new(move |t, f| {
+                    let z = Label::new();
+                    seq(vec![l(z.clone(), f.clone()), Stm::Label(z), r(t, f)])
+                }));
+            }
+            (ast::BinOp::Or, _) => {
+                let (l, r) = (un_cx(l), un_cx(r));
+                return TrExp::Cx(Box::new(move |t, f| {
+                    let z = Label::new();
+                    seq(vec![l(t.clone(), z.clone()), Stm::Label(z), r(t, f)])
+                }));
+            }
+            _ => {}
+        }
+        let (l, r) = (un_ex(l), un_ex(r));
+        let (float_op, rel_op) = match op {
+            ast::BinOp::Eq => (FLOAT_EQ, RelOp::Eq),
+            ast::BinOp::Neq => (FLOAT_NE, RelOp::Ne),
+            ast::BinOp::Lt => (FLOAT_LT, RelOp::Lt),
+            ast::BinOp::Le => (FLOAT_LE, RelOp::Le),
+            ast::BinOp::Gt => (FLOAT_GT, RelOp::Gt),
+            ast::BinOp::Ge => (FLOAT_GE, RelOp::Ge),
+            _ => return self.tr_arithmetic(l, op, r, operand_ty),
+        };
+        let (l, r) = match operand_ty {
+            Type::Float => (external_call(float_op, vec![l, r]), Exp::Const(0)),
+            Type::String => (external_call(STRING_EQUAL, vec![l, r]), Exp::Const(1)),
+            _ => (l, r),
+        };
+        let rel_op = if operand_ty == Type::Float {
+            RelOp::Ne
+        } else {
+            rel_op
+        };
+        return TrExp::Cx(Box::new(move |t, f| {
+            Stm::CJump(rel_op, Box::new(l), Box::new(r), t, f)
+        }));
+    }
+
+    fn tr_arithmetic(&mut self, l: Exp, op: ast::BinOp, r: Exp, ty: Type) -> TrExp {
+        if ty == Type::String {
+            return TrExp::Ex(external_call(STRING_CONCAT, vec![l, r]));
+        }
+        if ty == Type::Float {
+            let name = match op {
+                ast::BinOp::Add => FLOAT_ADD,
+                ast::BinOp::Sub => FLOAT_SUB,
+                ast::BinOp::Mul => FLOAT_MUL,
+                _ => FLOAT_DIV,
+            };
+            return TrExp::Ex(external_call(name, vec![l, r]));
+        }
+        let op = match op {
+            ast::BinOp::Add => BinOp::Plus,
+            ast::BinOp::Sub => BinOp::Minus,
+            ast::BinOp::Mul => BinOp::Mul,
+            _ => return TrExp::Ex(checked_div(l, r)),
+        };
+        return TrExp::Ex(Exp::BinOp(op, Box::new(l), Box::new(r)));
+    }
+
+    fn tr_if(
+        &mut self,
+        branches: &[(Expr, Block)],
+        else_block: &Option<Block>,
+        ty: Type,
+        level: &Rc<Level<F>>,
+    ) -> TrExp {
+        let join = Label::new();
+        let result = Temp::new();
+        let mut stms = Vec::new();
+        let tr_branch = |translator: &mut Translator<F>, block: &Block| {
+            let value = translator.tr_block(block, level);
+            if ty == Type::Unit {
+                return un_nx(value);
+            }
+            return Stm::Move(Box::new(Exp::Temp(result)), Box::new(un_ex(value)));
+        };
+        for (cond, block) in branches {
+            let t = Label::new();
+            let f = Label::new();
+            let cond = un_cx(self.tr_expr(cond, level));
+            stms.push(cond(t.clone(), f.clone()));
+            stms.push(Stm::Label(t));
+            stms.push(tr_branch(self, block));
+            stms.push(jump(join.clone()));
+            stms.push(Stm::Label(f));
+        }
+        if let Some(block) = else_block {
+            stms.push(tr_branch(self, block));
+        }
+        stms.push(Stm::Label(join));
+        if ty == Type::Unit {
+            return TrExp::Nx(seq(stms));
+        }
+        return TrExp::Ex(Exp::Eseq(Box::new(seq(stms)), Box::new(Exp::Temp(result))));
+    }
+}
+
+/// Integer division, calling the runtime to report an error when dividing by zero
+fn checked_div(l: Exp, r: Exp) -> Exp {
+    let (a, b) = (Temp::new(), Temp::new());
+    let (error, ok) = (Label::new(), Label::new());
+    return Exp::Eseq(
+        Box::new(seq(vec![
+            Stm::Move(Box::new(Exp::Temp(a)), Box::new(l)),
+            Stm::Move(Box::new(Exp::Temp(b)), Box::new(r)),
+            Stm::CJump(
+                RelOp::Eq,
+                Box::new(Exp::Temp(b)),
+                Box::new(Exp::Const(0)),
+                error.clone(),
+                ok.clone(),
+            ),
+            Stm::Label(error),
+            Stm::Exp(Box::new(external_call(DIV_BY_ZERO, vec![]))),
+            Stm::Label(ok),
+        ])),
+        Box::new(Exp::BinOp(
+            BinOp::Div,
+            Box::new(Exp::Temp(a)),
+            Box::new(Exp::Temp(b)),
+        )),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_3::parse;
+    use crate::chapter_5::check;
+
+    fn translate_str(input: &str) -> Vec<Frag<X86_64Frame>> {
+        let program = parse(input).unwrap();
+        check(&program).unwrap();
+        return translate(&program);
+    }
+
+    /// The body of the fragment for the function labelled `name`
+    fn proc_body(frags: &[Frag<X86_64Frame>], name: &str) -> String {
+        for frag in frags {
+            if let Frag::Proc { body, frame } = frag {
+                if frame.name().0 == name {
+                    return body.to_string();
+                }
+            }
+        }
+        panic!("no fragment for {}", name);
+    }
+
+    #[test]
+    fn test_seq() {
+        let label = |name: &str| Stm::Label(Label::named(name));
+        assert_eq!(seq(vec![]).to_string(), "EXP(CONST 0)");
+        assert_eq!(seq(vec![label("a")]).to_string(), "LABEL a");
+        assert_eq!(
+            seq(vec![label("a"), label("b"), label("c")]).to_string(),
+            "SEQ(LABEL a, SEQ(LABEL b, LABEL c))"
+        );
+    }
+
+    #[test]
+    fn test_rel_op_not_and_commute() {
+        assert_eq!(RelOp::Lt.not(), RelOp::Ge);
+        assert_eq!(RelOp::UGe.not(), RelOp::ULt);
+        assert_eq!(RelOp::Lt.commute(), RelOp::Gt);
+        assert_eq!(RelOp::Eq.commute(), RelOp::Eq);
+    }
+
+    #[test]
+    fn test_un_cx_of_constants_jumps_directly() {
+        let t = Label::named("t");
+        let f = Label::named("f");
+        assert_eq!(
+            un_cx(TrExp::Ex(Exp::Const(1)))(t.clone(), f.clone()).to_string(),
+            "JUMP(NAME t)"
+        );
+        assert_eq!(
+            un_cx(TrExp::Ex(Exp::Const(0)))(t.clone(), f.clone()).to_string(),
+            "JUMP(NAME f)"
+        );
+        assert_eq!(
+            un_cx(TrExp::Ex(Exp::Temp(Temp(200))))(t, f).to_string(),
+            "CJUMP(NE, TEMP t200, CONST 0, t, f)"
+        );
+    }
+
+    #[test]
+    fn test_un_ex_of_condition_materializes_bool() {
+        let cx = TrExp::Cx(Box::new(|t, f| {
+            Stm::CJump(
+                RelOp::Lt,
+                Box::new(Exp::Const(1)),
+                Box::new(Exp::Const(2)),
+                t,
+                f,
+            )
+        }));
+        let printed = un_ex(cx).to_string();
+        assert!(printed.starts_with("ESEQ(SEQ(MOVE(TEMP t"));
+        assert!(printed.contains("CONST 1), SEQ(CJUMP(LT, CONST 1, CONST 2, L"));
+    }
+
+    #[test]
+    fn test_translate_fragments() {
+        let frags = translate_str(
+            "fn f(x: int): int { x + 1 }
+             print(f(2), \"hi\");",
+        );
+        assert_eq!(frags.len(), 3);
+        assert!(matches!(&frags[0], Frag::Proc { frame, .. } if frame.name().0.starts_with("f_")));
+        assert!(matches!(&frags[1], Frag::Str(_, s) if s == "hi"));
+        assert!(matches!(&frags[2], Frag::Proc { frame, .. } if frame.name().0 == PROGRAM_MAIN));
+    }
+
+    #[test]
+    fn test_translate_function_body_returns_in_rv() {
+        let frags = translate_str("fn f(x: int): int { x * 2 }");
+        let name = match &frags[0] {
+            Frag::Proc { frame, .. } => frame.name().0.clone(),
+            _ => panic!(),
+        };
+        let x = match &frags[0] {
+            Frag::Proc { frame, .. } => frame.formals()[1],
+            _ => panic!(),
+        };
+        let x = match x {
+            Access::InReg(t) => t,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            proc_body(&frags, &name),
+            format!("MOVE(TEMP t0, BINOP(MUL, TEMP {}, CONST 2))", x)
+        );
+    }
+
+    #[test]
+    fn test_translate_static_links() {
+        let frags = translate_str(
+            "fn f(a: int): int {
+                 fn g(): int {
+                     fn h(): int { a }
+                     h()
+                 }
+                 g()
+             }",
+        );
+        // `h` reaches `a` in the frame of `f` through its own static link and then `g`'s
+        let h = frags
+            .iter()
+            .find_map(|frag| match frag {
+                Frag::Proc { body, frame } if frame.name().0.starts_with("h_") => {
+                    Some(body.to_string())
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            h,
+            "MOVE(TEMP t0, MEM(BINOP(PLUS, MEM(BINOP(PLUS, MEM(BINOP(PLUS, TEMP t6, CONST -8)), CONST -8)), CONST -16)))"
+        );
+        // `g` calls `h` passing its own frame pointer as the static link
+        let g = frags
+            .iter()
+            .find_map(|frag| match frag {
+                Frag::Proc { body, frame } if frame.name().0.starts_with("g_") => {
+                    Some(body.to_string())
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert!(g.starts_with("MOVE(TEMP t0, CALL(NAME h_"), "{}", g);
+        assert!(g.ends_with(", TEMP t6))"), "{}", g);
+    }
+
+    #[test]
+    fn test_translate_short_circuit() {
+        let frags = translate_str("let a = 1; let b = 2; if a < b & b < 3 { print(1); }");
+        let main = proc_body(&frags, PROGRAM_MAIN);
+        assert_eq!(main.matches("CJUMP(LT").count(), 2, "{}", main);
+        assert!(!main.contains("AND"), "{}", main);
+    }
+
+    #[test]
+    fn test_translate_runtime_calls() {
+        let frags = translate_str(
+            "let x = 1.5 + 2.0; let s = \"a\" + \"b\"; print(x, s == \"ab\", -x, 7 / 2);",
+        );
+        let main = proc_body(&frags, PROGRAM_MAIN);
+        for name in [
+            FLOAT_ADD,
+            STRING_CONCAT,
+            STRING_EQUAL,
+            PRINT_FLOAT,
+            PRINT_BOOL,
+            PRINT_INT,
+            FLOAT_NEG,
+            DIV_BY_ZERO,
+        ] {
+            assert!(
+                main.contains(&format!("NAME {}", name)),
+                "{} in {}",
+                name,
+                main
+            );
+        }
+    }
+}
@@ -0,0 +1,747 @@
+/// Implementations described in Chapter 8: basic blocks and traces
+use std::collections::{HashMap, HashSet};
+
+use crate::chapter_6::{Label, Temp};
+use crate::chapter_7::*;
+
+/// Rewrite a statement into an equivalent list of statements with no `Seq` or `Eseq`, where every
+/// `Call` is either `Exp(Call(..))` or `Move(Temp(..), Call(..))`
+pub fn linearize(stm: Stm) -> Vec<Stm> {
+    let mut stms = Vec::new();
+    flatten(do_stm(stm), &mut stms);
+    return stms;
+}
+
+/// Split linearized statements into basic blocks, returning them with the label every block
+/// that falls off the end jumps to
+///
+/// Every block starts with a `Label` and ends with a `Jump` or `CJump`, with no other labels or
+/// jumps in between.
+pub fn basic_blocks(stms: Vec<Stm>) -> (Vec<Vec<Stm>>, Label) {
+    let done = Label::new();
+    let mut blocks = Vec::new();
+    let mut current: Vec<Stm> = Vec::new();
+    for stm in stms {
+        match stm {
+            Stm::Label(label) => {
+                if !current.is_empty() {
+                    current.push(jump_to(label.clone()));
+                    blocks.push(std::mem::take(&mut current));
+                }
+                current.push(Stm::Label(label));
+            }
+            stm => {
+                if current.is_empty() {
+                    current.push(Stm::Label(Label::new()));
+                }
+                let ends_block = matches!(stm, Stm::Jump(..) | Stm::CJump(..));
+                current.push(stm);
+                if ends_block {
+                    blocks.push(std::mem::take(&mut current));
+                }
+            }
+        }
+    }
+    if !current.is_empty() {
+        current.push(jump_to(done.clone()));
+        blocks.push(current);
+    }
+    return (blocks, done);
+}
+
+/// Order basic blocks into traces so that every `CJump` is followed by its false label, and
+/// remove jumps to the label that immediately follows them
+pub fn trace_schedule(blocks: Vec<Vec<Stm>>, done: Label) -> Vec<Stm> {
+    let mut index_of = HashMap::new();
+    for (i, block) in blocks.iter().enumerate() {
+        index_of.insert(block_label(block).clone(), i);
+    }
+
+    let mut marked = HashSet::new();
+    let mut order = Vec::new();
+    for start in 0..blocks.len() {
+        let mut next = Some(start);
+        while let Some(i) = next.filter(|i| !marked.contains(i)) {
+            marked.insert(i);
+            order.push(i);
+            let successor = |label: &Label| index_of.get(label).copied();
+            next = match blocks[i].last() {
+                Some(Stm::Jump(_, targets)) => targets.first().and_then(successor),
+                Some(Stm::CJump(_, _, _, t, f)) => match (successor(f), successor(t)) {
+                    (Some(f), _) if !marked.contains(&f) => Some(f),
+                    (_, t) => t,
+                },
+                _ => None,
+            };
+        }
+    }
+
+    let mut blocks: Vec<Option<Vec<Stm>>> = blocks.into_iter().map(Some).collect();
+    let mut stms: Vec<Stm> = Vec::new();
+    for i in order {
+        stms.extend(blocks[i].take().unwrap());
+    }
+    stms.push(Stm::Label(done));
+    return fix_jumps(stms);
+}
+
+/// Run all of the canonicalization phases on a function body
+pub fn canonicalize(stm: Stm) -> Vec<Stm> {
+    let (blocks, done) = basic_blocks(linearize(stm));
+    return trace_schedule(blocks, done);
+}
+
+/// Compute the operators of constant operands ahead of time, for `-O2`. A division that would
+/// fail or overflow is left for the program to do, so that it fails where it did
+pub fn fold_constants(stm: Stm) -> Stm {
+    match stm {
+        Stm::Move(dst, src) => {
+            return Stm::Move(Box::new(fold_exp(*dst)), Box::new(fold_exp(*src)))
+        }
+        Stm::Exp(exp) => return Stm::Exp(Box::new(fold_exp(*exp))),
+        Stm::Jump(exp, labels) => return Stm::Jump(Box::new(fold_exp(*exp)), labels),
+        Stm::CJump(op, a, b, t, f) => {
+            return Stm::CJump(op, Box::new(fold_exp(*a)), Box::new(fold_exp(*b)), t, f)
+        }
+        Stm::Seq(a, b) => {
+            return Stm::Seq(Box::new(fold_constants(*a)), Box::new(fold_constants(*b)))
+        }
+        Stm::Label(label) => return Stm::Label(label),
+    }
+}
+
+fn fold_exp(exp: Exp) -> Exp {
+    match exp {
+        Exp::BinOp(op, a, b) => {
+            let (a, b) = (fold_exp(*a), fold_exp(*b));
+            if let (Exp::Const(x), Exp::Const(y)) = (&a, &b) {
+                if let Some(value) = fold_bin_op(op, *x, *y) {
+                    return Exp::Const(value);
+                }
+            }
+            return Exp::BinOp(op, Box::new(a), Box::new(b));
+        }
+        Exp::Mem(address) => return Exp::Mem(Box::new(fold_exp(*address))),
+        Exp::Call(func, args) => {
+            return Exp::Call(
+                Box::new(fold_exp(*func)),
+                args.into_iter().map(fold_exp).collect(),
+            )
+        }
+        Exp::Eseq(stm, exp) => {
+            return Exp::Eseq(Box::new(fold_constants(*stm)), Box::new(fold_exp(*exp)))
+        }
+        Exp::Const(_) | Exp::Name(_) | Exp::Temp(_) => return exp,
+    }
+}
+
+/// The value of an operator as the machine computes it, or `None` for a division to leave alone
+fn fold_bin_op(op: BinOp, a: i64, b: i64) -> Option<i64> {
+    match op {
+        BinOp::Plus => return Some(a.wrapping_add(b)),
+        BinOp::Minus => return Some(a.wrapping_sub(b)),
+        BinOp::Mul => return Some(a.wrapping_mul(b)),
+        BinOp::Div => return a.checked_div(b),
+        BinOp::And => return Some(a & b),
+        BinOp::Or => return Some(a | b),
+        BinOp::Xor => return Some(a ^ b),
+        BinOp::LShift => return Some(a.wrapping_shl(b as u32)),
+        BinOp::RShift => return Some((a as u64).wrapping_shr(b as u32) as i64),
+        BinOp::ARShift => return Some(a.wrapping_shr(b as u32)),
+    }
+}
+
+fn jump_to(label: Label) -> Stm {
+    return Stm::Jump(Box::new(Exp::Name(label.clone())), vec![label]);
+}
+
+fn block_label(block: &[Stm]) -> &Label {
+    match &block[0] {
+        Stm::Label(label) => return label,
+        _ => panic!("basic block does not start with a label"),
+    }
+}
+
+/// Make every `CJump` fall through to its false label and drop jumps to the next statement
+fn fix_jumps(stms: Vec<Stm>) -> Vec<Stm> {
+    let mut result: Vec<Stm> = Vec::new();
+    let mut stms = stms.into_iter().peekable();
+    while let Some(stm) = stms.next() {
+        let next_label = match stms.peek() {
+            Some(Stm::Label(label)) => Some(label.clone()),
+            _ => None,
+        };
+        match stm {
+            Stm::Jump(target, labels) if matches!(&*target, Exp::Name(l) if Some(l) == next_label.as_ref()) =>
+            {
+                drop(labels);
+            }
+            Stm::CJump(op, a, b, t, f) => {
+                if Some(&f) == next_label.as_ref() {
+                    result.push(Stm::CJump(op, a, b, t, f));
+                } else if Some(&t) == next_label.as_ref() {
+                    result.push(Stm::CJump(op.not(), a, b, f, t));
+                } else {
+                    let new_false = Label::new();
+                    result.push(Stm::CJump(op, a, b, t, new_false.clone()));
+                    result.push(Stm::Label(new_false));
+                    result.push(jump_to(f));
+                }
+            }
+            stm => result.push(stm),
+        }
+    }
+    return result;
+}
+
+fn is_nop(stm: &Stm) -> bool {
+    return matches!(stm, Stm::Exp(e) if matches!(**e, Exp::Const(_)));
+}
+
+/// Join two statements, leaving out either one if it does nothing
+fn seq2(a: Stm, b: Stm) -> Stm {
+    if is_nop(&a) {
+        return b;
+    }
+    if is_nop(&b) {
+        return a;
+    }
+    return Stm::Seq(Box::new(a), Box::new(b));
+}
+
+fn nop() -> Stm {
+    return Stm::Exp(Box::new(Exp::Const(0)));
+}
+
+/// Whether running the statement cannot change the value of the expression
+fn commute(stm: &Stm, exp: &Exp) -> bool {
+    return is_nop(stm) || matches!(exp, Exp::Const(_) | Exp::Name(_));
+}
+
+/// Pull the side effects out of a list of expressions, returning a statement that performs them
+/// followed by the expressions, which then have no side effects and can be computed in any order
+fn reorder(exps: Vec<Exp>) -> (Stm, Vec<Exp>) {
+    let mut stm = nop();
+    let mut result: Vec<Exp> = Vec::new();
+    for exp in exps.into_iter().rev() {
+        let (s, e) = do_exp(exp);
+        if commute(&stm, &e) {
+            stm = seq2(s, stm);
+            result.push(e);
+        } else {
+            let t = Temp::new();
+            stm = seq2(s, seq2(Stm::Move(Box::new(Exp::Temp(t)), Box::new(e)), stm));
+            result.push(Exp::Temp(t));
+        }
+    }
+    result.reverse();
+    return (stm, result);
+}
+
+fn do_exp(exp: Exp) -> (Stm, Exp) {
+    match exp {
+        Exp::BinOp(op, a, b) => {
+            let (s, mut exps) = reorder(vec![*a, *b]);
+            let b = exps.pop().unwrap();
+            let a = exps.pop().unwrap();
+            return (s, Exp::BinOp(op, Box::new(a), Box::new(b)));
+        }
+        Exp::Mem(a) => {
+            let (s, mut exps) = reorder(vec![*a]);
+            return (s, Exp::Mem(Box::new(exps.pop().unwrap())));
+        }
+        Exp::Eseq(s, e) => {
+            let s = do_stm(*s);
+            let (s2, e) = do_exp(*e);
+            return (seq2(s, s2), e);
+        }
+        Exp::Call(func, args) => {
+            // A call would clobber the return register of any other call, so save its result
+            let (s, call) = reorder_call(*func, args);
+            let t = Temp::new();
+            let save = Stm::Move(Box::new(Exp::Temp(t)), Box::new(call));
+            return (seq2(s, save), Exp::Temp(t));
+        }
+        exp => return (nop(), exp),
+    }
+}
+
+/// Reorder the function and arguments of a call, leaving the call itself in place
+fn reorder_call(func: Exp, args: Vec<Exp>) -> (Stm, Exp) {
+    let mut exps = vec![func];
+    exps.extend(args);
+    let (s, mut exps) = reorder(exps);
+    let args = exps.split_off(1);
+    return (s, Exp::Call(Box::new(exps.pop().unwrap()), args));
+}
+
+fn do_stm(stm: Stm) -> Stm {
+    match stm {
+        Stm::Seq(a, b) => return seq2(do_stm(*a), do_stm(*b)),
+        Stm::Jump(e, labels) => {
+            let (s, mut exps) = reorder(vec![*e]);
+            return seq2(s, Stm::Jump(Box::new(exps.pop().unwrap()), labels));
+        }
+        Stm::CJump(op, a, b, t, f) => {
+            let (s, mut exps) = reorder(vec![*a, *b]);
+            let b = exps.pop().unwrap();
+            let a = exps.pop().unwrap();
+            return seq2(s, Stm::CJump(op, Box::new(a), Box::new(b), t, f));
+        }
+        Stm::Move(dst, src) => match (*dst, *src) {
+            (Exp::Temp(t), Exp::Call(func, args)) => {
+                let (s, call) = reorder_call(*func, args);
+                return seq2(s, Stm::Move(Box::new(Exp::Temp(t)), Box::new(call)));
+            }
+            (Exp::Temp(t), src) => {
+                let (s, mut exps) = reorder(vec![src]);
+                return seq2(
+                    s,
+                    Stm::Move(Box::new(Exp::Temp(t)), Box::new(exps.pop().unwrap())),
+                );
+            }
+            (Exp::Mem(addr), src) => {
+                let (s, mut exps) = reorder(vec![*addr, src]);
+                let src = exps.pop().unwrap();
+                let addr = exps.pop().unwrap();
+                return seq2(
+                    s,
+                    Stm::Move(Box::new(Exp::Mem(Box::new(addr))), Box::new(src)),
+                );
+            }
+            (Exp::Eseq(s, e), src) => {
+                return do_stm(Stm::Seq(s, Box::new(Stm::Move(e, Box::new(src)))))
+            }
+            (dst, _) => panic!("cannot move into {}", dst),
+        },
+        Stm::Exp(e) => match *e {
+            Exp::Call(func, args) => {
+                let (s, call) = reorder_call(*func, args);
+                return seq2(s, Stm::Exp(Box::new(call)));
+            }
+            e => {
+                let (s, mut exps) = reorder(vec![e]);
+                return seq2(s, Stm::Exp(Box::new(exps.pop().unwrap())));
+            }
+        },
+        stm => return stm,
+    }
+}
+
+/// Flatten nested `Seq`s into a list, dropping statements that do nothing
+fn flatten(stm: Stm, stms: &mut Vec<Stm>) {
+    match stm {
+        Stm::Seq(a, b) => {
+            flatten(*a, stms);
+            flatten(*b, stms);
+        }
+        stm if is_nop(&stm) => {}
+        stm => stms.push(stm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_6::{Access, Frame, X86_64Frame};
+    use crate::ir_interpreter::{run_to_string, IrError, IrProgram, Proc};
+
+    /// Where the memory random trees use starts, above the strings the callees print
+    const MEMORY: i64 = 1024;
+
+    fn print(name: &str, e: Exp) -> Stm {
+        return Stm::Exp(Box::new(call(name, vec![e])));
+    }
+
+    /// A function printing its name and arguments and returning a value computed from them, so
+    /// that the order and results of calls show in the output
+    fn callee(name: &str, params: usize) -> Proc<X86_64Frame> {
+        let frame = X86_64Frame::new(Label::named(name), &vec![false; params]);
+        let args: Vec<Exp> = frame
+            .formals()
+            .iter()
+            .map(|access| match access {
+                Access::InReg(t) => Exp::Temp(*t),
+                Access::InFrame(_) => unreachable!("the formals do not escape"),
+            })
+            .collect();
+        let mut body = vec![print(PRINT_STRING, Exp::Name(Label::named(name)))];
+        body.extend(args.iter().map(|arg| print(PRINT_INT, arg.clone())));
+        let result = args
+            .into_iter()
+            .fold(Exp::Const(params as i64), |acc, arg| {
+                let scaled = Exp::BinOp(BinOp::Mul, Box::new(acc), Box::new(Exp::Const(31)));
+                return Exp::BinOp(BinOp::Xor, Box::new(scaled), Box::new(arg));
+            });
+        body.push(Stm::Move(
+            Box::new(Exp::Temp(X86_64Frame::rv())),
+            Box::new(result),
+        ));
+        return Proc { frame, body };
+    }
+
+    /// Run statements made by `random_stm` on the IR interpreter, returning what they print: the
+    /// calls they make, then the observed temporaries and the memory they leave
+    fn run_list(stms: &[Stm]) -> Result<String, IrError> {
+        let mut body: Vec<Stm> = OBSERVED
+            .iter()
+            .map(|t| Stm::Move(Box::new(Exp::Temp(*t)), Box::new(Exp::Const(0))))
+            .collect();
+        body.extend(stms.iter().cloned());
+        body.extend(OBSERVED.iter().map(|t| print(PRINT_INT, Exp::Temp(*t))));
+        for i in 0..4 {
+            let word = Exp::Mem(Box::new(Exp::Const(MEMORY + 8 * i)));
+            body.push(print(PRINT_INT, word));
+        }
+        let program = IrProgram {
+            procs: vec![
+                Proc {
+                    frame: X86_64Frame::new(Label::named(PROGRAM_MAIN), &[]),
+                    body,
+                },
+                callee("f", 2),
+                callee("g", 1),
+            ],
+            strings: vec![
+                (Label::named("f"), "f".to_string()),
+                (Label::named("g"), "g".to_string()),
+            ],
+        };
+        return run_to_string(&program);
+    }
+
+    fn run(stm: &Stm) -> Result<String, IrError> {
+        return run_list(std::slice::from_ref(stm));
+    }
+
+    /// A xorshift generator, so the random trees are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            return self.0 % n;
+        }
+    }
+
+    /// The temporaries random trees use, which are compared after running them
+    const OBSERVED: [Temp; 4] = [Temp(50), Temp(51), Temp(52), Temp(53)];
+
+    /// One of the words of memory random trees use, picked by the value of `e`, so that reads
+    /// and writes collide
+    fn address(e: Exp) -> Exp {
+        let offset = Exp::BinOp(BinOp::And, Box::new(e), Box::new(Exp::Const(24)));
+        return Exp::BinOp(BinOp::Plus, Box::new(Exp::Const(MEMORY)), Box::new(offset));
+    }
+
+    fn random_exp(rng: &mut Rng, depth: u32) -> Exp {
+        let choice = if depth == 0 {
+            rng.below(2)
+        } else {
+            rng.below(7)
+        };
+        match choice {
+            0 => return Exp::Const(rng.below(10) as i64),
+            1 => return Exp::Temp(OBSERVED[rng.below(4) as usize]),
+            2 => {
+                let op = [BinOp::Plus, BinOp::Minus, BinOp::Mul, BinOp::Xor][rng.below(4) as usize];
+                return Exp::BinOp(
+                    op,
+                    Box::new(random_exp(rng, depth - 1)),
+                    Box::new(random_exp(rng, depth - 1)),
+                );
+            }
+            3 => return Exp::Mem(Box::new(address(random_exp(rng, depth - 1)))),
+            4 => {
+                let (name, params) = [("f", 2), ("g", 1)][rng.below(2) as usize];
+                let args = (0..params).map(|_| random_exp(rng, depth - 1)).collect();
+                return call(name, args);
+            }
+            _ => {
+                return Exp::Eseq(
+                    Box::new(random_stm(rng, depth - 1)),
+                    Box::new(random_exp(rng, depth - 1)),
+                )
+            }
+        }
+    }
+
+    fn random_stm(rng: &mut Rng, depth: u32) -> Stm {
+        let choice = if depth == 0 {
+            rng.below(2)
+        } else {
+            rng.below(6)
+        };
+        match choice {
+            0 => {
+                return Stm::Move(
+                    Box::new(Exp::Temp(OBSERVED[rng.below(4) as usize])),
+                    Box::new(random_exp(rng, depth.saturating_sub(1))),
+                )
+            }
+            1 => {
+                let word = Exp::Const(MEMORY + 8 * rng.below(4) as i64);
+                return Stm::Move(
+                    Box::new(Exp::Mem(Box::new(word))),
+                    Box::new(random_exp(rng, depth.saturating_sub(1))),
+                );
+            }
+            2 => {
+                return Stm::Move(
+                    Box::new(Exp::Mem(Box::new(address(random_exp(rng, depth - 1))))),
+                    Box::new(random_exp(rng, depth - 1)),
+                )
+            }
+            3 => return Stm::Exp(Box::new(random_exp(rng, depth - 1))),
+            4 => {
+                return Stm::Seq(
+                    Box::new(random_stm(rng, depth - 1)),
+                    Box::new(random_stm(rng, depth - 1)),
+                )
+            }
+            _ => {
+                // if/else, shaped like the translation of an `if` expression
+                let (t, f, join) = (Label::new(), Label::new(), Label::new());
+                let op = [RelOp::Eq, RelOp::Lt, RelOp::Ge, RelOp::UGt][rng.below(4) as usize];
+                return seq(vec![
+                    Stm::CJump(
+                        op,
+                        Box::new(random_exp(rng, depth - 1)),
+                        Box::new(random_exp(rng, depth - 1)),
+                        t.clone(),
+                        f.clone(),
+                    ),
+                    Stm::Label(t),
+                    random_stm(rng, depth - 1),
+                    jump_to(join.clone()),
+                    Stm::Label(f),
+                    random_stm(rng, depth - 1),
+                    Stm::Label(join),
+                ]);
+            }
+        }
+    }
+
+    fn call(name: &str, args: Vec<Exp>) -> Exp {
+        return Exp::Call(Box::new(Exp::Name(Label::named(name))), args);
+    }
+
+    fn contains_call(exp: &Exp) -> bool {
+        match exp {
+            Exp::Call(..) => return true,
+            Exp::BinOp(_, a, b) => return contains_call(a) || contains_call(b),
+            Exp::Mem(a) => return contains_call(a),
+            Exp::Eseq(..) => panic!("ESEQ left after linearizing"),
+            _ => return false,
+        }
+    }
+
+    /// Check the shape `linearize` promises
+    fn assert_linear(stms: &[Stm]) {
+        for stm in stms {
+            match stm {
+                Stm::Seq(..) => panic!("SEQ left after linearizing"),
+                Stm::Move(dst, src) => {
+                    assert!(!contains_call(dst));
+                    match &**src {
+                        Exp::Call(_, args) => {
+                            assert!(matches!(**dst, Exp::Temp(_)));
+                            assert!(!args.iter().any(contains_call));
+                        }
+                        src => assert!(!contains_call(src)),
+                    }
+                }
+                Stm::Exp(e) => match &**e {
+                    Exp::Call(_, args) => assert!(!args.iter().any(contains_call)),
+                    e => assert!(!contains_call(e)),
+                },
+                Stm::Jump(e, _) => assert!(!contains_call(e)),
+                Stm::CJump(_, a, b, _, _) => assert!(!contains_call(a) && !contains_call(b)),
+                Stm::Label(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_linearize_removes_eseq() {
+        // MOVE(TEMP 50, BINOP(PLUS, CONST 1, ESEQ(MOVE(TEMP 51, CONST 2), TEMP 51)))
+        let stm = Stm::Move(
+            Box::new(Exp::Temp(Temp(50))),
+            Box::new(Exp::BinOp(
+                BinOp::Plus,
+                Box::new(Exp::Const(1)),
+                Box::new(Exp::Eseq(
+                    Box::new(Stm::Move(
+                        Box::new(Exp::Temp(Temp(51))),
+                        Box::new(Exp::Const(2)),
+                    )),
+                    Box::new(Exp::Temp(Temp(51))),
+                )),
+            )),
+        );
+        let stms = linearize(stm);
+        assert_eq!(
+            stms.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            vec![
+                "MOVE(TEMP t51, CONST 2)",
+                "MOVE(TEMP t50, BINOP(PLUS, CONST 1, TEMP t51))"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_linearize_keeps_order_of_side_effects() {
+        // The left operand is read before the ESEQ on the right changes it
+        let stm = Stm::Exp(Box::new(call(
+            "f",
+            vec![
+                Exp::Temp(Temp(50)),
+                Exp::Eseq(
+                    Box::new(Stm::Move(
+                        Box::new(Exp::Temp(Temp(50))),
+                        Box::new(Exp::Const(7)),
+                    )),
+                    Box::new(Exp::Temp(Temp(50))),
+                ),
+            ],
+        )));
+        let stms = linearize(stm.clone());
+        assert_linear(&stms);
+        assert_eq!(run(&stm), run_list(&stms));
+        assert!(run_list(&stms).unwrap().starts_with("f\n0\n7\n"));
+    }
+
+    #[test]
+    fn test_linearize_hoists_nested_calls() {
+        let stm = Stm::Exp(Box::new(call(
+            "f",
+            vec![
+                call("g", vec![Exp::Const(2)]),
+                call("g", vec![Exp::Const(1)]),
+            ],
+        )));
+        let stms = linearize(stm.clone());
+        assert_linear(&stms);
+        assert_eq!(run(&stm), run_list(&stms));
+        assert!(
+            matches!(&stms[0], Stm::Move(dst, src) if matches!(**dst, Exp::Temp(_)) && matches!(**src, Exp::Call(..)))
+        );
+        assert!(matches!(stms.last(), Some(Stm::Exp(e)) if matches!(**e, Exp::Call(..))));
+    }
+
+    #[test]
+    fn test_basic_blocks() {
+        let (a, b) = (Label::named("a"), Label::named("b"));
+        let stms = vec![
+            Stm::Move(Box::new(Exp::Temp(Temp(50))), Box::new(Exp::Const(1))),
+            Stm::Label(a.clone()),
+            Stm::CJump(
+                RelOp::Lt,
+                Box::new(Exp::Temp(Temp(50))),
+                Box::new(Exp::Const(3)),
+                a.clone(),
+                b.clone(),
+            ),
+            Stm::Label(b),
+        ];
+        let (blocks, done) = basic_blocks(stms);
+        assert_eq!(blocks.len(), 3);
+        for block in &blocks {
+            assert!(matches!(block[0], Stm::Label(_)));
+            assert!(matches!(block.last(), Some(Stm::Jump(..) | Stm::CJump(..))));
+        }
+        assert_eq!(block_label(&blocks[1]), &a);
+        assert!(matches!(&blocks[0][2], Stm::Jump(_, targets) if targets[0] == a));
+        assert!(matches!(&blocks[2][1], Stm::Jump(_, targets) if targets[0] == done));
+    }
+
+    #[test]
+    fn test_trace_schedule_puts_false_label_after_cjump() {
+        let (t, f, join) = (Label::named("t"), Label::named("f"), Label::named("join"));
+        let stm = seq(vec![
+            Stm::CJump(
+                RelOp::Lt,
+                Box::new(Exp::Temp(Temp(50))),
+                Box::new(Exp::Const(3)),
+                t.clone(),
+                f.clone(),
+            ),
+            Stm::Label(f),
+            Stm::Move(Box::new(Exp::Temp(Temp(51))), Box::new(Exp::Const(1))),
+            jump_to(join.clone()),
+            Stm::Label(t),
+            Stm::Move(Box::new(Exp::Temp(Temp(51))), Box::new(Exp::Const(2))),
+            jump_to(join.clone()),
+            Stm::Label(join.clone()),
+        ]);
+        let stms = canonicalize(stm);
+        assert_scheduled(&stms);
+        // The block scheduled just before `join` falls through to it
+        let jumps_to_join = stms
+            .iter()
+            .filter(|s| matches!(s, Stm::Jump(_, targets) if targets[0] == join))
+            .count();
+        assert_eq!(jumps_to_join, 1);
+    }
+
+    fn assert_scheduled(stms: &[Stm]) {
+        for (i, stm) in stms.iter().enumerate() {
+            if let Stm::CJump(_, _, _, _, f) = stm {
+                assert_eq!(stms.get(i + 1), Some(&Stm::Label(f.clone())));
+            }
+            if let Stm::Jump(e, _) = stm {
+                if let Some(Stm::Label(next)) = stms.get(i + 1) {
+                    assert_ne!(**e, Exp::Name(next.clone()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        let binop = |op, a, b| Exp::BinOp(op, Box::new(a), Box::new(b));
+        let stm = Stm::Move(
+            Box::new(Exp::Temp(Temp(100))),
+            Box::new(binop(
+                BinOp::Plus,
+                binop(BinOp::Mul, Exp::Const(6), Exp::Const(7)),
+                binop(BinOp::Div, Exp::Const(1), Exp::Const(0)),
+            )),
+        );
+        assert_eq!(
+            fold_constants(stm),
+            Stm::Move(
+                Box::new(Exp::Temp(Temp(100))),
+                Box::new(binop(
+                    BinOp::Plus,
+                    Exp::Const(42),
+                    binop(BinOp::Div, Exp::Const(1), Exp::Const(0)),
+                )),
+            )
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_random_trees_preserves_meaning() {
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        for _ in 0..500 {
+            let stm = random_stm(&mut rng, 4);
+            let expected = run(&stm);
+            assert!(expected.is_ok(), "running {}: {:?}", stm, expected);
+            let folded = run(&fold_constants(stm.clone()));
+            assert_eq!(folded, expected, "folding {}", stm);
+
+            let linear = linearize(stm.clone());
+            assert_linear(&linear);
+            assert_eq!(run_list(&linear), expected, "linearizing {}", stm);
+
+            let (blocks, done) = basic_blocks(linear);
+            let scheduled = trace_schedule(blocks, done);
+            assert_linear(&scheduled);
+            assert_scheduled(&scheduled);
+            assert_eq!(run_list(&scheduled), expected, "scheduling {}", stm);
+        }
+    }
+}
@@ -0,0 +1,662 @@
+/// Implementations described in Chapter 9: instruction selection
+use std::fmt;
+
+use crate::chapter_6::{Label, Temp, X86_64Frame};
+use crate::chapter_7::{BinOp, Exp, RelOp, Stm};
+
+/// An instruction of abstract assembly language, whose registers are still temporaries
+///
+/// The assembly text names its operands by position: `` `s0 `` is the first source, `` `d0 `` the
+/// first destination and `` `j0 `` the first jump target.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    /// Any instruction. `jump` lists every label it may go to, including the next instruction
+    /// when it can fall through
+    Oper {
+        assem: String,
+        dst: Vec<Temp>,
+        src: Vec<Temp>,
+        jump: Option<Vec<Label>>,
+    },
+    Label {
+        assem: String,
+        label: Label,
+    },
+    /// A copy from one register to another, which register allocation may remove
+    Move {
+        assem: String,
+        dst: Temp,
+        src: Temp,
+    },
+}
+
+impl Instr {
+    pub fn oper(assem: &str, dst: Vec<Temp>, src: Vec<Temp>) -> Instr {
+        return Instr::Oper {
+            assem: assem.to_string(),
+            dst,
+            src,
+            jump: None,
+        };
+    }
+
+    /// The temporaries the instruction writes
+    pub fn defs(&self) -> Vec<Temp> {
+        match self {
+            Instr::Oper { dst, .. } => return dst.clone(),
+            Instr::Label { .. } => return Vec::new(),
+            Instr::Move { dst, .. } => return vec![*dst],
+        }
+    }
+
+    /// The temporaries the instruction reads
+    pub fn uses(&self) -> Vec<Temp> {
+        match self {
+            Instr::Oper { src, .. } => return src.clone(),
+            Instr::Label { .. } => return Vec::new(),
+            Instr::Move { src, .. } => return vec![*src],
+        }
+    }
+
+    /// The assembly text with every operand replaced by the name `name` gives its temporary
+    pub fn format(&self, name: &dyn Fn(Temp) -> String) -> String {
+        let (assem, dst, src, jump): (&str, &[Temp], &[Temp], &[Label]) = match self {
+            Instr::Oper {
+                assem,
+                dst,
+                src,
+                jump,
+            } => (assem, dst, src, jump.as_deref().unwrap_or(&[])),
+            Instr::Label { assem, .. } => return assem.clone(),
+            Instr::Move { assem, dst, src } => (
+                assem,
+                std::slice::from_ref(dst),
+                std::slice::from_ref(src),
+                &[],
+            ),
+        };
+        let mut result = String::new();
+        let mut chars = assem.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '`' {
+                result.push(c);
+                continue;
+            }
+            let kind = chars.next().expect("operand kind after `");
+            let mut index = 0;
+            while let Some(digit) = chars.peek().and_then(|d| d.to_digit(10)) {
+                index = index * 10 + digit as usize;
+                chars.next();
+            }
+            match kind {
+                's' => result.push_str(&name(src[index])),
+                'd' => result.push_str(&name(dst[index])),
+                'j' => result.push_str(&jump[index].0),
+                _ => panic!("unknown operand kind `{}", kind),
+            }
+        }
+        return result;
+    }
+}
+
+/// The name of a temporary in x86-64 assembly: a register name, or `t` and its number
+pub fn x86_64_name(t: Temp) -> String {
+    match X86_64Frame::register_name(t) {
+        Some(name) => return name.to_string(),
+        None => return t.to_string(),
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}", self.format(&x86_64_name));
+    }
+}
+
+/// Select x86-64 instructions for the canonical statements of a function body by maximal munch
+///
+/// Every function call leaves its result in `%rax` and may overwrite every caller-save register.
+/// Arguments past the sixth are pushed, so the stack pointer must be 16-byte aligned between
+/// statements.
+pub fn codegen(stms: &[Stm]) -> Vec<Instr> {
+    let mut codegen = Codegen { instrs: Vec::new() };
+    for stm in stms {
+        codegen.munch_stm(stm);
+    }
+    return codegen.instrs;
+}
+
+/// Whether a constant can be an immediate operand, which x86-64 sign extends from 32 bits
+fn fits_imm(k: i64) -> bool {
+    return i32::try_from(k).is_ok();
+}
+
+/// The memory operand for a displacement from the register in `` `s{index} ``
+fn memory_operand(offset: i64, index: usize) -> String {
+    if offset == 0 {
+        return format!("(`s{})", index);
+    }
+    return format!("{}(`s{})", offset, index);
+}
+
+fn jump_mnemonic(op: RelOp) -> &'static str {
+    match op {
+        RelOp::Eq => return "je",
+        RelOp::Ne => return "jne",
+        RelOp::Lt => return "jl",
+        RelOp::Gt => return "jg",
+        RelOp::Le => return "jle",
+        RelOp::Ge => return "jge",
+        RelOp::ULt => return "jb",
+        RelOp::UGt => return "ja",
+        RelOp::ULe => return "jbe",
+        RelOp::UGe => return "jae",
+    }
+}
+
+struct Codegen {
+    instrs: Vec<Instr>,
+}
+
+impl Codegen {
+    fn emit(&mut self, instr: Instr) {
+        self.instrs.push(instr);
+    }
+
+    fn emit_move(&mut self, dst: Temp, src: Temp) {
+        self.emit(Instr::Move {
+            assem: "movq `s0, `d0".to_string(),
+            dst,
+            src,
+        });
+    }
+
+    fn emit_jump(&mut self, assem: &str, jump: Vec<Label>) {
+        self.emit(Instr::Oper {
+            assem: assem.to_string(),
+            dst: Vec::new(),
+            src: Vec::new(),
+            jump: Some(jump),
+        });
+    }
+
+    fn emit_label(&mut self, label: Label) {
+        self.emit(Instr::Label {
+            assem: format!("{}:", label),
+            label,
+        });
+    }
+
+    fn munch_stm(&mut self, stm: &Stm) {
+        match stm {
+            Stm::Move(dst, src) => self.munch_move(dst, src),
+            Stm::Exp(e) => match e.as_ref() {
+                Exp::Call(f, args) => self.munch_call(f, args),
+                _ => {
+                    self.munch_exp(e);
+                }
+            },
+            Stm::Jump(target, labels) => match target.as_ref() {
+                Exp::Name(label) => self.emit_jump("jmp `j0", vec![label.clone()]),
+                _ => {
+                    let address = self.munch_exp(target);
+                    self.emit(Instr::Oper {
+                        assem: "jmp *`s0".to_string(),
+                        dst: Vec::new(),
+                        src: vec![address],
+                        jump: Some(labels.clone()),
+                    });
+                }
+            },
+            Stm::CJump(op, a, b, t, f) => {
+                let a = self.munch_exp(a);
+                match b.as_ref() {
+                    Exp::Const(k) if fits_imm(*k) => {
+                        self.emit(Instr::oper(&format!("cmpq ${}, `s0", k), vec![], vec![a]))
+                    }
+                    _ => {
+                        let b = self.munch_exp(b);
+                        self.emit(Instr::oper("cmpq `s1, `s0", vec![], vec![a, b]));
+                    }
+                }
+                self.emit_jump(
+                    &format!("{} `j0", jump_mnemonic(*op)),
+                    vec![t.clone(), f.clone()],
+                );
+            }
+            Stm::Label(label) => self.emit_label(label.clone()),
+            Stm::Seq(..) => panic!("instruction selection needs canonical statements"),
+        }
+    }
+
+    fn munch_move(&mut self, dst: &Exp, src: &Exp) {
+        match dst {
+            Exp::Mem(address) => {
+                let (base, offset) = self.munch_address(address);
+                let operand = memory_operand(offset, 0);
+                match src {
+                    Exp::Const(k) if fits_imm(*k) => self.emit(Instr::oper(
+                        &format!("movq ${}, {}", k, operand),
+                        vec![],
+                        vec![base],
+                    )),
+                    _ => {
+                        let value = self.munch_exp(src);
+                        self.emit(Instr::oper(
+                            &format!("movq `s1, {}", operand),
+                            vec![],
+                            vec![base, value],
+                        ));
+                    }
+                }
+            }
+            Exp::Temp(t) => match src {
+                Exp::Call(f, args) => {
+                    self.munch_call(f, args);
+                    self.emit_move(*t, X86_64Frame::RAX);
+                }
+                Exp::Const(k) => self.load_const(*k, *t),
+                Exp::Name(label) => self.emit(Instr::oper(
+                    &format!("leaq {}(%rip), `d0", label),
+                    vec![*t],
+                    vec![],
+                )),
+                Exp::Mem(address) => self.load(address, *t),
+                _ => {
+                    let value = self.munch_exp(src);
+                    self.emit_move(*t, value);
+                }
+            },
+            _ => panic!("cannot move into {}", dst),
+        }
+    }
+
+    /// Compute an address as a register plus a constant displacement
+    fn munch_address(&mut self, address: &Exp) -> (Temp, i64) {
+        match address {
+            Exp::BinOp(BinOp::Plus, base, offset) | Exp::BinOp(BinOp::Plus, offset, base) if matches!(offset.as_ref(), Exp::Const(k) if fits_imm(*k)) =>
+            {
+                let Exp::Const(k) = offset.as_ref() else {
+                    unreachable!()
+                };
+                return (self.munch_exp(base), *k);
+            }
+            Exp::BinOp(BinOp::Minus, base, offset) if matches!(offset.as_ref(), Exp::Const(k) if fits_imm(-*k)) =>
+            {
+                let Exp::Const(k) = offset.as_ref() else {
+                    unreachable!()
+                };
+                return (self.munch_exp(base), -k);
+            }
+            _ => return (self.munch_exp(address), 0),
+        }
+    }
+
+    fn load(&mut self, address: &Exp, dst: Temp) {
+        let (base, offset) = self.munch_address(address);
+        self.emit(Instr::oper(
+            &format!("movq {}, `d0", memory_operand(offset, 0)),
+            vec![dst],
+            vec![base],
+        ));
+    }
+
+    fn load_const(&mut self, k: i64, dst: Temp) {
+        let mnemonic = if fits_imm(k) { "movq" } else { "movabsq" };
+        self.emit(Instr::oper(
+            &format!("{} ${}, `d0", mnemonic, k),
+            vec![dst],
+            vec![],
+        ));
+    }
+
+    /// Emit instructions computing an expression and return the temporary holding its value
+    fn munch_exp(&mut self, exp: &Exp) -> Temp {
+        match exp {
+            Exp::Temp(t) => return *t,
+            Exp::Const(_) | Exp::Name(_) | Exp::Mem(_) | Exp::Call(..) => {
+                let t = Temp::new();
+                self.munch_move(&Exp::Temp(t), exp);
+                return t;
+            }
+            Exp::BinOp(op, a, b) => return self.munch_bin_op(*op, a, b),
+            Exp::Eseq(..) => panic!("instruction selection needs canonical statements"),
+        }
+    }
+
+    fn munch_bin_op(&mut self, op: BinOp, a: &Exp, b: &Exp) -> Temp {
+        let d = Temp::new();
+        let mnemonic = match op {
+            BinOp::Plus => "addq",
+            BinOp::Minus => "subq",
+            BinOp::Mul => "imulq",
+            BinOp::And => "andq",
+            BinOp::Or => "orq",
+            BinOp::Xor => "xorq",
+            BinOp::LShift => "salq",
+            BinOp::RShift => "shrq",
+            BinOp::ARShift => "sarq",
+            BinOp::Div => {
+                let a = self.munch_exp(a);
+                let b = self.munch_exp(b);
+                let (rax, rdx) = (X86_64Frame::RAX, X86_64Frame::RDX);
+                let (negate, divide, done) = (Label::new(), Label::new(), Label::new());
+                self.emit_move(rax, a);
+                // `idivq` traps on the one quotient that overflows, which wraps around instead
+                self.emit(Instr::oper("cmpq $-1, `s0", vec![], vec![b]));
+                self.emit_jump("jne `j0", vec![divide.clone(), negate.clone()]);
+                self.emit_label(negate);
+                self.emit(Instr::oper("negq `d0", vec![rax], vec![rax]));
+                self.emit_jump("jmp `j0", vec![done.clone()]);
+                self.emit_label(divide);
+                self.emit(Instr::oper("cqto", vec![rdx], vec![rax]));
+                self.emit(Instr::oper("idivq `s0", vec![rax, rdx], vec![b, rax, rdx]));
+                self.emit_label(done);
+                self.emit_move(d, rax);
+                return d;
+            }
+        };
+        let commutes = matches!(
+            op,
+            BinOp::Plus | BinOp::Mul | BinOp::And | BinOp::Or | BinOp::Xor
+        );
+        let (a, b) = match a {
+            Exp::Const(_) if commutes && !matches!(b, Exp::Const(_)) => (b, a),
+            _ => (a, b),
+        };
+        let is_shift = matches!(op, BinOp::LShift | BinOp::RShift | BinOp::ARShift);
+        match b {
+            Exp::Const(k) if is_shift => {
+                let a = self.munch_exp(a);
+                self.emit_move(d, a);
+                self.emit(Instr::oper(
+                    &format!("{} ${}, `d0", mnemonic, k & 63),
+                    vec![d],
+                    vec![d],
+                ));
+            }
+            Exp::Const(k) if fits_imm(*k) && op == BinOp::Mul => {
+                let a = self.munch_exp(a);
+                self.emit(Instr::oper(
+                    &format!("imulq ${}, `s0, `d0", k),
+                    vec![d],
+                    vec![a],
+                ));
+            }
+            Exp::Const(k) if fits_imm(*k) => {
+                let a = self.munch_exp(a);
+                self.emit_move(d, a);
+                self.emit(Instr::oper(
+                    &format!("{} ${}, `d0", mnemonic, k),
+                    vec![d],
+                    vec![d],
+                ));
+            }
+            _ if is_shift => {
+                let a = self.munch_exp(a);
+                let b = self.munch_exp(b);
+                self.emit_move(d, a);
+                self.emit_move(X86_64Frame::RCX, b);
+                self.emit(Instr::oper(
+                    &format!("{} %cl, `d0", mnemonic),
+                    vec![d],
+                    vec![X86_64Frame::RCX, d],
+                ));
+            }
+            _ => {
+                let a = self.munch_exp(a);
+                let b = self.munch_exp(b);
+                self.emit_move(d, a);
+                self.emit(Instr::oper(
+                    &format!("{} `s0, `d0", mnemonic),
+                    vec![d],
+                    vec![b, d],
+                ));
+            }
+        }
+        return d;
+    }
+
+    /// Emit a call, leaving its result in `%rax`
+    fn munch_call(&mut self, f: &Exp, args: &[Exp]) {
+        let target = match f {
+            Exp::Name(_) => None,
+            _ => Some(self.munch_exp(f)),
+        };
+        let values: Vec<Temp> = args.iter().map(|arg| self.munch_exp(arg)).collect();
+        let in_regs = values.len().min(X86_64Frame::ARG_REGS.len());
+
+        // Arguments past the registers are pushed last to first, keeping the stack aligned
+        let pushed = &values[in_regs..];
+        let padding = pushed.len() % 2;
+        if padding == 1 {
+            self.emit(Instr::oper("subq $8, %rsp", vec![], vec![]));
+        }
+        for value in pushed.iter().rev() {
+            self.emit(Instr::oper("pushq `s0", vec![], vec![*value]));
+        }
+        for (reg, value) in X86_64Frame::ARG_REGS.iter().zip(&values) {
+            self.emit_move(*reg, *value);
+        }
+
+        let mut src = X86_64Frame::ARG_REGS[..in_regs].to_vec();
+        let assem = match (f, target) {
+            (Exp::Name(label), _) => format!("call {}", label),
+            (_, Some(target)) => {
+                src.insert(0, target);
+                "call *`s0".to_string()
+            }
+            _ => unreachable!(),
+        };
+        self.emit(Instr::oper(&assem, X86_64Frame::CALLER_SAVES.to_vec(), src));
+        let popped = (pushed.len() + padding) * 8;
+        if popped > 0 {
+            self.emit(Instr::oper(
+                &format!("addq ${}, %rsp", popped),
+                vec![],
+                vec![],
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_6::Frame;
+    use crate::chapter_7::{translate, Frag, PRINT_INT};
+    use crate::chapter_8::canonicalize;
+    use crate::test_programs::*;
+
+    fn temp(t: Temp) -> Box<Exp> {
+        return Box::new(Exp::Temp(t));
+    }
+
+    fn constant(k: i64) -> Box<Exp> {
+        return Box::new(Exp::Const(k));
+    }
+
+    fn frame_slot(offset: i64) -> Box<Exp> {
+        return Box::new(Exp::Mem(Box::new(Exp::BinOp(
+            BinOp::Plus,
+            temp(X86_64Frame::fp()),
+            constant(offset),
+        ))));
+    }
+
+    fn assembly(stms: &[Stm]) -> Vec<String> {
+        return codegen(stms).iter().map(|i| i.to_string()).collect();
+    }
+
+    #[test]
+    fn test_instr_format() {
+        let (a, b) = (Temp(120), Temp(121));
+        let instr = Instr::oper("addq `s0, `d0", vec![a], vec![b, a]);
+        assert_eq!(instr.to_string(), "addq t121, t120");
+        let jump = Instr::Oper {
+            assem: "jl `j0".to_string(),
+            dst: vec![],
+            src: vec![],
+            jump: Some(vec![Label::named("L7"), Label::named("L8")]),
+        };
+        assert_eq!(jump.to_string(), "jl L7");
+        let mov = Instr::Move {
+            assem: "movq `s0, `d0".to_string(),
+            dst: X86_64Frame::RAX,
+            src: a,
+        };
+        assert_eq!(mov.to_string(), "movq t120, %rax");
+        assert_eq!(mov.defs(), vec![X86_64Frame::RAX]);
+        assert_eq!(mov.uses(), vec![a]);
+    }
+
+    #[test]
+    fn test_munch_frame_accesses_use_displacements() {
+        let t = Temp(130);
+        assert_eq!(
+            assembly(&[
+                Stm::Move(frame_slot(-8), constant(5)),
+                Stm::Move(temp(t), frame_slot(-16)),
+                Stm::Move(frame_slot(24), temp(t)),
+            ]),
+            vec![
+                "movq $5, -8(%rbp)",
+                "movq -16(%rbp), t130",
+                "movq t130, 24(%rbp)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_munch_arithmetic() {
+        let (a, b, c) = (Temp(140), Temp(141), Temp(142));
+        let sum = Exp::BinOp(BinOp::Plus, constant(3), temp(a));
+        let product = Exp::BinOp(BinOp::Mul, Box::new(sum), temp(b));
+        let code = assembly(&[Stm::Move(temp(c), Box::new(product))]);
+        assert_eq!(code.len(), 5);
+        assert!(code[1].starts_with("addq $3, "));
+        assert!(code[3].starts_with("imulq t141, "));
+        assert!(code[4].ends_with(", t142"));
+    }
+
+    #[test]
+    fn test_munch_large_constants() {
+        let t = Temp(150);
+        assert_eq!(
+            assembly(&[Stm::Move(temp(t), constant(1 << 40))]),
+            vec!["movabsq $1099511627776, t150"]
+        );
+    }
+
+    #[test]
+    fn test_munch_division_uses_rax_and_rdx() {
+        let (a, b, c) = (Temp(160), Temp(161), Temp(162));
+        let quotient = Exp::BinOp(BinOp::Div, temp(a), temp(b));
+        let code = codegen(&[Stm::Move(temp(c), Box::new(quotient))]);
+        let text: Vec<String> = code.iter().map(|i| i.to_string()).collect();
+        assert_eq!(text[0], "movq t160, %rax");
+        assert_eq!(text[7], "cqto");
+        assert_eq!(text[8], "idivq t161");
+        assert_eq!(code[8].defs(), vec![X86_64Frame::RAX, X86_64Frame::RDX]);
+        assert!(text[10].starts_with("movq %rax, t"));
+    }
+
+    /// Dividing by -1 negates instead, since `idivq` traps when the quotient overflows
+    #[test]
+    fn test_munch_division_by_minus_one_negates() {
+        let (a, b, c) = (Temp(163), Temp(164), Temp(165));
+        let quotient = Exp::BinOp(BinOp::Div, temp(a), temp(b));
+        let code = codegen(&[Stm::Move(temp(c), Box::new(quotient))]);
+        let text: Vec<String> = code.iter().map(|i| i.to_string()).collect();
+        assert_eq!(text[1], "cmpq $-1, t164");
+        assert!(text[2].starts_with("jne L"));
+        assert_eq!(text[4], "negq %rax");
+        assert!(text[5].starts_with("jmp L"));
+    }
+
+    #[test]
+    fn test_munch_cjump() {
+        let t = Temp(170);
+        let (yes, no) = (Label::named("yes"), Label::named("no"));
+        let code = codegen(&[Stm::CJump(
+            RelOp::Ge,
+            temp(t),
+            constant(10),
+            yes.clone(),
+            no.clone(),
+        )]);
+        assert_eq!(code[0].to_string(), "cmpq $10, t170");
+        assert_eq!(
+            code[1],
+            Instr::Oper {
+                assem: "jge `j0".to_string(),
+                dst: vec![],
+                src: vec![],
+                jump: Some(vec![yes, no]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_munch_call_with_stack_arguments() {
+        let args: Vec<Exp> = (1..=9).map(Exp::Const).collect();
+        let call = Exp::Call(Box::new(Exp::Name(Label::named("f"))), args);
+        let code = assembly(&[Stm::Exp(Box::new(call))]);
+        let pushes: Vec<&String> = code.iter().filter(|i| i.starts_with("pushq")).collect();
+        assert_eq!(pushes.len(), 3);
+        let call_at = code.iter().position(|i| i == "call f").unwrap();
+        assert_eq!(
+            code[call_at - 6],
+            "movq t".to_string() + &code[call_at - 6][6..]
+        );
+        assert!(code[call_at - 1].ends_with(", %r9"));
+        assert_eq!(code[call_at + 1], "addq $32, %rsp");
+        assert!(code.contains(&"subq $8, %rsp".to_string()));
+    }
+
+    #[test]
+    fn test_munch_call_result() {
+        let t = Temp(180);
+        let call = Exp::Call(
+            Box::new(Exp::Name(Label::named(PRINT_INT))),
+            vec![Exp::Const(1)],
+        );
+        let code = codegen(&[Stm::Move(temp(t), Box::new(call))]);
+        let text: Vec<String> = code.iter().map(|i| i.to_string()).collect();
+        assert_eq!(text.last().unwrap(), "movq %rax, t180");
+        let call = &code[code.len() - 2];
+        assert!(call.defs().contains(&X86_64Frame::RAX));
+        assert_eq!(call.uses(), vec![X86_64Frame::RDI]);
+    }
+
+    #[test]
+    fn test_codegen_programs() {
+        for (name, source) in PROGRAMS {
+            for frag in translate::<X86_64Frame>(&load(source)) {
+                if let Frag::Proc { body, .. } = frag {
+                    let code = codegen(&canonicalize(body));
+                    let labels: Vec<&Label> = code
+                        .iter()
+                        .filter_map(|i| match i {
+                            Instr::Label { label, .. } => Some(label),
+                            _ => None,
+                        })
+                        .collect();
+                    for instr in &code {
+                        // Formatting checks every operand exists
+                        instr.to_string();
+                        if let Instr::Oper {
+                            jump: Some(targets),
+                            ..
+                        } = instr
+                        {
+                            for target in targets {
+                                assert!(labels.contains(&target), "program {}", name);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
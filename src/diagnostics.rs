@@ -0,0 +1,494 @@
+/// Errors and warnings from every phase of the compiler, rendered with the source they point at
+///
+/// A diagnostic has a severity, a code naming the phase it comes from, a message, labelled spans
+/// of the source and any notes and help. It is shown to people in the style of rustc:
+///
+/// ```text
+/// error[E0002]: cannot assign twice to immutable variable `x`
+///  --> program.txt:2:1
+///   |
+/// 1 | let x = 1;
+///   | --- `x` is declared here
+/// 2 | x = 2;
+///   | ^
+///   |
+///   = help: make `x` mutable by declaring it with `mut`
+/// ```
+///
+/// and to tools as a line of JSON holding the same information. Positions in JSON are byte
+/// offsets, with lines and columns counted from 1.
+use std::fmt;
+
+use crate::chapter_2::{line_col, LexError};
+use crate::chapter_3::ParseError;
+use crate::chapter_4::Pos;
+use crate::chapter_5::{TypeError, TypeWarning};
+use crate::interpreter::RuntimeError;
+
+/// Errors found while lexing or parsing
+pub const SYNTAX_ERROR: &str = "E0001";
+/// Errors found by the type checker
+pub const TYPE_ERROR: &str = "E0002";
+/// Errors that stop a running program
+pub const RUNTIME_ERROR: &str = "E0003";
+/// Code the type checker accepts but that is likely a mistake
+pub const TYPE_WARNING: &str = "W0002";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    /// Information on its own, which no phase reports yet
+    #[allow(dead_code)]
+    Note,
+}
+
+/// The bytes from `start` up to `end` of the source. An empty span stands for the token
+/// starting there
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+/// A span with a message. The primary labels show where the problem is, the others why
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+/// The output format for diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    #[default]
+    Human,
+    Json,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+impl Span {
+    pub fn new(start: Pos, end: Pos) -> Span {
+        return Span { start, end };
+    }
+
+    /// The span of the token at a position
+    pub fn at(pos: Pos) -> Span {
+        return Span::new(pos, pos);
+    }
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: &str) -> Diagnostic {
+        return Diagnostic {
+            severity,
+            code: None,
+            message: message.to_string(),
+            labels: vec![],
+            notes: vec![],
+            help: vec![],
+        };
+    }
+
+    pub fn error(message: &str) -> Diagnostic {
+        return Diagnostic::new(Severity::Error, message);
+    }
+
+    pub fn warning(message: &str) -> Diagnostic {
+        return Diagnostic::new(Severity::Warning, message);
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Diagnostic {
+        self.code = Some(code);
+        return self;
+    }
+
+    /// Add a primary label
+    pub fn with_label(mut self, span: Span, message: &str) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: message.to_string(),
+            primary: true,
+        });
+        return self;
+    }
+
+    /// Add a secondary label
+    pub fn with_secondary(mut self, span: Span, message: &str) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: message.to_string(),
+            primary: false,
+        });
+        return self;
+    }
+
+    pub fn with_note(mut self, note: &str) -> Diagnostic {
+        self.notes.push(note.to_string());
+        return self;
+    }
+
+    pub fn with_help(mut self, help: &str) -> Diagnostic {
+        self.help.push(help.to_string());
+        return self;
+    }
+
+    /// Render in the chosen format, for the source file `name`. Either way the result ends in a
+    /// newline
+    pub fn format(&self, format: Format, name: &str, source: &str) -> String {
+        match format {
+            Format::Human => return self.render(name, source),
+            Format::Json => return format!("{}\n", self.to_json(name, source)),
+        }
+    }
+
+    /// Render for people, quoting the lines of the source the labels are on
+    pub fn render(&self, name: &str, source: &str) -> String {
+        let mut out = format!("{}", self.severity);
+        if let Some(code) = self.code {
+            out.push_str(&format!("[{}]", code));
+        }
+        out.push_str(&format!(": {}\n", self.message));
+        let mut labels: Vec<(usize, usize, usize, &Label)> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let (line, start, end) = line_columns(source, resolve(source, label.span));
+                return (line, start, end, label);
+            })
+            .collect();
+        labels.sort_by_key(|(line, start, _, label)| (*line, *start, !label.primary));
+        let last_line = labels.iter().map(|(line, ..)| *line).max().unwrap_or(1);
+        let pad = " ".repeat(last_line.to_string().len());
+        let primary = self
+            .labels
+            .iter()
+            .find(|l| l.primary)
+            .or(self.labels.first());
+        if let Some(label) = primary {
+            let (line, col) = line_col(source, resolve(source, label.span).start);
+            out.push_str(&format!("{}--> {}:{}:{}\n", pad, name, line, col));
+            out.push_str(&format!("{} |\n", pad));
+        }
+        let lines: Vec<&str> = source.split('\n').collect();
+        let mut previous = None;
+        for (i, (line, start, end, label)) in labels.iter().enumerate() {
+            if previous != Some(*line) {
+                if previous.is_some_and(|p| line - p > 1) {
+                    out.push_str("...\n");
+                }
+                let text = lines.get(line - 1).copied().unwrap_or("");
+                let text = expand_tabs(text.trim_end_matches('\r'));
+                out.push_str(&format!("{:>w$} | {}\n", line, text, w = pad.len()));
+                previous = Some(*line);
+            }
+            let mark = if label.primary { "^" } else { "-" };
+            let underline = format!(
+                "{}{}",
+                " ".repeat(*start),
+                mark.repeat((end - start).max(1))
+            );
+            let text = format!("{} {}", underline, label.message);
+            out.push_str(&format!("{} | {}\n", pad, text.trim_end()));
+            if i + 1 == labels.len() && !(self.notes.is_empty() && self.help.is_empty()) {
+                out.push_str(&format!("{} |\n", pad));
+            }
+        }
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", pad, note));
+        }
+        for help in &self.help {
+            out.push_str(&format!("{} = help: {}\n", pad, help));
+        }
+        return out;
+    }
+
+    /// One line of JSON, including the rendering for people under `rendered`
+    pub fn to_json(&self, name: &str, source: &str) -> String {
+        let position = |pos: Pos| {
+            let (line, column) = line_col(source, pos);
+            return format!(
+                "{{\"offset\":{},\"line\":{},\"column\":{}}}",
+                pos, line, column
+            );
+        };
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let span = resolve(source, label.span);
+                return format!(
+                    "{{\"primary\":{},\"message\":{},\"start\":{},\"end\":{}}}",
+                    label.primary,
+                    json_string(&label.message),
+                    position(span.start),
+                    position(span.end)
+                );
+            })
+            .collect();
+        let strings = |items: &[String]| {
+            let items: Vec<String> = items.iter().map(|s| json_string(s)).collect();
+            return format!("[{}]", items.join(","));
+        };
+        return format!(
+            "{{\"severity\":\"{}\",\"code\":{},\"message\":{},\"file\":{},\"labels\":[{}],\"notes\":{},\"help\":{},\"rendered\":{}}}",
+            self.severity,
+            self.code.map_or("null".to_string(), json_string),
+            json_string(&self.message),
+            json_string(name),
+            labels.join(","),
+            strings(&self.notes),
+            strings(&self.help),
+            json_string(&self.render(name, source))
+        );
+    }
+}
+
+/// The span a label covers: an empty span is widened to the token starting there. A span at the
+/// very end of the source, after its last newline, is moved onto the last line
+pub fn resolve(source: &str, span: Span) -> Span {
+    let mut span = Span::new(span.start.min(source.len()), span.end.min(source.len()));
+    if span.start == source.len() && source.ends_with('\n') {
+        span.start -= 1;
+        span.end = span.start;
+        return span;
+    }
+    if span.start < span.end {
+        return span;
+    }
+    let rest = &source[span.start..];
+    let word = |c: char| c.is_alphanumeric() || c == '_';
+    let len = match rest.chars().next() {
+        None => 0,
+        // A string runs to its closing quote, or to the end of the line if it is never closed
+        Some('"') => match rest[1..].find(['"', '\n']) {
+            Some(i) if rest.as_bytes()[i + 1] == b'"' => i + 2,
+            Some(i) => i + 1,
+            None => rest.len(),
+        },
+        Some(c) if c.is_ascii_digit() => rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len()),
+        Some(c) if word(c) => rest.find(|c: char| !word(c)).unwrap_or(rest.len()),
+        Some(c)
+            if ["==", "<=", ">=", "<>"]
+                .iter()
+                .any(|op| rest.starts_with(op)) =>
+        {
+            c.len_utf8() + 1
+        }
+        Some(c) => c.len_utf8(),
+    };
+    span.end = span.start + len;
+    return span;
+}
+
+/// The line a span starts on, and the columns on it it covers as they are displayed. A span
+/// running onto later lines is cut at the end of its first
+fn line_columns(source: &str, span: Span) -> (usize, usize, usize) {
+    let (line, _) = line_col(source, span.start);
+    let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[line_start..]
+        .find('\n')
+        .map_or(source.len(), |i| line_start + i);
+    let width = |text: &str| expand_tabs(text).chars().count();
+    let start = width(&source[line_start..span.start]);
+    let end = width(&source[line_start..span.end.clamp(span.start, line_end)]);
+    return (line, start, end);
+}
+
+fn expand_tabs(text: &str) -> String {
+    return text.replace('\t', "    ");
+}
+
+/// A string as a JSON string literal
+pub fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+impl From<LexError> for Diagnostic {
+    fn from(error: LexError) -> Diagnostic {
+        return Diagnostic::error(&error.message)
+            .with_code(SYNTAX_ERROR)
+            .with_label(Span::at(error.pos), "");
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(&error.message)
+            .with_code(SYNTAX_ERROR)
+            .with_label(Span::at(error.pos), "");
+        for (pos, message) in &error.related {
+            diagnostic = diagnostic.with_secondary(Span::at(*pos), message);
+        }
+        return diagnostic;
+    }
+}
+
+impl From<TypeError> for Diagnostic {
+    fn from(error: TypeError) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(&error.message)
+            .with_code(TYPE_ERROR)
+            .with_label(Span::at(error.pos), "");
+        for (pos, message) in &error.related {
+            diagnostic = diagnostic.with_secondary(Span::at(*pos), message);
+        }
+        if let Some(help) = &error.help {
+            diagnostic = diagnostic.with_help(help);
+        }
+        return diagnostic;
+    }
+}
+
+impl From<TypeWarning> for Diagnostic {
+    fn from(warning: TypeWarning) -> Diagnostic {
+        return Diagnostic::warning(&warning.message)
+            .with_code(TYPE_WARNING)
+            .with_label(Span::at(warning.pos), "")
+            .with_help(&warning.help);
+    }
+}
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(error: RuntimeError) -> Diagnostic {
+        return Diagnostic::error(&error.message)
+            .with_code(RUNTIME_ERROR)
+            .with_label(Span::at(error.pos), "");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_3::parse;
+    use crate::chapter_5::check;
+
+    #[test]
+    fn test_render_labels_notes_and_help() {
+        let source = "let x = 1;\nlet y = 2;\n\tx = y;\n";
+        let diagnostic = Diagnostic::error("cannot assign twice")
+            .with_code(TYPE_ERROR)
+            .with_label(Span::new(12 + 11, 12 + 12), "assigned here")
+            .with_secondary(Span::at(4), "declared here")
+            .with_note("variables are immutable by default")
+            .with_help("use `let mut`");
+        assert_eq!(
+            diagnostic.render("a.txt", source),
+            "\
+error[E0002]: cannot assign twice
+ --> a.txt:3:2
+  |
+1 | let x = 1;
+  |     - declared here
+...
+3 |     x = y;
+  |     ^ assigned here
+  |
+  = note: variables are immutable by default
+  = help: use `let mut`
+"
+        );
+    }
+
+    #[test]
+    fn test_render_without_labels() {
+        let diagnostic = Diagnostic::warning("nothing to see").with_note("really");
+        assert_eq!(
+            diagnostic.render("a.txt", ""),
+            "warning: nothing to see\n  = note: really\n"
+        );
+    }
+
+    #[test]
+    fn test_spans_widen_to_tokens() {
+        fn widened(source: &str, pos: Pos) -> &str {
+            let span = resolve(source, Span::at(pos));
+            return &source[span.start..span.end];
+        }
+        let source = "print(count <= 12.5, \"a b\", \"open";
+        assert_eq!(widened(source, 6), "count");
+        assert_eq!(widened(source, 12), "<=");
+        assert_eq!(widened(source, 15), "12.5");
+        assert_eq!(widened(source, 21), "\"a b\"");
+        assert_eq!(widened(source, 28), "\"open");
+        assert_eq!(widened(source, source.len()), "");
+        // The end of a source ending in a newline is shown at the end of its last line
+        assert_eq!(resolve("x\n", Span::at(2)), Span::at(1));
+    }
+
+    #[test]
+    fn test_diagnostics_from_every_phase() {
+        let source = "fn f(): int {\n  1";
+        let diagnostic: Diagnostic = parse(source).unwrap_err().into();
+        assert_eq!(
+            diagnostic.render("p", source),
+            "\
+error[E0001]: expected `;` or `}`, found end of input
+ --> p:2:4
+  |
+1 | fn f(): int {
+  |             - this `{` is never closed
+2 |   1
+  |    ^
+"
+        );
+        let program = parse("let x = True;\nprint(x + 1);").unwrap();
+        let diagnostic: Diagnostic = check(&program).unwrap_err().into();
+        assert_eq!(diagnostic.code, Some(TYPE_ERROR));
+        assert_eq!(diagnostic.labels[0].span, Span::at(22));
+    }
+
+    #[test]
+    fn test_json() {
+        let source = "let s = \"\\\";\nx";
+        let diagnostic = Diagnostic::error("undefined variable `x`")
+            .with_code(TYPE_ERROR)
+            .with_label(Span::at(13), "not found")
+            .with_help("declare it");
+        let json = diagnostic.to_json("dir\\a.txt", source);
+        assert!(json.starts_with(
+            "{\"severity\":\"error\",\"code\":\"E0002\",\"message\":\"undefined variable `x`\",\
+             \"file\":\"dir\\\\a.txt\",\"labels\":[{\"primary\":true,\"message\":\"not found\",\
+             \"start\":{\"offset\":13,\"line\":2,\"column\":1},\
+             \"end\":{\"offset\":14,\"line\":2,\"column\":2}}],\
+             \"notes\":[],\"help\":[\"declare it\"],\"rendered\":\"error[E0002]: undefined"
+        ));
+        assert!(!json.contains('\n'));
+        assert_eq!(json_string("a\"\\\n\u{1}é"), "\"a\\\"\\\\\\n\\u0001é\"");
+    }
+}
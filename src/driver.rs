@@ -0,0 +1,1379 @@
+/// The command line driver, compiling and running programs through any of the back ends
+///
+/// ```text
+/// compile [options] [file]   build an executable, or the target's output file
+/// run [options] [file]       build and run a program, exiting with its exit code
+/// repl                       read, evaluate and print programs interactively
+/// fmt [--check] [file]      reprint a program in the canonical layout
+/// lsp                        serve editors over the Language Server Protocol
+/// bench                      compare the register allocators on the example programs, and the
+///                            virtual machine with the interpreter
+/// grammar [options] [file]   analyze a grammar in the notation of `grammar`, or generate an
+///                            LALR(1) parser for it
+/// ```
+///
+/// Without a file, or with `-`, the source is read from standard input. The exit code is 0 on
+/// success, 1 when the program has an error and 2 when the command line is wrong.
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::bytecode::{self, Bytecode};
+use crate::chapter_10::{dump_liveness, liveness, FlowGraph, InterferenceGraph};
+use crate::chapter_11::Allocator;
+use crate::chapter_12::{self, RUNTIME};
+use crate::chapter_2::{line_col, tokenize_with_positions, LexError};
+use crate::chapter_3::parse_recovering;
+use crate::chapter_4::*;
+use crate::chapter_5::check_with_warnings;
+use crate::chapter_6::{Frame, Label, Temp, X86_64Frame};
+use crate::chapter_7::{translate, Frag, Stm};
+use crate::chapter_8::{canonicalize, fold_constants};
+use crate::chapter_9::{self, Instr};
+use crate::diagnostics::{Diagnostic, Format, RUNTIME_ERROR};
+use crate::formatter;
+use crate::grammar::{self, Grammar, Ll1};
+use crate::interpreter::interpret;
+use crate::ir_interpreter::{self, IrProgram};
+use crate::lalr::Lalr;
+use crate::linear_scan;
+use crate::lsp::serve;
+use crate::repl::repl;
+use crate::riscv::{self, RiscVFrame};
+use crate::{c, llvm, wasm};
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+pub const USAGE: &str = "\
+usage: modern_compiler_implementation_in_rust <command> [options] [file]
+
+commands:
+    compile    build an executable, or the output file of the target
+    run        build and run the program
+    repl       evaluate programs as they are typed, see `:help`
+    fmt        reprint the program in the canonical layout, in place when it is a file
+    lsp        serve editors over the Language Server Protocol on standard input and output
+    bench      time the register allocators on the example programs and count their spills,
+               and time the bytecode virtual machine against the interpreter
+    grammar    report the nullable, FIRST and FOLLOW sets of a grammar and the conflicts of
+               its LL(1) table and its LALR(1) tables
+
+options:
+    -o <path>                where to write the output, `-` for standard output except for
+                             an executable
+    --target=<target>        x86-64 (default), riscv, llvm, c, wasm, bytecode, interpreter, or
+                             ir to run the intermediate code on its interpreter
+    --emit=<stage>           write a stage instead: tokens, ast, typed-ast, ir, canon, asm,
+                             liveness or bytecode. Liveness is the instructions of x86-64 or
+                             riscv before register allocation with the temporaries live around
+                             each, and the interference graph
+    -O0, -O1, -O2            optimization level, -O1 by default. -O2 also folds constants in
+                             the IR of x86-64 and riscv, and is passed on to cc, opt and llc
+    --regalloc=<allocator>   linear-scan or graph-coloring, for x86-64 and riscv. Linear scan
+                             is the default at -O0 and graph coloring above it
+    --error-format=<format>  human (default), or json for one diagnostic to a line
+    --check                  with `fmt`, only check that the program is formatted
+    --rewrite                with `grammar`, write it without left recursion and left
+                             factored instead
+    --parser                 with `grammar`, write a Rust module parsing with its LALR(1)
+                             tables instead
+    --parse=<path>           with `grammar`, parse the file and write its tree, predictively
+                             when the grammar is LL(1) and with the LALR(1) tables otherwise
+
+Without a file, or with `-`, the program is read from standard input.
+";
+
+/// The Node.js host the WebAssembly target runs under
+const WASM_HOST: &str = include_str!("../runtime/wasm_host.js");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Compile,
+    Run,
+    Repl,
+    Fmt,
+    Lsp,
+    Bench,
+    Grammar,
+}
+
+/// What `grammar` writes
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum GrammarOutput {
+    #[default]
+    Report,
+    Rewrite,
+    Parser,
+    /// The parse tree of a file
+    Parse(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    TypedAst,
+    Ir,
+    Canon,
+    Asm,
+    Liveness,
+    Bytecode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Target {
+    #[default]
+    X86_64,
+    RiscV,
+    Llvm,
+    C,
+    Wasm,
+    Bytecode,
+    Ir,
+    Interpreter,
+}
+
+/// Everything the command line asks for
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub mode: Mode,
+    /// The source file, or `None` for standard input
+    pub input: Option<PathBuf>,
+    /// Where to write the output, or `None` to derive it from the input
+    pub output: Option<PathBuf>,
+    pub emit: Option<Emit>,
+    pub target: Target,
+    pub opt_level: u8,
+    /// The register allocator, or `None` to pick one by the optimization level
+    pub allocator: Option<Allocator>,
+    pub error_format: Format,
+    /// Whether `fmt` only checks that the program is formatted
+    pub check: bool,
+    pub grammar: GrammarOutput,
+}
+
+/// Why the driver stopped, and the exit code to stop with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub code: i32,
+    pub message: String,
+}
+
+impl Failure {
+    fn usage(message: &str) -> Failure {
+        return Failure {
+            code: EXIT_USAGE,
+            message: message.to_string(),
+        };
+    }
+
+    fn error(message: &str) -> Failure {
+        return Failure {
+            code: EXIT_FAILURE,
+            message: message.to_string(),
+        };
+    }
+}
+
+impl Target {
+    fn parse(name: &str) -> Option<Target> {
+        return match name {
+            "x86-64" | "x86_64" => Some(Target::X86_64),
+            "riscv" | "riscv64" => Some(Target::RiscV),
+            "llvm" => Some(Target::Llvm),
+            "c" => Some(Target::C),
+            "wasm" => Some(Target::Wasm),
+            "bytecode" => Some(Target::Bytecode),
+            "ir" => Some(Target::Ir),
+            "interpreter" => Some(Target::Interpreter),
+            _ => None,
+        };
+    }
+
+    /// The extension of the file `compile` writes, or `None` when it writes an executable
+    fn extension(self) -> Option<&'static str> {
+        return match self {
+            Target::X86_64 | Target::Llvm | Target::C => None,
+            Target::RiscV => Some("s"),
+            Target::Wasm => Some("wasm"),
+            Target::Bytecode => Some("mcib"),
+            Target::Ir | Target::Interpreter => None,
+        };
+    }
+}
+
+impl Emit {
+    fn parse(name: &str) -> Option<Emit> {
+        return match name {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "typed-ast" => Some(Emit::TypedAst),
+            "ir" => Some(Emit::Ir),
+            "canon" => Some(Emit::Canon),
+            "asm" => Some(Emit::Asm),
+            "liveness" => Some(Emit::Liveness),
+            "bytecode" => Some(Emit::Bytecode),
+            _ => None,
+        };
+    }
+}
+
+/// Read the command line, without the program name
+pub fn parse_args(args: &[String]) -> Result<Options, Failure> {
+    if args.len() > 1 && (args[0] == "repl" || args[0] == "bench") {
+        return Err(Failure::usage(&format!("`{}` takes no options", args[0])));
+    }
+    // Editors commonly start servers with `--stdio`, the only transport there is
+    if args.len() > 1 && args[0] == "lsp" && args[1..] != ["--stdio"] {
+        return Err(Failure::usage("`lsp` takes no options but `--stdio`"));
+    }
+    let mut args = args.iter();
+    let mode = match args.next().map(|a| a.as_str()) {
+        Some("compile") => Mode::Compile,
+        Some("run") => Mode::Run,
+        Some("repl") => Mode::Repl,
+        Some("fmt") => Mode::Fmt,
+        Some("lsp") => Mode::Lsp,
+        Some("bench") => Mode::Bench,
+        Some("grammar") => Mode::Grammar,
+        Some(other) => return Err(Failure::usage(&format!("unknown command `{}`", other))),
+        None => return Err(Failure::usage(USAGE)),
+    };
+    let mut options = Options {
+        mode,
+        input: None,
+        output: None,
+        emit: None,
+        target: Target::default(),
+        opt_level: 1,
+        allocator: None,
+        error_format: Format::default(),
+        check: false,
+        grammar: GrammarOutput::default(),
+    };
+    let mut input = None;
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            let Some(path) = args.next() else {
+                return Err(Failure::usage("`-o` needs a path"));
+            };
+            options.output = Some(PathBuf::from(path));
+        } else if let Some(name) = arg.strip_prefix("--target=") {
+            options.target = Target::parse(name)
+                .ok_or_else(|| Failure::usage(&format!("unknown target `{}`", name)))?;
+        } else if let Some(name) = arg.strip_prefix("--error-format=") {
+            options.error_format = match name {
+                "human" => Format::Human,
+                "json" => Format::Json,
+                _ => return Err(Failure::usage(&format!("unknown error format `{}`", name))),
+            };
+        } else if let Some(name) = arg.strip_prefix("--emit=") {
+            options.emit = Some(
+                Emit::parse(name)
+                    .ok_or_else(|| Failure::usage(&format!("unknown stage `{}`", name)))?,
+            );
+        } else if let Some(name) = arg.strip_prefix("--regalloc=") {
+            options.allocator = match name {
+                "linear-scan" => Some(Allocator::LinearScan),
+                "graph-coloring" => Some(Allocator::GraphColoring),
+                _ => return Err(Failure::usage(&format!("unknown allocator `{}`", name))),
+            };
+        } else if let Some(level) = arg.strip_prefix("-O") {
+            options.opt_level = match level {
+                "0" => 0,
+                "1" => 1,
+                "2" => 2,
+                _ => return Err(Failure::usage(&format!("unknown optimization `{}`", arg))),
+            };
+        } else if arg == "--stdio" && options.mode == Mode::Lsp {
+        } else if arg == "--check" && options.mode == Mode::Fmt {
+            options.check = true;
+        } else if arg == "--rewrite" && options.mode == Mode::Grammar {
+            options.grammar = GrammarOutput::Rewrite;
+        } else if arg == "--parser" && options.mode == Mode::Grammar {
+            options.grammar = GrammarOutput::Parser;
+        } else if let Some(path) = arg.strip_prefix("--parse=") {
+            if options.mode != Mode::Grammar {
+                return Err(Failure::usage("`--parse` only applies to `grammar`"));
+            }
+            options.grammar = GrammarOutput::Parse(PathBuf::from(path));
+        } else if arg.starts_with('-') && arg != "-" {
+            return Err(Failure::usage(&format!("unknown option `{}`", arg)));
+        } else if input.replace(arg).is_some() {
+            return Err(Failure::usage("more than one input file"));
+        }
+    }
+    options.input = input.filter(|a| *a != "-").map(PathBuf::from);
+    if options.mode != Mode::Compile && options.emit.is_some() {
+        return Err(Failure::usage("`--emit` only applies to `compile`"));
+    }
+    return Ok(options);
+}
+
+/// Run the driver on a command line, returning the exit code. The program and its compiled
+/// output read and write through the given streams
+pub fn main(
+    args: &[String],
+    stdin: &mut (dyn Read + Send),
+    stdout: &mut (dyn Write + Send),
+    stderr: &mut dyn Write,
+) -> i32 {
+    let result = parse_args(args).and_then(|options| drive(&options, stdin, stdout, stderr));
+    match result {
+        Ok(code) => return code,
+        Err(failure) => {
+            let _ = write!(stderr, "{}", failure.message);
+            if !failure.message.ends_with('\n') {
+                let _ = writeln!(stderr);
+            }
+            return failure.code;
+        }
+    }
+}
+
+fn drive(
+    options: &Options,
+    stdin: &mut (dyn Read + Send),
+    stdout: &mut (dyn Write + Send),
+    stderr: &mut dyn Write,
+) -> Result<i32, Failure> {
+    if options.mode == Mode::Repl {
+        repl(&mut BufReader::new(stdin), stdout).map_err(|e| Failure::error(&e.to_string()))?;
+        return Ok(0);
+    }
+    if options.mode == Mode::Lsp {
+        return serve(&mut BufReader::new(stdin), stdout)
+            .map_err(|e| Failure::error(&e.to_string()));
+    }
+    if options.mode == Mode::Bench {
+        let report = format!("{}\n{}", linear_scan::benchmark(), bytecode::benchmark());
+        return write_output(options, report.as_bytes(), stdout);
+    }
+    let (name, bytes) = read_input(options.input.as_deref(), stdin)?;
+    if options.mode == Mode::Run && bytes.starts_with(bytecode::MAGIC) {
+        let code = Bytecode::from_bytes(&bytes).map_err(|e| Failure::error(&e))?;
+        // Without the source there is nothing for the labels to point at
+        let report = |diagnostic: Diagnostic| {
+            let diagnostic = Diagnostic {
+                labels: vec![],
+                ..diagnostic
+            };
+            return diagnostic.format(options.error_format, &name, "");
+        };
+        return run_bytecode(&code, &report, stdout, stderr);
+    }
+    let source = String::from_utf8(bytes)
+        .map_err(|_| Failure::error(&format!("{}: not valid UTF-8", name)))?;
+    if options.mode == Mode::Grammar {
+        return analyze_grammar(options, &name, &source, stdout);
+    }
+    let report = |diagnostic: Diagnostic| diagnostic.format(options.error_format, &name, &source);
+    let fail = |diagnostic: Diagnostic| Failure::error(&report(diagnostic));
+    if options.mode == Mode::Fmt {
+        let formatted = formatter::format(&source).map_err(fail)?;
+        if options.check && formatted != source {
+            return Err(Failure::error(&format!("{} is not formatted", name)));
+        }
+        match (&options.output, &options.input) {
+            _ if options.check => return Ok(0),
+            (None, Some(path)) if formatted != source => std::fs::write(path, formatted)
+                .map_err(|e| Failure::error(&format!("{}: {}", name, e)))?,
+            (None, Some(_)) => {}
+            _ => return write_output(options, formatted.as_bytes(), stdout),
+        }
+        return Ok(0);
+    }
+    if options.emit == Some(Emit::Tokens) {
+        let tokens = dump_tokens(&source).map_err(|e| fail(e.into()))?;
+        return write_output(options, tokens.as_bytes(), stdout);
+    }
+    let (program, errors) = parse_recovering(&source);
+    if !errors.is_empty() {
+        let reports: String = errors.into_iter().map(|e| report(e.into())).collect();
+        return Err(Failure::error(&reports));
+    }
+    if options.emit == Some(Emit::Ast) {
+        return write_output(options, dump_ast(&program).as_bytes(), stdout);
+    }
+    let warnings = check_with_warnings(&program).map_err(|e| fail(e.into()))?;
+    for warning in warnings {
+        let _ = write!(stderr, "{}", report(warning.into()));
+    }
+    match options.mode {
+        Mode::Compile => return compile(options, &name, &program, stdout),
+        Mode::Run => return run(options, &program, &report, stdout, stderr),
+        Mode::Fmt => unreachable!("formatting stops before checking"),
+        Mode::Repl | Mode::Lsp => unreachable!("the loop reads its own input"),
+        Mode::Bench => unreachable!("the benchmark has no input"),
+        Mode::Grammar => unreachable!("a grammar is not a program"),
+    }
+}
+
+/// Report on a grammar, rewrite it, generate a parser for it, or parse a file with it
+fn analyze_grammar(
+    options: &Options,
+    name: &str,
+    text: &str,
+    stdout: &mut (dyn Write + Send),
+) -> Result<i32, Failure> {
+    let grammar = Grammar::parse(text).map_err(|e| Failure::error(&format!("{}: {}", name, e)))?;
+    let output = match &options.grammar {
+        GrammarOutput::Report => {
+            format!(
+                "{}\n{}",
+                grammar::report(&grammar),
+                Lalr::new(&grammar).report()
+            )
+        }
+        GrammarOutput::Rewrite => grammar.eliminate_left_recursion().left_factor().to_string(),
+        GrammarOutput::Parser => Lalr::new(&grammar).generate(),
+        GrammarOutput::Parse(path) => {
+            let (input, bytes) = read_input(Some(path), &mut std::io::empty())?;
+            let source = String::from_utf8(bytes)
+                .map_err(|_| Failure::error(&format!("{}: not valid UTF-8", input)))?;
+            let ll1 = Ll1::new(&grammar);
+            let tree = match ll1.conflicts.is_empty() {
+                true => ll1.parse(&source),
+                false => Lalr::new(&grammar).parse(&source),
+            };
+            let tree = tree.map_err(|e| {
+                let diagnostic = Diagnostic::from(e);
+                return Failure::error(&diagnostic.format(options.error_format, &input, &source));
+            })?;
+            format!("{}\n", tree)
+        }
+    };
+    return write_output(options, output.as_bytes(), stdout);
+}
+
+/// The name to report errors against and the bytes of the input
+fn read_input(path: Option<&Path>, stdin: &mut dyn Read) -> Result<(String, Vec<u8>), Failure> {
+    let mut bytes = Vec::new();
+    match path {
+        Some(path) => {
+            let name = path.display().to_string();
+            bytes = std::fs::read(path).map_err(|e| Failure::error(&format!("{}: {}", name, e)))?;
+            return Ok((name, bytes));
+        }
+        None => {
+            stdin
+                .read_to_end(&mut bytes)
+                .map_err(|e| Failure::error(&format!("<stdin>: {}", e)))?;
+            return Ok(("<stdin>".to_string(), bytes));
+        }
+    }
+}
+
+/// Write what `compile` produced to the output path, or to standard output when there is none
+fn write_output(
+    options: &Options,
+    bytes: &[u8],
+    stdout: &mut (dyn Write + Send),
+) -> Result<i32, Failure> {
+    match options.output.as_deref() {
+        Some(path) if path != Path::new("-") => std::fs::write(path, bytes)
+            .map_err(|e| Failure::error(&format!("{}: {}", path.display(), e)))?,
+        _ => stdout
+            .write_all(bytes)
+            .map_err(|e| Failure::error(&e.to_string()))?,
+    }
+    return Ok(0);
+}
+
+/// The register allocator `--regalloc` asks for, or the one suiting the optimization level
+fn allocator(options: &Options) -> Allocator {
+    if let Some(allocator) = options.allocator {
+        return allocator;
+    }
+    if options.opt_level == 0 {
+        return Allocator::LinearScan;
+    }
+    return Allocator::GraphColoring;
+}
+
+fn compile(
+    options: &Options,
+    name: &str,
+    program: &Program,
+    stdout: &mut (dyn Write + Send),
+) -> Result<i32, Failure> {
+    let target = options.target;
+    let output = match options.emit {
+        None => None,
+        Some(Emit::Tokens | Emit::Ast) => unreachable!("emitted before type checking"),
+        Some(Emit::TypedAst) => Some(dump_ast(program)),
+        Some(Emit::Ir | Emit::Canon) => {
+            let canon = options.emit == Some(Emit::Canon);
+            match target {
+                Target::X86_64 => Some(dump_ir(ir_for::<X86_64Frame>(program, options), canon)),
+                Target::RiscV => Some(dump_ir(ir_for::<RiscVFrame>(program, options), canon)),
+                _ => return Err(Failure::usage("only x86-64 and riscv use the IR")),
+            }
+        }
+        Some(Emit::Asm) => Some(source_for(target, program, options)?),
+        Some(Emit::Liveness) => match target {
+            Target::X86_64 => {
+                let procs = procs(ir_for::<X86_64Frame>(program, options), |frame, body| {
+                    let instrs = chapter_12::proc_entry_exit1(frame, chapter_9::codegen(&body));
+                    return chapter_12::proc_entry_exit2(instrs);
+                });
+                Some(dump_flow(procs, &chapter_9::x86_64_name))
+            }
+            Target::RiscV => {
+                let procs = procs(ir_for::<RiscVFrame>(program, options), |frame, body| {
+                    return riscv::proc_entry_exit(frame, riscv::codegen(&body));
+                });
+                Some(dump_flow(procs, &riscv::riscv_name))
+            }
+            _ => return Err(Failure::usage("only x86-64 and riscv allocate registers")),
+        },
+        Some(Emit::Bytecode) => Some(bytecode::compile(program).to_string()),
+    };
+    if let Some(output) = output {
+        return write_output(options, output.as_bytes(), stdout);
+    }
+    let path = match (&options.output, &options.input) {
+        (Some(path), _) => path.clone(),
+        (None, Some(input)) => output_beside(input, target),
+        (None, None) => PathBuf::from("a").with_extension(target.extension().unwrap_or("out")),
+    };
+    let bytes = match target {
+        Target::X86_64 | Target::Llvm | Target::C if path == Path::new("-") => {
+            return Err(Failure::usage(
+                "an executable cannot be written to standard output, use `--emit=asm`",
+            ))
+        }
+        Target::X86_64 | Target::Llvm | Target::C => {
+            build(target, program, options, &path)?;
+            return Ok(0);
+        }
+        Target::RiscV => source_for(target, program, options)?.into_bytes(),
+        Target::Wasm => wasm::compile(program).to_binary(),
+        Target::Bytecode => bytecode::compile(program).to_bytes(),
+        Target::Ir | Target::Interpreter => {
+            return Err(Failure::usage(&format!(
+                "{}: the target can only run programs",
+                name
+            )))
+        }
+    };
+    let options = Options {
+        output: Some(path),
+        ..options.clone()
+    };
+    return write_output(&options, &bytes, stdout);
+}
+
+/// Where `compile` writes the output for an input file without `-o`: beside it, with the extension
+/// of the target. When that is the input itself, as for a source without an extension compiled
+/// to an executable, `out` goes before the extension so the source is not overwritten
+fn output_beside(input: &Path, target: Target) -> PathBuf {
+    let path = input.with_extension(target.extension().unwrap_or(""));
+    if path != input {
+        return path;
+    }
+    match target.extension() {
+        Some(extension) => return input.with_extension(format!("out.{}", extension)),
+        None => return input.with_extension("out"),
+    }
+}
+
+/// The text `--emit=asm` writes for a target: its assembly, or the program in the language the
+/// target compiles to
+fn source_for(target: Target, program: &Program, options: &Options) -> Result<String, Failure> {
+    match target {
+        Target::X86_64 => {
+            let frags = ir_for::<X86_64Frame>(program, options);
+            return Ok(chapter_12::emit_program(frags, allocator(options)));
+        }
+        Target::RiscV => {
+            let frags = ir_for::<RiscVFrame>(program, options);
+            return Ok(riscv::emit_program(frags, allocator(options)));
+        }
+        Target::Llvm => return Ok(llvm::compile(program)),
+        Target::C => return Ok(c::compile(program)),
+        Target::Wasm => return Ok(wasm::compile(program).to_wat()),
+        Target::Bytecode | Target::Ir | Target::Interpreter => {
+            return Err(Failure::usage("the target has no assembly"))
+        }
+    }
+}
+
+/// A directory of its own for the files of one build, removed afterwards
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new() -> Result<Scratch, Failure> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "driver_{}_{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).map_err(|e| Failure::error(&e.to_string()))?;
+        return Ok(Scratch(dir));
+    }
+
+    /// Write a file into the directory, returning its path
+    fn write(&self, name: &str, contents: &[u8]) -> Result<PathBuf, Failure> {
+        let path = self.0.join(name);
+        std::fs::write(&path, contents).map_err(|e| Failure::error(&e.to_string()))?;
+        return Ok(path);
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run a tool the build needs, failing with what it reported if it fails
+fn tool(command: &mut Command) -> Result<(), Failure> {
+    let name = command.get_program().to_string_lossy().into_owned();
+    match command.output() {
+        Ok(out) if out.status.success() => return Ok(()),
+        Ok(out) => return Err(Failure::error(&String::from_utf8_lossy(&out.stderr))),
+        Err(error) => return Err(Failure::error(&format!("cannot run {}: {}", name, error))),
+    }
+}
+
+/// Build an executable at `exe` with a target that makes native code
+fn build(target: Target, program: &Program, options: &Options, exe: &Path) -> Result<(), Failure> {
+    let level = format!("-O{}", options.opt_level);
+    let scratch = Scratch::new()?;
+    match target {
+        Target::X86_64 => {
+            let assembly = source_for(target, program, options)?;
+            return chapter_12::link(&assembly, exe).map_err(|e| Failure::error(&e));
+        }
+        Target::C => {
+            let source = scratch.write("program.c", c::compile(program).as_bytes())?;
+            return tool(
+                Command::new("cc")
+                    .args(["-std=c99", &level, "-o"])
+                    .arg(exe)
+                    .arg(&source)
+                    .arg("-lm"),
+            );
+        }
+        Target::Llvm => {
+            let flags = |name: &str| {
+                return llvm::llvm_flags(name).ok_or_else(|| {
+                    Failure::error(&format!("cannot run {}: LLVM 14 or later is needed", name))
+                });
+            };
+            let (opt, llc) = (flags("opt")?, flags("llc")?);
+            let source = scratch.write("program.ll", llvm::compile(program).as_bytes())?;
+            let optimized = scratch.0.join("program.opt.ll");
+            let assembly = scratch.0.join("program.s");
+            let runtime = scratch.write("runtime.c", RUNTIME.as_bytes())?;
+            tool(
+                Command::new("opt")
+                    .args(&opt)
+                    .args([&level, "-S", "-o"])
+                    .arg(&optimized)
+                    .arg(&source),
+            )?;
+            tool(
+                Command::new("llc")
+                    .args(&llc)
+                    .args([&level, "-relocation-model=pic", "-o"])
+                    .arg(&assembly)
+                    .arg(&optimized),
+            )?;
+            return tool(
+                Command::new("cc")
+                    .arg("-o")
+                    .arg(exe)
+                    .arg(&assembly)
+                    .arg(&runtime)
+                    .arg("-lm"),
+            );
+        }
+        _ => unreachable!("{:?} does not make native code", target),
+    }
+}
+
+/// Run a program, reporting runtime errors the virtual machines find through `report`. Native
+/// programs report their own, without positions
+fn run(
+    options: &Options,
+    program: &Program,
+    report: &dyn Fn(Diagnostic) -> String,
+    stdout: &mut (dyn Write + Send),
+    stderr: &mut dyn Write,
+) -> Result<i32, Failure> {
+    let scratch = Scratch::new()?;
+    match options.target {
+        Target::Interpreter => match interpret(program, stdout) {
+            Ok(()) => return Ok(0),
+            Err(error) => {
+                let _ = write!(stderr, "{}", report(error.into()));
+                return Ok(EXIT_FAILURE);
+            }
+        },
+        Target::Bytecode => {
+            return run_bytecode(&bytecode::compile(program), report, stdout, stderr)
+        }
+        Target::Ir => {
+            let code = IrProgram::new(ir_for::<X86_64Frame>(program, options));
+            match ir_interpreter::run(&code, stdout) {
+                Ok(()) => return Ok(0),
+                Err(error) => {
+                    let diagnostic = Diagnostic::error(&error.message).with_code(RUNTIME_ERROR);
+                    let _ = write!(stderr, "{}", report(diagnostic));
+                    return Ok(EXIT_FAILURE);
+                }
+            }
+        }
+        Target::X86_64 | Target::Llvm | Target::C => {
+            let exe = scratch.0.join("program");
+            build(options.target, program, options, &exe)?;
+            return execute(&mut Command::new(&exe), stdout, stderr);
+        }
+        Target::Wasm => {
+            let module = scratch.write("program.wasm", &wasm::compile(program).to_binary())?;
+            let host = scratch.write("wasm_host.js", WASM_HOST.as_bytes())?;
+            return execute(Command::new("node").arg(&host).arg(&module), stdout, stderr);
+        }
+        Target::RiscV => {
+            return Err(Failure::usage(
+                "riscv programs cannot run on this machine, `compile` them instead",
+            ))
+        }
+    }
+}
+
+fn run_bytecode(
+    code: &Bytecode,
+    report: &dyn Fn(Diagnostic) -> String,
+    stdout: &mut (dyn Write + Send),
+    stderr: &mut dyn Write,
+) -> Result<i32, Failure> {
+    match bytecode::run(code, stdout) {
+        Ok(()) => return Ok(0),
+        Err(error) => {
+            let _ = write!(stderr, "{}", report(error.into()));
+            return Ok(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Run a built program, passing on what it writes and its exit code
+fn execute(
+    command: &mut Command,
+    stdout: &mut (dyn Write + Send),
+    stderr: &mut dyn Write,
+) -> Result<i32, Failure> {
+    let name = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|e| Failure::error(&format!("cannot run {}: {}", name, e)))?;
+    let _ = stdout.write_all(&output.stdout);
+    let _ = stderr.write_all(&output.stderr);
+    return Ok(output.status.code().unwrap_or(EXIT_FAILURE));
+}
+
+/// The intermediate code of every fragment, one statement to a line. Canonical code is the
+/// linearized statement list the instruction selector works from
+fn dump_ir<F: Frame>(frags: Vec<Frag<F>>, canon: bool) -> String {
+    let mut out = String::new();
+    for frag in frags {
+        match frag {
+            Frag::Proc { body, frame } => {
+                out.push_str(&format!("{}:\n", frame.name()));
+                let stms = if canon {
+                    canonicalize(body)
+                } else {
+                    let mut stms = Vec::new();
+                    flatten_seq(body, &mut stms);
+                    stms
+                };
+                for stm in stms {
+                    out.push_str(&format!("\t{}\n", stm));
+                }
+            }
+            Frag::Str(label, s) => out.push_str(&format!("{}: {:?}\n", label, s)),
+        }
+    }
+    return out;
+}
+
+/// The IR of a program, with the operators of constant operands computed at `-O2`
+fn ir_for<F: Frame>(program: &Program, options: &Options) -> Vec<Frag<F>> {
+    let frags = translate::<F>(program);
+    if options.opt_level < 2 {
+        return frags;
+    }
+    return frags
+        .into_iter()
+        .map(|frag| match frag {
+            Frag::Proc { body, frame } => Frag::Proc {
+                body: fold_constants(body),
+                frame,
+            },
+            frag => frag,
+        })
+        .collect();
+}
+
+/// The name and instructions of every function, as `codegen` selects them from the canonical
+/// IR of its body before register allocation
+fn procs<F: Frame>(
+    frags: Vec<Frag<F>>,
+    codegen: impl Fn(&F, Vec<Stm>) -> Vec<Instr>,
+) -> Vec<(Label, Vec<Instr>)> {
+    let mut procs = Vec::new();
+    for frag in frags {
+        if let Frag::Proc { body, frame } = frag {
+            procs.push((frame.name().clone(), codegen(&frame, canonicalize(body))));
+        }
+    }
+    return procs;
+}
+
+/// Each function with the temporaries live around its instructions, then its interference graph
+fn dump_flow(procs: Vec<(Label, Vec<Instr>)>, name: &dyn Fn(Temp) -> String) -> String {
+    let mut out = String::new();
+    for (label, instrs) in procs {
+        let graph = FlowGraph::new(&instrs);
+        let live = liveness(&graph);
+        out.push_str(&format!("{}:\n", label));
+        out.push_str(&dump_liveness(&instrs, &graph, &live, name));
+        out.push_str(&format!("{} interference:\n", label));
+        out.push_str(&InterferenceGraph::new(&graph, &live).dump(name));
+    }
+    return out;
+}
+
+fn flatten_seq(stm: Stm, out: &mut Vec<Stm>) {
+    match stm {
+        Stm::Seq(a, b) => {
+            flatten_seq(*a, out);
+            flatten_seq(*b, out);
+        }
+        stm => out.push(stm),
+    }
+}
+
+/// Each token with the line and column it starts at, one token to a line
+pub fn dump_tokens(source: &str) -> Result<String, LexError> {
+    let mut out = String::new();
+    for (token, pos) in tokenize_with_positions(source)? {
+        let (line, col) = line_col(source, pos);
+        out.push_str(&format!("{}:{}\t{:?}\n", line, col, token));
+    }
+    return Ok(out);
+}
+
+/// The syntax tree as an indented outline, one node to a line. Once the program has been type
+/// checked, each expression is followed by its type
+pub fn dump_ast(program: &Program) -> String {
+    let mut out = String::new();
+    for stmt in &program.stmts {
+        dump_stmt(stmt, 0, &mut out);
+    }
+    return out;
+}
+
+fn line(depth: usize, text: &str, out: &mut String) {
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(text);
+    out.push('\n');
+}
+
+fn dump_stmt(stmt: &Stmt, depth: usize, out: &mut String) {
+    match &stmt.kind {
+        StmtKind::Let {
+            name,
+            mutable,
+            ty,
+            init,
+            ..
+        } => {
+            let ty = ty.map(|t| format!(": {}", t)).unwrap_or_default();
+            let text = format!("let {}{}{}", if *mutable { "mut " } else { "" }, name, ty);
+            line(depth, &text, out);
+            dump_expr(init, depth + 1, out);
+        }
+        StmtKind::Assign { name, value } => {
+            line(depth, &format!("assign {}", name), out);
+            dump_expr(value, depth + 1, out);
+        }
+        StmtKind::While { cond, body } => {
+            line(depth, "while", out);
+            dump_expr(cond, depth + 1, out);
+            dump_block(body, depth + 1, out);
+        }
+        StmtKind::For { var, lo, hi, body } => {
+            line(depth, &format!("for {}", var), out);
+            dump_expr(lo, depth + 1, out);
+            dump_expr(hi, depth + 1, out);
+            dump_block(body, depth + 1, out);
+        }
+        StmtKind::Fn(decl) => {
+            let params: Vec<String> = decl
+                .params
+                .iter()
+                .map(|p| {
+                    format!(
+                        "{}{}: {}",
+                        if p.mutable { "mut " } else { "" },
+                        p.name,
+                        p.ty
+                    )
+                })
+                .collect();
+            let text = format!("fn {}({}): {}", decl.name, params.join(", "), decl.result);
+            line(depth, &text, out);
+            dump_block(&decl.body, depth + 1, out);
+        }
+        StmtKind::Expr(expr) => dump_expr(expr, depth, out),
+        StmtKind::Error => line(depth, "error", out),
+    }
+}
+
+fn dump_block(block: &Block, depth: usize, out: &mut String) {
+    line(depth, "block", out);
+    for stmt in &block.stmts {
+        dump_stmt(stmt, depth + 1, out);
+    }
+    if let Some(result) = &block.result {
+        line(depth + 1, "result", out);
+        dump_expr(result, depth + 2, out);
+    }
+}
+
+fn dump_expr(expr: &Expr, depth: usize, out: &mut String) {
+    let text = match &expr.kind {
+        ExprKind::Int(i) => format!("int {}", i),
+        ExprKind::Float(x) => format!("float {:?}", x),
+        ExprKind::Bool(true) => "bool True".to_string(),
+        ExprKind::Bool(false) => "bool False".to_string(),
+        ExprKind::Str(s) => format!("string {:?}", s),
+        ExprKind::Var(name) => format!("var {}", name),
+        ExprKind::Unary(UnOp::Neg, _) => "neg".to_string(),
+        ExprKind::Binary(_, op, _) => format!("binary {}", op),
+        ExprKind::Call(name, _) => format!("call {}", name),
+        ExprKind::If { .. } => "if".to_string(),
+        ExprKind::Error => "error".to_string(),
+    };
+    match expr.ty.get().copied() {
+        Some(ty) => line(depth, &format!("{} : {}", text, ty), out),
+        None => line(depth, &text, out),
+    }
+    match &expr.kind {
+        ExprKind::Unary(_, operand) => dump_expr(operand, depth + 1, out),
+        ExprKind::Binary(left, _, right) => {
+            dump_expr(left, depth + 1, out);
+            dump_expr(right, depth + 1, out);
+        }
+        ExprKind::Call(_, args) => {
+            for arg in args {
+                dump_expr(arg, depth + 1, out);
+            }
+        }
+        ExprKind::If {
+            branches,
+            else_block,
+        } => {
+            for (cond, block) in branches {
+                dump_expr(cond, depth + 1, out);
+                dump_block(block, depth + 1, out);
+            }
+            if let Some(block) = else_block {
+                line(depth + 1, "else", out);
+                dump_block(block, depth + 2, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::*;
+
+    fn args(line: &str) -> Vec<String> {
+        return line.split_whitespace().map(|a| a.to_string()).collect();
+    }
+
+    /// Run the driver with a program on standard input, returning the exit code and what it
+    /// wrote to standard output and standard error
+    fn drive_with(line: &str, source: &str) -> (i32, String, String) {
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let code = main(
+            &args(line),
+            &mut source.as_bytes(),
+            &mut stdout,
+            &mut stderr,
+        );
+        return (
+            code,
+            String::from_utf8(stdout).unwrap(),
+            String::from_utf8(stderr).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(&args("compile --target=c -O2 -o out prog.txt")).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                mode: Mode::Compile,
+                input: Some(PathBuf::from("prog.txt")),
+                output: Some(PathBuf::from("out")),
+                emit: None,
+                target: Target::C,
+                opt_level: 2,
+                allocator: None,
+                error_format: Format::Human,
+                check: false,
+                grammar: GrammarOutput::Report,
+            }
+        );
+        let options = parse_args(&args("run - --error-format=json")).unwrap();
+        assert_eq!(
+            (options.input, options.opt_level, options.error_format),
+            (None, 1, Format::Json)
+        );
+        let options = parse_args(&args("compile -O2 --regalloc=linear-scan")).unwrap();
+        assert_eq!(allocator(&options), Allocator::LinearScan);
+        let options = parse_args(&args("compile -O0")).unwrap();
+        assert_eq!(allocator(&options), Allocator::LinearScan);
+        let options = parse_args(&args("compile -O0 --regalloc=graph-coloring")).unwrap();
+        assert_eq!(allocator(&options), Allocator::GraphColoring);
+        assert_eq!(parse_args(&args("lsp --stdio")).unwrap().mode, Mode::Lsp);
+        assert!(parse_args(&args("fmt --check prog.txt")).unwrap().check);
+        assert_eq!(
+            parse_args(&args("grammar --parse=prog.txt g.y"))
+                .unwrap()
+                .grammar,
+            GrammarOutput::Parse(PathBuf::from("prog.txt"))
+        );
+        for line in [
+            "",
+            "build prog.txt",
+            "compile -O3",
+            "compile --target=arm",
+            "compile --regalloc=greedy",
+            "compile --emit=cfg",
+            "compile a b",
+            "compile -o",
+            "compile --error-format=xml",
+            "run --emit=ast",
+            "fmt --emit=ast",
+            "compile --check",
+            "repl prog.txt",
+            "bench prog.txt",
+            "lsp prog.txt",
+            "compile --rewrite",
+            "fmt --parse=prog.txt",
+            "run --parser",
+        ] {
+            assert_eq!(
+                parse_args(&args(line)).unwrap_err().code,
+                EXIT_USAGE,
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_bench_compares_allocators() {
+        let (code, table, _) = drive_with("bench", "");
+        assert_eq!(code, 0);
+        assert!(table.starts_with("program "), "{}", table);
+        assert!(table.contains("\npressure_40 "), "{}", table);
+        assert!(table.contains(" bytecode  interpreter "), "{}", table);
+        assert!(table.contains("\ncalls "), "{}", table);
+    }
+
+    #[test]
+    fn test_emit_front_end_stages() {
+        let (code, tokens, _) = drive_with("compile --emit=tokens", "let x =\n  1;");
+        assert_eq!(code, 0);
+        assert_eq!(
+            tokens,
+            "1:1\tLet\n1:5\tId(\"x\")\n1:7\tEqual\n2:3\tNum(1)\n2:4\tSemicolon\n"
+        );
+        let source = "let x = -1; print(x + 2);";
+        let (_, ast, _) = drive_with("compile --emit=ast", source);
+        assert_eq!(
+            ast,
+            "let x\n  neg\n    int 1\ncall print\n  binary +\n    var x\n    int 2\n"
+        );
+        let (_, typed, _) = drive_with("compile --emit=typed-ast", source);
+        assert!(typed.starts_with("let x\n  neg : int\n    int 1 : int\ncall print : ()\n"));
+    }
+
+    #[test]
+    fn test_emit_back_end_stages() {
+        let source = "fn f(x: int): int { x * 2 } print(f(3));";
+        let (_, ir, _) = drive_with("compile --emit=ir", source);
+        assert!(ir.contains("program_main:\n\tEXP(CALL(NAME print_int, CALL(NAME f_"));
+        let (_, canon, _) = drive_with("compile --emit=canon --target=riscv", source);
+        assert!(canon.contains("program_main:\n\tLABEL L"), "{}", canon);
+        let (_, asm, _) = drive_with("compile --emit=asm", source);
+        assert!(asm.contains("program_main:\n\tpushq %rbp\n"), "{}", asm);
+        let (_, c, _) = drive_with("compile --emit=asm --target=c", source);
+        assert!(c.contains("int main(void)"), "{}", c);
+        let (_, code, _) = drive_with("compile --emit=bytecode", source);
+        assert!(code.starts_with("constants:\n     0  Int(2)\n"), "{}", code);
+        let (code, _, stderr) = drive_with("compile --emit=ir --target=wasm", source);
+        assert_eq!(
+            (code, stderr.as_str()),
+            (EXIT_USAGE, "only x86-64 and riscv use the IR\n")
+        );
+    }
+
+    #[test]
+    fn test_executable_to_stdout_is_a_usage_error() {
+        for target in ["x86-64", "llvm", "c"] {
+            let line = format!("compile --target={} -o -", target);
+            let (code, stdout, stderr) = drive_with(&line, "print(1);");
+            assert_eq!((code, stdout.as_str()), (EXIT_USAGE, ""), "{}", target);
+            assert!(stderr.starts_with("an executable cannot"), "{}", stderr);
+            assert!(!Path::new("-").exists());
+        }
+        let (code, stdout, _) = drive_with("compile --target=bytecode -o -", "print(1);");
+        assert_eq!(code, 0);
+        assert!(stdout.as_bytes().starts_with(bytecode::MAGIC));
+    }
+
+    #[test]
+    fn test_o2_folds_constants() {
+        let source = "print(2 * 3 + 1);";
+        let (_, canon, _) = drive_with("compile --emit=canon", source);
+        assert!(canon.contains("BINOP(MUL, CONST 2, CONST 3)"), "{}", canon);
+        let (_, canon, _) = drive_with("compile --emit=canon -O2", source);
+        assert!(canon.contains("CALL(NAME print_int, CONST 7)"), "{}", canon);
+        let (code, stdout, _) = drive_with("run --target=ir -O2", source);
+        assert_eq!((code, stdout.as_str()), (0, "7\n"));
+    }
+
+    #[test]
+    fn test_emit_liveness() {
+        let source = "fn f(x: int): int { x * 2 } print(f(3));";
+        let (code, live, _) = drive_with("compile --emit=liveness", source);
+        assert_eq!(code, 0);
+        assert!(live.starts_with("f_L"), "{}", live);
+        assert!(live.contains(":\nmovq %rbx, t"), "{}", live);
+        assert!(live.contains(" in: {%rbx %rsi %rdi %rbp %rsp "), "{}", live);
+        assert!(live.contains("\nprogram_main interference:\n"), "{}", live);
+        let (_, live, _) = drive_with("compile --emit=liveness --target=riscv", source);
+        assert!(live.contains(":\nmv t"), "{}", live);
+        let (code, _, stderr) = drive_with("compile --emit=liveness --target=c", source);
+        assert_eq!(
+            (code, stderr.as_str()),
+            (EXIT_USAGE, "only x86-64 and riscv allocate registers\n")
+        );
+    }
+
+    #[test]
+    fn test_warnings_do_not_fail() {
+        let (code, stdout, stderr) = drive_with("run", "let x = 1;\nprint(2);");
+        assert_eq!((code, stdout.as_str()), (0, "2\n"));
+        assert_eq!(
+            stderr,
+            "warning[W0002]: unused variable `x`\n --> <stdin>:1:1\n  |\n1 | let x = 1;\n  | ^^^\n  |\n  = help: if this is intentional, name it `_x`\n"
+        );
+    }
+
+    #[test]
+    fn test_errors_have_positions_and_exit_codes() {
+        let (code, _, stderr) = drive_with("run", "let x = 1;\nprint(x +);");
+        assert_eq!(code, EXIT_FAILURE);
+        assert_eq!(
+            stderr,
+            "error[E0001]: expected expression, found `)`\n --> <stdin>:2:10\n  |\n2 | print(x +);\n  |          ^\n"
+        );
+        let (code, _, stderr) = drive_with("compile --error-format=json", "print(y);");
+        assert_eq!(code, EXIT_FAILURE);
+        assert!(stderr.starts_with("{\"severity\":\"error\",\"code\":\"E0002\","));
+        assert_eq!(stderr.lines().count(), 1);
+        // Every syntax error is reported, not only the first
+        let (code, _, stderr) = drive_with("compile --error-format=json", "let = 1;\nprint(1 +);");
+        assert_eq!(code, EXIT_FAILURE);
+        assert_eq!(
+            stderr.matches("\"code\":\"E0001\"").count(),
+            2,
+            "{}",
+            stderr
+        );
+        let (code, _, stderr) = drive_with("compile --emit=tokens", "let ~");
+        assert_eq!(code, EXIT_FAILURE);
+        assert!(stderr.starts_with("error[E0001]: unexpected character `~`\n"));
+        let (code, _, stderr) = drive_with("run --target=riscv", "print(1);");
+        assert_eq!(code, EXIT_USAGE, "{}", stderr);
+    }
+
+    #[test]
+    fn test_run_programs_on_virtual_machines() {
+        for target in ["interpreter", "bytecode"] {
+            for (name, source) in PROGRAMS {
+                let (code, stdout, _) = drive_with(&format!("run --target={}", target), source);
+                assert_eq!(code, 0, "program {} on {}", name, target);
+                assert_eq!(
+                    stdout,
+                    expected_output(source),
+                    "program {} on {}",
+                    name,
+                    target
+                );
+            }
+            for (name, source) in FAILING_PROGRAMS {
+                let (code, _, stderr) = drive_with(&format!("run --target={}", target), source);
+                assert_eq!(code, EXIT_FAILURE, "program {} on {}", name, target);
+                let header = format!("error[E0003]: {}\n --> <stdin>:", expected_error(source));
+                assert!(stderr.starts_with(&header), "{}", stderr);
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_programs_on_ir_interpreter() {
+        for (name, source) in PROGRAMS {
+            let (code, stdout, _) = drive_with("run --target=ir", source);
+            assert_eq!(code, 0, "program {}", name);
+            assert_eq!(stdout, expected_output(source), "program {}", name);
+        }
+        let (code, stdout, stderr) = drive_with("run --target=ir", FAILING_PROGRAMS[0].1);
+        assert_eq!(code, EXIT_FAILURE);
+        assert_eq!(stdout, "1\n");
+        assert_eq!(stderr, "error[E0003]: division by zero\n");
+        let (code, _, stderr) = drive_with("compile --target=ir", "print(1);");
+        assert_eq!(code, EXIT_USAGE, "{}", stderr);
+    }
+
+    #[test]
+    fn test_compile_bytecode_file_and_run_it() {
+        let path = std::env::temp_dir().join(format!("driver_{}.mcib", std::process::id()));
+        let line = format!("compile --target=bytecode -o {}", path.display());
+        assert_eq!(drive_with(&line, "print(\"saved\", 2);").0, 0);
+        let (code, stdout, _) = drive_with(&format!("run {}", path.display()), "");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((code, stdout.as_str()), (0, "saved\n2\n"));
+    }
+
+    #[test]
+    fn test_output_never_overwrites_input() {
+        let output = |input: &str, target| output_beside(Path::new(input), target);
+        assert_eq!(
+            output("dir/prog.txt", Target::X86_64),
+            Path::new("dir/prog")
+        );
+        assert_eq!(output("prog.txt", Target::Wasm), Path::new("prog.wasm"));
+        assert_eq!(
+            output("dir/prog", Target::X86_64),
+            Path::new("dir/prog.out")
+        );
+        assert_eq!(output("prog", Target::C), Path::new("prog.out"));
+        assert_eq!(output("prog.s", Target::RiscV), Path::new("prog.out.s"));
+        assert_eq!(output("prog", Target::Bytecode), Path::new("prog.mcib"));
+    }
+
+    #[test]
+    fn test_fmt_in_place_and_check() {
+        let messy = "let x=1;\nprint( x+1 );";
+        let tidy = "let x = 1;\nprint(x + 1);\n";
+        assert_eq!(
+            drive_with("fmt", messy),
+            (0, tidy.to_string(), String::new())
+        );
+        assert_eq!(drive_with("fmt --check", tidy).0, 0);
+        let (code, _, stderr) = drive_with("fmt --check", messy);
+        assert_eq!(
+            (code, stderr.as_str()),
+            (EXIT_FAILURE, "<stdin> is not formatted\n")
+        );
+        let (code, _, stderr) = drive_with("fmt", "let x = ;");
+        assert_eq!(code, EXIT_FAILURE);
+        assert!(stderr.starts_with("error[E0001]: expected expression"));
+        let path = std::env::temp_dir().join(format!("driver_fmt_{}.txt", std::process::id()));
+        std::fs::write(&path, messy).unwrap();
+        assert_eq!(drive_with(&format!("fmt {}", path.display()), "").0, 0);
+        let formatted = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(formatted, tidy);
+    }
+
+    #[test]
+    fn test_grammar_report_rewrite_parse_and_generate() {
+        let grammar = "expr : expr \"+\" ID | ID ;";
+        let (code, report, _) = drive_with("grammar", grammar);
+        assert_eq!(code, 0);
+        assert!(
+            report.starts_with("      nullable  FIRST  FOLLOW\nexpr  no        ID     $ \"+\"\n")
+        );
+        assert!(report.contains("\n1 LL(1) conflict\n"), "{}", report);
+        assert!(
+            report.ends_with(
+                "\nLALR(1) automaton of 5 states\nthe LALR(1) tables have no conflicts\n"
+            ),
+            "{}",
+            report
+        );
+        let (code, rewritten, _) = drive_with("grammar --rewrite", grammar);
+        assert_eq!(
+            (code, rewritten.as_str()),
+            (0, "expr : ID expr_tail ;\nexpr_tail : \"+\" ID expr_tail\n          | %empty\n          ;\n")
+        );
+        let path = std::env::temp_dir().join(format!("driver_grammar_{}.txt", std::process::id()));
+        std::fs::write(&path, "a + b").unwrap();
+        let line = format!("grammar --parse={}", path.display());
+        // Not LL(1), so the LALR(1) tables parse it
+        let (code, tree, _) = drive_with(&line, grammar);
+        assert_eq!((code, tree.as_str()), (0, "(expr (expr a) + b)\n"));
+        let (code, tree, _) = drive_with(&line, &rewritten);
+        assert_eq!(
+            (code, tree.as_str()),
+            (0, "(expr a (expr_tail + b (expr_tail)))\n")
+        );
+        std::fs::write(&path, "a +").unwrap();
+        let (code, _, stderr) = drive_with(&line, &rewritten);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(code, EXIT_FAILURE);
+        assert!(
+            stderr.starts_with("error[E0001]: expected ID, found end of input"),
+            "{}",
+            stderr
+        );
+        let (code, _, stderr) = drive_with("grammar", "expr : term ;");
+        assert_eq!(
+            (code, stderr.as_str()),
+            (EXIT_FAILURE, "<stdin>: line 1: `term` has no rules\n")
+        );
+        let (code, parser, _) = drive_with("grammar --parser", grammar);
+        assert_eq!(code, 0);
+        assert!(
+            parser.starts_with("/// An LALR(1) parser generated"),
+            "{}",
+            parser
+        );
+        assert!(parser.ends_with("return run(&TABLES, &tokens, input.len(), &terminal);\n}\n"));
+    }
+
+    #[test]
+    #[ignore = "needs an x86-64 host with `cc`, run with `cargo test -- --ignored`"]
+    fn test_run_native_executables() {
+        require_tool("cc");
+        let source =
+            "fn f(n: int): int { if n < 2 { n } else { f(n - 1) + f(n - 2) } } print(f(15));";
+        for line in ["run", "run -O0", "run --target=c -O2"] {
+            assert_eq!(
+                drive_with(line, source),
+                (0, "610\n".to_string(), String::new())
+            );
+        }
+        let (code, stdout, stderr) = drive_with("run", "print(1); print(1 / 0);");
+        assert_eq!((code, stdout.as_str()), (EXIT_FAILURE, "1\n"));
+        assert_eq!(stderr, "division by zero\n");
+    }
+}
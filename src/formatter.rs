@@ -0,0 +1,729 @@
+/// A formatter reprinting programs in one canonical layout
+///
+/// Blocks are indented by four spaces, with their `{` on the line of the `fn`, `if`, `while` or
+/// `for` they belong to and their `}` on a line of its own. `=` and the binary operators have a
+/// space on each side, and commas a space after. Argument and parameter lists that would run past
+/// `MAX_WIDTH` columns are put one to a line. Parentheses are kept only where they are needed.
+///
+/// Comments stay before the statement they are in front of, or at the end of the line they end,
+/// and single blank lines between statements are kept. A comment inside an expression goes
+/// before the operand or argument it is in front of, which starts the next line. The result is checked by parsing it again:
+/// it must give the same syntax tree, positions aside, and keep every comment.
+use std::collections::HashMap;
+
+use crate::chapter_2::{tokenize_with_comments, Token};
+use crate::chapter_3::parse;
+use crate::chapter_4::*;
+use crate::diagnostics::Diagnostic;
+
+pub const MAX_WIDTH: usize = 100;
+const INDENT: &str = "    ";
+
+const COMPARISON: u8 = 3;
+const UNARY: u8 = 6;
+
+/// Format a program
+pub fn format(source: &str) -> Result<String, Diagnostic> {
+    let program = parse(source).map_err(Diagnostic::from)?;
+    let tokens = tokenize_with_comments(source).map_err(Diagnostic::from)?;
+    let mut formatter = Formatter::new(source, &tokens);
+    let mut out = String::new();
+    formatter.items(&program.stmts, None, 0, source.len(), false, &mut out);
+    let reparsed = parse(&out).map_err(|e| changed(&format!("it would not parse: {}", e)))?;
+    if erase_positions(&reparsed) != erase_positions(&program) {
+        return Err(changed("its syntax tree would differ"));
+    }
+    let kept: Vec<&String> = formatter.comments.iter().map(|(_, text)| text).collect();
+    if comments(&out).iter().collect::<Vec<_>>() != kept {
+        return Err(changed("its comments would differ"));
+    }
+    return Ok(out);
+}
+
+/// The error for a program the formatter would get wrong, which is a bug in the formatter
+fn changed(reason: &str) -> Diagnostic {
+    return Diagnostic::error("the program cannot be formatted")
+        .with_note(&format!("formatting would change it: {}", reason));
+}
+
+fn comments(source: &str) -> Vec<String> {
+    return tokenize_with_comments(source)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(token, _)| match token {
+            Token::Comment(text) => Some(format!("//{}", text)),
+            _ => None,
+        })
+        .collect();
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    /// The comments by where they start, and how many of them have been written
+    comments: Vec<(Pos, String)>,
+    written: usize,
+    /// Where the `}` closing each `{` starts, by where the `{` starts
+    closing: HashMap<Pos, Pos>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, tokens: &[(Token, Pos)]) -> Formatter<'a> {
+        let mut comments = Vec::new();
+        let mut closing = HashMap::new();
+        let mut open = Vec::new();
+        for (token, pos) in tokens {
+            match token {
+                Token::Comment(text) => comments.push((*pos, format!("//{}", text))),
+                Token::Lbrace => open.push(*pos),
+                Token::Rbrace => {
+                    if let Some(start) = open.pop() {
+                        closing.insert(start, *pos);
+                    }
+                }
+                _ => {}
+            }
+        }
+        return Formatter {
+            source,
+            comments,
+            written: 0,
+            closing,
+        };
+    }
+
+    /// Write the statements of a block or of the program at `depth`, with the expression ending
+    /// the block and the comments up to `end`
+    fn items(
+        &mut self,
+        stmts: &[Stmt],
+        result: Option<&Expr>,
+        depth: usize,
+        end: Pos,
+        in_block: bool,
+        out: &mut String,
+    ) {
+        let mut first = true;
+        for (i, stmt) in stmts.iter().enumerate() {
+            let start = match &stmt.kind {
+                StmtKind::Expr(expr) => stmt.pos.min(expr_start(expr)),
+                _ => stmt.pos,
+            };
+            self.comments_before(start, depth, &mut first, out);
+            if !first && self.blank_before(start) {
+                out.push('\n');
+            }
+            first = false;
+            // An `if` needs no `;`, unless what follows would continue it or it would become the
+            // value of its block
+            let semicolon = match stmts.get(i + 1) {
+                Some(Stmt {
+                    kind: StmtKind::Expr(next),
+                    ..
+                }) => starts_with_negation(next),
+                Some(_) => false,
+                None => result.map_or(in_block, starts_with_negation),
+            };
+            self.stmt(stmt, depth, semicolon, out);
+        }
+        if let Some(expr) = result {
+            let start = expr_start(expr);
+            self.comments_before(start, depth, &mut first, out);
+            if !first && self.blank_before(start) {
+                out.push('\n');
+            }
+            first = false;
+            let text = self.expr(expr, depth, depth * INDENT.len(), 0);
+            out.push_str(&format!("{}{}\n", INDENT.repeat(depth), text));
+        }
+        self.comments_before(end, depth, &mut first, out);
+    }
+
+    /// Write the comments starting before `pos`. A comment after code on its line is put at the
+    /// end of the last line written, the others on lines of their own
+    fn comments_before(&mut self, pos: Pos, depth: usize, first: &mut bool, out: &mut String) {
+        while let Some((start, text)) = self.comments.get(self.written).cloned() {
+            if start >= pos {
+                return;
+            }
+            self.written += 1;
+            let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+            let after_code = !self.source[line_start..start].trim().is_empty();
+            // Two comments on one line would read as one
+            let previous = self
+                .written
+                .checked_sub(2)
+                .map(|i| self.comments[i].1.as_str());
+            let line_has_comment = previous.is_some_and(|p| out.trim_end().ends_with(p));
+            if after_code && out.ends_with('\n') && !line_has_comment {
+                out.pop();
+                out.push_str(&format!(" {}\n", text));
+                continue;
+            }
+            if !*first && self.blank_before(start) {
+                out.push('\n');
+            }
+            out.push_str(&format!("{}{}\n", INDENT.repeat(depth), text));
+            *first = false;
+        }
+    }
+
+    /// The comments starting before `pos` inside an expression, each followed by a line break and
+    /// the indentation of `depth`
+    fn comments_in(&mut self, pos: Pos, depth: usize) -> String {
+        let mut text = String::new();
+        while self.comment_before(pos) {
+            let comment = &self.comments[self.written].1;
+            text.push_str(&format!("{}\n{}", comment, INDENT.repeat(depth)));
+            self.written += 1;
+        }
+        return text;
+    }
+
+    fn comment_before(&self, pos: Pos) -> bool {
+        return self
+            .comments
+            .get(self.written)
+            .is_some_and(|(start, _)| *start < pos);
+    }
+
+    /// Whether a blank line comes before what starts at `pos`
+    fn blank_before(&self, pos: Pos) -> bool {
+        let before = self.source[..pos].trim_end_matches(|c: char| c.is_whitespace() || c == '(');
+        return self.source[before.len()..pos].matches('\n').count() > 1;
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, depth: usize, semicolon: bool, out: &mut String) {
+        let column = depth * INDENT.len();
+        let text = match &stmt.kind {
+            StmtKind::Let {
+                name,
+                mutable,
+                ty,
+                init,
+                ..
+            } => {
+                let ty = ty.map(|ty| format!(": {}", ty)).unwrap_or_default();
+                let head = format!("let {}{}{} = ", mutable_prefix(*mutable), name, ty);
+                let init = self.expr(init, depth, column + head.len(), 1);
+                format!("{}{};", head, init)
+            }
+            StmtKind::Assign { name, value } => {
+                let head = format!("{} = ", name);
+                let value = self.expr(value, depth, column + head.len(), 1);
+                format!("{}{};", head, value)
+            }
+            StmtKind::While { cond, body } => {
+                let cond = self.expr(cond, depth, column + "while ".len(), " {".len());
+                format!("while {} {}", cond, self.block(body, depth))
+            }
+            StmtKind::For { var, lo, hi, body } => {
+                let head = format!("for {} = ", var);
+                let lo = self.expr(lo, depth, column + head.len(), " :".len());
+                let column = end_column(column + head.len(), &lo) + " : ".len();
+                let hi = self.expr(hi, depth, column, " {".len());
+                format!("{}{} : {} {}", head, lo, hi, self.block(body, depth))
+            }
+            StmtKind::Fn(decl) => self.function(decl, depth),
+            StmtKind::Expr(expr) => {
+                let text = self.expr(expr, depth, column, 1);
+                if semicolon || !matches!(expr.kind, ExprKind::If { .. }) {
+                    format!("{};", text)
+                } else {
+                    text
+                }
+            }
+            StmtKind::Error => unreachable!("only programs that parse are formatted"),
+        };
+        out.push_str(&format!("{}{}\n", INDENT.repeat(depth), text));
+    }
+
+    fn function(&mut self, decl: &FnDecl, depth: usize) -> String {
+        let params: Vec<String> = decl
+            .params
+            .iter()
+            .map(|param| {
+                format!(
+                    "{}{}: {}",
+                    mutable_prefix(param.mutable),
+                    param.name,
+                    param.ty
+                )
+            })
+            .collect();
+        let result = match decl.result {
+            Type::Unit => String::new(),
+            ty => format!(": {}", ty),
+        };
+        let mut head = format!("fn {}({}){}", decl.name, params.join(", "), result);
+        if depth * INDENT.len() + head.len() + " {".len() > MAX_WIDTH && !params.is_empty() {
+            head = format!(
+                "fn {}(\n{}{}){}",
+                decl.name,
+                one_to_a_line(&params, depth + 1),
+                INDENT.repeat(depth),
+                result
+            );
+        }
+        return format!("{} {}", head, self.block(&decl.body, depth));
+    }
+
+    /// A block whose `{` is on a line indented to `depth`
+    fn block(&mut self, block: &Block, depth: usize) -> String {
+        let end = self
+            .closing
+            .get(&block.pos)
+            .copied()
+            .unwrap_or(self.source.len());
+        let mut out = String::from("{\n");
+        self.items(
+            &block.stmts,
+            block.result.as_deref(),
+            depth + 1,
+            end,
+            true,
+            &mut out,
+        );
+        if out == "{\n" {
+            return "{}".to_string();
+        }
+        out.push_str(&format!("{}}}", INDENT.repeat(depth)));
+        return out;
+    }
+
+    /// An expression starting at `column` of a line indented to `depth`, and followed by
+    /// `suffix` more columns on its last line. Comments in front of it come first, continuing
+    /// the line before them, and the expression starts the next line
+    fn expr(&mut self, expr: &Expr, depth: usize, column: usize, suffix: usize) -> String {
+        let comments = self.comments_in(expr_start(expr), depth + 1);
+        if comments.is_empty() {
+            return self.expr_after_comments(expr, depth, column, suffix);
+        }
+        let column = (depth + 1) * INDENT.len();
+        return comments + &self.expr_after_comments(expr, depth, column, suffix);
+    }
+
+    fn expr_after_comments(
+        &mut self,
+        expr: &Expr,
+        depth: usize,
+        column: usize,
+        suffix: usize,
+    ) -> String {
+        // An expression with comments inside is split at them
+        if !has_block(expr) && !self.comment_before(last_pos(expr)) {
+            let flat = flat(expr);
+            if column + flat.chars().count() + suffix <= MAX_WIDTH {
+                return flat;
+            }
+        }
+        match &expr.kind {
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let parens = negation_parens(operand);
+                return format!(
+                    "-{}",
+                    self.operand(operand, parens, depth, column + 1, suffix)
+                );
+            }
+            ExprKind::Binary(left, op, right) => {
+                let op = format!(" {} ", op);
+                let (left_parens, right_parens) = operand_parens(expr);
+                let left = self.operand(left, left_parens, depth, column, op.len());
+                let column = end_column(column, &left) + op.len();
+                let right = self.operand(right, right_parens, depth, column, suffix);
+                return format!("{}{}{}", left, op, right);
+            }
+            ExprKind::Call(name, args) if !args.is_empty() => {
+                let column = (depth + 1) * INDENT.len();
+                let args: Vec<String> = args
+                    .iter()
+                    .map(|arg| {
+                        // Comments before an argument go on lines of their own
+                        let comments = self.comments_in(expr_start(arg), depth + 1);
+                        let arg = self.expr(arg, depth + 1, column, ",".len());
+                        return comments + &arg;
+                    })
+                    .collect();
+                return format!(
+                    "{}(\n{}{})",
+                    name,
+                    one_to_a_line(&args, depth + 1),
+                    INDENT.repeat(depth)
+                );
+            }
+            ExprKind::If {
+                branches,
+                else_block,
+            } => {
+                let mut text = String::new();
+                for (i, (cond, block)) in branches.iter().enumerate() {
+                    text.push_str(if i == 0 { "if " } else { " elseif " });
+                    let cond = self.expr(cond, depth, end_column(column, &text), " {".len());
+                    let block = self.block(block, depth);
+                    text.push_str(&format!("{} {}", cond, block));
+                }
+                if let Some(block) = else_block {
+                    text.push_str(&format!(" else {}", self.block(block, depth)));
+                }
+                return text;
+            }
+            _ => return flat(expr),
+        }
+    }
+
+    fn operand(
+        &mut self,
+        expr: &Expr,
+        parens: bool,
+        depth: usize,
+        column: usize,
+        suffix: usize,
+    ) -> String {
+        if parens {
+            return format!("({})", self.expr(expr, depth, column + 1, suffix + 1));
+        }
+        return self.expr(expr, depth, column, suffix);
+    }
+}
+
+/// An expression on one line. Expressions holding blocks never are
+fn flat(expr: &Expr) -> String {
+    let operand = |expr: &Expr, parens: bool| {
+        if parens {
+            return format!("({})", flat(expr));
+        }
+        return flat(expr);
+    };
+    match &expr.kind {
+        ExprKind::Int(n) => return n.to_string(),
+        ExprKind::Float(x) => {
+            let text = x.to_string();
+            if text.contains('.') {
+                return text;
+            }
+            return format!("{}.0", text);
+        }
+        ExprKind::Bool(true) => return "True".to_string(),
+        ExprKind::Bool(false) => return "False".to_string(),
+        ExprKind::Str(s) => return format!("\"{}\"", s),
+        ExprKind::Var(name) => return name.clone(),
+        ExprKind::Unary(UnOp::Neg, inner) => {
+            return format!("-{}", operand(inner, negation_parens(inner)))
+        }
+        ExprKind::Binary(left, op, right) => {
+            let (left_parens, right_parens) = operand_parens(expr);
+            return format!(
+                "{} {} {}",
+                operand(left, left_parens),
+                op,
+                operand(right, right_parens)
+            );
+        }
+        ExprKind::Call(name, args) => {
+            let args: Vec<String> = args.iter().map(flat).collect();
+            return format!("{}({})", name, args.join(", "));
+        }
+        ExprKind::If { .. } => unreachable!("an `if` always takes more than one line"),
+        ExprKind::Error => unreachable!("only programs that parse are formatted"),
+    }
+}
+
+/// How tightly an expression binds, as the levels of the grammar in Chapter 3
+fn precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::Binary(_, BinOp::Or, _) => return 1,
+        ExprKind::Binary(_, BinOp::And, _) => return 2,
+        ExprKind::Binary(_, BinOp::Add | BinOp::Sub, _) => return 4,
+        ExprKind::Binary(_, BinOp::Mul | BinOp::Div, _) => return 5,
+        ExprKind::Binary(..) => return COMPARISON,
+        ExprKind::Unary(..) => return UNARY,
+        _ => return 7,
+    }
+}
+
+/// Whether the operands of a binary expression need parentheses. The operators group to the
+/// left, except that comparisons do not group at all
+fn operand_parens(expr: &Expr) -> (bool, bool) {
+    let ExprKind::Binary(left, _, right) = &expr.kind else {
+        return (false, false);
+    };
+    let p = precedence(expr);
+    let left = precedence(left) < p || (p == COMPARISON && precedence(left) == COMPARISON);
+    return (left, precedence(right) <= p);
+}
+
+fn has_block(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::If { .. } => return true,
+        ExprKind::Unary(_, operand) => return has_block(operand),
+        ExprKind::Binary(left, _, right) => return has_block(left) || has_block(right),
+        ExprKind::Call(_, args) => return args.iter().any(has_block),
+        _ => return false,
+    }
+}
+
+/// Where an expression starts: a binary expression is positioned at its operator
+fn expr_start(expr: &Expr) -> Pos {
+    match &expr.kind {
+        ExprKind::Binary(left, _, _) => return expr_start(left),
+        _ => return expr.pos,
+    }
+}
+
+/// Where the last token of an expression without blocks starts
+fn last_pos(expr: &Expr) -> Pos {
+    match &expr.kind {
+        ExprKind::Unary(_, operand) => return last_pos(operand),
+        ExprKind::Binary(_, _, right) => return last_pos(right),
+        ExprKind::Call(_, args) => return args.last().map_or(expr.pos, last_pos),
+        _ => return expr.pos,
+    }
+}
+
+/// Whether the operand of a `-` needs parentheses: when it binds less tightly, or when it starts
+/// with a `-` of its own, which would otherwise read as `--`
+fn negation_parens(operand: &Expr) -> bool {
+    return precedence(operand) < UNARY || flat_starts_with_minus(operand);
+}
+
+fn flat_starts_with_minus(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Int(n) => return *n < 0,
+        ExprKind::Float(x) => return x.is_sign_negative(),
+        _ => return starts_with_negation(expr),
+    }
+}
+
+fn starts_with_negation(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Unary(UnOp::Neg, _) => return true,
+        ExprKind::Binary(left, _, _) => return starts_with_negation(left),
+        _ => return false,
+    }
+}
+
+fn mutable_prefix(mutable: bool) -> &'static str {
+    if mutable {
+        return "mut ";
+    }
+    return "";
+}
+
+/// The items of a list on lines of their own at `depth`, separated by commas. The grammar has
+/// no trailing comma
+fn one_to_a_line(items: &[String], depth: usize) -> String {
+    let lines: Vec<String> = items
+        .iter()
+        .map(|item| format!("{}{}", INDENT.repeat(depth), item))
+        .collect();
+    return format!("{}\n", lines.join(",\n"));
+}
+
+/// The column text ends at, starting at `column`
+fn end_column(column: usize, text: &str) -> usize {
+    match text.rfind('\n') {
+        Some(i) => return text[i + 1..].chars().count(),
+        None => return column + text.chars().count(),
+    }
+}
+
+/// A copy of a program with every position 0, to compare programs by their structure
+pub fn erase_positions(program: &Program) -> Program {
+    let mut program = program.clone();
+    walk_program_mut(&mut PositionEraser, &mut program);
+    return program;
+}
+
+struct PositionEraser;
+
+impl VisitorMut for PositionEraser {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        stmt.pos = 0;
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_fn_decl_mut(&mut self, decl: &mut FnDecl) {
+        decl.pos = 0;
+        decl.params.iter_mut().for_each(|param| param.pos = 0);
+        walk_fn_decl_mut(self, decl);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        block.pos = 0;
+        walk_block_mut(self, block);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        expr.pos = 0;
+        walk_expr_mut(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::*;
+
+    #[test]
+    fn test_format_layout() {
+        let source = "\
+fn   add( a:int,mut b : int ) : int{b=b+1;a+b}
+let x=if add(1,2)==4{ \"four\" }elseif True{\"?\"}else{\"no\"};
+while False{}
+for i=0:-(1+2)*3{print(i);}
+print((1-(2-3))*-x,(True|False)&True);
+";
+        assert_eq!(
+            format(source).unwrap(),
+            "\
+fn add(a: int, mut b: int): int {
+    b = b + 1;
+    a + b
+}
+let x = if add(1, 2) == 4 {
+    \"four\"
+} elseif True {
+    \"?\"
+} else {
+    \"no\"
+};
+while False {}
+for i = 0 : -(1 + 2) * 3 {
+    print(i);
+}
+print((1 - (2 - 3)) * -x, (True | False) & True);
+"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_comments_and_blank_lines() {
+        let source = "\
+// leading
+let x = 1; // one
+
+
+fn f(n: int): int { // body
+    // inside
+
+    n // result
+    // last
+}
+print(f( // argument
+  x));
+// end
+";
+        assert_eq!(
+            format(source).unwrap(),
+            "\
+// leading
+let x = 1; // one
+
+fn f(n: int): int { // body
+    // inside
+
+    n // result
+    // last
+}
+print(
+    f(
+        // argument
+        x
+    )
+);
+// end
+"
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_comments_inside_expressions() {
+        let source = "let x = // c1\n 1 + // c2\n 2;\nprint(x, // c3\n 3);\n";
+        let formatted = format(source).unwrap();
+        assert_eq!(
+            formatted,
+            "let x = // c1\n    1 + // c2\n    2;\nprint(\n    x,\n    // c3\n    3\n);\n"
+        );
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        let source = "print(1 // a\n); // b\n";
+        assert_eq!(format(source).unwrap(), "print(1); // a\n// b\n");
+    }
+
+    #[test]
+    fn test_format_keeps_double_negation_apart() {
+        let source = "print(-(-1), -(-x), - -1, -(-f(1)));\n";
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, "print(-(-1), -(-x), -(-1), -(-f(1)));\n");
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_wraps_long_lists() {
+        let long = "a_rather_long_argument_name";
+        let source = format!(
+            "fn f(p_one: int, p_two: int, p_three: int, p_four: int, p_five: int, p_six: int, p_seven: int): int {{ 0 }}\nprint(f({0}, {0}, {0}, {0}, 1, 2));",
+            long
+        );
+        assert_eq!(
+            format(&source).unwrap(),
+            format!(
+                "\
+fn f(
+    p_one: int,
+    p_two: int,
+    p_three: int,
+    p_four: int,
+    p_five: int,
+    p_six: int,
+    p_seven: int
+): int {{
+    0
+}}
+print(
+    f(
+        {0},
+        {0},
+        {0},
+        {0},
+        1,
+        2
+    )
+);
+",
+                long
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_keeps_ifs_apart_from_what_follows() {
+        let source = "fn f(): int { if True { print(1); }; -1 }\nif True {}; -f();";
+        let formatted = format(source).unwrap();
+        assert_eq!(
+            formatted,
+            "fn f(): int {\n    if True {\n        print(1);\n    };\n    -1\n}\nif True {};\n-f();\n"
+        );
+        let source = "fn g() { if True { print(1); }; }";
+        assert_eq!(
+            format(source).unwrap(),
+            "fn g() {\n    if True {\n        print(1);\n    };\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_format_is_idempotent() {
+        let mut sources: Vec<String> = PROGRAMS
+            .iter()
+            .chain(FAILING_PROGRAMS.iter())
+            .map(|(_, source)| source.to_string())
+            .collect();
+        sources.push(pressure_program(40));
+        for source in sources {
+            let once = format(&source).unwrap();
+            assert_eq!(format(&once).unwrap(), once, "{}", source);
+        }
+        assert_eq!(
+            format("let x = ;").unwrap_err().message,
+            "expected expression, found `;`"
+        );
+    }
+}
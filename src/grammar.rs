@@ -0,0 +1,1191 @@
+/// Context-free grammars over the tokens lexed in Chapter 2, in the notation of yacc, with the
+/// analyses for designing them and the transforms that make a grammar fit for predictive parsing
+///
+/// A grammar is a list of precedence declarations followed by its rules:
+///
+/// ```text
+/// %left "+" "-"            // each declaration binds tighter than the ones before it
+/// %left "*" "/"
+/// %right NEG               // a name only for use after %prec
+/// expr : expr "+" expr
+///      | "-" expr %prec NEG
+///      | "(" expr ")"
+///      | NUM
+///      ;
+/// ```
+///
+/// A quoted terminal is the lexeme of a token, and NUM, REAL, BOOLEAN, STRING and ID stand for
+/// the tokens carrying a value. Other names are nonterminals, the first rule's being the start
+/// symbol, and `%empty` is an empty alternative
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::chapter_2::{tokenize, tokenize_with_positions, Token};
+use crate::chapter_3::ParseError;
+use crate::chapter_4::Pos;
+
+/// The names standing for the tokens that carry a value
+const TOKEN_CLASSES: &[&str] = &["NUM", "REAL", "BOOLEAN", "STRING", "ID"];
+
+/// The name of terminal 0, the end of the input
+pub const END: &str = "$";
+
+/// A symbol of a grammar, indexing its terminals or its nonterminals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Symbol {
+    Terminal(usize),
+    Nonterminal(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assoc {
+    Left,
+    Right,
+    Nonassoc,
+}
+
+/// The precedence of a terminal or a production. Higher levels bind tighter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Precedence {
+    pub level: usize,
+    pub assoc: Assoc,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Production {
+    pub lhs: usize,
+    pub rhs: Vec<Symbol>,
+    /// Set by `%prec`, or else that of the last terminal of the right side that has one
+    pub precedence: Option<Precedence>,
+}
+
+/// A grammar read by `Grammar::parse`. Terminal 0 is the end of the input, and nonterminal 0 is
+/// the start symbol of the augmented grammar, whose only production is production 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grammar {
+    /// Terminals as they are written in the grammar, `"+"` or NUM
+    pub terminals: Vec<String>,
+    pub nonterminals: Vec<String>,
+    pub productions: Vec<Production>,
+    /// The declared precedence of each terminal
+    pub precedence: Vec<Option<Precedence>>,
+    /// The text the grammar was read from
+    pub source: String,
+}
+
+/// A word of the text of a grammar
+#[derive(Debug, Clone, PartialEq)]
+enum Word {
+    Directive(String),
+    Quoted(String),
+    Name(String),
+    Colon,
+    Bar,
+    Semicolon,
+}
+
+/// Split the text of a grammar into words, each with the line it is on
+fn words(text: &str) -> Result<Vec<(Word, usize)>, String> {
+    let mut words = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let line = line.split("//").next().unwrap_or_default();
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let word = match c {
+                c if c.is_whitespace() => continue,
+                ':' => Word::Colon,
+                '|' => Word::Bar,
+                ';' => Word::Semicolon,
+                '"' => {
+                    let rest = &line[start + 1..];
+                    let Some(len) = rest.find('"') else {
+                        return Err(format!("line {}: unterminated string", number));
+                    };
+                    for _ in 0..=rest[..len].chars().count() {
+                        chars.next();
+                    }
+                    Word::Quoted(rest[..len].to_string())
+                }
+                c if c == '%' || c.is_alphabetic() || c == '_' => {
+                    let mut end = start + c.len_utf8();
+                    while let Some((i, c)) = chars.peek() {
+                        if !(c.is_alphanumeric() || *c == '_') {
+                            break;
+                        }
+                        end = i + c.len_utf8();
+                        chars.next();
+                    }
+                    match line[start..end].strip_prefix('%') {
+                        Some(directive) => Word::Directive(directive.to_string()),
+                        None => Word::Name(line[start..end].to_string()),
+                    }
+                }
+                c => return Err(format!("line {}: unexpected character `{}`", number, c)),
+            };
+            words.push((word, number));
+        }
+    }
+    return Ok(words);
+}
+
+/// Whether a name stands for terminals, rather than being a nonterminal
+fn is_upper(name: &str) -> bool {
+    return !name.chars().any(|c| c.is_lowercase());
+}
+
+/// The terminal a token is an instance of, as grammars write it
+fn terminal_name(token: &Token) -> Option<String> {
+    let name = match token {
+        Token::Num(_) => "NUM",
+        Token::Real(_) => "REAL",
+        Token::Boolean(_) => "BOOLEAN",
+        Token::StaticString(_) => "STRING",
+        Token::Id(_) => "ID",
+        Token::Comment(_) | Token::Passthrough => return None,
+        token => return Some(format!("\"{}\"", token)),
+    };
+    return Some(name.to_string());
+}
+
+/// Reads the words of a grammar into a `Grammar`
+struct GrammarParser {
+    words: Vec<(Word, usize)>,
+    current: usize,
+    grammar: Grammar,
+    /// The names only used with `%prec`
+    precedence_names: HashMap<String, Precedence>,
+    /// The line each nonterminal is first used on
+    used: Vec<usize>,
+    /// The number of precedence declarations read so far
+    levels: usize,
+}
+
+impl Grammar {
+    pub fn parse(text: &str) -> Result<Grammar, String> {
+        let mut parser = GrammarParser {
+            words: words(text)?,
+            current: 0,
+            grammar: Grammar {
+                terminals: vec![END.to_string()],
+                nonterminals: vec![String::new()],
+                productions: vec![],
+                precedence: vec![None],
+                source: text.to_string(),
+            },
+            precedence_names: HashMap::new(),
+            used: vec![0],
+            levels: 0,
+        };
+        parser.declarations()?;
+        parser.rules()?;
+        let mut grammar = parser.grammar;
+        for (nonterminal, line) in parser.used.iter().enumerate().skip(1) {
+            if !grammar.productions.iter().any(|p| p.lhs == nonterminal) {
+                return Err(format!(
+                    "line {}: `{}` has no rules",
+                    line, grammar.nonterminals[nonterminal]
+                ));
+            }
+        }
+        grammar.nonterminals[0] = format!("{}'", grammar.nonterminals[1]);
+        return Ok(grammar);
+    }
+
+    /// The terminal a token is an instance of, if it is one of the grammar's
+    pub fn terminal(&self, token: &Token) -> Option<usize> {
+        let name = terminal_name(token)?;
+        return self.terminals.iter().position(|t| *t == name);
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        match symbol {
+            Symbol::Terminal(t) => return &self.terminals[t],
+            Symbol::Nonterminal(n) => return &self.nonterminals[n],
+        }
+    }
+
+    /// Symbols as grammars write them
+    pub fn names(&self, symbols: &[Symbol]) -> Vec<&str> {
+        return symbols.iter().map(|symbol| self.name(*symbol)).collect();
+    }
+
+    /// A production as grammars write its alternative, as in `expr : expr "+" term`
+    pub fn production(&self, p: usize) -> String {
+        let production = &self.productions[p];
+        let rhs = match production.rhs.is_empty() {
+            true => "%empty".to_string(),
+            false => self.names(&production.rhs).join(" "),
+        };
+        return format!("{} : {}", self.nonterminals[production.lhs], rhs);
+    }
+
+    /// The right sides of the productions of each nonterminal
+    fn rules(&self) -> Vec<Vec<Vec<Symbol>>> {
+        let mut rules = vec![vec![]; self.nonterminals.len()];
+        for production in &self.productions {
+            rules[production.lhs].push(production.rhs.clone());
+        }
+        return rules;
+    }
+
+    /// A grammar with the terminals of this one and new rules, nonterminal 0 still being the
+    /// augmented start. Each production takes the precedence of its last terminal that has one
+    fn with_rules(&self, nonterminals: Vec<String>, rules: Vec<Vec<Vec<Symbol>>>) -> Grammar {
+        let mut grammar = Grammar {
+            terminals: self.terminals.clone(),
+            nonterminals,
+            productions: vec![],
+            precedence: self.precedence.clone(),
+            source: String::new(),
+        };
+        for (lhs, alternatives) in rules.into_iter().enumerate() {
+            for rhs in alternatives {
+                let precedence = rhs.iter().rev().find_map(|symbol| match symbol {
+                    Symbol::Terminal(t) => self.precedence[*t],
+                    Symbol::Nonterminal(_) => None,
+                });
+                grammar.productions.push(Production {
+                    lhs,
+                    rhs,
+                    precedence,
+                });
+            }
+        }
+        grammar.source = grammar.to_string();
+        return grammar;
+    }
+
+    /// An equivalent grammar without left recursion, by the algorithm of the Dragon book. In
+    /// turn, each nonterminal has the alternatives starting with an earlier nonterminal that
+    /// leads back to it expanded, and then `a : a x | y ;` becomes
+    /// `a : y a_tail ; a_tail : x a_tail | %empty ;`. The grammar must have no cycles, and no
+    /// empty alternatives on the way back to a nonterminal
+    pub fn eliminate_left_recursion(&self) -> Grammar {
+        let mut names = self.nonterminals.clone();
+        let mut rules = self.rules();
+        for i in 1..self.nonterminals.len() {
+            for j in 1..i {
+                if !left_reaches(&rules, &[], j, i) {
+                    continue;
+                }
+                let mut expanded = Vec::new();
+                for rhs in std::mem::take(&mut rules[i]) {
+                    if rhs.first() != Some(&Symbol::Nonterminal(j)) {
+                        expanded.push(rhs);
+                        continue;
+                    }
+                    for start in &rules[j] {
+                        expanded.push(start.iter().chain(&rhs[1..]).copied().collect());
+                    }
+                }
+                rules[i] = expanded;
+            }
+            let (recursive, other): (Vec<_>, Vec<_>) = std::mem::take(&mut rules[i])
+                .into_iter()
+                .partition(|rhs| rhs.first() == Some(&Symbol::Nonterminal(i)));
+            if recursive.is_empty() {
+                rules[i] = other;
+                continue;
+            }
+            names.push(fresh(&names, &format!("{}_tail", names[i])));
+            let tail = Symbol::Nonterminal(names.len() - 1);
+            rules[i] = other
+                .into_iter()
+                .map(|rhs| rhs.into_iter().chain([tail]).collect())
+                .collect();
+            let mut tails: Vec<Vec<Symbol>> = recursive
+                .into_iter()
+                .map(|rhs| rhs[1..].iter().copied().chain([tail]).collect())
+                .collect();
+            tails.push(vec![]);
+            rules.push(tails);
+        }
+        return self.with_rules(names, rules);
+    }
+
+    /// An equivalent grammar where no two alternatives of a nonterminal start with the same
+    /// symbol: `a : x y | x z ;` becomes `a : x a_rest ; a_rest : y | z ;`, taking the longest
+    /// prefix the alternatives share, until none do
+    pub fn left_factor(&self) -> Grammar {
+        let mut names = self.nonterminals.clone();
+        let mut rules = self.rules();
+        let mut n = 1;
+        while n < rules.len() {
+            let alternatives = &rules[n];
+            let shared = alternatives.iter().enumerate().find_map(|(i, rhs)| {
+                let first = rhs.first()?;
+                let again = alternatives[i + 1..]
+                    .iter()
+                    .any(|other| other.first() == Some(first));
+                return again.then_some(*first);
+            });
+            let Some(first) = shared else {
+                n += 1;
+                continue;
+            };
+            let alternatives = std::mem::take(&mut rules[n]);
+            let group: Vec<&Vec<Symbol>> = alternatives
+                .iter()
+                .filter(|rhs| rhs.first() == Some(&first))
+                .collect();
+            let mut len = 1;
+            while group
+                .iter()
+                .all(|rhs| rhs.len() > len && rhs[len] == group[0][len])
+            {
+                len += 1;
+            }
+            names.push(fresh(&names, &format!("{}_rest", names[n])));
+            let rest = Symbol::Nonterminal(names.len() - 1);
+            let suffixes = group.iter().map(|rhs| rhs[len..].to_vec()).collect();
+            let mut factored = Some(group[0][..len].iter().copied().chain([rest]).collect());
+            for rhs in alternatives {
+                if rhs.first() != Some(&first) {
+                    rules[n].push(rhs);
+                } else if let Some(factored) = factored.take() {
+                    rules[n].push(factored);
+                }
+            }
+            rules.push(suffixes);
+        }
+        return self.with_rules(names, rules);
+    }
+
+    /// Which nonterminals derive the empty string, the terminals their derivations can start
+    /// with and the terminals that can follow them
+    pub fn sets(&self) -> Sets {
+        let count = self.nonterminals.len();
+        let mut sets = Sets {
+            nullable: vec![false; count],
+            first: vec![BTreeSet::new(); count],
+            follow: vec![BTreeSet::new(); count],
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for production in &self.productions {
+                let (starts, empty) = sets.first_of(&production.rhs);
+                let lhs = production.lhs;
+                if empty && !sets.nullable[lhs] {
+                    sets.nullable[lhs] = true;
+                    changed = true;
+                }
+                let before = sets.first[lhs].len();
+                sets.first[lhs].extend(starts);
+                changed |= sets.first[lhs].len() > before;
+            }
+        }
+        sets.follow[0].insert(0);
+        changed = true;
+        while changed {
+            changed = false;
+            for production in &self.productions {
+                for (i, symbol) in production.rhs.iter().enumerate() {
+                    let Symbol::Nonterminal(n) = *symbol else {
+                        continue;
+                    };
+                    let (mut follow, empty) = sets.first_of(&production.rhs[i + 1..]);
+                    if empty {
+                        follow.extend(&sets.follow[production.lhs]);
+                    }
+                    let before = sets.follow[n].len();
+                    sets.follow[n].extend(follow);
+                    changed |= sets.follow[n].len() > before;
+                }
+            }
+        }
+        return sets;
+    }
+}
+
+/// The nullable, FIRST and FOLLOW sets of the nonterminals of a grammar
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sets {
+    pub nullable: Vec<bool>,
+    pub first: Vec<BTreeSet<usize>>,
+    pub follow: Vec<BTreeSet<usize>>,
+}
+
+impl Sets {
+    /// The terminals a string of symbols can start with, and whether it can derive the empty
+    /// string
+    pub fn first_of(&self, symbols: &[Symbol]) -> (BTreeSet<usize>, bool) {
+        let mut starts = BTreeSet::new();
+        for symbol in symbols {
+            match *symbol {
+                Symbol::Terminal(t) => {
+                    starts.insert(t);
+                    return (starts, false);
+                }
+                Symbol::Nonterminal(n) => {
+                    starts.extend(&self.first[n]);
+                    if !self.nullable[n] {
+                        return (starts, false);
+                    }
+                }
+            }
+        }
+        return (starts, true);
+    }
+}
+
+/// The nullable, FIRST and FOLLOW sets of each nonterminal of a grammar as a table, then the
+/// conflicts of its LL(1) table
+pub fn report(grammar: &Grammar) -> String {
+    let sets = grammar.sets();
+    let names = |terminals: &BTreeSet<usize>| {
+        let names: Vec<&str> = terminals
+            .iter()
+            .map(|t| grammar.terminals[*t].as_str())
+            .collect();
+        return names.join(" ");
+    };
+    let mut rows = vec![[
+        String::new(),
+        "nullable".to_string(),
+        "FIRST".to_string(),
+        "FOLLOW".to_string(),
+    ]];
+    for n in 1..grammar.nonterminals.len() {
+        let nullable = if sets.nullable[n] { "yes" } else { "no" };
+        rows.push([
+            grammar.nonterminals[n].clone(),
+            nullable.to_string(),
+            names(&sets.first[n]),
+            names(&sets.follow[n]),
+        ]);
+    }
+    let widths: Vec<usize> = (0..3)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap())
+        .collect();
+    let mut out = String::new();
+    for row in &rows {
+        let mut line = String::new();
+        for (cell, width) in row.iter().zip(&widths) {
+            line.push_str(&format!("{:<width$}  ", cell, width = width));
+        }
+        line.push_str(&row[3]);
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    let conflicts = Ll1::new(grammar).conflicts;
+    match conflicts.len() {
+        0 => out.push_str("\nthe grammar is LL(1)\n"),
+        1 => out.push_str("\n1 LL(1) conflict\n"),
+        n => out.push_str(&format!("\n{} LL(1) conflicts\n", n)),
+    }
+    for conflict in conflicts {
+        out.push_str(&format!("{}\n", conflict));
+    }
+    return out;
+}
+
+impl GrammarParser {
+    fn peek(&self) -> Option<&Word> {
+        return self.words.get(self.current).map(|(word, _)| word);
+    }
+
+    /// The line of the current word, or of the last one at the end
+    fn line(&self) -> usize {
+        let index = self.current.min(self.words.len().saturating_sub(1));
+        return self.words.get(index).map_or(1, |(_, line)| *line);
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        return Err(format!("line {}: {}", self.line(), message));
+    }
+
+    fn expect(&mut self, word: Word, what: &str) -> Result<(), String> {
+        if self.peek() != Some(&word) {
+            return self.error(&format!("expected {}", what));
+        }
+        self.current += 1;
+        return Ok(());
+    }
+
+    fn declarations(&mut self) -> Result<(), String> {
+        while let Some(Word::Directive(directive)) = self.peek() {
+            let assoc = match directive.as_str() {
+                "left" => Assoc::Left,
+                "right" => Assoc::Right,
+                "nonassoc" => Assoc::Nonassoc,
+                other => return self.error(&format!("unknown declaration `%{}`", other)),
+            };
+            self.current += 1;
+            self.levels += 1;
+            let precedence = Precedence {
+                level: self.levels,
+                assoc,
+            };
+            loop {
+                match self.peek().cloned() {
+                    // A declaration runs until the first rule
+                    Some(Word::Name(name)) if !is_upper(&name) => break,
+                    Some(Word::Name(name)) if !TOKEN_CLASSES.contains(&name.as_str()) => {
+                        self.current += 1;
+                        self.precedence_names.insert(name, precedence);
+                    }
+                    Some(Word::Name(_) | Word::Quoted(_)) => {
+                        let terminal = self.terminal()?;
+                        self.grammar.precedence[terminal] = Some(precedence);
+                    }
+                    _ => break,
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Read the terminal at the current word, adding it to the grammar the first time
+    fn terminal(&mut self) -> Result<usize, String> {
+        let name = match self.peek() {
+            Some(Word::Name(name)) if TOKEN_CLASSES.contains(&name.as_str()) => name.clone(),
+            Some(Word::Name(name)) => return self.error(&format!("`{}` is not a token", name)),
+            Some(Word::Quoted(lexeme)) => {
+                let name = format!("\"{}\"", lexeme);
+                match tokenize(lexeme).as_deref() {
+                    Ok([token]) if terminal_name(token).as_ref() == Some(&name) => {}
+                    _ => return self.error(&format!("{} is not the lexeme of a token", name)),
+                }
+                name
+            }
+            _ => return self.error("expected a terminal"),
+        };
+        self.current += 1;
+        if let Some(terminal) = self.grammar.terminals.iter().position(|t| *t == name) {
+            return Ok(terminal);
+        }
+        self.grammar.terminals.push(name);
+        self.grammar.precedence.push(None);
+        return Ok(self.grammar.terminals.len() - 1);
+    }
+
+    fn nonterminal(&mut self, name: &str) -> usize {
+        if let Some(n) = self.grammar.nonterminals.iter().position(|n| n == name) {
+            return n;
+        }
+        self.grammar.nonterminals.push(name.to_string());
+        self.used.push(self.line());
+        return self.grammar.nonterminals.len() - 1;
+    }
+
+    fn rules(&mut self) -> Result<(), String> {
+        if self.peek().is_none() {
+            return self.error("the grammar has no rules");
+        }
+        while let Some(word) = self.peek().cloned() {
+            let lhs = match word {
+                Word::Name(name) if !is_upper(&name) => self.nonterminal(&name),
+                _ => return self.error("expected the name of a rule"),
+            };
+            if self.grammar.productions.is_empty() {
+                self.grammar.productions.push(Production {
+                    lhs: 0,
+                    rhs: vec![Symbol::Nonterminal(lhs)],
+                    precedence: None,
+                });
+            }
+            self.current += 1;
+            self.expect(Word::Colon, "`:`")?;
+            loop {
+                self.alternative(lhs)?;
+                if self.peek() != Some(&Word::Bar) {
+                    break;
+                }
+                self.current += 1;
+            }
+            self.expect(Word::Semicolon, "`|` or `;`")?;
+        }
+        return Ok(());
+    }
+
+    fn alternative(&mut self, lhs: usize) -> Result<(), String> {
+        let mut rhs = Vec::new();
+        let mut precedence = None;
+        let mut empty = false;
+        while let Some(word) = self.peek().cloned() {
+            match word {
+                Word::Name(name) if !is_upper(&name) => {
+                    rhs.push(Symbol::Nonterminal(self.nonterminal(&name)));
+                    self.current += 1;
+                }
+                Word::Name(_) | Word::Quoted(_) => {
+                    let terminal = self.terminal()?;
+                    rhs.push(Symbol::Terminal(terminal));
+                }
+                Word::Directive(directive) if directive == "empty" => {
+                    self.current += 1;
+                    empty = true;
+                }
+                Word::Directive(directive) if directive == "prec" => {
+                    self.current += 1;
+                    precedence = match self.peek().cloned() {
+                        Some(Word::Name(name)) if self.precedence_names.contains_key(&name) => {
+                            self.current += 1;
+                            self.precedence_names.get(&name).copied()
+                        }
+                        Some(Word::Name(_) | Word::Quoted(_)) => {
+                            let terminal = self.terminal()?;
+                            self.grammar.precedence[terminal]
+                        }
+                        _ => return self.error("expected a terminal after `%prec`"),
+                    };
+                    if precedence.is_none() {
+                        self.current -= 1;
+                        return self.error("this terminal has no precedence");
+                    }
+                }
+                Word::Directive(other) => {
+                    return self.error(&format!("unknown declaration `%{}`", other))
+                }
+                Word::Colon | Word::Bar | Word::Semicolon => break,
+            }
+        }
+        if empty && !rhs.is_empty() {
+            return self.error("`%empty` in an alternative that is not empty");
+        }
+        let precedence = precedence.or_else(|| {
+            return rhs.iter().rev().find_map(|symbol| match symbol {
+                Symbol::Terminal(t) => self.grammar.precedence[*t],
+                Symbol::Nonterminal(_) => None,
+            });
+        });
+        self.grammar.productions.push(Production {
+            lhs,
+            rhs,
+            precedence,
+        });
+        return Ok(());
+    }
+}
+
+/// Whether the leftmost symbols of the derivations of one nonterminal can reach another. The
+/// nonterminals not in `nullable` are taken not to derive the empty string
+fn left_reaches(rules: &[Vec<Vec<Symbol>>], nullable: &[bool], from: usize, to: usize) -> bool {
+    let mut seen = vec![false; rules.len()];
+    let mut work = vec![from];
+    while let Some(n) = work.pop() {
+        for rhs in &rules[n] {
+            for symbol in rhs {
+                let Symbol::Nonterminal(m) = *symbol else {
+                    break;
+                };
+                if m == to {
+                    return true;
+                }
+                if !seen[m] {
+                    seen[m] = true;
+                    work.push(m);
+                }
+                if !nullable.get(m).copied().unwrap_or(false) {
+                    break;
+                }
+            }
+        }
+    }
+    return false;
+}
+
+/// A name not among some names, made from a base
+fn fresh(names: &[String], base: &str) -> String {
+    let mut name = base.to_string();
+    let mut n = 1;
+    while names.contains(&name) {
+        n += 1;
+        name = format!("{}{}", base, n);
+    }
+    return name;
+}
+
+/// The rules of a grammar in the notation `Grammar::parse` reads, without its precedence
+/// declarations, in the order of their first productions
+impl fmt::Display for Grammar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut order: Vec<usize> = Vec::new();
+        for production in &self.productions[1..] {
+            if !order.contains(&production.lhs) {
+                order.push(production.lhs);
+            }
+        }
+        for n in order {
+            let name = &self.nonterminals[n];
+            let alternatives: Vec<String> = (0..self.productions.len())
+                .filter(|p| self.productions[*p].lhs == n)
+                .map(|p| self.production(p)[name.len() + " : ".len()..].to_string())
+                .collect();
+            let pad = " ".repeat(name.len());
+            let separator = format!("\n{} | ", pad);
+            write!(f, "{} : {}", name, alternatives.join(&separator))?;
+            match alternatives.len() {
+                1 => writeln!(f, " ;")?,
+                _ => writeln!(f, "\n{} ;", pad)?,
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// A conflict in an LL(1) table: more than one production to predict on a lookahead
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlConflict {
+    pub nonterminal: String,
+    pub lookahead: String,
+    /// Why each of the productions is predicted on the lookahead
+    pub reasons: Vec<String>,
+    /// A transform that may remove the conflict
+    pub help: Option<String>,
+}
+
+impl fmt::Display for LlConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LL(1) conflict for {} on {}",
+            self.nonterminal, self.lookahead
+        )?;
+        for reason in &self.reasons {
+            write!(f, "\n  {}", reason)?;
+        }
+        if let Some(help) = &self.help {
+            write!(f, "\n  help: {}", help)?;
+        }
+        return Ok(());
+    }
+}
+
+/// The predictive parse table of a grammar
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ll1<'g> {
+    grammar: &'g Grammar,
+    /// The productions predicted for each nonterminal on each lookahead terminal. The grammar is
+    /// LL(1) when there is at most one
+    pub table: Vec<Vec<Vec<usize>>>,
+    pub conflicts: Vec<LlConflict>,
+}
+
+impl<'g> Ll1<'g> {
+    /// Predict each production on the terminals its right side can start with, and on those
+    /// that can follow its left side if the right side can derive nothing
+    pub fn new(grammar: &'g Grammar) -> Ll1<'g> {
+        let sets = grammar.sets();
+        let mut table = vec![vec![vec![]; grammar.terminals.len()]; grammar.nonterminals.len()];
+        for (p, production) in grammar.productions.iter().enumerate() {
+            let (mut lookaheads, empty) = sets.first_of(&production.rhs);
+            if empty {
+                lookaheads.extend(&sets.follow[production.lhs]);
+            }
+            for t in lookaheads {
+                table[production.lhs][t].push(p);
+            }
+        }
+        let rules = grammar.rules();
+        let mut conflicts = Vec::new();
+        for (n, row) in table.iter().enumerate() {
+            for (t, predicted) in row.iter().enumerate() {
+                if predicted.len() < 2 {
+                    continue;
+                }
+                let (name, lookahead) = (&grammar.nonterminals[n], &grammar.terminals[t]);
+                let reasons = predicted
+                    .iter()
+                    .map(|&p| {
+                        let production = grammar.production(p);
+                        if sets.first_of(&grammar.productions[p].rhs).0.contains(&t) {
+                            return format!("`{}` can start with {}", production, lookahead);
+                        }
+                        return format!(
+                            "`{}` can derive nothing, and {} can follow {}",
+                            production, lookahead, name
+                        );
+                    })
+                    .collect();
+                let starts: Vec<_> = predicted
+                    .iter()
+                    .filter_map(|&p| grammar.productions[p].rhs.first())
+                    .collect();
+                let help = if left_reaches(&rules, &sets.nullable, n, n) {
+                    Some(format!(
+                        "{} is left recursive, which no LL(1) grammar is: try \
+                         `eliminate_left_recursion`",
+                        name
+                    ))
+                } else if (1..starts.len()).any(|i| starts[..i].contains(&starts[i])) {
+                    Some(format!(
+                        "alternatives of {} start alike: try `left_factor`",
+                        name
+                    ))
+                } else {
+                    None
+                };
+                conflicts.push(LlConflict {
+                    nonterminal: name.clone(),
+                    lookahead: lookahead.clone(),
+                    reasons,
+                    help,
+                });
+            }
+        }
+        return Ll1 {
+            grammar,
+            table,
+            conflicts,
+        };
+    }
+
+    /// Parse source by predicting productions with the table. The grammar must be LL(1)
+    pub fn parse(&self, input: &str) -> Result<Tree, ParseError> {
+        assert!(
+            self.conflicts.is_empty(),
+            "only an LL(1) grammar parses predictively"
+        );
+        let tokens = tokenize_with_positions(input)?;
+        let mut next = 0;
+        let tree = self.derive(1, &tokens, &mut next, input.len())?;
+        if next < tokens.len() {
+            return Err(syntax_error(
+                [END].into_iter(),
+                tokens.get(next),
+                input.len(),
+            ));
+        }
+        return Ok(tree);
+    }
+
+    /// Parse a nonterminal from the token at `next` on
+    fn derive(
+        &self,
+        n: usize,
+        tokens: &[(Token, Pos)],
+        next: &mut usize,
+        end: Pos,
+    ) -> Result<Tree, ParseError> {
+        let grammar = self.grammar;
+        let row = &self.table[n];
+        let lookahead = match tokens.get(*next) {
+            Some((token, _)) => grammar.terminal(token),
+            None => Some(0),
+        };
+        let Some(&p) = lookahead.and_then(|t| row[t].first()) else {
+            let expected = (0..row.len())
+                .filter(|t| !row[*t].is_empty())
+                .map(|t| grammar.terminals[t].as_str());
+            return Err(syntax_error(expected, tokens.get(*next), end));
+        };
+        let mut children = Vec::new();
+        for symbol in &grammar.productions[p].rhs {
+            match *symbol {
+                Symbol::Nonterminal(m) => children.push(self.derive(m, tokens, next, end)?),
+                Symbol::Terminal(t) => match tokens.get(*next) {
+                    Some((token, pos)) if grammar.terminal(token) == Some(t) => {
+                        children.push(Tree::Leaf(token.clone(), *pos));
+                        *next += 1;
+                    }
+                    found => {
+                        let expected = [grammar.terminals[t].as_str()].into_iter();
+                        return Err(syntax_error(expected, found, end));
+                    }
+                },
+            }
+        }
+        return Ok(Tree::Node {
+            symbol: grammar.nonterminals[n].clone(),
+            production: p,
+            children,
+        });
+    }
+}
+
+/// A parse tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tree {
+    Leaf(Token, Pos),
+    Node {
+        symbol: String,
+        production: usize,
+        children: Vec<Tree>,
+    },
+}
+
+/// A tree as an s-expression, each node labelled with its nonterminal
+impl fmt::Display for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tree::Leaf(token, _) => return write!(f, "{}", token),
+            Tree::Node {
+                symbol, children, ..
+            } => {
+                write!(f, "({}", symbol)?;
+                for child in children {
+                    write!(f, " {}", child)?;
+                }
+                return write!(f, ")");
+            }
+        }
+    }
+}
+
+/// The error for a token no action is expected on, listing the terminals that are
+pub fn syntax_error<'a>(
+    expected: impl Iterator<Item = &'a str>,
+    found: Option<&(Token, Pos)>,
+    end: Pos,
+) -> ParseError {
+    let expected: Vec<String> = expected
+        .map(|terminal| match terminal {
+            END => "end of input".to_string(),
+            quoted if quoted.starts_with('"') => format!("`{}`", &quoted[1..quoted.len() - 1]),
+            class => class.to_string(),
+        })
+        .collect();
+    let expected = match expected.as_slice() {
+        [one] => one.clone(),
+        many => format!("one of {}", many.join(", ")),
+    };
+    let (found, pos) = match found {
+        Some((token, pos)) => (format!("`{}`", token), *pos),
+        None => ("end of input".to_string(), end),
+    };
+    return ParseError {
+        message: format!("expected {}, found {}", expected, found),
+        pos,
+        related: vec![],
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arithmetic as the Dragon book writes it, left recursive
+    const ARITHMETIC: &str = r#"
+expr : expr "+" term
+     | term
+     ;
+term : term "*" factor
+     | factor
+     ;
+factor : "(" expr ")"
+       | ID
+       ;
+"#;
+
+    fn names<'a>(grammar: &'a Grammar, terminals: &BTreeSet<usize>) -> Vec<&'a str> {
+        return terminals
+            .iter()
+            .map(|t| grammar.terminals[*t].as_str())
+            .collect();
+    }
+
+    #[test]
+    fn test_grammar_errors() {
+        for (text, message) in [
+            ("", "line 1: the grammar has no rules"),
+            ("s : a ;", "line 1: `a` has no rules"),
+            (
+                "s : \"+\" \n | \"a b\" ;",
+                "line 2: \"a b\" is not the lexeme of a token",
+            ),
+            (
+                "s : \"True\" ;",
+                "line 1: \"True\" is not the lexeme of a token",
+            ),
+            ("s : NEG ;", "line 1: `NEG` is not a token"),
+            (
+                "s : ID %prec \"+\" ;",
+                "line 1: this terminal has no precedence",
+            ),
+            (
+                "%token ID\ns : ID ;",
+                "line 1: unknown declaration `%token`",
+            ),
+            ("s : ID", "line 1: expected `|` or `;`"),
+            ("s : ID ; ~", "line 1: unexpected character `~`"),
+        ] {
+            assert_eq!(Grammar::parse(text), Err(message.to_string()), "{}", text);
+        }
+        let grammar = Grammar::parse("s : s \"+\" ID // a comment\n | %empty ;").unwrap();
+        assert_eq!(grammar.terminals, ["$", "\"+\"", "ID"]);
+        assert_eq!(grammar.nonterminals, ["s'", "s"]);
+        assert_eq!(grammar.productions.len(), 3);
+        assert!(grammar.productions[2].rhs.is_empty());
+    }
+
+    #[test]
+    fn test_nullable_first_and_follow() {
+        let grammar = Grammar::parse(ARITHMETIC)
+            .unwrap()
+            .eliminate_left_recursion();
+        let sets = grammar.sets();
+        let rows: Vec<(&str, bool, Vec<&str>, Vec<&str>)> = (1..grammar.nonterminals.len())
+            .map(|n| {
+                return (
+                    grammar.nonterminals[n].as_str(),
+                    sets.nullable[n],
+                    names(&grammar, &sets.first[n]),
+                    names(&grammar, &sets.follow[n]),
+                );
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("expr", false, vec!["\"(\"", "ID"], vec!["$", "\")\""]),
+                (
+                    "term",
+                    false,
+                    vec!["\"(\"", "ID"],
+                    vec!["$", "\"+\"", "\")\""]
+                ),
+                (
+                    "factor",
+                    false,
+                    vec!["\"(\"", "ID"],
+                    vec!["$", "\"+\"", "\"*\"", "\")\""]
+                ),
+                ("expr_tail", true, vec!["\"+\""], vec!["$", "\")\""]),
+                (
+                    "term_tail",
+                    true,
+                    vec!["\"*\""],
+                    vec!["$", "\"+\"", "\")\""]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let grammar = Grammar::parse(ARITHMETIC).unwrap();
+        let text = report(&grammar);
+        assert!(text.starts_with(
+            "        nullable  FIRST   FOLLOW
+expr    no        \"(\" ID  $ \"+\" \")\"
+term    no        \"(\" ID  $ \"+\" \"*\" \")\"
+factor  no        \"(\" ID  $ \"+\" \"*\" \")\"
+
+4 LL(1) conflicts
+LL(1) conflict for expr on \"(\"
+"
+        ));
+        assert!(text.contains("help: expr is left recursive"), "{}", text);
+        let grammar = grammar.eliminate_left_recursion();
+        assert!(report(&grammar).ends_with("\nthe grammar is LL(1)\n"));
+    }
+
+    #[test]
+    fn test_eliminate_left_recursion() {
+        let grammar = Grammar::parse(ARITHMETIC).unwrap();
+        let conflicts: Vec<String> = Ll1::new(&grammar)
+            .conflicts
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            conflicts[0],
+            "LL(1) conflict for expr on \"(\"\n  \
+             `expr : expr \"+\" term` can start with \"(\"\n  \
+             `expr : term` can start with \"(\"\n  \
+             help: expr is left recursive, which no LL(1) grammar is: try \
+             `eliminate_left_recursion`"
+        );
+        assert_eq!(conflicts.len(), 4);
+
+        let grammar = grammar.eliminate_left_recursion();
+        assert_eq!(
+            grammar.to_string(),
+            "expr : term expr_tail ;\n\
+             term : factor term_tail ;\n\
+             factor : \"(\" expr \")\"\n       | ID\n       ;\n\
+             expr_tail : \"+\" term expr_tail\n          | %empty\n          ;\n\
+             term_tail : \"*\" factor term_tail\n          | %empty\n          ;\n"
+        );
+        let reparsed = Grammar::parse(&grammar.to_string()).unwrap();
+        assert_eq!(reparsed.to_string(), grammar.to_string());
+        let ll1 = Ll1::new(&grammar);
+        assert_eq!(ll1.conflicts, []);
+        assert_eq!(
+            ll1.parse("a + b * c").unwrap().to_string(),
+            "(expr (term (factor a) (term_tail)) (expr_tail + (term (factor b) (term_tail * \
+             (factor c) (term_tail))) (expr_tail)))"
+        );
+        assert_eq!(
+            ll1.parse("a + * c").unwrap_err().message,
+            "expected one of `(`, ID, found `*`"
+        );
+        assert_eq!(
+            ll1.parse("(a b").unwrap_err().message,
+            "expected one of end of input, `+`, `*`, `)`, found `b`"
+        );
+
+        // Recursion through another nonterminal is expanded first
+        let grammar = Grammar::parse("s : a \"+\" | \"-\" ; a : s \"*\" | \"/\" ;").unwrap();
+        assert_eq!(
+            grammar.eliminate_left_recursion().to_string(),
+            "s : a \"+\"\n  | \"-\"\n  ;\n\
+             a : \"-\" \"*\" a_tail\n  | \"/\" a_tail\n  ;\n\
+             a_tail : \"+\" \"*\" a_tail\n       | %empty\n       ;\n"
+        );
+    }
+
+    #[test]
+    fn test_left_factor() {
+        let text = r#"
+stmt : "if" expr block
+     | "if" expr block "else" block
+     | "while" expr block
+     ;
+block : "{" "}" ;
+expr : ID | ID "(" ")" ;
+"#;
+        let grammar = Grammar::parse(text).unwrap();
+        let conflicts: Vec<String> = Ll1::new(&grammar)
+            .conflicts
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            conflicts,
+            [
+                "LL(1) conflict for stmt on \"if\"\n  \
+                 `stmt : \"if\" expr block` can start with \"if\"\n  \
+                 `stmt : \"if\" expr block \"else\" block` can start with \"if\"\n  \
+                 help: alternatives of stmt start alike: try `left_factor`",
+                "LL(1) conflict for expr on ID\n  \
+                 `expr : ID` can start with ID\n  \
+                 `expr : ID \"(\" \")\"` can start with ID\n  \
+                 help: alternatives of expr start alike: try `left_factor`"
+            ]
+        );
+        let factored = grammar.left_factor();
+        assert_eq!(
+            factored.to_string(),
+            "stmt : \"if\" expr block stmt_rest\n     | \"while\" expr block\n     ;\n\
+             expr : ID expr_rest ;\n\
+             block : \"{\" \"}\" ;\n\
+             stmt_rest : %empty\n          | \"else\" block\n          ;\n\
+             expr_rest : %empty\n          | \"(\" \")\"\n          ;\n"
+        );
+        let ll1 = Ll1::new(&factored);
+        assert_eq!(ll1.conflicts, []);
+        assert_eq!(
+            ll1.parse("if f() {} else {}").unwrap().to_string(),
+            "(stmt if (expr f (expr_rest ( ))) (block { }) (stmt_rest else (block { })))"
+        );
+
+        // A statement that can end in a block of its own cannot also decide the `else` by one
+        // token: the dangling else
+        let nested = text.replace("block : \"{\" \"}\" ;", "block : \"{\" \"}\" | stmt ;");
+        let factored = Grammar::parse(&nested).unwrap().left_factor();
+        let conflicts = Ll1::new(&factored).conflicts;
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].reasons,
+            [
+                "`stmt_rest : %empty` can derive nothing, and \"else\" can follow stmt_rest",
+                "`stmt_rest : \"else\" block` can start with \"else\""
+            ]
+        );
+        assert_eq!(conflicts[0].help, None);
+    }
+}
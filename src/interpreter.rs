@@ -0,0 +1,592 @@
+/// A reference tree-walking interpreter for the language parsed in Chapter 3
+///
+/// This is the oracle compiled output is checked against, so every construct has one defined
+/// meaning here:
+///
+/// * `int` is a 64-bit two's complement integer. Arithmetic wraps, `/` truncates toward zero
+///   and dividing by zero is a runtime error.
+/// * `float` is a 64-bit IEEE float. `print` shows it with six decimal places.
+/// * `bool` prints as `True` or `False`. `&` and `|` short circuit.
+/// * `string` values are immutable. `+` concatenates and `==`/`<>` compare contents.
+/// * `if` is an expression whose value is the value of the branch taken. Without an `else` it
+///   produces no value.
+/// * `for i = lo : hi` evaluates both bounds once and runs with `i` from `lo` up to `hi - 1`.
+/// * Functions are statically scoped and may read and assign any variable visible where they are
+///   declared. Consecutive declarations in a block may call each other.
+/// * `print(a, b, ...)` prints each argument on its own line, like `print` in Chapter 1.
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::chapter_4::*;
+
+/// The deepest the call stack may grow before the program is stopped. Every back end stops
+/// programs at the same depth
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Stack size of the thread programs run on, enough for `MAX_CALL_DEPTH` nested calls
+pub const STACK_SIZE: usize = 1 << 30;
+
+/// A value computed by the program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    Unit,
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Int(i) => return write!(f, "{}", i),
+            Value::Float(x) => return write!(f, "{:.6}", x),
+            Value::Bool(true) => return write!(f, "True"),
+            Value::Bool(false) => return write!(f, "False"),
+            Value::Str(s) => return write!(f, "{}", s),
+            Value::Unit => return write!(f, "()"),
+        }
+    }
+}
+
+/// An error that stops the program while it is running
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub pos: Pos,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
+/// Functions declared together, along with the environment they were declared in
+struct FnGroup {
+    decls: Vec<Rc<FnDecl>>,
+    env: Env,
+}
+
+enum Binding {
+    Var(String, Rc<std::cell::RefCell<Value>>),
+    Fn(String, Rc<FnGroup>, usize),
+}
+
+/// A persistent linked list of bindings. Declaring a name pushes a new node, so a function keeps
+/// seeing exactly the bindings that existed where it was declared
+#[derive(Clone, Default)]
+struct Env(Option<Rc<EnvNode>>);
+
+struct EnvNode {
+    binding: Binding,
+    parent: Env,
+}
+
+impl Env {
+    fn push(&self, binding: Binding) -> Env {
+        return Env(Some(Rc::new(EnvNode {
+            binding,
+            parent: self.clone(),
+        })));
+    }
+
+    fn define_var(&self, name: &str, value: Value) -> Env {
+        return self.push(Binding::Var(
+            name.to_string(),
+            Rc::new(std::cell::RefCell::new(value)),
+        ));
+    }
+
+    fn lookup_var(&self, name: &str) -> Option<Rc<std::cell::RefCell<Value>>> {
+        let mut env = &self.0;
+        while let Some(node) = env {
+            if let Binding::Var(n, cell) = &node.binding {
+                if n == name {
+                    return Some(cell.clone());
+                }
+            }
+            env = &node.parent.0;
+        }
+        return None;
+    }
+
+    fn lookup_fn(&self, name: &str) -> Option<(Rc<FnGroup>, usize)> {
+        let mut env = &self.0;
+        while let Some(node) = env {
+            if let Binding::Fn(n, group, index) = &node.binding {
+                if n == name {
+                    return Some((group.clone(), *index));
+                }
+            }
+            env = &node.parent.0;
+        }
+        return None;
+    }
+}
+
+/// Run a program, writing anything it prints to `out`
+pub fn interpret(program: &Program, out: &mut (dyn Write + Send)) -> Result<(), RuntimeError> {
+    return std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || {
+                let mut interpreter = Interpreter { out, depth: 0 };
+                interpreter.exec_stmts(&program.stmts, &Env::default())?;
+                return Ok(());
+            })
+            .unwrap();
+        return thread.join().unwrap();
+    });
+}
+
+/// Run a program and collect everything it prints
+#[cfg(test)]
+pub fn interpret_to_string(program: &Program) -> Result<String, RuntimeError> {
+    let mut out = Vec::new();
+    interpret(program, &mut out)?;
+    return Ok(String::from_utf8(out).unwrap());
+}
+
+/// Runs programs one after another, each seeing the top level bindings of those before it, as a
+/// read-eval-print loop needs
+///
+/// Programs run on the calling thread, which needs a stack of `STACK_SIZE` for the deepest
+/// recursion the interpreter allows.
+#[derive(Default)]
+pub struct Session {
+    env: Env,
+}
+
+impl Session {
+    /// Run a type checked program, returning the value of its last statement if that is an
+    /// expression. The program's bindings are kept only if it runs without error
+    pub fn run(
+        &mut self,
+        program: &Program,
+        out: &mut dyn Write,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let mut interpreter = Interpreter { out, depth: 0 };
+        let (last, stmts) = match program.stmts.split_last() {
+            Some((
+                Stmt {
+                    kind: StmtKind::Expr(expr),
+                    ..
+                },
+                stmts,
+            )) => (Some(expr), stmts),
+            _ => (None, &program.stmts[..]),
+        };
+        let env = interpreter.exec_stmts(stmts, &self.env)?;
+        let value = match last {
+            Some(expr) => Some(interpreter.eval(expr, &env)?),
+            None => None,
+        };
+        self.env = env;
+        return Ok(value);
+    }
+}
+
+struct Interpreter<'a> {
+    out: &'a mut dyn Write,
+    depth: usize,
+}
+
+fn error<T>(message: &str, pos: Pos) -> Result<T, RuntimeError> {
+    return Err(RuntimeError {
+        message: message.to_string(),
+        pos,
+    });
+}
+
+impl Interpreter<'_> {
+    /// Execute statements in order, returning the environment with their declarations added
+    fn exec_stmts(&mut self, stmts: &[Stmt], env: &Env) -> Result<Env, RuntimeError> {
+        let mut env = env.clone();
+        let mut i = 0;
+        while i < stmts.len() {
+            if let StmtKind::Fn(_) = stmts[i].kind {
+                let mut decls = Vec::new();
+                while let Some(Stmt {
+                    kind: StmtKind::Fn(decl),
+                    ..
+                }) = stmts.get(i)
+                {
+                    decls.push(Rc::new(decl.clone()));
+                    i += 1;
+                }
+                env = self.declare_fns(decls, &env);
+            } else {
+                env = self.exec_stmt(&stmts[i], &env)?;
+                i += 1;
+            }
+        }
+        return Ok(env);
+    }
+
+    fn declare_fns(&mut self, decls: Vec<Rc<FnDecl>>, env: &Env) -> Env {
+        let group = Rc::new(FnGroup {
+            decls,
+            env: env.clone(),
+        });
+        let mut env = env.clone();
+        for (index, decl) in group.decls.iter().enumerate() {
+            env = env.push(Binding::Fn(decl.name.clone(), group.clone(), index));
+        }
+        return env;
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt, env: &Env) -> Result<Env, RuntimeError> {
+        match &stmt.kind {
+            StmtKind::Let { name, init, .. } => {
+                let value = self.eval(init, env)?;
+                return Ok(env.define_var(name, value));
+            }
+            StmtKind::Assign { name, value } => {
+                let value = self.eval(value, env)?;
+                match env.lookup_var(name) {
+                    Some(cell) => *cell.borrow_mut() = value,
+                    None => return error(&format!("undefined variable `{}`", name), stmt.pos),
+                }
+            }
+            StmtKind::While { cond, body } => {
+                while self.eval_bool(cond, env)? {
+                    self.eval_block(body, env)?;
+                }
+            }
+            StmtKind::For { var, lo, hi, body } => {
+                let lo = self.eval_int(lo, env)?;
+                let hi = self.eval_int(hi, env)?;
+                for i in lo..hi {
+                    self.eval_block(body, &env.define_var(var, Value::Int(i)))?;
+                }
+            }
+            StmtKind::Fn(decl) => return Ok(self.declare_fns(vec![Rc::new(decl.clone())], env)),
+            StmtKind::Error => unreachable!("the type checker rejects syntax errors"),
+            StmtKind::Expr(expr) => {
+                self.eval(expr, env)?;
+            }
+        }
+        return Ok(env.clone());
+    }
+
+    fn eval_block(&mut self, block: &Block, env: &Env) -> Result<Value, RuntimeError> {
+        let env = self.exec_stmts(&block.stmts, env)?;
+        match &block.result {
+            Some(expr) => return self.eval(expr, &env),
+            None => return Ok(Value::Unit),
+        }
+    }
+
+    fn eval_bool(&mut self, expr: &Expr, env: &Env) -> Result<bool, RuntimeError> {
+        match self.eval(expr, env)? {
+            Value::Bool(b) => return Ok(b),
+            other => return error(&format!("expected bool, found {:?}", other), expr.pos),
+        }
+    }
+
+    fn eval_int(&mut self, expr: &Expr, env: &Env) -> Result<i64, RuntimeError> {
+        match self.eval(expr, env)? {
+            Value::Int(i) => return Ok(i),
+            other => return error(&format!("expected int, found {:?}", other), expr.pos),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, env: &Env) -> Result<Value, RuntimeError> {
+        match &expr.kind {
+            ExprKind::Int(i) => return Ok(Value::Int(*i)),
+            ExprKind::Float(x) => return Ok(Value::Float(*x)),
+            ExprKind::Bool(b) => return Ok(Value::Bool(*b)),
+            ExprKind::Str(s) => return Ok(Value::Str(Rc::from(s.as_str()))),
+            ExprKind::Var(name) => match env.lookup_var(name) {
+                Some(cell) => return Ok(cell.borrow().clone()),
+                None => return error(&format!("undefined variable `{}`", name), expr.pos),
+            },
+            ExprKind::Unary(UnOp::Neg, operand) => match self.eval(operand, env)? {
+                Value::Int(i) => return Ok(Value::Int(i.wrapping_neg())),
+                Value::Float(x) => return Ok(Value::Float(-x)),
+                other => return error(&format!("cannot negate {:?}", other), expr.pos),
+            },
+            ExprKind::Binary(left, BinOp::And, right) => {
+                return Ok(Value::Bool(
+                    self.eval_bool(left, env)? && self.eval_bool(right, env)?,
+                ))
+            }
+            ExprKind::Binary(left, BinOp::Or, right) => {
+                return Ok(Value::Bool(
+                    self.eval_bool(left, env)? || self.eval_bool(right, env)?,
+                ))
+            }
+            ExprKind::Binary(left, op, right) => {
+                let left = self.eval(left, env)?;
+                let right = self.eval(right, env)?;
+                return calc_bin_op(left, *op, right, expr.pos);
+            }
+            ExprKind::Error => unreachable!("the type checker rejects syntax errors"),
+            ExprKind::Call(name, args) => {
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.eval(arg, env)?);
+                }
+                return self.call(name, values, env, expr.pos);
+            }
+            ExprKind::If {
+                branches,
+                else_block,
+            } => {
+                for (cond, block) in branches {
+                    if self.eval_bool(cond, env)? {
+                        return self.eval_block(block, env);
+                    }
+                }
+                match else_block {
+                    Some(block) => return self.eval_block(block, env),
+                    None => return Ok(Value::Unit),
+                }
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        env: &Env,
+        pos: Pos,
+    ) -> Result<Value, RuntimeError> {
+        let (group, index) = match env.lookup_fn(name) {
+            Some(found) => found,
+            None if name == "print" => {
+                for arg in args {
+                    if let Err(e) = writeln!(self.out, "{}", arg) {
+                        return error(&format!("could not print: {}", e), pos);
+                    }
+                }
+                return Ok(Value::Unit);
+            }
+            None => return error(&format!("undefined function `{}`", name), pos),
+        };
+        let decl = &group.decls[index];
+        if decl.params.len() != args.len() {
+            return error(
+                &format!(
+                    "`{}` takes {} arguments but {} were given",
+                    name,
+                    decl.params.len(),
+                    args.len()
+                ),
+                pos,
+            );
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return error("stack overflow", pos);
+        }
+
+        // The callee sees its own group, declared in the environment the group was declared in
+        let mut callee_env = group.env.clone();
+        for (i, sibling) in group.decls.iter().enumerate() {
+            callee_env = callee_env.push(Binding::Fn(sibling.name.clone(), group.clone(), i));
+        }
+        for (param, arg) in decl.params.iter().zip(args) {
+            callee_env = callee_env.define_var(&param.name, arg);
+        }
+
+        self.depth += 1;
+        let result = self.eval_block(&decl.body, &callee_env);
+        self.depth -= 1;
+        return result;
+    }
+}
+
+/// Calculate the result of a binary operator other than `&` and `|`
+fn calc_bin_op(left: Value, op: BinOp, right: Value, pos: Pos) -> Result<Value, RuntimeError> {
+    match (&left, op, &right) {
+        (Value::Int(_), BinOp::Div, Value::Int(0)) => return error("division by zero", pos),
+        (Value::Int(l), _, Value::Int(r)) => {
+            let (l, r) = (*l, *r);
+            let value = match op {
+                BinOp::Add => Value::Int(l.wrapping_add(r)),
+                BinOp::Sub => Value::Int(l.wrapping_sub(r)),
+                BinOp::Mul => Value::Int(l.wrapping_mul(r)),
+                BinOp::Div => Value::Int(l.wrapping_div(r)),
+                BinOp::Eq => Value::Bool(l == r),
+                BinOp::Neq => Value::Bool(l != r),
+                BinOp::Lt => Value::Bool(l < r),
+                BinOp::Le => Value::Bool(l <= r),
+                BinOp::Gt => Value::Bool(l > r),
+                BinOp::Ge => Value::Bool(l >= r),
+                BinOp::And | BinOp::Or => unreachable!(),
+            };
+            return Ok(value);
+        }
+        (Value::Float(l), _, Value::Float(r)) => {
+            let (l, r) = (*l, *r);
+            let value = match op {
+                BinOp::Add => Value::Float(l + r),
+                BinOp::Sub => Value::Float(l - r),
+                BinOp::Mul => Value::Float(l * r),
+                BinOp::Div => Value::Float(l / r),
+                BinOp::Eq => Value::Bool(l == r),
+                BinOp::Neq => Value::Bool(l != r),
+                BinOp::Lt => Value::Bool(l < r),
+                BinOp::Le => Value::Bool(l <= r),
+                BinOp::Gt => Value::Bool(l > r),
+                BinOp::Ge => Value::Bool(l >= r),
+                BinOp::And | BinOp::Or => unreachable!(),
+            };
+            return Ok(value);
+        }
+        (Value::Str(l), BinOp::Add, Value::Str(r)) => {
+            return Ok(Value::Str(Rc::from(format!("{}{}", l, r).as_str())))
+        }
+        (Value::Str(_), BinOp::Eq, Value::Str(_)) | (Value::Bool(_), BinOp::Eq, Value::Bool(_)) => {
+            return Ok(Value::Bool(left == right))
+        }
+        (Value::Str(_), BinOp::Neq, Value::Str(_))
+        | (Value::Bool(_), BinOp::Neq, Value::Bool(_)) => return Ok(Value::Bool(left != right)),
+        _ => {
+            return error(
+                &format!("cannot apply `{}` to {:?} and {:?}", op, left, right),
+                pos,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_3::parse;
+
+    fn run(input: &str) -> String {
+        return interpret_to_string(&parse(input).unwrap()).unwrap();
+    }
+
+    fn run_err(input: &str) -> String {
+        return interpret_to_string(&parse(input).unwrap())
+            .unwrap_err()
+            .message;
+    }
+
+    #[test]
+    fn test_interp_arithmetic() {
+        assert_eq!(
+            run("print(1 + 2 * 3, (1 + 2) * 3, 7 / 2, -7 / 2);"),
+            "7\n9\n3\n-3\n"
+        );
+        assert_eq!(run("print(1.5 * 2.0, 1.0 / 4.0);"), "3.000000\n0.250000\n");
+        assert_eq!(
+            run("print(2000000000 * 2000000000 * 3);"),
+            "-6446744073709551616\n"
+        );
+    }
+
+    #[test]
+    fn test_session_keeps_bindings() {
+        let mut session = Session::default();
+        let mut out = Vec::new();
+        let mut step = |input: &str| session.run(&parse(input).unwrap(), &mut out);
+        assert_eq!(
+            step("let x = 2; fn double(n: int): int { n * x }"),
+            Ok(None)
+        );
+        assert_eq!(step("print(x); double(21)"), Ok(Some(Value::Int(42))));
+        assert_eq!(
+            step("let y = 1 / 0;").unwrap_err().message,
+            "division by zero"
+        );
+        assert_eq!(step("y").unwrap_err().message, "undefined variable `y`");
+        assert_eq!(String::from_utf8(out).unwrap(), "2\n");
+    }
+
+    #[test]
+    fn test_interp_comparisons_and_logic() {
+        assert_eq!(
+            run("print(1 < 2, 2 <= 1, 3 == 3, 3 <> 3, 1.5 > 0.5, True & False, True | False);"),
+            "True\nFalse\nTrue\nFalse\nTrue\nFalse\nTrue\n"
+        );
+    }
+
+    #[test]
+    fn test_interp_short_circuit() {
+        assert_eq!(
+            run("print(False & 1 / 0 == 0, True | 1 / 0 == 0);"),
+            "False\nTrue\n"
+        );
+    }
+
+    #[test]
+    fn test_interp_strings() {
+        assert_eq!(
+            run(r#"let s = "ab" + "cd"; print(s, s == "abcd", s <> "abcd");"#),
+            "abcd\nTrue\nFalse\n"
+        );
+    }
+
+    #[test]
+    fn test_interp_if_chain() {
+        let program = "fn sign(x: int): int { if x < 0 { -1 } elseif x == 0 { 0 } else { 1 } }
+                       print(sign(-5), sign(0), sign(5));";
+        assert_eq!(run(program), "-1\n0\n1\n");
+    }
+
+    #[test]
+    fn test_interp_loops() {
+        let program = "let mut total = 0;
+                       for i = 0 : 5 { total = total + i; }
+                       let mut n = 0;
+                       while n < 3 { n = n + 1; }
+                       print(total, n);";
+        assert_eq!(run(program), "10\n3\n");
+    }
+
+    #[test]
+    fn test_interp_recursion() {
+        let program = "fn fact(n: int): int { if n == 0 { 1 } else { n * fact(n - 1) } }
+                       print(fact(10));";
+        assert_eq!(run(program), "3628800\n");
+    }
+
+    #[test]
+    fn test_interp_mutual_recursion() {
+        let program = "fn even(n: int): bool { if n == 0 { True } else { odd(n - 1) } }
+                       fn odd(n: int): bool { if n == 0 { False } else { even(n - 1) } }
+                       print(even(10), odd(7));";
+        assert_eq!(run(program), "True\nTrue\n");
+    }
+
+    #[test]
+    fn test_interp_nested_functions_share_outer_variables() {
+        let program = "fn counter(): int {
+                           let mut count = 0;
+                           fn bump(by: int) { count = count + by; }
+                           bump(2);
+                           bump(3);
+                           count
+                       }
+                       print(counter());";
+        assert_eq!(run(program), "5\n");
+    }
+
+    #[test]
+    fn test_interp_static_scoping() {
+        let program = "let x = 1;
+                       fn show() { print(x); }
+                       let x = 2;
+                       show();
+                       print(x);";
+        assert_eq!(run(program), "1\n2\n");
+    }
+
+    #[test]
+    fn test_interp_runtime_errors() {
+        assert_eq!(run_err("print(1 / 0);"), "division by zero");
+        assert_eq!(run_err("print(y);"), "undefined variable `y`");
+        assert_eq!(run_err("f();"), "undefined function `f`");
+        assert_eq!(run_err("fn f() { f(); } f();"), "stack overflow");
+        assert_eq!(
+            run_err("print(1 + True);"),
+            "cannot apply `+` to Int(1) and Bool(true)"
+        );
+    }
+}
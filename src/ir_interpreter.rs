@@ -0,0 +1,745 @@
+/// An interpreter for the intermediate representation of Chapter 7
+///
+/// It runs the tree code a program is lowered to, so every pass between translation and
+/// instruction selection can be checked against the reference interpreter:
+///
+/// * Memory is a flat array of bytes holding little-endian words, which need not be aligned.
+///   String literals sit at the bottom, the heap grows up above them and the stack grows down
+///   from the top.
+/// * Every call gets fresh temporaries. The arguments are stored straight into the formals of the
+///   callee's frame, the frame pointer points at the new frame and the result is whatever is left
+///   in the return value register when the body finishes.
+/// * A call to a label that is not a function of the program goes to the runtime library.
+/// * A jump goes to a label at the top level of the body or, from inside an `ESEQ`, to a label of
+///   that `ESEQ`, so bodies can run before and after they are linearized.
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::chapter_6::{Access, Frame, Label, Temp};
+use crate::chapter_7::*;
+use crate::chapter_8::canonicalize;
+use crate::interpreter::{MAX_CALL_DEPTH, STACK_SIZE};
+
+/// Bytes of simulated memory
+const MEMORY_SIZE: usize = 1 << 24;
+
+/// An error that stops the program while it is running
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrError {
+    pub message: String,
+}
+
+impl std::fmt::Display for IrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
+/// A function ready to run: its frame and its body as a list of statements
+#[derive(Debug, Clone)]
+pub struct Proc<F: Frame> {
+    pub frame: F,
+    pub body: Vec<Stm>,
+}
+
+/// A translated program ready to run. The function labelled `PROGRAM_MAIN` is run first
+#[derive(Debug, Clone)]
+pub struct IrProgram<F: Frame> {
+    pub procs: Vec<Proc<F>>,
+    pub strings: Vec<(Label, String)>,
+}
+
+impl<F: Frame> IrProgram<F> {
+    /// Collect the fragments of a program, canonicalizing every function body
+    pub fn new(frags: Vec<Frag<F>>) -> IrProgram<F> {
+        return IrProgram::with_pass(frags, canonicalize);
+    }
+
+    /// Collect the fragments of a program, turning every function body into statements with `pass`
+    pub fn with_pass(frags: Vec<Frag<F>>, pass: impl Fn(Stm) -> Vec<Stm>) -> IrProgram<F> {
+        let mut program = IrProgram {
+            procs: Vec::new(),
+            strings: Vec::new(),
+        };
+        for frag in frags {
+            match frag {
+                Frag::Proc { body, frame } => program.procs.push(Proc {
+                    frame,
+                    body: pass(body),
+                }),
+                Frag::Str(label, s) => program.strings.push((label, s)),
+            }
+        }
+        return program;
+    }
+}
+
+/// Run a program, writing anything it prints to `out`
+pub fn run<F: Frame + Sync>(
+    program: &IrProgram<F>,
+    out: &mut (dyn Write + Send),
+) -> Result<(), IrError> {
+    return std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, move || {
+                let mut machine = Machine::new(program, out)?;
+                machine.call(PROGRAM_MAIN, Vec::new())?;
+                return Ok(());
+            })
+            .unwrap();
+        return thread.join().unwrap();
+    });
+}
+
+/// Run a program and collect everything it prints
+#[cfg(test)]
+pub fn run_to_string<F: Frame + Sync>(program: &IrProgram<F>) -> Result<String, IrError> {
+    let mut out = Vec::new();
+    run(program, &mut out)?;
+    return Ok(String::from_utf8(out).unwrap());
+}
+
+fn error<T>(message: String) -> Result<T, IrError> {
+    return Err(IrError { message });
+}
+
+/// A function body prepared for running
+struct Code<'a, F: Frame> {
+    frame: &'a F,
+    stms: Vec<&'a Stm>,
+    labels: HashMap<&'a Label, usize>,
+    /// Bytes below the frame pointer used by the formals and locals of the frame
+    locals_size: i64,
+    /// Bytes above the frame pointer holding arguments passed in memory
+    args_size: i64,
+}
+
+impl<'a, F: Frame> Code<'a, F> {
+    fn new(proc: &'a Proc<F>) -> Code<'a, F> {
+        let mut stms = Vec::new();
+        for stm in &proc.body {
+            flatten(stm, &mut stms);
+        }
+        let mut labels = HashMap::new();
+        for (i, stm) in stms.iter().enumerate() {
+            if let Stm::Label(label) = stm {
+                labels.insert(label, i);
+            }
+        }
+        let mut locals_size = 0;
+        for stm in &stms {
+            locals_size = locals_size.max(stm_locals_size::<F>(stm));
+        }
+        let mut args_size = 0;
+        for access in proc.frame.formals() {
+            match access {
+                Access::InFrame(offset) if *offset < 0 => locals_size = locals_size.max(-offset),
+                Access::InFrame(offset) => args_size = args_size.max(offset + F::WORD_SIZE),
+                Access::InReg(_) => {}
+            }
+        }
+        return Code {
+            frame: &proc.frame,
+            stms,
+            labels,
+            locals_size,
+            args_size,
+        };
+    }
+}
+
+fn flatten<'a>(stm: &'a Stm, stms: &mut Vec<&'a Stm>) {
+    match stm {
+        Stm::Seq(a, b) => {
+            flatten(a, stms);
+            flatten(b, stms);
+        }
+        _ => stms.push(stm),
+    }
+}
+
+/// The furthest below the frame pointer a statement reaches with `MEM(fp + k)`
+fn stm_locals_size<F: Frame>(stm: &Stm) -> i64 {
+    match stm {
+        Stm::Move(dst, src) => return exp_locals_size::<F>(dst).max(exp_locals_size::<F>(src)),
+        Stm::Exp(e) | Stm::Jump(e, _) => return exp_locals_size::<F>(e),
+        Stm::CJump(_, a, b, _, _) => return exp_locals_size::<F>(a).max(exp_locals_size::<F>(b)),
+        Stm::Seq(a, b) => return stm_locals_size::<F>(a).max(stm_locals_size::<F>(b)),
+        Stm::Label(_) => return 0,
+    }
+}
+
+fn exp_locals_size<F: Frame>(exp: &Exp) -> i64 {
+    match exp {
+        Exp::Mem(address) => {
+            if let Exp::BinOp(BinOp::Plus, base, offset) = address.as_ref() {
+                if let (Exp::Temp(t), Exp::Const(k)) = (base.as_ref(), offset.as_ref()) {
+                    if *t == F::fp() && *k < 0 {
+                        return -k;
+                    }
+                }
+            }
+            return exp_locals_size::<F>(address);
+        }
+        Exp::BinOp(_, a, b) => return exp_locals_size::<F>(a).max(exp_locals_size::<F>(b)),
+        Exp::Call(f, args) => {
+            let mut size = exp_locals_size::<F>(f);
+            for arg in args {
+                size = size.max(exp_locals_size::<F>(arg));
+            }
+            return size;
+        }
+        Exp::Eseq(s, e) => return stm_locals_size::<F>(s).max(exp_locals_size::<F>(e)),
+        Exp::Const(_) | Exp::Name(_) | Exp::Temp(_) => return 0,
+    }
+}
+
+struct Machine<'a, F: Frame> {
+    procs: HashMap<&'a str, Code<'a, F>>,
+    strings: HashMap<&'a str, i64>,
+    memory: Vec<u8>,
+    /// First free byte of the heap
+    heap: i64,
+    /// Lowest byte of the stack in use
+    sp: i64,
+    depth: usize,
+    out: &'a mut dyn Write,
+}
+
+impl<'a, F: Frame> Machine<'a, F> {
+    fn new(program: &'a IrProgram<F>, out: &'a mut dyn Write) -> Result<Machine<'a, F>, IrError> {
+        let mut machine = Machine {
+            procs: HashMap::new(),
+            strings: HashMap::new(),
+            memory: vec![0; MEMORY_SIZE],
+            // Address zero is never handed out
+            heap: F::WORD_SIZE,
+            sp: MEMORY_SIZE as i64,
+            depth: 0,
+            out,
+        };
+        for proc in &program.procs {
+            machine
+                .procs
+                .insert(proc.frame.name().0.as_str(), Code::new(proc));
+        }
+        for (label, s) in &program.strings {
+            let address = machine.alloc_string(s.as_bytes())?;
+            machine.strings.insert(label.0.as_str(), address);
+        }
+        return Ok(machine);
+    }
+
+    fn check_address(&self, address: i64, size: i64) -> Result<usize, IrError> {
+        if address < F::WORD_SIZE || address + size > MEMORY_SIZE as i64 {
+            return error(format!("invalid memory access at address {}", address));
+        }
+        return Ok(address as usize);
+    }
+
+    fn load(&self, address: i64) -> Result<i64, IrError> {
+        let start = self.check_address(address, 8)?;
+        let bytes: [u8; 8] = self.memory[start..start + 8].try_into().unwrap();
+        return Ok(i64::from_le_bytes(bytes));
+    }
+
+    fn store(&mut self, address: i64, value: i64) -> Result<(), IrError> {
+        let start = self.check_address(address, 8)?;
+        self.memory[start..start + 8].copy_from_slice(&value.to_le_bytes());
+        return Ok(());
+    }
+
+    /// Allocate a string on the heap: a word holding the length followed by the bytes
+    fn alloc_string(&mut self, bytes: &[u8]) -> Result<i64, IrError> {
+        let address = self.heap;
+        let size = F::WORD_SIZE + bytes.len() as i64;
+        // Keep the heap word aligned
+        let size = (size + F::WORD_SIZE - 1) / F::WORD_SIZE * F::WORD_SIZE;
+        if address + size > self.sp {
+            return error("out of memory".to_string());
+        }
+        self.heap += size;
+        self.store(address, bytes.len() as i64)?;
+        let start = (address + F::WORD_SIZE) as usize;
+        self.memory[start..start + bytes.len()].copy_from_slice(bytes);
+        return Ok(address);
+    }
+
+    fn read_string(&self, address: i64) -> Result<Vec<u8>, IrError> {
+        let len = self.load(address)?;
+        if len < 0 {
+            return error(format!("invalid string at address {}", address));
+        }
+        let start = self.check_address(address + F::WORD_SIZE, len)?;
+        return Ok(self.memory[start..start + len as usize].to_vec());
+    }
+
+    fn call(&mut self, name: &str, args: Vec<i64>) -> Result<i64, IrError> {
+        if !self.procs.contains_key(name) {
+            return self.call_runtime(name, args);
+        }
+        if self.depth == MAX_CALL_DEPTH {
+            return error("stack overflow".to_string());
+        }
+        let code = &self.procs[name];
+        let (frame, locals_size, args_size) = (code.frame, code.locals_size, code.args_size);
+        if frame.formals().len() != args.len() {
+            return error(format!(
+                "`{}` expects {} arguments, found {}",
+                name,
+                frame.formals().len(),
+                args.len()
+            ));
+        }
+
+        let saved_sp = self.sp;
+        let fp = self.sp - args_size;
+        self.sp = fp - locals_size;
+        if self.sp < self.heap {
+            return error("stack overflow".to_string());
+        }
+        let mut temps = HashMap::new();
+        temps.insert(F::fp(), fp);
+        for (access, value) in frame.formals().iter().zip(args) {
+            match access {
+                Access::InFrame(offset) => self.store(fp + offset, value)?,
+                Access::InReg(t) => {
+                    temps.insert(*t, value);
+                }
+            }
+        }
+
+        self.depth += 1;
+        self.exec_body(name, &mut temps)?;
+        self.depth -= 1;
+        self.sp = saved_sp;
+        return Ok(temps.get(&F::rv()).copied().unwrap_or(0));
+    }
+
+    fn exec_body(&mut self, name: &str, temps: &mut HashMap<Temp, i64>) -> Result<(), IrError> {
+        let mut pc = 0;
+        while pc < self.procs[name].stms.len() {
+            let stm = self.procs[name].stms[pc];
+            match self.step(stm, temps)? {
+                Some(label) => match self.procs[name].labels.get(label) {
+                    Some(i) => pc = *i,
+                    None => return error(format!("jump to unknown label {}", label)),
+                },
+                None => pc += 1,
+            }
+        }
+        return Ok(());
+    }
+
+    /// Run the statements of an `ESEQ`, which may jump among their own labels
+    fn exec_eseq(&mut self, stm: &Stm, temps: &mut HashMap<Temp, i64>) -> Result<(), IrError> {
+        let mut stms = Vec::new();
+        flatten(stm, &mut stms);
+        let mut pc = 0;
+        while pc < stms.len() {
+            match self.step(stms[pc], temps)? {
+                Some(label) => match stms
+                    .iter()
+                    .position(|s| matches!(s, Stm::Label(l) if l == label))
+                {
+                    Some(i) => pc = i,
+                    None => return error(format!("jump out of an ESEQ to {}", label)),
+                },
+                None => pc += 1,
+            }
+        }
+        return Ok(());
+    }
+
+    /// Execute one statement of a list, returning the label it jumps to, if any
+    fn step<'s>(
+        &mut self,
+        stm: &'s Stm,
+        temps: &mut HashMap<Temp, i64>,
+    ) -> Result<Option<&'s Label>, IrError> {
+        match stm {
+            Stm::Label(_) => return Ok(None),
+            Stm::Jump(target, _) => match target.as_ref() {
+                Exp::Name(label) => return Ok(Some(label)),
+                _ => return error(format!("cannot jump to {}", target)),
+            },
+            Stm::CJump(op, a, b, t, f) => {
+                let a = self.eval(a, temps)?;
+                let b = self.eval(b, temps)?;
+                if compare(*op, a, b) {
+                    return Ok(Some(t));
+                } else {
+                    return Ok(Some(f));
+                }
+            }
+            _ => {
+                self.exec(stm, temps)?;
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Execute a statement that does not jump
+    fn exec(&mut self, stm: &Stm, temps: &mut HashMap<Temp, i64>) -> Result<(), IrError> {
+        match stm {
+            Stm::Move(dst, src) => match dst.as_ref() {
+                Exp::Temp(t) => {
+                    let value = self.eval(src, temps)?;
+                    temps.insert(*t, value);
+                }
+                Exp::Mem(address) => {
+                    let address = self.eval(address, temps)?;
+                    let value = self.eval(src, temps)?;
+                    self.store(address, value)?;
+                }
+                _ => return error(format!("cannot move into {}", dst)),
+            },
+            Stm::Exp(e) => {
+                self.eval(e, temps)?;
+            }
+            Stm::Seq(a, b) => {
+                self.exec(a, temps)?;
+                self.exec(b, temps)?;
+            }
+            Stm::Label(_) => {}
+            Stm::Jump(..) | Stm::CJump(..) => {
+                return error(format!("cannot jump from inside an expression: {}", stm))
+            }
+        }
+        return Ok(());
+    }
+
+    fn eval(&mut self, exp: &Exp, temps: &mut HashMap<Temp, i64>) -> Result<i64, IrError> {
+        match exp {
+            Exp::Const(k) => return Ok(*k),
+            Exp::Name(label) => match self.strings.get(label.0.as_str()) {
+                Some(address) => return Ok(*address),
+                None => return error(format!("{} is not the label of a string", label)),
+            },
+            Exp::Temp(t) => match temps.get(t) {
+                Some(value) => return Ok(*value),
+                None => return error(format!("read of uninitialized temporary {}", t)),
+            },
+            Exp::BinOp(op, a, b) => {
+                let a = self.eval(a, temps)?;
+                let b = self.eval(b, temps)?;
+                return bin_op(*op, a, b);
+            }
+            Exp::Mem(address) => {
+                let address = self.eval(address, temps)?;
+                return self.load(address);
+            }
+            Exp::Call(f, args) => {
+                let name = match f.as_ref() {
+                    Exp::Name(label) => label,
+                    _ => return error(format!("cannot call {}", f)),
+                };
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.eval(arg, temps)?);
+                }
+                return self.call(&name.0, values);
+            }
+            Exp::Eseq(s, e) => {
+                self.exec_eseq(s, temps)?;
+                return self.eval(e, temps);
+            }
+        }
+    }
+
+    fn call_runtime(&mut self, name: &str, args: Vec<i64>) -> Result<i64, IrError> {
+        let float = |i: usize| f64::from_bits(args[i] as u64);
+        let bits = |x: f64| x.to_bits() as i64;
+        let expected = match name {
+            DIV_BY_ZERO => 0,
+            PRINT_INT | PRINT_FLOAT | PRINT_BOOL | PRINT_STRING | FLOAT_NEG => 1,
+            STRING_CONCAT | STRING_EQUAL | FLOAT_ADD | FLOAT_SUB | FLOAT_MUL | FLOAT_DIV
+            | FLOAT_EQ | FLOAT_NE | FLOAT_LT | FLOAT_LE | FLOAT_GT | FLOAT_GE => 2,
+            _ => return error(format!("undefined function `{}`", name)),
+        };
+        if args.len() != expected {
+            return error(format!(
+                "`{}` expects {} arguments, found {}",
+                name,
+                expected,
+                args.len()
+            ));
+        }
+        match name {
+            DIV_BY_ZERO => return error("division by zero".to_string()),
+            PRINT_INT => writeln!(self.out, "{}", args[0]).unwrap(),
+            PRINT_FLOAT => writeln!(self.out, "{:.6}", float(0)).unwrap(),
+            PRINT_BOOL => {
+                writeln!(self.out, "{}", if args[0] != 0 { "True" } else { "False" }).unwrap()
+            }
+            PRINT_STRING => {
+                let bytes = self.read_string(args[0])?;
+                self.out.write_all(&bytes).unwrap();
+                writeln!(self.out).unwrap();
+            }
+            STRING_CONCAT => {
+                let mut bytes = self.read_string(args[0])?;
+                bytes.extend(self.read_string(args[1])?);
+                return self.alloc_string(&bytes);
+            }
+            STRING_EQUAL => {
+                return Ok((self.read_string(args[0])? == self.read_string(args[1])?) as i64)
+            }
+            FLOAT_ADD => return Ok(bits(float(0) + float(1))),
+            FLOAT_SUB => return Ok(bits(float(0) - float(1))),
+            FLOAT_MUL => return Ok(bits(float(0) * float(1))),
+            FLOAT_DIV => return Ok(bits(float(0) / float(1))),
+            FLOAT_NEG => return Ok(bits(-float(0))),
+            FLOAT_EQ => return Ok((float(0) == float(1)) as i64),
+            FLOAT_NE => return Ok((float(0) != float(1)) as i64),
+            FLOAT_LT => return Ok((float(0) < float(1)) as i64),
+            FLOAT_LE => return Ok((float(0) <= float(1)) as i64),
+            FLOAT_GT => return Ok((float(0) > float(1)) as i64),
+            FLOAT_GE => return Ok((float(0) >= float(1)) as i64),
+            _ => unreachable!(),
+        }
+        return Ok(0);
+    }
+}
+
+fn bin_op(op: BinOp, a: i64, b: i64) -> Result<i64, IrError> {
+    match op {
+        BinOp::Plus => return Ok(a.wrapping_add(b)),
+        BinOp::Minus => return Ok(a.wrapping_sub(b)),
+        BinOp::Mul => return Ok(a.wrapping_mul(b)),
+        BinOp::Div => {
+            if b == 0 {
+                return error("division by zero".to_string());
+            }
+            return Ok(a.wrapping_div(b));
+        }
+        BinOp::And => return Ok(a & b),
+        BinOp::Or => return Ok(a | b),
+        BinOp::Xor => return Ok(a ^ b),
+        BinOp::LShift => return Ok(a.wrapping_shl(b as u32)),
+        BinOp::RShift => return Ok((a as u64).wrapping_shr(b as u32) as i64),
+        BinOp::ARShift => return Ok(a.wrapping_shr(b as u32)),
+    }
+}
+
+fn compare(op: RelOp, a: i64, b: i64) -> bool {
+    let (ua, ub) = (a as u64, b as u64);
+    match op {
+        RelOp::Eq => return a == b,
+        RelOp::Ne => return a != b,
+        RelOp::Lt => return a < b,
+        RelOp::Gt => return a > b,
+        RelOp::Le => return a <= b,
+        RelOp::Ge => return a >= b,
+        RelOp::ULt => return ua < ub,
+        RelOp::UGt => return ua > ub,
+        RelOp::ULe => return ua <= ub,
+        RelOp::UGe => return ua >= ub,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_6::X86_64Frame;
+    use crate::chapter_8::linearize;
+    use crate::test_programs::*;
+
+    fn run_source(source: &str) -> Result<String, IrError> {
+        let frags = translate::<X86_64Frame>(&load(source));
+        return run_to_string(&IrProgram::new(frags));
+    }
+
+    fn temp(t: Temp) -> Box<Exp> {
+        return Box::new(Exp::Temp(t));
+    }
+
+    fn main_proc(body: Vec<Stm>) -> IrProgram<X86_64Frame> {
+        return IrProgram {
+            procs: vec![Proc {
+                frame: X86_64Frame::new(Label::named(PROGRAM_MAIN), &[]),
+                body,
+            }],
+            strings: Vec::new(),
+        };
+    }
+
+    fn print_int(e: Exp) -> Stm {
+        return Stm::Exp(Box::new(Exp::Call(
+            Box::new(Exp::Name(Label::named(PRINT_INT))),
+            vec![e],
+        )));
+    }
+
+    #[test]
+    fn test_ir_programs_match_interpreter() {
+        for (name, source) in PROGRAMS {
+            assert_eq!(
+                run_source(source),
+                Ok(expected_output(source)),
+                "program {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_ir_failing_programs_match_interpreter() {
+        for (name, source) in FAILING_PROGRAMS {
+            assert_eq!(
+                run_source(source).unwrap_err().message,
+                expected_error(source),
+                "program {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_ir_prints_before_error() {
+        let frags = translate::<X86_64Frame>(&load(FAILING_PROGRAMS[0].1));
+        let mut out = Vec::new();
+        assert!(run(&IrProgram::new(frags), &mut out).is_err());
+        assert_eq!(String::from_utf8(out).unwrap(), "1\n");
+    }
+
+    #[test]
+    fn test_ir_runs_linearized_bodies() {
+        for (name, source) in PROGRAMS {
+            let frags = translate::<X86_64Frame>(&load(source));
+            assert_eq!(
+                run_to_string(&IrProgram::with_pass(frags, linearize)),
+                Ok(expected_output(source)),
+                "program {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_ir_memory_and_temps() {
+        let (a, b) = (Temp::new(), Temp::new());
+        let fp = X86_64Frame::fp();
+        let slot = Exp::Mem(Box::new(Exp::BinOp(
+            BinOp::Plus,
+            temp(fp),
+            Box::new(Exp::Const(-8)),
+        )));
+        let program = main_proc(vec![
+            Stm::Move(temp(a), Box::new(Exp::Const(40))),
+            Stm::Move(Box::new(slot.clone()), Box::new(Exp::Const(2))),
+            Stm::Move(
+                temp(b),
+                Box::new(Exp::BinOp(BinOp::Plus, temp(a), Box::new(slot))),
+            ),
+            print_int(Exp::Temp(b)),
+            print_int(Exp::BinOp(
+                BinOp::RShift,
+                Box::new(Exp::Const(-1)),
+                Box::new(Exp::Const(60)),
+            )),
+            print_int(Exp::BinOp(
+                BinOp::ARShift,
+                Box::new(Exp::Const(-16)),
+                Box::new(Exp::Const(2)),
+            )),
+        ]);
+        assert_eq!(run_to_string(&program), Ok("42\n15\n-4\n".to_string()));
+    }
+
+    #[test]
+    fn test_ir_jumps() {
+        let (i, top, body, done) = (Temp::new(), Label::new(), Label::new(), Label::new());
+        let program = main_proc(vec![
+            Stm::Move(temp(i), Box::new(Exp::Const(0))),
+            Stm::Label(top.clone()),
+            Stm::CJump(
+                RelOp::ULt,
+                temp(i),
+                Box::new(Exp::Const(3)),
+                body.clone(),
+                done.clone(),
+            ),
+            Stm::Label(body),
+            print_int(Exp::Temp(i)),
+            Stm::Move(
+                temp(i),
+                Box::new(Exp::BinOp(BinOp::Plus, temp(i), Box::new(Exp::Const(1)))),
+            ),
+            Stm::Jump(Box::new(Exp::Name(top.clone())), vec![top]),
+            Stm::Label(done),
+        ]);
+        assert_eq!(run_to_string(&program), Ok("0\n1\n2\n".to_string()));
+    }
+
+    #[test]
+    fn test_ir_jumps_inside_eseq() {
+        let (a, t, f, join) = (Temp::new(), Label::new(), Label::new(), Label::new());
+        let choose = seq(vec![
+            Stm::CJump(
+                RelOp::Lt,
+                Box::new(Exp::Const(1)),
+                Box::new(Exp::Const(2)),
+                t.clone(),
+                f.clone(),
+            ),
+            Stm::Label(t),
+            Stm::Move(temp(a), Box::new(Exp::Const(1))),
+            Stm::Jump(Box::new(Exp::Name(join.clone())), vec![join.clone()]),
+            Stm::Label(f),
+            Stm::Move(temp(a), Box::new(Exp::Const(2))),
+            Stm::Label(join),
+        ]);
+        let program = main_proc(vec![print_int(Exp::Eseq(Box::new(choose), temp(a)))]);
+        assert_eq!(run_to_string(&program), Ok("1\n".to_string()));
+
+        let outside = Label::named("outside");
+        let escape = Stm::Jump(Box::new(Exp::Name(outside.clone())), vec![outside.clone()]);
+        let program = main_proc(vec![
+            print_int(Exp::Eseq(Box::new(escape), Box::new(Exp::Const(0)))),
+            Stm::Label(outside),
+        ]);
+        assert_eq!(
+            run_to_string(&program).unwrap_err().message,
+            "jump out of an ESEQ to outside"
+        );
+    }
+
+    #[test]
+    fn test_ir_errors() {
+        let message = |body: Vec<Stm>| run_to_string(&main_proc(body)).unwrap_err().message;
+        let t = Temp::new();
+        assert_eq!(
+            message(vec![print_int(Exp::Temp(t))]),
+            format!("read of uninitialized temporary {}", t)
+        );
+        assert_eq!(
+            message(vec![print_int(Exp::Mem(Box::new(Exp::Const(0))))]),
+            "invalid memory access at address 0"
+        );
+        let missing = Label::named("missing");
+        assert_eq!(
+            message(vec![Stm::Jump(
+                Box::new(Exp::Name(missing.clone())),
+                vec![missing]
+            )]),
+            "jump to unknown label missing"
+        );
+        assert_eq!(
+            message(vec![Stm::Exp(Box::new(Exp::Call(
+                Box::new(Exp::Name(Label::named("nowhere"))),
+                vec![]
+            )))]),
+            "undefined function `nowhere`"
+        );
+        assert_eq!(
+            message(vec![print_int(Exp::BinOp(
+                BinOp::Div,
+                Box::new(Exp::Const(1)),
+                Box::new(Exp::Const(0))
+            ))]),
+            "division by zero"
+        );
+    }
+}
@@ -0,0 +1,683 @@
+/// An LALR(1) parser generator in the manner of yacc, for the grammars of `grammar`
+///
+/// Conflicts are resolved as yacc does: by the precedence of the production and the lookahead
+/// where both have one, and otherwise in favor of the shift or of the earlier production, each
+/// conflict resolved that way being reported with examples of the two parses
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+
+use crate::chapter_2::{tokenize, tokenize_with_positions, Token};
+use crate::chapter_3::ParseError;
+use crate::chapter_4::Pos;
+use crate::grammar::{syntax_error, Assoc, Grammar, Symbol, Tree};
+
+/// The pattern matching the tokens of a terminal, in a generated parser
+fn token_pattern(name: &str) -> String {
+    match name {
+        "NUM" => return "Token::Num(_)".to_string(),
+        "REAL" => return "Token::Real(_)".to_string(),
+        "BOOLEAN" => return "Token::Boolean(_)".to_string(),
+        "STRING" => return "Token::StaticString(_)".to_string(),
+        "ID" => return "Token::Id(_)".to_string(),
+        quoted => {
+            let token = &tokenize(&quoted[1..quoted.len() - 1]).expect("checked by the grammar")[0];
+            return format!("Token::{:?}", token);
+        }
+    }
+}
+
+/// An entry of the action table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Error,
+    Shift(usize),
+    Reduce(usize),
+    Accept,
+}
+
+/// The tables driving an LALR(1) parser, borrowed so a generated parser can keep its tables in
+/// a static
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tables<'a> {
+    pub terminals: &'a [&'a str],
+    pub nonterminals: &'a [&'a str],
+    /// The left side and the length of the right side of each production
+    pub productions: &'a [(usize, usize)],
+    /// A row of one action per terminal for each state
+    pub actions: &'a [Action],
+    /// A row of one entry per nonterminal for each state
+    pub gotos: &'a [Option<usize>],
+}
+/// Parse tokens with LALR(1) tables, `terminal` telling the terminal of the grammar each token
+/// is an instance of
+pub fn run(
+    tables: &Tables,
+    tokens: &[(Token, Pos)],
+    end: Pos,
+    terminal: &dyn Fn(&Token) -> Option<usize>,
+) -> Result<Tree, ParseError> {
+    let width = tables.terminals.len();
+    let mut states = vec![0];
+    let mut trees = Vec::new();
+    let mut next = 0;
+    loop {
+        let state = *states.last().unwrap();
+        let lookahead = match tokens.get(next) {
+            Some((token, _)) => terminal(token),
+            None => Some(0),
+        };
+        let action = lookahead.map_or(Action::Error, |t| tables.actions[state * width + t]);
+        match action {
+            Action::Shift(target) => {
+                let (token, pos) = tokens[next].clone();
+                trees.push(Tree::Leaf(token, pos));
+                states.push(target);
+                next += 1;
+            }
+            Action::Reduce(production) => {
+                let (lhs, len) = tables.productions[production];
+                states.truncate(states.len() - len);
+                let children = trees.split_off(trees.len() - len);
+                trees.push(Tree::Node {
+                    symbol: tables.nonterminals[lhs].to_string(),
+                    production,
+                    children,
+                });
+                let state = *states.last().unwrap();
+                let target = tables.gotos[state * tables.nonterminals.len() + lhs];
+                states.push(target.expect("a goto follows every reduction"));
+            }
+            Action::Accept => return Ok(trees.pop().unwrap()),
+            Action::Error => {
+                let expected = (0..width)
+                    .filter(|t| tables.actions[state * width + t] != Action::Error)
+                    .map(|t| tables.terminals[t]);
+                return Err(syntax_error(expected, tokens.get(next), end));
+            }
+        }
+    }
+}
+
+/// A production with how much of its right side has been seen
+type Item = (usize, usize);
+
+/// Items with the terminals that may follow them
+type Items = BTreeMap<Item, BTreeSet<usize>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictKind {
+    ShiftReduce,
+    ReduceReduce,
+}
+
+/// A conflict between two actions, resolved without precedence to decide it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub state: usize,
+    /// The terminal both actions are possible on
+    pub lookahead: String,
+    /// The input read so far as each action would parse it, the action taken first. The
+    /// production of each is bracketed with its left side
+    pub examples: Vec<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, taken) = match self.kind {
+            ConflictKind::ShiftReduce => ("shift/reduce", "the shift"),
+            ConflictKind::ReduceReduce => ("reduce/reduce", "the earlier reduction"),
+        };
+        write!(
+            f,
+            "{} conflict in state {} on {}, resolved as {}",
+            kind, self.state, self.lookahead, taken
+        )?;
+        for example in &self.examples {
+            write!(f, "\n  {}", example)?;
+        }
+        return Ok(());
+    }
+}
+
+/// The LALR(1) automaton of a grammar with its parse tables
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lalr<'g> {
+    grammar: &'g Grammar,
+    /// The kernel items of each state
+    kernels: Vec<Vec<Item>>,
+    terminals: Vec<&'g str>,
+    nonterminals: Vec<&'g str>,
+    productions: Vec<(usize, usize)>,
+    actions: Vec<Action>,
+    gotos: Vec<Option<usize>>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl<'g> Lalr<'g> {
+    /// Build the LR(0) automaton of a grammar, then find the lookaheads of its items by
+    /// propagating them along the transitions until nothing changes. States with the same
+    /// kernel are one state, which is what makes the tables LALR(1) rather than LR(1)
+    pub fn new(grammar: &'g Grammar) -> Lalr<'g> {
+        let sets = grammar.sets();
+        let closure = |kernel: &[Item], lookaheads: &[BTreeSet<usize>]| {
+            let mut items: Items = kernel
+                .iter()
+                .copied()
+                .zip(lookaheads.iter().cloned())
+                .collect();
+            let mut work: Vec<Item> = kernel.to_vec();
+            while let Some((p, dot)) = work.pop() {
+                let rhs = &grammar.productions[p].rhs;
+                let Some(Symbol::Nonterminal(n)) = rhs.get(dot) else {
+                    continue;
+                };
+                let (mut follow, empty) = sets.first_of(&rhs[dot + 1..]);
+                if empty {
+                    follow.extend(&items[&(p, dot)]);
+                }
+                for (q, production) in grammar.productions.iter().enumerate() {
+                    if production.lhs != *n {
+                        continue;
+                    }
+                    let new = !items.contains_key(&(q, 0));
+                    let entry = items.entry((q, 0)).or_default();
+                    let before = entry.len();
+                    entry.extend(&follow);
+                    if new || entry.len() > before {
+                        work.push((q, 0));
+                    }
+                }
+            }
+            return items;
+        };
+
+        let mut kernels = vec![vec![(0, 0)]];
+        let mut index = HashMap::from([(vec![(0, 0)], 0)]);
+        let mut transitions = Vec::new();
+        while transitions.len() < kernels.len() {
+            let kernel = &kernels[transitions.len()];
+            let items = closure(kernel, &vec![BTreeSet::new(); kernel.len()]);
+            let mut successors: BTreeMap<Symbol, Vec<Item>> = BTreeMap::new();
+            for &(p, dot) in items.keys() {
+                if let Some(symbol) = grammar.productions[p].rhs.get(dot) {
+                    successors.entry(*symbol).or_default().push((p, dot + 1));
+                }
+            }
+            let mut edges = BTreeMap::new();
+            for (symbol, kernel) in successors {
+                let target = *index.entry(kernel.clone()).or_insert_with(|| {
+                    kernels.push(kernel);
+                    return kernels.len() - 1;
+                });
+                edges.insert(symbol, target);
+            }
+            transitions.push(edges);
+        }
+
+        let mut lookaheads: Vec<Vec<BTreeSet<usize>>> = kernels
+            .iter()
+            .map(|kernel| vec![BTreeSet::new(); kernel.len()])
+            .collect();
+        lookaheads[0][0].insert(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for state in 0..kernels.len() {
+                for ((p, dot), follow) in closure(&kernels[state], &lookaheads[state]) {
+                    let Some(symbol) = grammar.productions[p].rhs.get(dot) else {
+                        continue;
+                    };
+                    let target = transitions[state][symbol];
+                    let k = kernels[target].binary_search(&(p, dot + 1)).unwrap();
+                    let before = lookaheads[target][k].len();
+                    lookaheads[target][k].extend(follow);
+                    changed |= lookaheads[target][k].len() > before;
+                }
+            }
+        }
+
+        let mut lalr = Lalr {
+            grammar,
+            terminals: grammar.terminals.iter().map(String::as_str).collect(),
+            nonterminals: grammar.nonterminals.iter().map(String::as_str).collect(),
+            productions: grammar
+                .productions
+                .iter()
+                .map(|p| (p.lhs, p.rhs.len()))
+                .collect(),
+            actions: vec![],
+            gotos: vec![],
+            conflicts: vec![],
+            kernels: vec![],
+        };
+        let paths = shortest_paths(&transitions);
+        for state in 0..kernels.len() {
+            let items = closure(&kernels[state], &lookaheads[state]);
+            let row = lalr.actions_of(&items, &transitions[state], state, &paths[state]);
+            lalr.actions.extend(row);
+            lalr.gotos.extend((0..grammar.nonterminals.len()).map(|n| {
+                return transitions[state].get(&Symbol::Nonterminal(n)).copied();
+            }));
+        }
+        lalr.kernels = kernels;
+        return lalr;
+    }
+
+    /// The row of the action table for a state, resolving conflicts between its actions
+    fn actions_of(
+        &mut self,
+        items: &Items,
+        transitions: &BTreeMap<Symbol, usize>,
+        state: usize,
+        path: &[Symbol],
+    ) -> Vec<Action> {
+        let grammar = self.grammar;
+        let mut row = vec![None; grammar.terminals.len()];
+        for (symbol, target) in transitions {
+            if let Symbol::Terminal(t) = symbol {
+                row[*t] = Some(Action::Shift(*target));
+            }
+        }
+        // Items are ordered by production, so the earlier of two reductions is seen first
+        for (&(p, dot), follow) in items {
+            if dot < grammar.productions[p].rhs.len() {
+                continue;
+            }
+            let reduce = if p == 0 {
+                Action::Accept
+            } else {
+                Action::Reduce(p)
+            };
+            for &t in follow {
+                let kind = match row[t] {
+                    None => {
+                        row[t] = Some(reduce);
+                        continue;
+                    }
+                    Some(Action::Shift(_)) => {
+                        let production = grammar.productions[p].precedence;
+                        if let (Some(production), Some(terminal)) =
+                            (production, grammar.precedence[t])
+                        {
+                            if production.level > terminal.level {
+                                row[t] = Some(reduce);
+                            } else if production.level == terminal.level {
+                                row[t] = match terminal.assoc {
+                                    Assoc::Left => Some(reduce),
+                                    Assoc::Right => row[t],
+                                    Assoc::Nonassoc => Some(Action::Error),
+                                };
+                            }
+                            continue;
+                        }
+                        ConflictKind::ShiftReduce
+                    }
+                    // Made an error by `%nonassoc`
+                    Some(Action::Error) => continue,
+                    Some(Action::Reduce(_) | Action::Accept) => ConflictKind::ReduceReduce,
+                };
+                let taken = match row[t] {
+                    Some(Action::Shift(_)) => items
+                        .keys()
+                        .find(|&&(q, dot)| {
+                            return grammar.productions[q].rhs.get(dot)
+                                == Some(&Symbol::Terminal(t));
+                        })
+                        .copied()
+                        .unwrap(),
+                    Some(Action::Reduce(q)) => (q, grammar.productions[q].rhs.len()),
+                    _ => (0, 1),
+                };
+                self.conflicts.push(Conflict {
+                    kind,
+                    state,
+                    lookahead: grammar.terminals[t].clone(),
+                    examples: vec![
+                        self.example(path, taken, t),
+                        self.example(path, (p, dot), t),
+                    ],
+                });
+            }
+        }
+        return row
+            .into_iter()
+            .map(|action| action.unwrap_or(Action::Error))
+            .collect();
+    }
+
+    /// How the input along a path parses with an item, followed by a lookahead: the part of the
+    /// item's production already read is bracketed with its left side, with the rest of it when
+    /// the lookahead is shifted into it, as in `expr "+" [expr: expr • "+" expr]`
+    fn example(&self, path: &[Symbol], (p, dot): Item, lookahead: usize) -> String {
+        let grammar = self.grammar;
+        let production = &grammar.productions[p];
+        let before = grammar.names(&path[..path.len() - dot]);
+        let seen = grammar.names(&production.rhs[..dot]);
+        let rest = grammar.names(&production.rhs[dot..]);
+        let lhs = &grammar.nonterminals[production.lhs];
+        let mut parts = before;
+        let (action, bracket) = if rest.is_empty() {
+            let lookahead = &grammar.terminals[lookahead];
+            (
+                "reduce",
+                format!("[{}: {}] • {}", lhs, seen.join(" "), lookahead),
+            )
+        } else {
+            let inside = seen.into_iter().chain(["•"]).chain(rest);
+            (
+                "shift",
+                format!("[{}: {}]", lhs, inside.collect::<Vec<_>>().join(" ")),
+            )
+        };
+        parts.push(&bracket);
+        return format!("{:<7} {}", format!("{}:", action), parts.join(" "));
+    }
+
+    pub fn tables(&self) -> Tables<'_> {
+        return Tables {
+            terminals: &self.terminals,
+            nonterminals: &self.nonterminals,
+            productions: &self.productions,
+            actions: &self.actions,
+            gotos: &self.gotos,
+        };
+    }
+
+    pub fn states(&self) -> usize {
+        return self.kernels.len();
+    }
+
+    /// The size of the automaton and the conflicts of its tables
+    pub fn report(&self) -> String {
+        let mut out = format!("LALR(1) automaton of {} states\n", self.states());
+        match self.conflicts.len() {
+            0 => out.push_str("the LALR(1) tables have no conflicts\n"),
+            1 => out.push_str("1 LALR(1) conflict\n"),
+            n => out.push_str(&format!("{} LALR(1) conflicts\n", n)),
+        }
+        for conflict in &self.conflicts {
+            out.push_str(&format!("{}\n", conflict));
+        }
+        return out;
+    }
+
+    /// Parse source with the tables
+    pub fn parse(&self, input: &str) -> Result<Tree, ParseError> {
+        let tokens = tokenize_with_positions(input)?;
+        let terminal = |token: &Token| self.grammar.terminal(token);
+        return run(&self.tables(), &tokens, input.len(), &terminal);
+    }
+
+    /// The source of a Rust module holding the tables, with a `parse` function running them
+    pub fn generate(&self) -> String {
+        let mut out = String::from(
+            "/// An LALR(1) parser generated by `lalr::Lalr::generate` from this grammar:\n///\n",
+        );
+        out.push_str("/// ```text\n");
+        for line in self.grammar.source.trim().lines() {
+            out.push_str(format!("/// {}", line).trim_end());
+            out.push('\n');
+        }
+        out.push_str("/// ```\n");
+        out.push_str("use crate::chapter_2::{tokenize_with_positions, Token};\n");
+        out.push_str("use crate::chapter_3::ParseError;\n");
+        out.push_str("use crate::grammar::Tree;\n");
+        out.push_str("use crate::lalr::{run, Action, Tables};\n\n");
+        out.push_str("use Action::{Accept as A, Error as E, Reduce as R, Shift as S};\n\n");
+        out.push_str("static TABLES: Tables<'static> = Tables {\n");
+        let quote = |names: &[&str]| {
+            return names
+                .iter()
+                .map(|name| format!("{:?}", name))
+                .collect::<Vec<_>>()
+                .join(", ");
+        };
+        out.push_str(&format!("    terminals: &[{}],\n", quote(&self.terminals)));
+        out.push_str(&format!(
+            "    nonterminals: &[{}],\n",
+            quote(&self.nonterminals)
+        ));
+        let productions: Vec<String> = self
+            .productions
+            .iter()
+            .map(|(lhs, len)| format!("({}, {})", lhs, len))
+            .collect();
+        out.push_str(&format!(
+            "    productions: &[{}],\n",
+            productions.join(", ")
+        ));
+        out.push_str("    actions: &[\n");
+        for row in self.actions.chunks(self.terminals.len()) {
+            let row: Vec<String> = row
+                .iter()
+                .map(|action| match action {
+                    Action::Error => "E".to_string(),
+                    Action::Shift(state) => format!("S({})", state),
+                    Action::Reduce(production) => format!("R({})", production),
+                    Action::Accept => "A".to_string(),
+                })
+                .collect();
+            out.push_str(&format!("        {},\n", row.join(", ")));
+        }
+        out.push_str("    ],\n    gotos: &[\n");
+        for row in self.gotos.chunks(self.nonterminals.len()) {
+            let row: Vec<String> = row
+                .iter()
+                .map(|target| match target {
+                    Some(state) => format!("Some({})", state),
+                    None => "None".to_string(),
+                })
+                .collect();
+            out.push_str(&format!("        {},\n", row.join(", ")));
+        }
+        out.push_str("    ],\n};\n\n");
+        out.push_str("/// The terminal of the grammar a token is an instance of\n");
+        out.push_str("fn terminal(token: &Token) -> Option<usize> {\n    match token {\n");
+        for (t, name) in self.terminals.iter().enumerate().skip(1) {
+            let pattern = token_pattern(name);
+            out.push_str(&format!("        {} => return Some({}),\n", pattern, t));
+        }
+        out.push_str("        _ => return None,\n    }\n}\n\n");
+        out.push_str(&format!(
+            "/// Parse source as a `{}`\n",
+            self.grammar.nonterminals[1]
+        ));
+        out.push_str("pub fn parse(input: &str) -> Result<Tree, ParseError> {\n");
+        out.push_str("    let tokens = tokenize_with_positions(input)?;\n");
+        out.push_str("    return run(&TABLES, &tokens, input.len(), &terminal);\n}\n");
+        return out;
+    }
+}
+
+/// The symbols along a shortest path from the start state to each state
+fn shortest_paths(transitions: &[BTreeMap<Symbol, usize>]) -> Vec<Vec<Symbol>> {
+    let mut paths = vec![None; transitions.len()];
+    paths[0] = Some(vec![]);
+    let mut queue = VecDeque::from([0]);
+    while let Some(state) = queue.pop_front() {
+        for (symbol, &target) in &transitions[state] {
+            if paths[target].is_none() {
+                let mut path = paths[state].clone().unwrap();
+                path.push(*symbol);
+                paths[target] = Some(path);
+                queue.push_back(target);
+            }
+        }
+    }
+    return paths.into_iter().map(Option::unwrap).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The expressions of the language, with the precedence the parser in Chapter 3 gives them.
+    /// `lalr_expr` is generated from it
+    const EXPRESSIONS: &str = r#"
+// Expressions of the language, as the parser in Chapter 3 reads them
+%left "|"
+%left "&"
+%nonassoc "<" ">"
+%left "+" "-"
+%left "*" "/"
+%right NEG
+expr : expr "|" expr
+     | expr "&" expr
+     | expr "<" expr
+     | expr ">" expr
+     | expr "+" expr
+     | expr "-" expr
+     | expr "*" expr
+     | expr "/" expr
+     | "-" expr %prec NEG
+     | "(" expr ")"
+     | ID "(" args ")"
+     | ID
+     | NUM
+     | REAL
+     | STRING
+     | BOOLEAN
+     ;
+args : %empty
+     | list
+     ;
+list : expr
+     | list "," expr
+     ;
+"#;
+
+    fn parse_with(grammar: &str, input: &str) -> String {
+        let grammar = Grammar::parse(grammar).unwrap();
+        let lalr = Lalr::new(&grammar);
+        match lalr.parse(input) {
+            Ok(tree) => return tree.to_string(),
+            Err(error) => return format!("{} at {}", error.message, error.pos),
+        }
+    }
+
+    #[test]
+    fn test_precedence_resolves_conflicts() {
+        let grammar = Grammar::parse(EXPRESSIONS).unwrap();
+        assert_eq!(Lalr::new(&grammar).conflicts, []);
+        assert_eq!(
+            parse_with(EXPRESSIONS, "1 + 2 * 3 - 4"),
+            "(expr (expr (expr 1) + (expr (expr 2) * (expr 3))) - (expr 4))"
+        );
+        assert_eq!(
+            parse_with(EXPRESSIONS, "-x * f() | g(1, y) & True"),
+            "(expr (expr (expr - (expr x)) * (expr f ( (args) ))) | (expr (expr g ( (args (list \
+             (list (expr 1)) , (expr y))) )) & (expr True)))"
+        );
+        // States with the same items share their lookaheads, so an error can list terminals that
+        // only follow the same items elsewhere, like the `)` and `,` here
+        assert_eq!(
+            parse_with(EXPRESSIONS, "a < b < c"),
+            "expected one of end of input, `|`, `&`, `+`, `-`, `*`, `/`, `)`, `,`, found `<` at 6"
+        );
+        assert_eq!(
+            parse_with(EXPRESSIONS, "(1 +"),
+            "expected one of `-`, `(`, ID, NUM, REAL, STRING, BOOLEAN, found end of input at 4"
+        );
+        assert_eq!(
+            parse_with(EXPRESSIONS, "1 let"),
+            "expected one of end of input, `|`, `&`, `<`, `>`, `+`, `-`, `*`, `/`, `)`, `,`, found `let` at 2"
+        );
+    }
+
+    #[test]
+    fn test_conflicts_are_reported_with_examples() {
+        let grammar = Grammar::parse("expr : expr \"-\" expr | NUM ;").unwrap();
+        let lalr = Lalr::new(&grammar);
+        let conflicts: Vec<String> = lalr.conflicts.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            conflicts,
+            [
+                "shift/reduce conflict in state 4 on \"-\", resolved as the shift\n  \
+              shift:  expr \"-\" [expr: expr • \"-\" expr]\n  \
+              reduce: [expr: expr \"-\" expr] • \"-\""
+            ]
+        );
+        // Shifting makes the operator right associative
+        assert_eq!(
+            lalr.parse("3 - 2 - 1").unwrap().to_string(),
+            "(expr (expr 3) - (expr (expr 2) - (expr 1)))"
+        );
+
+        let grammar = Grammar::parse("s : a ID | b ID ; a : ID ; b : ID ;").unwrap();
+        let conflicts: Vec<String> = Lalr::new(&grammar)
+            .conflicts
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            conflicts,
+            [
+                "reduce/reduce conflict in state 1 on ID, resolved as the earlier reduction\n  \
+              reduce: [a: ID] • ID\n  \
+              reduce: [b: ID] • ID"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dangling_else_binds_to_the_nearest_if() {
+        let grammar = "stmt : \"if\" ID stmt | \"if\" ID stmt \"else\" stmt | ID ;";
+        let parsed = Grammar::parse(grammar).unwrap();
+        let lalr = Lalr::new(&parsed);
+        assert_eq!(lalr.conflicts.len(), 1);
+        assert_eq!(lalr.conflicts[0].kind, ConflictKind::ShiftReduce);
+        assert_eq!(
+            parse_with(grammar, "if a if b x else y"),
+            "(stmt if a (stmt if b (stmt x) else (stmt y)))"
+        );
+        // Giving `else` a higher precedence than the production without it settles it silently
+        let grammar = format!("%nonassoc THEN\n%nonassoc \"else\"\n{}", grammar)
+            .replace("ID stmt |", "ID stmt %prec THEN |");
+        let parsed = Grammar::parse(&grammar).unwrap();
+        assert_eq!(Lalr::new(&parsed).conflicts, []);
+        assert_eq!(
+            parse_with(&grammar, "if a if b x else y"),
+            "(stmt if a (stmt if b (stmt x) else (stmt y)))"
+        );
+    }
+
+    #[test]
+    fn test_lookaheads_are_lalr() {
+        // Not SLR(1): `=` follows both `l` and `r`, but never an `r` the parser can be reducing
+        // to when it sees `=`
+        let grammar = "s : l \"=\" r | r ; l : \"*\" r | ID ; r : l ;";
+        let parsed = Grammar::parse(grammar).unwrap();
+        let lalr = Lalr::new(&parsed);
+        assert_eq!(lalr.conflicts, []);
+        assert_eq!(lalr.states(), 10);
+        assert_eq!(
+            lalr.report(),
+            "LALR(1) automaton of 10 states\nthe LALR(1) tables have no conflicts\n"
+        );
+        assert_eq!(
+            parse_with(grammar, "*a = b"),
+            "(s (l * (r (l a))) = (r (l b)))"
+        );
+    }
+
+    #[test]
+    fn test_generated_parser() {
+        let grammar = Grammar::parse(EXPRESSIONS).unwrap();
+        let lalr = Lalr::new(&grammar);
+        // The generated module is part of the crate, so this also checks that it compiles
+        assert_eq!(
+            lalr.generate(),
+            include_str!("lalr_expr.rs"),
+            "src/lalr_expr.rs is out of date"
+        );
+        for input in ["f(1, 2.5) * -(x + 3) / \"s\"", "a < b & b > c", "f(1,"] {
+            assert_eq!(
+                crate::lalr_expr::parse(input),
+                lalr.parse(input),
+                "{}",
+                input
+            );
+        }
+    }
+}
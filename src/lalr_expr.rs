@@ -0,0 +1,149 @@
+/// An LALR(1) parser generated by `lalr::Lalr::generate` from this grammar:
+///
+/// ```text
+/// // Expressions of the language, as the parser in Chapter 3 reads them
+/// %left "|"
+/// %left "&"
+/// %nonassoc "<" ">"
+/// %left "+" "-"
+/// %left "*" "/"
+/// %right NEG
+/// expr : expr "|" expr
+///      | expr "&" expr
+///      | expr "<" expr
+///      | expr ">" expr
+///      | expr "+" expr
+///      | expr "-" expr
+///      | expr "*" expr
+///      | expr "/" expr
+///      | "-" expr %prec NEG
+///      | "(" expr ")"
+///      | ID "(" args ")"
+///      | ID
+///      | NUM
+///      | REAL
+///      | STRING
+///      | BOOLEAN
+///      ;
+/// args : %empty
+///      | list
+///      ;
+/// list : expr
+///      | list "," expr
+///      ;
+/// ```
+use crate::chapter_2::{tokenize_with_positions, Token};
+use crate::chapter_3::ParseError;
+use crate::grammar::Tree;
+use crate::lalr::{run, Action, Tables};
+
+use Action::{Accept as A, Error as E, Reduce as R, Shift as S};
+
+static TABLES: Tables<'static> = Tables {
+    terminals: &["$", "\"|\"", "\"&\"", "\"<\"", "\">\"", "\"+\"", "\"-\"", "\"*\"", "\"/\"", "\"(\"", "\")\"", "ID", "NUM", "REAL", "STRING", "BOOLEAN", "\",\""],
+    nonterminals: &["expr'", "expr", "args", "list"],
+    productions: &[(0, 1), (1, 3), (1, 3), (1, 3), (1, 3), (1, 3), (1, 3), (1, 3), (1, 3), (1, 2), (1, 3), (1, 4), (1, 1), (1, 1), (1, 1), (1, 1), (1, 1), (2, 0), (2, 1), (3, 1), (3, 3)],
+    actions: &[
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        R(12), R(12), R(12), R(12), R(12), R(12), R(12), R(12), R(12), S(11), R(12), E, E, E, E, E, R(12),
+        R(13), R(13), R(13), R(13), R(13), R(13), R(13), R(13), R(13), E, R(13), E, E, E, E, E, R(13),
+        R(14), R(14), R(14), R(14), R(14), R(14), R(14), R(14), R(14), E, R(14), E, E, E, E, E, R(14),
+        R(15), R(15), R(15), R(15), R(15), R(15), R(15), R(15), R(15), E, R(15), E, E, E, E, E, R(15),
+        R(16), R(16), R(16), R(16), R(16), R(16), R(16), R(16), R(16), E, R(16), E, E, E, E, E, R(16),
+        A, S(12), S(13), S(14), S(15), S(16), S(17), S(18), S(19), E, E, E, E, E, E, E, E,
+        R(9), R(9), R(9), R(9), R(9), R(9), R(9), R(9), R(9), E, R(9), E, E, E, E, E, R(9),
+        E, S(12), S(13), S(14), S(15), S(16), S(17), S(18), S(19), E, S(20), E, E, E, E, E, E,
+        E, E, E, E, E, E, S(1), E, E, S(2), R(17), S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        R(10), R(10), R(10), R(10), R(10), R(10), R(10), R(10), R(10), E, R(10), E, E, E, E, E, R(10),
+        E, S(12), S(13), S(14), S(15), S(16), S(17), S(18), S(19), E, R(19), E, E, E, E, E, R(19),
+        E, E, E, E, E, E, E, E, E, E, S(32), E, E, E, E, E, E,
+        E, E, E, E, E, E, E, E, E, E, R(18), E, E, E, E, E, S(33),
+        R(1), R(1), S(13), S(14), S(15), S(16), S(17), S(18), S(19), E, R(1), E, E, E, E, E, R(1),
+        R(2), R(2), R(2), S(14), S(15), S(16), S(17), S(18), S(19), E, R(2), E, E, E, E, E, R(2),
+        R(3), R(3), R(3), E, E, S(16), S(17), S(18), S(19), E, R(3), E, E, E, E, E, R(3),
+        R(4), R(4), R(4), E, E, S(16), S(17), S(18), S(19), E, R(4), E, E, E, E, E, R(4),
+        R(5), R(5), R(5), R(5), R(5), R(5), R(5), S(18), S(19), E, R(5), E, E, E, E, E, R(5),
+        R(6), R(6), R(6), R(6), R(6), R(6), R(6), S(18), S(19), E, R(6), E, E, E, E, E, R(6),
+        R(7), R(7), R(7), R(7), R(7), R(7), R(7), R(7), R(7), E, R(7), E, E, E, E, E, R(7),
+        R(8), R(8), R(8), R(8), R(8), R(8), R(8), R(8), R(8), E, R(8), E, E, E, E, E, R(8),
+        R(11), R(11), R(11), R(11), R(11), R(11), R(11), R(11), R(11), E, R(11), E, E, E, E, E, R(11),
+        E, E, E, E, E, E, S(1), E, E, S(2), E, S(3), S(4), S(5), S(6), S(7), E,
+        E, S(12), S(13), S(14), S(15), S(16), S(17), S(18), S(19), E, R(20), E, E, E, E, E, R(20),
+    ],
+    gotos: &[
+        None, Some(8), None, None,
+        None, Some(9), None, None,
+        None, Some(10), None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, Some(21), Some(22), Some(23),
+        None, Some(24), None, None,
+        None, Some(25), None, None,
+        None, Some(26), None, None,
+        None, Some(27), None, None,
+        None, Some(28), None, None,
+        None, Some(29), None, None,
+        None, Some(30), None, None,
+        None, Some(31), None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, None, None, None,
+        None, Some(34), None, None,
+        None, None, None, None,
+    ],
+};
+
+/// The terminal of the grammar a token is an instance of
+fn terminal(token: &Token) -> Option<usize> {
+    match token {
+        Token::Bar => return Some(1),
+        Token::Ampersand => return Some(2),
+        Token::LessThan => return Some(3),
+        Token::GreaterThan => return Some(4),
+        Token::Plus => return Some(5),
+        Token::Minus => return Some(6),
+        Token::Star => return Some(7),
+        Token::ForwardSlash => return Some(8),
+        Token::Lparen => return Some(9),
+        Token::Rparen => return Some(10),
+        Token::Id(_) => return Some(11),
+        Token::Num(_) => return Some(12),
+        Token::Real(_) => return Some(13),
+        Token::StaticString(_) => return Some(14),
+        Token::Boolean(_) => return Some(15),
+        Token::Comma => return Some(16),
+        _ => return None,
+    }
+}
+
+/// Parse source as a `expr`
+pub fn parse(input: &str) -> Result<Tree, ParseError> {
+    let tokens = tokenize_with_positions(input)?;
+    return run(&TABLES, &tokens, input.len(), &terminal);
+}
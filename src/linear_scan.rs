@@ -0,0 +1,777 @@
+/// A linear scan register allocator, much faster than graph colouring for somewhat worse code
+///
+/// Instructions are numbered in order: instruction `i` reads its operands at position `2i` and
+/// writes its results at `2i + 1`. Each temporary gets a live interval over these positions, with
+/// holes where it is dead. An interval that cannot keep one register throughout is split at an
+/// instruction boundary, so a temporary may move between registers and its stack slot; moves on
+/// the edges of the flow graph then put each value where the code after the edge expects it.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::chapter_10::{liveness, FlowGraph, Liveness};
+use crate::chapter_11::{rename, Allocation, Allocator, Machine};
+use crate::chapter_4::Program;
+use crate::chapter_6::{Frame, Label, Temp, X86_64Frame};
+use crate::chapter_7::{access_exp, translate, Exp, Frag, Stm};
+use crate::chapter_8::canonicalize;
+use crate::chapter_9::{codegen, Instr};
+use crate::test_programs::{load, pressure_program, PROGRAMS};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Location {
+    Register(Temp),
+    Slot,
+}
+
+/// The positions a temporary, or part of one, is live at
+#[derive(Debug, Clone)]
+struct Interval {
+    temp: Temp,
+    /// Sorted, disjoint and half open
+    ranges: Vec<(usize, usize)>,
+    /// Sorted positions where the temporary is an operand, so must be in a register
+    uses: Vec<usize>,
+    location: Option<Location>,
+}
+
+impl Interval {
+    fn new(temp: Temp) -> Interval {
+        return Interval {
+            temp,
+            ranges: Vec::new(),
+            uses: Vec::new(),
+            location: None,
+        };
+    }
+
+    /// Add a position after all the others
+    fn cover(&mut self, pos: usize) {
+        match self.ranges.last_mut() {
+            Some((_, end)) if *end == pos => *end += 1,
+            _ => self.ranges.push((pos, pos + 1)),
+        }
+    }
+
+    fn start(&self) -> usize {
+        return self.ranges[0].0;
+    }
+
+    fn end(&self) -> usize {
+        return self.ranges.last().map_or(0, |r| r.1);
+    }
+
+    fn covers(&self, pos: usize) -> bool {
+        return self
+            .ranges
+            .iter()
+            .any(|(start, end)| *start <= pos && pos < *end);
+    }
+
+    /// The first position both intervals cover
+    fn intersection(&self, other: &Interval) -> Option<usize> {
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (self.ranges[i], other.ranges[j]);
+            let start = a.0.max(b.0);
+            if start < a.1.min(b.1) {
+                return Some(start);
+            }
+            if a.1 <= b.1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        return None;
+    }
+
+    fn next_use(&self, from: usize) -> Option<usize> {
+        return self.uses.iter().copied().find(|u| *u >= from);
+    }
+
+    /// Cut the interval at `pos`, keeping the positions before it and returning the rest
+    fn split(&mut self, pos: usize) -> Interval {
+        let mut rest = Interval::new(self.temp);
+        let mut ranges = Vec::new();
+        for (start, end) in self.ranges.drain(..) {
+            if end <= pos {
+                ranges.push((start, end));
+            } else if start >= pos {
+                rest.ranges.push((start, end));
+            } else {
+                ranges.push((start, pos));
+                rest.ranges.push((pos, end));
+            }
+        }
+        self.ranges = ranges;
+        let at = self.uses.partition_point(|u| *u < pos);
+        rest.uses = self.uses.split_off(at);
+        return rest;
+    }
+}
+
+/// Give every temporary of a function body a register of `machine`, or parts of its live range a
+/// new local of `frame`
+pub fn allocate<F: Frame>(instrs: Vec<Instr>, frame: &mut F, machine: &Machine) -> Allocation {
+    let graph = FlowGraph::new(&instrs);
+    let live = liveness(&graph);
+    let mut scan = Scan::new(&graph, &live, machine);
+    scan.run();
+    return scan.rewrite(instrs, &graph, &live, frame);
+}
+
+struct Scan<'a> {
+    machine: &'a Machine<'a>,
+    /// Every interval, including the parts split off others
+    intervals: Vec<Interval>,
+    /// Where each allocatable register is already taken by the code, such as around calls
+    fixed: Vec<Interval>,
+    /// Intervals yet to be given a location, by start
+    unhandled: BTreeSet<(usize, usize)>,
+    /// Intervals holding a register at the current position
+    active: Vec<usize>,
+    /// Intervals holding a register but in a hole at the current position
+    inactive: Vec<usize>,
+}
+
+impl<'a> Scan<'a> {
+    fn new(graph: &FlowGraph, live: &Liveness, machine: &'a Machine<'a>) -> Scan<'a> {
+        let mut intervals: Vec<Interval> = graph.temps.iter().map(|t| Interval::new(*t)).collect();
+        for node in 0..graph.len() {
+            for t in live.live_in[node].iter() {
+                intervals[t].cover(2 * node);
+            }
+            let mut written = live.live_out[node].clone();
+            written.union_with(&graph.defs[node]);
+            for t in written.iter() {
+                intervals[t].cover(2 * node + 1);
+            }
+            for t in graph.uses[node].iter() {
+                intervals[t].uses.push(2 * node);
+            }
+            for t in graph.defs[node].iter() {
+                intervals[t].uses.push(2 * node + 1);
+            }
+        }
+        let fixed = machine
+            .registers
+            .iter()
+            .map(|r| match graph.numbers.get(r) {
+                Some(n) => intervals[*n].clone(),
+                None => Interval::new(*r),
+            })
+            .collect();
+        let mut scan = Scan {
+            machine,
+            intervals: Vec::new(),
+            fixed,
+            unhandled: BTreeSet::new(),
+            active: Vec::new(),
+            inactive: Vec::new(),
+        };
+        for interval in intervals {
+            if !machine.precolored.contains(&interval.temp) {
+                scan.add_unhandled(interval);
+            }
+        }
+        return scan;
+    }
+
+    fn add_unhandled(&mut self, interval: Interval) -> usize {
+        let id = self.intervals.len();
+        self.unhandled.insert((interval.start(), id));
+        self.intervals.push(interval);
+        return id;
+    }
+
+    fn register(&self, id: usize) -> usize {
+        match self.intervals[id].location {
+            Some(Location::Register(r)) => {
+                return self.machine.registers.iter().position(|x| *x == r).unwrap()
+            }
+            _ => panic!("interval {} has no register", id),
+        }
+    }
+
+    /// The register whose score is highest, the most preferred of equals
+    fn best(scores: &[usize]) -> usize {
+        let mut best = 0;
+        for (r, score) in scores.iter().enumerate() {
+            if *score > scores[best] {
+                best = r;
+            }
+        }
+        return best;
+    }
+
+    fn run(&mut self) {
+        while let Some((pos, current)) = self.unhandled.pop_first() {
+            let mut active = Vec::new();
+            let mut inactive = Vec::new();
+            let holders = std::mem::take(&mut self.active);
+            for id in holders
+                .into_iter()
+                .chain(std::mem::take(&mut self.inactive))
+            {
+                let interval = &self.intervals[id];
+                if interval.end() <= pos {
+                    continue;
+                }
+                match interval.covers(pos) {
+                    true => active.push(id),
+                    false => inactive.push(id),
+                }
+            }
+            self.active = active;
+            self.inactive = inactive;
+
+            if !self.try_allocate_free(current) {
+                self.allocate_blocked(current);
+            }
+            if matches!(
+                self.intervals[current].location,
+                Some(Location::Register(_))
+            ) {
+                self.active.push(current);
+            }
+        }
+    }
+
+    /// Give the interval the register that stays free longest, splitting it where that register
+    /// is next needed. Fails if no register is free up to the next instruction boundary
+    fn try_allocate_free(&mut self, current: usize) -> bool {
+        let mut free_until = vec![usize::MAX; self.machine.registers.len()];
+        for id in &self.active {
+            free_until[self.register(*id)] = 0;
+        }
+        let cur = &self.intervals[current];
+        for id in &self.inactive {
+            if let Some(x) = self.intervals[*id].intersection(cur) {
+                let r = self.register(*id);
+                free_until[r] = free_until[r].min(x);
+            }
+        }
+        for (r, fixed) in self.fixed.iter().enumerate() {
+            if let Some(x) = fixed.intersection(cur) {
+                free_until[r] = free_until[r].min(x);
+            }
+        }
+
+        let r = Scan::best(&free_until);
+        if free_until[r] < cur.end() {
+            let boundary = free_until[r] & !1;
+            if boundary <= cur.start() {
+                return false;
+            }
+            let rest = self.intervals[current].split(boundary);
+            self.add_unhandled(rest);
+        }
+        self.intervals[current].location = Some(Location::Register(self.machine.registers[r]));
+        return true;
+    }
+
+    /// With every register taken, either spill the interval up to its first use or take the
+    /// register whose holders need it furthest away, spilling them instead
+    fn allocate_blocked(&mut self, current: usize) {
+        let k = self.machine.registers.len();
+        let cur = &self.intervals[current];
+        let boundary = cur.start() & !1;
+        let mut use_pos = vec![usize::MAX; k];
+        let mut block_pos = vec![usize::MAX; k];
+        for id in &self.active {
+            let r = self.register(*id);
+            let next = self.intervals[*id].next_use(boundary).unwrap_or(usize::MAX);
+            use_pos[r] = use_pos[r].min(next);
+        }
+        for id in &self.inactive {
+            if self.intervals[*id].intersection(cur).is_some() {
+                let r = self.register(*id);
+                let next = self.intervals[*id].next_use(boundary).unwrap_or(usize::MAX);
+                use_pos[r] = use_pos[r].min(next);
+            }
+        }
+        for (r, fixed) in self.fixed.iter().enumerate() {
+            if let Some(x) = fixed.intersection(cur) {
+                block_pos[r] = x;
+                use_pos[r] = use_pos[r].min(x);
+            }
+        }
+
+        let r = Scan::best(&use_pos);
+        let first_use = cur.next_use(cur.start());
+        let can_evict = use_pos[r] & !1 > boundary;
+        if !can_evict || first_use.is_none_or(|u| use_pos[r] < u) {
+            let Some(first_use) = first_use else {
+                self.intervals[current].location = Some(Location::Slot);
+                return;
+            };
+            let split = first_use & !1;
+            assert!(
+                split > cur.start(),
+                "no register for {} at {}",
+                cur.temp,
+                cur.start()
+            );
+            let rest = self.intervals[current].split(split);
+            self.intervals[current].location = Some(Location::Slot);
+            self.add_unhandled(rest);
+            return;
+        }
+
+        let register = self.machine.registers[r];
+        self.intervals[current].location = Some(Location::Register(register));
+        if block_pos[r] < self.intervals[current].end() {
+            let rest = self.intervals[current].split(block_pos[r] & !1);
+            self.add_unhandled(rest);
+        }
+        let holders: Vec<usize> = self
+            .active
+            .iter()
+            .chain(&self.inactive)
+            .copied()
+            .filter(|id| self.register(*id) == r)
+            .filter(|id| {
+                self.intervals[*id]
+                    .intersection(&self.intervals[current])
+                    .is_some()
+            })
+            .collect();
+        for id in holders {
+            self.evict(id, boundary);
+        }
+        self.active
+            .retain(|id| matches!(self.intervals[*id].location, Some(Location::Register(_))));
+        self.inactive
+            .retain(|id| matches!(self.intervals[*id].location, Some(Location::Register(_))));
+    }
+
+    /// Take away an interval's register from `boundary` on, keeping it in its stack slot until
+    /// its next use
+    fn evict(&mut self, id: usize, boundary: usize) {
+        let mut rest = match self.intervals[id].start() < boundary {
+            true => self.intervals[id].split(boundary),
+            false => {
+                let whole = self.intervals[id].clone();
+                self.intervals[id].location = Some(Location::Slot);
+                self.intervals[id].ranges.clear();
+                self.intervals[id].uses.clear();
+                whole
+            }
+        };
+        rest.location = None;
+        match rest.next_use(rest.start()) {
+            None => {
+                rest.location = Some(Location::Slot);
+                self.intervals.push(rest);
+            }
+            Some(next) if next & !1 > rest.start() => {
+                let tail = rest.split(next & !1);
+                rest.location = Some(Location::Slot);
+                self.intervals.push(rest);
+                self.add_unhandled(tail);
+            }
+            Some(_) => {
+                self.add_unhandled(rest);
+            }
+        }
+    }
+
+    /// Where a temporary is at a position it is live at
+    fn location(&self, parts: &HashMap<Temp, Vec<usize>>, t: Temp, pos: usize) -> Location {
+        for id in &parts[&t] {
+            if self.intervals[*id].covers(pos) {
+                return self.intervals[*id].location.unwrap();
+            }
+        }
+        panic!("{} is not live at {}", t, pos);
+    }
+
+    /// Replace every temporary by its register and add the moves between locations
+    fn rewrite<F: Frame>(
+        &self,
+        instrs: Vec<Instr>,
+        graph: &FlowGraph,
+        live: &Liveness,
+        frame: &mut F,
+    ) -> Allocation {
+        let mut parts: HashMap<Temp, Vec<usize>> = HashMap::new();
+        for (id, interval) in self.intervals.iter().enumerate() {
+            parts.entry(interval.temp).or_default().push(id);
+        }
+        let mut labels = HashMap::new();
+        for (i, instr) in instrs.iter().enumerate() {
+            if let Instr::Label { label, .. } = instr {
+                labels.insert(label.clone(), i);
+            }
+        }
+        let mut resolver = Resolver {
+            scan: self,
+            parts: &parts,
+            labels,
+            graph,
+            live,
+            slots: HashMap::new(),
+        };
+
+        let mut result = Vec::new();
+        let mut trampolines = Vec::new();
+        for (i, mut instr) in instrs.into_iter().enumerate() {
+            let register = |t: &mut Temp, pos: usize| {
+                if parts.contains_key(t) {
+                    match self.location(&parts, *t, pos) {
+                        Location::Register(r) => *t = r,
+                        Location::Slot => panic!("{} is an operand in its stack slot", t),
+                    }
+                }
+            };
+            let mut falls_through = true;
+            match &mut instr {
+                Instr::Oper {
+                    assem,
+                    dst,
+                    src,
+                    jump,
+                } => {
+                    src.iter_mut().for_each(|t| register(t, 2 * i));
+                    dst.iter_mut().for_each(|t| register(t, 2 * i + 1));
+                    if let Some(targets) = jump {
+                        falls_through = false;
+                        for (k, target) in targets.iter_mut().enumerate() {
+                            let s = resolver.node_of(target);
+                            if !assem.contains(&format!("`j{}", k)) {
+                                // A target the instruction does not name is the next one
+                                falls_through = true;
+                                continue;
+                            }
+                            let moves = resolver.edge_moves(i, s, frame);
+                            if !moves.is_empty() {
+                                let label = Label::new();
+                                trampolines.extend(resolver.code(&[Stm::Label(label.clone())]));
+                                trampolines.extend(moves);
+                                let back = Stm::Jump(
+                                    Box::new(Exp::Name(target.clone())),
+                                    vec![target.clone()],
+                                );
+                                trampolines.extend(resolver.code(&[back]));
+                                *target = label;
+                            }
+                        }
+                    }
+                }
+                Instr::Label { .. } => {}
+                Instr::Move { dst, src, .. } => {
+                    register(src, 2 * i);
+                    register(dst, 2 * i + 1);
+                }
+            }
+            result.push(instr);
+            if falls_through && i + 1 < graph.len() {
+                result.extend(resolver.edge_moves(i, i + 1, frame));
+            }
+        }
+        if !trampolines.is_empty() {
+            let end = Label::new();
+            let over = Stm::Jump(Box::new(Exp::Name(end.clone())), vec![end.clone()]);
+            result.extend(resolver.code(&[over]));
+            result.extend(trampolines);
+            result.extend(resolver.code(&[Stm::Label(end)]));
+        }
+
+        let mut colors = HashMap::new();
+        for instr in &result {
+            for t in instr.defs().into_iter().chain(instr.uses()) {
+                colors.insert(t, t);
+            }
+        }
+        return Allocation {
+            instrs: result,
+            colors,
+        };
+    }
+}
+
+/// Makes the code moving values between locations on the edges of the flow graph
+struct Resolver<'a> {
+    scan: &'a Scan<'a>,
+    parts: &'a HashMap<Temp, Vec<usize>>,
+    labels: HashMap<Label, usize>,
+    graph: &'a FlowGraph,
+    live: &'a Liveness,
+    slots: HashMap<Temp, Exp>,
+}
+
+impl Resolver<'_> {
+    fn node_of(&self, label: &Label) -> usize {
+        return self.labels[label];
+    }
+
+    fn code(&self, stms: &[Stm]) -> Vec<Instr> {
+        return (self.scan.machine.codegen)(stms);
+    }
+
+    /// Stores of every value leaving a register on the edge from `p` to `s`, then loads of every
+    /// value arriving in one. A value moving between registers goes through its stack slot, so
+    /// the moves never overwrite each other's sources
+    fn edge_moves<F: Frame>(&mut self, p: usize, s: usize, frame: &mut F) -> Vec<Instr> {
+        let mut stores = Vec::new();
+        let mut loads = Vec::new();
+        for t in self.live.live_in[s].iter() {
+            let t = self.graph.temps[t];
+            if !self.parts.contains_key(&t) {
+                continue;
+            }
+            let from = self.scan.location(self.parts, t, 2 * p + 1);
+            let to = self.scan.location(self.parts, t, 2 * s);
+            if from == to {
+                continue;
+            }
+            let slot = self
+                .slots
+                .entry(t)
+                .or_insert_with(|| access_exp(frame.alloc_local(true), Exp::Temp(F::fp())))
+                .clone();
+            if let Location::Register(r) = from {
+                let store = Stm::Move(Box::new(slot.clone()), Box::new(Exp::Temp(r)));
+                stores.push(self.code(&[store]));
+            }
+            if let Location::Register(r) = to {
+                let load = Stm::Move(Box::new(Exp::Temp(r)), Box::new(slot));
+                loads.push(self.code(&[load]));
+            }
+        }
+        let mut moves = Vec::new();
+        for mut code in stores.into_iter().chain(loads) {
+            self.use_scratch(&mut code, p, s);
+            moves.extend(code);
+        }
+        return moves;
+    }
+
+    /// Give any temporary the code for a load or store needs, such as for a large frame offset,
+    /// a register no value occupies on the edge
+    fn use_scratch(&self, code: &mut [Instr], p: usize, s: usize) {
+        let precolored = self.scan.machine.precolored;
+        let temps: HashSet<Temp> = code
+            .iter()
+            .flat_map(|i| i.defs().into_iter().chain(i.uses()))
+            .filter(|t| !precolored.contains(t))
+            .collect();
+        if temps.is_empty() {
+            return;
+        }
+        let busy = |r: Temp| {
+            let held = self.scan.intervals.iter().any(|interval| {
+                interval.location == Some(Location::Register(r))
+                    && (interval.covers(2 * p + 1) || interval.covers(2 * s))
+            });
+            let fixed = self.scan.fixed.iter().any(|interval| {
+                interval.temp == r && (interval.covers(2 * p + 1) || interval.covers(2 * s))
+            });
+            let operand = code
+                .iter()
+                .any(|i| i.defs().contains(&r) || i.uses().contains(&r));
+            held || fixed || operand
+        };
+        let free: Vec<Temp> = self
+            .scan
+            .machine
+            .registers
+            .iter()
+            .copied()
+            .filter(|r| !busy(*r))
+            .collect();
+        assert!(free.len() >= temps.len(), "no register free for spill code");
+        for (t, scratch) in temps.into_iter().zip(free) {
+            for instr in code.iter_mut() {
+                rename(instr, t, scratch);
+            }
+        }
+    }
+}
+
+/// How the two allocators did on one program, compiled for x86-64: the fastest of `RUNS` times and
+/// the number of spills
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub name: String,
+    pub coloring_time: Duration,
+    pub coloring_spills: i64,
+    pub linear_scan_time: Duration,
+    pub linear_scan_spills: i64,
+}
+
+/// How many times `compare` allocates each program, keeping the fastest time so that a run
+/// slowed down by the rest of the machine does not count
+const RUNS: usize = 5;
+
+/// Allocate registers for every function of each program with both allocators, measuring the
+/// time allocation takes and the number of stack slots it adds
+pub fn compare(corpus: &[(&str, Program)]) -> Vec<Comparison> {
+    let machine = Machine {
+        registers: &X86_64Frame::REGISTERS,
+        precolored: &X86_64Frame::PRECOLORED,
+        codegen,
+    };
+    let mut comparisons = Vec::new();
+    for (name, program) in corpus {
+        let mut procs = Vec::new();
+        for frag in translate::<X86_64Frame>(program) {
+            if let Frag::Proc { body, frame } = frag {
+                procs.push((codegen(&canonicalize(body)), frame));
+            }
+        }
+        let measure = |allocator: Allocator| {
+            let mut fastest = Duration::MAX;
+            let mut spills = 0;
+            for _ in 0..RUNS {
+                let mut time = Duration::ZERO;
+                spills = 0;
+                for (instrs, frame) in &procs {
+                    let (instrs, mut frame) = (instrs.clone(), frame.clone());
+                    let before = frame.locals_size();
+                    let start = Instant::now();
+                    allocator.allocate(instrs, &mut frame, &machine);
+                    time += start.elapsed();
+                    spills += (frame.locals_size() - before) / X86_64Frame::WORD_SIZE;
+                }
+                fastest = fastest.min(time);
+            }
+            return (fastest, spills);
+        };
+        let (coloring_time, coloring_spills) = measure(Allocator::GraphColoring);
+        let (linear_scan_time, linear_scan_spills) = measure(Allocator::LinearScan);
+        comparisons.push(Comparison {
+            name: name.to_string(),
+            coloring_time,
+            coloring_spills,
+            linear_scan_time,
+            linear_scan_spills,
+        });
+    }
+    return comparisons;
+}
+
+impl fmt::Display for Comparison {
+    /// One row of a table: the name, then the time and spills of graph colouring and linear scan
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(
+            f,
+            "{:<24} {:>10.3?} {:>6} {:>10.3?} {:>6}",
+            self.name,
+            self.coloring_time,
+            self.coloring_spills,
+            self.linear_scan_time,
+            self.linear_scan_spills
+        );
+    }
+}
+
+/// Compare the allocators on the example programs and on one that has to spill, as a table with
+/// a row for each program
+pub fn benchmark() -> String {
+    let mut sources: Vec<(String, String)> = PROGRAMS
+        .iter()
+        .map(|(name, source)| (name.to_string(), source.to_string()))
+        .collect();
+    sources.push(("pressure_40".to_string(), pressure_program(40)));
+    let corpus: Vec<(&str, Program)> = sources
+        .iter()
+        .map(|(name, source)| (name.as_str(), load(source)))
+        .collect();
+    let mut table = format!(
+        "{:<24} {:>10} {:>6} {:>10} {:>6}\n",
+        "program", "coloring", "spills", "linear", "spills"
+    );
+    for comparison in compare(&corpus) {
+        table.push_str(&format!("{}\n", comparison));
+    }
+    return table;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::FAILING_PROGRAMS;
+
+    fn interval(ranges: &[(usize, usize)], uses: &[usize]) -> Interval {
+        let mut interval = Interval::new(Temp(500));
+        interval.ranges = ranges.to_vec();
+        interval.uses = uses.to_vec();
+        return interval;
+    }
+
+    #[test]
+    fn test_interval_cover_and_holes() {
+        let mut a = Interval::new(Temp(500));
+        for pos in [2, 3, 4, 8, 9] {
+            a.cover(pos);
+        }
+        assert_eq!(a.ranges, [(2, 5), (8, 10)]);
+        assert!(a.covers(4) && !a.covers(5) && a.covers(9));
+        assert_eq!((a.start(), a.end()), (2, 10));
+    }
+
+    #[test]
+    fn test_interval_intersection() {
+        let a = interval(&[(2, 5), (8, 10)], &[]);
+        assert_eq!(a.intersection(&interval(&[(5, 8)], &[])), None);
+        assert_eq!(a.intersection(&interval(&[(0, 1), (6, 12)], &[])), Some(8));
+        assert_eq!(a.intersection(&interval(&[(4, 6)], &[])), Some(4));
+    }
+
+    #[test]
+    fn test_interval_split() {
+        let mut a = interval(&[(2, 5), (8, 12)], &[3, 8, 11]);
+        let rest = a.split(10);
+        assert_eq!(
+            (a.ranges.clone(), a.uses.clone()),
+            (vec![(2, 5), (8, 10)], vec![3, 8])
+        );
+        assert_eq!((rest.ranges, rest.uses), (vec![(10, 12)], vec![11]));
+        // Splitting in a hole leaves no empty range
+        let rest = a.split(6);
+        assert_eq!(rest.next_use(0), Some(8));
+        assert_eq!((a.ranges, rest.ranges), (vec![(2, 5)], vec![(8, 10)]));
+    }
+
+    /// Every temporary is replaced by a register, and the code still jumps only to its labels
+    #[test]
+    fn test_linear_scan_leaves_only_registers() {
+        let machine = Machine {
+            registers: &X86_64Frame::REGISTERS,
+            precolored: &X86_64Frame::PRECOLORED,
+            codegen,
+        };
+        for (name, source) in PROGRAMS.iter().chain(FAILING_PROGRAMS) {
+            for frag in translate::<X86_64Frame>(&load(source)) {
+                if let Frag::Proc { body, mut frame } = frag {
+                    let instrs = codegen(&canonicalize(body));
+                    let allocation = allocate(instrs, &mut frame, &machine);
+                    let graph = FlowGraph::new(&allocation.instrs);
+                    for t in &graph.temps {
+                        assert!(X86_64Frame::PRECOLORED.contains(t), "program {}", name);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compare_with_graph_coloring() {
+        let sources = [
+            ("arithmetic".to_string(), PROGRAMS[0].1.to_string()),
+            ("pressure_40".to_string(), pressure_program(40)),
+        ];
+        let corpus: Vec<(&str, Program)> = sources
+            .iter()
+            .map(|(name, source)| (name.as_str(), load(source)))
+            .collect();
+        let comparisons = compare(&corpus);
+        assert_eq!(comparisons[0].coloring_spills, 0);
+        assert_eq!(comparisons[0].linear_scan_spills, 0);
+        assert!(comparisons[1].coloring_spills > 0);
+        assert!(comparisons[1].linear_scan_spills >= comparisons[1].coloring_spills);
+        assert!(comparisons[1].to_string().starts_with("pressure_40 "));
+    }
+}
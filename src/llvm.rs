@@ -0,0 +1,865 @@
+/// A backend writing type checked programs as textual LLVM IR modules
+///
+/// The module is linked with `runtime/runtime.c` and can be optimized and compiled by any LLVM
+/// tool reading `.ll` files with opaque pointers:
+///
+/// * `int` is `i64`, `float` is `double`, `bool` is `i1` and a string is a `ptr` to its length
+///   followed by its bytes, as the runtime expects. String literals are private constants.
+/// * Every variable is an `alloca` in the entry block of its function, which `mem2reg` turns into
+///   SSA values. Variables captured by nested functions are fields of a frame struct instead,
+///   whose first field is the static link.
+/// * Every function of the program is a private LLVM function taking the static link first, and
+///   the program is `program_main`.
+use std::process::Command;
+
+use crate::chapter_4::*;
+use crate::chapter_6::{find_escape, Escapes};
+use crate::interpreter::MAX_CALL_DEPTH;
+
+/// Declarations of the runtime and the helpers every module defines, with `MAX_CALL_DEPTH` standing
+/// for the call depth limit of the interpreter
+const PRELUDE: &str = "\
+@call_depth = internal global i64 0
+
+declare void @print_int(i64)
+declare void @print_float(i64)
+declare void @print_bool(i64)
+declare void @print_string(ptr)
+declare ptr @string_concat(ptr, ptr)
+declare i64 @string_equal(ptr, ptr)
+declare void @div_by_zero()
+declare void @stack_overflow()
+
+define private i64 @int_div(i64 %a, i64 %b) {
+entry:
+  %zero = icmp eq i64 %b, 0
+  br i1 %zero, label %error, label %nonzero
+error:
+  call void @div_by_zero()
+  unreachable
+nonzero:
+  %minus_one = icmp eq i64 %b, -1
+  br i1 %minus_one, label %negate, label %divide
+negate:
+  %negated = sub i64 0, %a
+  ret i64 %negated
+divide:
+  %quotient = sdiv i64 %a, %b
+  ret i64 %quotient
+}
+
+define private void @enter_call() {
+entry:
+  %depth = load i64, ptr @call_depth
+  %overflow = icmp sge i64 %depth, MAX_CALL_DEPTH
+  br i1 %overflow, label %error, label %enter
+error:
+  call void @stack_overflow()
+  unreachable
+enter:
+  %deeper = add i64 %depth, 1
+  store i64 %deeper, ptr @call_depth
+  ret void
+}
+
+define private void @leave_call() {
+entry:
+  %depth = load i64, ptr @call_depth
+  %shallower = sub i64 %depth, 1
+  store i64 %shallower, ptr @call_depth
+  ret void
+}
+";
+
+fn llvm_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Int => return "i64",
+        Type::Float => return "double",
+        Type::Bool => return "i1",
+        Type::String => return "ptr",
+        Type::Unit => return "void",
+    }
+}
+
+/// An LLVM string constant with the given bytes
+fn llvm_string(s: &str) -> String {
+    let mut out = String::from("c\"");
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' => out.push_str(&format!("\\{:02X}", b)),
+            b' '..=b'~' => out.push(b as char),
+            _ => out.push_str(&format!("\\{:02X}", b)),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+/// What a name of the program is in the IR: an `alloca`, a field of a frame struct, or a private
+/// LLVM function
+#[derive(Clone)]
+enum Binding {
+    /// The `alloca` of a variable
+    Local(String),
+    /// A field of the frame of the function at a nesting level
+    Field { level: usize, index: usize },
+    /// An LLVM function, declared in the function at a nesting level
+    Fn { name: String, level: usize },
+}
+
+/// A function being translated
+struct FnContext {
+    level: usize,
+    /// The name of the type of its frame
+    frame: String,
+    /// Types of the fields of its frame, the static link first
+    fields: Vec<&'static str>,
+    /// Instructions of the entry block allocating the variables
+    allocas: String,
+    /// The label of the block instructions are added to
+    block: String,
+}
+
+struct Compiler {
+    /// The declarations that escape, found in Chapter 6
+    escapes: Escapes,
+    env: Vec<(String, Binding)>,
+    fns: Vec<FnContext>,
+    types: String,
+    strings: String,
+    string_count: usize,
+    functions: String,
+    next_id: usize,
+}
+
+/// Translate a type checked program to an LLVM IR module
+pub fn compile(program: &Program) -> String {
+    let mut compiler = Compiler {
+        escapes: find_escape(program),
+        env: Vec::new(),
+        fns: Vec::new(),
+        types: String::new(),
+        strings: String::new(),
+        string_count: 0,
+        functions: String::new(),
+        next_id: 0,
+    };
+    compiler.begin_fn(0, "%frame.main".to_string());
+    let mut body = String::new();
+    compiler.compile_stmts(&program.stmts, &mut body);
+    let main = compiler.end_fn();
+    let mut function = String::from("define void @program_main() {\nentry:\n");
+    function.push_str(&main.allocas);
+    function.push_str(&body);
+    function.push_str("  ret void\n}\n");
+
+    let mut out = String::new();
+    out.push_str(&compiler.types);
+    out.push('\n');
+    if !compiler.strings.is_empty() {
+        out.push_str(&compiler.strings);
+        out.push('\n');
+    }
+    out.push_str(&PRELUDE.replace("MAX_CALL_DEPTH", &MAX_CALL_DEPTH.to_string()));
+    out.push('\n');
+    out.push_str(&compiler.functions);
+    out.push_str(&function);
+    return out;
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut FnContext {
+        return self.fns.last_mut().unwrap();
+    }
+
+    fn fresh(&mut self, name: &str) -> String {
+        self.next_id += 1;
+        return format!("{}.{}", name, self.next_id);
+    }
+
+    fn begin_fn(&mut self, level: usize, frame: String) {
+        let allocas = format!("  %frame = alloca {}\n", frame);
+        self.fns.push(FnContext {
+            level,
+            frame,
+            fields: vec!["ptr"],
+            allocas,
+            block: "entry".to_string(),
+        });
+    }
+
+    /// Finish a function, defining the type of its frame
+    fn end_fn(&mut self) -> FnContext {
+        let context = self.fns.pop().unwrap();
+        self.types.push_str(&format!(
+            "{} = type {{ {} }}\n",
+            context.frame,
+            context.fields.join(", ")
+        ));
+        return context;
+    }
+
+    /// Emit an instruction computing a value, returning the value
+    fn value(&mut self, instr: &str, out: &mut String) -> String {
+        self.next_id += 1;
+        let name = format!("%t{}", self.next_id);
+        out.push_str(&format!("  {} = {}\n", name, instr));
+        return name;
+    }
+
+    fn instr(&self, instr: &str, out: &mut String) {
+        out.push_str(&format!("  {}\n", instr));
+    }
+
+    /// Start a new block. The block before it must have been terminated
+    fn label(&mut self, label: &str, out: &mut String) {
+        out.push_str(&format!("{}:\n", label));
+        self.current().block = label.to_string();
+    }
+
+    fn string_constant(&mut self, s: &str) -> String {
+        let name = format!("@str.{}", self.string_count);
+        self.string_count += 1;
+        self.strings.push_str(&format!(
+            "{} = private unnamed_addr constant {{ i64, [{} x i8] }} {{ i64 {}, [{} x i8] {} }}\n",
+            name,
+            s.len(),
+            s.len(),
+            s.len(),
+            llvm_string(s)
+        ));
+        return name;
+    }
+
+    /// Give a variable an `alloca` in the entry block, or a field of the frame struct of the current
+    /// function if a nested function reaches it through the static link
+    fn declare(&mut self, name: &str, ty: Type, escape: bool) -> Binding {
+        if escape {
+            let context = self.current();
+            context.fields.push(llvm_type(ty));
+            return Binding::Field {
+                level: context.level,
+                index: context.fields.len() - 1,
+            };
+        }
+        let alloca = format!("%{}", self.fresh(name));
+        self.current()
+            .allocas
+            .push_str(&format!("  {} = alloca {}\n", alloca, llvm_type(ty)));
+        return Binding::Local(alloca);
+    }
+
+    fn lookup(&self, name: &str) -> Binding {
+        for (n, binding) in self.env.iter().rev() {
+            if n == name {
+                return binding.clone();
+            }
+        }
+        panic!("`{}` is not declared", name);
+    }
+
+    /// A pointer to the frame of the function at `level`, found by following static links
+    fn frame_pointer(&mut self, level: usize, out: &mut String) -> String {
+        let mut pointer = "%frame".to_string();
+        for _ in level..self.current().level {
+            pointer = self.value(&format!("load ptr, ptr {}", pointer), out);
+        }
+        return pointer;
+    }
+
+    /// A pointer to where a variable is stored
+    fn address(&mut self, binding: &Binding, out: &mut String) -> String {
+        match binding {
+            Binding::Local(alloca) => return alloca.clone(),
+            Binding::Field { level, index } => {
+                let frame = self.fns[*level].frame.clone();
+                let pointer = self.frame_pointer(*level, out);
+                return self.value(
+                    &format!(
+                        "getelementptr {}, ptr {}, i32 0, i32 {}",
+                        frame, pointer, index
+                    ),
+                    out,
+                );
+            }
+            Binding::Fn { .. } => panic!("a function is not a value"),
+        }
+    }
+
+    fn store(&mut self, binding: &Binding, ty: Type, value: &str, out: &mut String) {
+        let address = self.address(binding, out);
+        self.instr(
+            &format!("store {} {}, ptr {}", llvm_type(ty), value, address),
+            out,
+        );
+    }
+
+    fn compile_stmts(&mut self, stmts: &[Stmt], out: &mut String) {
+        let mut i = 0;
+        while i < stmts.len() {
+            let mut decls = Vec::new();
+            while let Some(Stmt {
+                kind: StmtKind::Fn(decl),
+                ..
+            }) = stmts.get(i)
+            {
+                decls.push(decl);
+                i += 1;
+            }
+            if !decls.is_empty() {
+                self.compile_fn_group(&decls);
+            } else {
+                self.compile_stmt(&stmts[i], out);
+                i += 1;
+            }
+        }
+    }
+
+    /// Compile consecutive function declarations, naming all of them first. LLVM functions are
+    /// module level, so each body can call any of them
+    fn compile_fn_group(&mut self, decls: &[&FnDecl]) {
+        let level = self.current().level;
+        let mut names = Vec::new();
+        for decl in decls {
+            let name = format!("@{}", self.fresh(&decl.name));
+            self.env.push((
+                decl.name.clone(),
+                Binding::Fn {
+                    name: name.clone(),
+                    level,
+                },
+            ));
+            names.push(name);
+        }
+        for (decl, name) in decls.iter().zip(names) {
+            self.compile_fn(decl, &name, level + 1);
+        }
+    }
+
+    fn compile_fn(&mut self, decl: &FnDecl, name: &str, level: usize) {
+        let mut signature = format!(
+            "define private {} {}(ptr %link",
+            llvm_type(decl.result),
+            name
+        );
+        self.begin_fn(level, format!("%frame.{}", &name[1..]));
+        let scope = self.env.len();
+        let mut body = String::from("  store ptr %link, ptr %frame\n");
+        for param in &decl.params {
+            let binding = self.declare(&param.name, param.ty, self.escapes.escapes(param.pos));
+            let value = format!("%{}", self.fresh(&param.name));
+            signature.push_str(&format!(", {} {}", llvm_type(param.ty), value));
+            self.store(&binding, param.ty, &value, &mut body);
+            self.env.push((param.name.clone(), binding));
+        }
+        signature.push_str(") {\n");
+        let value = self.compile_block(&decl.body, &mut body);
+        self.env.truncate(scope);
+        self.instr("call void @leave_call()", &mut body);
+        match decl.result {
+            Type::Unit => self.instr("ret void", &mut body),
+            ty => self.instr(&format!("ret {} {}", llvm_type(ty), value), &mut body),
+        }
+        let context = self.end_fn();
+
+        self.functions.push_str(&signature);
+        self.functions.push_str("entry:\n");
+        self.functions.push_str(&context.allocas);
+        self.functions.push_str("  call void @enter_call()\n");
+        self.functions.push_str(&body);
+        self.functions.push_str("}\n\n");
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt, out: &mut String) {
+        match &stmt.kind {
+            StmtKind::Let { name, init, .. } => {
+                let value = self.compile_expr(init, out);
+                let binding = self.declare(name, init.ty(), self.escapes.escapes(stmt.pos));
+                self.store(&binding, init.ty(), &value, out);
+                self.env.push((name.clone(), binding));
+            }
+            StmtKind::Assign { name, value } => {
+                let value_ty = value.ty();
+                let value = self.compile_expr(value, out);
+                let binding = self.lookup(name);
+                self.store(&binding, value_ty, &value, out);
+            }
+            StmtKind::While { cond, body } => {
+                let id = self.next_id + 1;
+                self.next_id += 1;
+                let (test, body_label, exit) = (
+                    format!("while.cond.{}", id),
+                    format!("while.body.{}", id),
+                    format!("while.end.{}", id),
+                );
+                self.instr(&format!("br label %{}", test), out);
+                self.label(&test, out);
+                let cond = self.compile_expr(cond, out);
+                self.instr(
+                    &format!("br i1 {}, label %{}, label %{}", cond, body_label, exit),
+                    out,
+                );
+                self.label(&body_label, out);
+                self.compile_block(body, out);
+                self.instr(&format!("br label %{}", test), out);
+                self.label(&exit, out);
+            }
+            StmtKind::For { var, lo, hi, body } => {
+                let lo = self.compile_expr(lo, out);
+                let hi = self.compile_expr(hi, out);
+                let counter = format!("%{}", self.fresh("counter"));
+                self.current()
+                    .allocas
+                    .push_str(&format!("  {} = alloca i64\n", counter));
+                self.instr(&format!("store i64 {}, ptr {}", lo, counter), out);
+                let id = self.next_id;
+                let (test, body_label, exit) = (
+                    format!("for.cond.{}", id),
+                    format!("for.body.{}", id),
+                    format!("for.end.{}", id),
+                );
+                self.instr(&format!("br label %{}", test), out);
+                self.label(&test, out);
+                let i = self.value(&format!("load i64, ptr {}", counter), out);
+                let cond = self.value(&format!("icmp slt i64 {}, {}", i, hi), out);
+                self.instr(
+                    &format!("br i1 {}, label %{}, label %{}", cond, body_label, exit),
+                    out,
+                );
+                self.label(&body_label, out);
+                let scope = self.env.len();
+                let binding = self.declare(var, Type::Int, self.escapes.escapes(stmt.pos));
+                self.store(&binding, Type::Int, &i, out);
+                self.env.push((var.clone(), binding));
+                self.compile_block(body, out);
+                self.env.truncate(scope);
+                let next = self.value(&format!("add i64 {}, 1", i), out);
+                self.instr(&format!("store i64 {}, ptr {}", next, counter), out);
+                self.instr(&format!("br label %{}", test), out);
+                self.label(&exit, out);
+            }
+            StmtKind::Fn(_) => unreachable!("function declarations are compiled in groups"),
+            StmtKind::Error => unreachable!("the type checker rejects syntax errors"),
+            StmtKind::Expr(expr) => {
+                self.compile_expr(expr, out);
+            }
+        }
+    }
+
+    /// Compile a block, returning its value or an empty string if it has none
+    fn compile_block(&mut self, block: &Block, out: &mut String) -> String {
+        let scope = self.env.len();
+        self.compile_stmts(&block.stmts, out);
+        let value = match &block.result {
+            Some(result) => self.compile_expr(result, out),
+            None => String::new(),
+        };
+        self.env.truncate(scope);
+        return value;
+    }
+
+    /// Compile an expression, returning the operand holding its value, or an empty string if
+    /// it has none
+    fn compile_expr(&mut self, expr: &Expr, out: &mut String) -> String {
+        match &expr.kind {
+            ExprKind::Int(i) => return i.to_string(),
+            // Decimal constants must be exact, so floats are written as their bits
+            ExprKind::Float(x) => return format!("0x{:016X}", x.to_bits()),
+            ExprKind::Bool(b) => return b.to_string(),
+            ExprKind::Str(s) => return self.string_constant(s),
+            ExprKind::Var(name) => {
+                let binding = self.lookup(name);
+                let address = self.address(&binding, out);
+                return self.value(
+                    &format!("load {}, ptr {}", llvm_type(expr.ty()), address),
+                    out,
+                );
+            }
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                let value = self.compile_expr(operand, out);
+                if operand.ty() == Type::Float {
+                    return self.value(&format!("fneg double {}", value), out);
+                }
+                return self.value(&format!("sub i64 0, {}", value), out);
+            }
+            ExprKind::Binary(left, op, right) => return self.compile_binary(left, *op, right, out),
+            ExprKind::Error => unreachable!("the type checker rejects syntax errors"),
+            ExprKind::Call(name, args) => return self.compile_call(name, args, expr.ty(), out),
+            ExprKind::If {
+                branches,
+                else_block,
+            } => {
+                self.next_id += 1;
+                let join = format!("if.end.{}", self.next_id);
+                let mut incoming = Vec::new();
+                self.compile_if(branches, else_block.as_ref(), &join, &mut incoming, out);
+                self.label(&join, out);
+                if expr.ty() == Type::Unit {
+                    return String::new();
+                }
+                let incoming: Vec<String> = incoming
+                    .iter()
+                    .map(|(value, block)| format!("[ {}, %{} ]", value, block))
+                    .collect();
+                return self.value(
+                    &format!("phi {} {}", llvm_type(expr.ty()), incoming.join(", ")),
+                    out,
+                );
+            }
+        }
+    }
+
+    fn compile_binary(&mut self, left: &Expr, op: BinOp, right: &Expr, out: &mut String) -> String {
+        if op == BinOp::And || op == BinOp::Or {
+            // The right operand is only computed when it decides the result
+            self.next_id += 1;
+            let (rhs, join) = (
+                format!("rhs.{}", self.next_id),
+                format!("rhs.end.{}", self.next_id),
+            );
+            let left = self.compile_expr(left, out);
+            let skipped_from = self.current().block.clone();
+            let (on_true, on_false, skipped) = match op {
+                BinOp::And => (&rhs, &join, "false"),
+                _ => (&join, &rhs, "true"),
+            };
+            self.instr(
+                &format!("br i1 {}, label %{}, label %{}", left, on_true, on_false),
+                out,
+            );
+            self.label(&rhs, out);
+            let right = self.compile_expr(right, out);
+            let right_from = self.current().block.clone();
+            self.instr(&format!("br label %{}", join), out);
+            self.label(&join, out);
+            return self.value(
+                &format!(
+                    "phi i1 [ {}, %{} ], [ {}, %{} ]",
+                    skipped, skipped_from, right, right_from
+                ),
+                out,
+            );
+        }
+        let l = self.compile_expr(left, out);
+        let r = self.compile_expr(right, out);
+        let instr = match (left.ty(), op) {
+            (Type::Int, BinOp::Div) => format!("call i64 @int_div(i64 {}, i64 {})", l, r),
+            (Type::String, BinOp::Add) => format!("call ptr @string_concat(ptr {}, ptr {})", l, r),
+            (Type::String, BinOp::Eq | BinOp::Neq) => {
+                let equal = self.value(
+                    &format!("call i64 @string_equal(ptr {}, ptr {})", l, r),
+                    out,
+                );
+                let cond = if op == BinOp::Eq { "ne" } else { "eq" };
+                format!("icmp {} i64 {}, 0", cond, equal)
+            }
+            (Type::Int | Type::Bool, _) => {
+                let name = match op {
+                    BinOp::Add => "add",
+                    BinOp::Sub => "sub",
+                    BinOp::Mul => "mul",
+                    BinOp::Eq => "icmp eq",
+                    BinOp::Neq => "icmp ne",
+                    BinOp::Lt => "icmp slt",
+                    BinOp::Le => "icmp sle",
+                    BinOp::Gt => "icmp sgt",
+                    BinOp::Ge => "icmp sge",
+                    _ => unreachable!(),
+                };
+                format!("{} {} {}, {}", name, llvm_type(left.ty()), l, r)
+            }
+            (Type::Float, _) => {
+                // Unordered inequality, so that NaN is not equal to itself
+                let name = match op {
+                    BinOp::Add => "fadd",
+                    BinOp::Sub => "fsub",
+                    BinOp::Mul => "fmul",
+                    BinOp::Div => "fdiv",
+                    BinOp::Eq => "fcmp oeq",
+                    BinOp::Neq => "fcmp une",
+                    BinOp::Lt => "fcmp olt",
+                    BinOp::Le => "fcmp ole",
+                    BinOp::Gt => "fcmp ogt",
+                    BinOp::Ge => "fcmp oge",
+                    _ => unreachable!(),
+                };
+                format!("{} double {}, {}", name, l, r)
+            }
+            (ty, _) => panic!("cannot apply `{}` to {}", op, ty),
+        };
+        return self.value(&instr, out);
+    }
+
+    fn compile_call(&mut self, name: &str, args: &[Expr], ty: Type, out: &mut String) -> String {
+        let binding = self.env.iter().rev().find(|(n, _)| n == name);
+        if let Some((_, Binding::Fn { name, level })) = binding {
+            let (name, level) = (name.clone(), *level);
+            let link = self.frame_pointer(level, out);
+            let mut arguments = vec![format!("ptr {}", link)];
+            for arg in args {
+                let value = self.compile_expr(arg, out);
+                arguments.push(format!("{} {}", llvm_type(arg.ty()), value));
+            }
+            let call = format!("call {} {}({})", llvm_type(ty), name, arguments.join(", "));
+            if ty == Type::Unit {
+                self.instr(&call, out);
+                return String::new();
+            }
+            return self.value(&call, out);
+        }
+        // `print` computes every argument before printing any of them
+        let values: Vec<String> = args.iter().map(|arg| self.compile_expr(arg, out)).collect();
+        for (arg, value) in args.iter().zip(values) {
+            match arg.ty() {
+                Type::Int => self.instr(&format!("call void @print_int(i64 {})", value), out),
+                Type::Float => {
+                    let bits = self.value(&format!("bitcast double {} to i64", value), out);
+                    self.instr(&format!("call void @print_float(i64 {})", bits), out);
+                }
+                Type::Bool => {
+                    let int = self.value(&format!("zext i1 {} to i64", value), out);
+                    self.instr(&format!("call void @print_bool(i64 {})", int), out);
+                }
+                Type::String => self.instr(&format!("call void @print_string(ptr {})", value), out),
+                Type::Unit => unreachable!(),
+            }
+        }
+        return String::new();
+    }
+
+    /// Compile the branches of an `if`, each jumping to `join` and adding its value and the
+    /// block it comes from to `incoming`
+    fn compile_if(
+        &mut self,
+        branches: &[(Expr, Block)],
+        else_block: Option<&Block>,
+        join: &str,
+        incoming: &mut Vec<(String, String)>,
+        out: &mut String,
+    ) {
+        let (cond, then) = &branches[0];
+        self.next_id += 1;
+        let (then_label, else_label) = (
+            format!("if.then.{}", self.next_id),
+            format!("if.else.{}", self.next_id),
+        );
+        let cond = self.compile_expr(cond, out);
+        let otherwise = match branches.len() > 1 || else_block.is_some() {
+            true => &else_label,
+            false => join,
+        };
+        self.instr(
+            &format!(
+                "br i1 {}, label %{}, label %{}",
+                cond, then_label, otherwise
+            ),
+            out,
+        );
+        self.label(&then_label, out);
+        self.compile_branch(then, join, incoming, out);
+        if branches.len() > 1 {
+            self.label(&else_label, out);
+            self.compile_if(&branches[1..], else_block, join, incoming, out);
+        } else if let Some(block) = else_block {
+            self.label(&else_label, out);
+            self.compile_branch(block, join, incoming, out);
+        }
+    }
+
+    fn compile_branch(
+        &mut self,
+        block: &Block,
+        join: &str,
+        incoming: &mut Vec<(String, String)>,
+        out: &mut String,
+    ) {
+        let value = self.compile_block(block, out);
+        incoming.push((value, self.current().block.clone()));
+        self.instr(&format!("br label %{}", join), out);
+    }
+}
+
+/// The flags making an LLVM tool read opaque pointers, which LLVM 14 needs, or `None` if the
+/// tool is not installed
+pub fn llvm_flags(tool: &str) -> Option<Vec<&'static str>> {
+    let output = Command::new(tool).arg("--version").output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).to_string();
+    let major: u32 = version
+        .split("LLVM version ")
+        .nth(1)?
+        .split('.')
+        .next()?
+        .parse()
+        .ok()?;
+    return Some(match major {
+        ..=13 => return None,
+        14 => vec!["-opaque-pointers"],
+        _ => vec![],
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::*;
+    use std::process::{Command, Output};
+
+    /// Optimize a program, compile it and link it with the runtime library, then run it
+    fn run(dir: &std::path::Path, name: &str, source: &str) -> Output {
+        let (opt, llc) = (llvm_flags("opt").unwrap(), llvm_flags("llc").unwrap());
+        let path = dir.join(format!("{}.ll", name));
+        let optimized = dir.join(format!("{}.opt.ll", name));
+        let assembly = dir.join(format!("{}.s", name));
+        let exe = dir.join(name);
+        std::fs::write(&path, compile(&load(source))).unwrap();
+        let steps = [
+            Command::new("opt")
+                .args(&opt)
+                .args(["-O2", "-S", "-o"])
+                .arg(&optimized)
+                .arg(&path)
+                .output()
+                .unwrap(),
+            Command::new("llc")
+                .args(&llc)
+                .args(["-relocation-model=pic", "-o"])
+                .arg(&assembly)
+                .arg(&optimized)
+                .output()
+                .unwrap(),
+            Command::new("cc")
+                .arg("-o")
+                .arg(&exe)
+                .arg(&assembly)
+                .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/runtime/runtime.c"))
+                .arg("-lm")
+                .output()
+                .unwrap(),
+        ];
+        for output in steps {
+            assert!(
+                output.status.success(),
+                "program {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        return Command::new(&exe).output().unwrap();
+    }
+
+    /// Fail the test unless the LLVM tools and a C compiler are installed, for the tests that are
+    /// only run on request
+    fn require_tools() {
+        for name in ["opt", "llc", "cc"] {
+            require_tool(name);
+        }
+    }
+
+    #[test]
+    fn test_llvm_string_constants() {
+        assert_eq!(llvm_string("a \"b\"\\\n"), "c\"a \\22b\\22\\5C\\0A\"");
+        let ir = compile(&load("print(\"hi\", \"\");"));
+        assert!(ir.contains(
+            "@str.0 = private unnamed_addr constant { i64, [2 x i8] } { i64 2, [2 x i8] c\"hi\" }"
+        ));
+        assert!(ir.contains("  call void @print_string(ptr @str.1)\n"));
+    }
+
+    /// The complete function emitted for a small program
+    #[test]
+    fn test_llvm_golden_function() {
+        let ir = compile(&load(
+            "fn fact(n: int): int { if n == 0 { 1 } else { n * fact(n - 1) } } print(fact(5));",
+        ));
+        let expected = "\
+%frame.fact.1 = type { ptr }
+%frame.main = type { ptr }
+";
+        assert!(ir.starts_with(expected), "{}", ir);
+        let expected = "\
+define private i64 @fact.1(ptr %link, i64 %n.3) {
+entry:
+  %frame = alloca %frame.fact.1
+  %n.2 = alloca i64
+  call void @enter_call()
+  store ptr %link, ptr %frame
+  store i64 %n.3, ptr %n.2
+  %t6 = load i64, ptr %n.2
+  %t7 = icmp eq i64 %t6, 0
+  br i1 %t7, label %if.then.5, label %if.else.5
+if.then.5:
+  br label %if.end.4
+if.else.5:
+  %t8 = load i64, ptr %n.2
+  %t9 = load ptr, ptr %frame
+  %t10 = load i64, ptr %n.2
+  %t11 = sub i64 %t10, 1
+  %t12 = call i64 @fact.1(ptr %t9, i64 %t11)
+  %t13 = mul i64 %t8, %t12
+  br label %if.end.4
+if.end.4:
+  %t14 = phi i64 [ 1, %if.then.5 ], [ %t13, %if.else.5 ]
+  call void @leave_call()
+  ret i64 %t14
+}
+
+define void @program_main() {
+entry:
+  %frame = alloca %frame.main
+  %t15 = call i64 @fact.1(ptr %frame, i64 5)
+  call void @print_int(i64 %t15)
+  ret void
+}
+";
+        assert!(ir.ends_with(expected), "{}", ir);
+    }
+
+    #[test]
+    fn test_llvm_escaping_variable_is_frame_field() {
+        let ir = compile(&load(
+            "let mut x = 1; fn f(): int { fn g(): int { x } g() } x = 2; print(f());",
+        ));
+        assert!(ir.contains("%frame.main = type { ptr, i64 }"));
+        assert!(ir.contains("getelementptr %frame.main, ptr %frame, i32 0, i32 1"));
+        // `g` reaches `x` through the static links of both frames
+        assert!(ir.contains("  %t4 = load ptr, ptr %frame\n  %t5 = load ptr, ptr %t4\n"));
+    }
+
+    #[test]
+    #[ignore = "needs `opt`, `llc` and `cc`, run with `cargo test -- --ignored`"]
+    fn test_llvm_programs_run() {
+        require_tools();
+        let dir = std::env::temp_dir().join(format!("llvm_run_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in PROGRAMS {
+            let output = run(&dir, name, source);
+            assert!(output.status.success(), "program {}", name);
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                expected_output(source),
+                "program {}",
+                name
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs `opt`, `llc` and `cc`, run with `cargo test -- --ignored`"]
+    fn test_llvm_runtime_errors() {
+        require_tools();
+        let dir = std::env::temp_dir().join(format!("llvm_fail_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in FAILING_PROGRAMS {
+            let output = run(&dir, name, source);
+            assert_eq!(output.status.code(), Some(1), "program {}", name);
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap().trim_end(),
+                expected_error(source),
+                "program {}",
+                name
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1573 @@
+/// A Language Server Protocol server for the language, speaking JSON-RPC over standard input and
+/// output
+///
+/// The server keeps the text of every open document and analyses it again on each change,
+/// publishing the diagnostics of the lexer, parser and type checker. It answers requests for
+/// semantic tokens, which classify the tokens of Chapter 2 for highlighting, for the declaration
+/// and the type of what is under the cursor, and for the names and keywords that can be written
+/// there. Positions in the protocol are lines and UTF-16 columns, both counted from 0.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::chapter_2::{tokenize_with_comments, Token, RESERVED_WORDS};
+use crate::chapter_3::parse_recovering;
+use crate::chapter_4::*;
+use crate::chapter_5::check_with_warnings;
+use crate::diagnostics::{json_string, resolve, Diagnostic, Severity, Span};
+use crate::interpreter::STACK_SIZE;
+
+pub const SERVER_NAME: &str = "modern_compiler_implementation_in_rust";
+
+/// How tokens are highlighted, in the order of the legend sent to the client
+pub const TOKEN_TYPES: [&str; 9] = [
+    "keyword",
+    "type",
+    "function",
+    "variable",
+    "parameter",
+    "number",
+    "string",
+    "operator",
+    "comment",
+];
+
+// JSON-RPC error codes
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+/// Documents are sent whole on every change
+const FULL_SYNC: usize = 1;
+
+// Kinds of completion items in the protocol
+pub const FUNCTION_ITEM: usize = 3;
+pub const VARIABLE_ITEM: usize = 6;
+pub const KEYWORD_ITEM: usize = 14;
+
+/// A JSON value. Objects keep their members in the order they were written
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+static NULL: Json = Json::Null;
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = JsonParser { text, pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.rest().chars().next() {
+            return Err(format!("unexpected `{}` after the value", c));
+        }
+        return Ok(value);
+    }
+
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        return Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        );
+    }
+
+    /// The member of an object with the given key, or null when there is none
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(members) => {
+                return members
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map_or(&NULL, |(_, value)| value)
+            }
+            _ => return &NULL,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => return Some(s),
+            _ => return None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => return Some(*n as usize),
+            _ => return None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => return Some(items),
+            _ => return None,
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        return Json::Str(s.to_string());
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        return Json::Str(s);
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        return Json::Bool(b);
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        return Json::Number(n as f64);
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Json {
+        return Json::Number(f64::from(n));
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Json {
+        return Json::Array(items);
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => return write!(f, "null"),
+            Json::Bool(b) => return write!(f, "{}", b),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+                return write!(f, "{}", *n as i64)
+            }
+            Json::Number(n) => return write!(f, "{}", n),
+            Json::Str(s) => return write!(f, "{}", json_string(s)),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                return write!(f, "]");
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", json_string(key), value)?;
+                }
+                return write!(f, "}}");
+            }
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn rest(&self) -> &'a str {
+        return &self.text[self.pos..];
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(text) {
+            self.pos += text.len();
+            return true;
+        }
+        return false;
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.rest().chars().next() {
+            None => return Err("unexpected end of JSON".to_string()),
+            Some('{') => return self.object(),
+            Some('[') => return self.array(),
+            Some('"') => return Ok(Json::Str(self.string()?)),
+            Some(c) if c == '-' || c.is_ascii_digit() => return self.number(),
+            _ if self.eat("true") => return Ok(Json::Bool(true)),
+            _ if self.eat("false") => return Ok(Json::Bool(false)),
+            _ if self.eat("null") => return Ok(Json::Null),
+            Some(c) => return Err(format!("unexpected `{}`", c)),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        if self.eat("}") {
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if !self.rest().starts_with('"') {
+                return Err("expected a string for the key of a member".to_string());
+            }
+            let key = self.string()?;
+            if !self.eat(":") {
+                return Err(format!("expected `:` after the key `{}`", key));
+            }
+            members.push((key, self.value()?));
+            if self.eat("}") {
+                return Ok(Json::Object(members));
+            }
+            if !self.eat(",") {
+                return Err("expected `,` or `}` in an object".to_string());
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        if self.eat("]") {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            if self.eat("]") {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(",") {
+                return Err("expected `,` or `]` in an array".to_string());
+            }
+        }
+    }
+
+    /// A string, starting at its opening quote. Escaped UTF-16 code units are gathered until the
+    /// next character that is not one, so surrogate pairs make a single character
+    fn string(&mut self) -> Result<String, String> {
+        let text = self.text;
+        let mut chars = text[self.pos + 1..].char_indices();
+        let mut out = String::new();
+        let mut units: Vec<u16> = Vec::new();
+        loop {
+            let Some((i, c)) = chars.next() else {
+                return Err("unterminated string".to_string());
+            };
+            if c == '\\' && chars.clone().next().map(|(_, c)| c) == Some('u') {
+                chars.next();
+                let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                let unit = u16::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid escape `\\u{}`", hex))?;
+                units.push(unit);
+                continue;
+            }
+            out.push_str(&String::from_utf16_lossy(&units));
+            units.clear();
+            match c {
+                '"' => {
+                    self.pos += i + 2;
+                    return Ok(out);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some(c @ ('"' | '\\' | '/')) => out.push(c),
+                    Some(c) => return Err(format!("invalid escape `\\{}`", c)),
+                    None => return Err("unterminated string".to_string()),
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+            .unwrap_or(rest.len());
+        let number = rest[..len]
+            .parse()
+            .map_err(|_| format!("invalid number `{}`", &rest[..len]))?;
+        self.pos += len;
+        return Ok(Json::Number(number));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+}
+
+/// A variable, parameter or function declared in a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The name where it is declared
+    pub span: Span,
+    /// The part of the document the name can be used in
+    pub scope: Span,
+    /// How the declaration reads, with its type when it is known
+    pub detail: String,
+}
+
+/// Something that can be written at a point of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: usize,
+    pub detail: String,
+}
+
+/// What the server knows of a document, worked out from its text once on every change. The
+/// requests are all answered from it, without lexing or parsing again
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    /// The tokens and comments with the bytes they cover, up to the first error of the lexer
+    pub tokens: Vec<(Token, Span)>,
+    /// How each token is highlighted, as its index in `TOKEN_TYPES`
+    pub token_types: Vec<Option<usize>>,
+    /// The syntax tree, with the types of its expressions when it type checks
+    pub program: Program,
+    /// The symbols declared, including those in the parts of a document around syntax errors
+    pub symbols: Vec<Symbol>,
+    /// Every use of a symbol, with the index of the symbol
+    pub references: Vec<(Span, usize)>,
+    /// The names of declarations and uses, sorted by where they start, with the index of the
+    /// symbol they name
+    names: Vec<(Span, usize)>,
+}
+
+impl Analysis {
+    pub fn new(source: &str) -> Analysis {
+        let (lexed, end) = match tokenize_with_comments(source) {
+            Ok(tokens) => (tokens, source.len()),
+            // The tokens before the error lex the same way on their own
+            Err(error) => (
+                tokenize_with_comments(&source[..error.pos]).unwrap_or_default(),
+                error.pos,
+            ),
+        };
+        let ends = lexed.iter().skip(1).map(|(_, pos)| *pos).chain([end]);
+        let tokens = lexed
+            .iter()
+            .zip(ends)
+            .map(|((token, start), end)| {
+                let text = source[*start..end].trim_end();
+                return (token.clone(), Span::new(*start, start + text.len()));
+            })
+            .collect();
+        // The parser recovers from syntax errors, so the parts of the program around them can
+        // still be resolved. Only a program without them is type checked
+        let (program, errors) = parse_recovering(source);
+        let mut analysis = Analysis {
+            diagnostics: vec![],
+            tokens,
+            token_types: vec![],
+            program,
+            symbols: vec![],
+            references: vec![],
+            names: vec![],
+        };
+        if errors.is_empty() {
+            match check_with_warnings(&analysis.program) {
+                Ok(warnings) => analysis
+                    .diagnostics
+                    .extend(warnings.into_iter().map(Diagnostic::from)),
+                Err(error) => analysis.diagnostics.push(error.into()),
+            }
+        }
+        analysis
+            .diagnostics
+            .extend(errors.into_iter().map(Diagnostic::from));
+        let mut resolver = Resolver::new(&analysis.tokens, source.len());
+        resolver.stmts(&analysis.program.stmts, None, source.len());
+        analysis.symbols = resolver.symbols;
+        analysis.references = resolver.references;
+        analysis.names = analysis
+            .symbols
+            .iter()
+            .enumerate()
+            .map(|(i, symbol)| (symbol.span, i))
+            .chain(analysis.references.iter().copied())
+            .collect();
+        analysis.names.sort_by_key(|(span, _)| span.start);
+        analysis.token_types = (0..analysis.tokens.len())
+            .map(|i| analysis.classify(i))
+            .collect();
+        return analysis;
+    }
+
+    /// The symbol named at an offset, by its declaration or by one of its uses
+    pub fn symbol_at(&self, offset: Pos) -> Option<&Symbol> {
+        // Names do not overlap, so only the last one starting at or before the offset can hold it
+        let after = self.names.partition_point(|(span, _)| span.start <= offset);
+        let (span, i) = self.names[..after].last()?;
+        if offset > span.end {
+            return None;
+        }
+        return Some(&self.symbols[*i]);
+    }
+
+    /// What to show for an offset: the declaration of the name there, or else the type of the
+    /// expression positioned there
+    pub fn hover(&self, offset: Pos) -> Option<String> {
+        if let Some(symbol) = self.symbol_at(offset) {
+            return Some(symbol.detail.clone());
+        }
+        let token = self.tokens.partition_point(|(_, span)| span.end < offset);
+        let (_, span) = self.tokens.get(token)?;
+        if span.start > offset {
+            return None;
+        }
+        return expr_type_at(&self.program.stmts, None, span.start).map(|ty| ty.to_string());
+    }
+
+    /// The names in scope at an offset, nearest first, then `print` and the keywords
+    pub fn completions(&self, offset: Pos) -> Vec<Completion> {
+        let mut completions = Vec::new();
+        // The symbols in scope are declared in nested scopes, so a later one shadows an earlier
+        // one of the same name
+        let mut seen = HashSet::new();
+        for symbol in self.symbols.iter().rev() {
+            let in_scope = symbol.scope.start <= offset && offset <= symbol.scope.end;
+            let function = symbol.kind == SymbolKind::Function;
+            if in_scope && seen.insert((symbol.name.as_str(), function)) {
+                completions.push(Completion {
+                    label: symbol.name.clone(),
+                    kind: if function {
+                        FUNCTION_ITEM
+                    } else {
+                        VARIABLE_ITEM
+                    },
+                    detail: symbol.detail.clone(),
+                });
+            }
+        }
+        completions.push(Completion {
+            label: "print".to_string(),
+            kind: FUNCTION_ITEM,
+            detail: "fn print(...)".to_string(),
+        });
+        for word in RESERVED_WORDS {
+            completions.push(Completion {
+                label: word.to_string(),
+                kind: KEYWORD_ITEM,
+                detail: String::new(),
+            });
+        }
+        return completions;
+    }
+
+    /// The index in `TOKEN_TYPES` of how the token at an index is highlighted, if it is
+    fn classify(&self, index: usize) -> Option<usize> {
+        let (token, span) = &self.tokens[index];
+        let name = match token {
+            Token::Int | Token::Bool | Token::Float | Token::String | Token::Char => "type",
+            Token::Id(_) => match self.symbol_at(span.start).map(|symbol| symbol.kind) {
+                Some(SymbolKind::Function) => "function",
+                Some(SymbolKind::Parameter) => "parameter",
+                Some(SymbolKind::Variable) => "variable",
+                None if matches!(self.tokens.get(index + 1), Some((Token::Lparen, _))) => {
+                    "function"
+                }
+                None => "variable",
+            },
+            Token::Num(_) | Token::Real(_) => "number",
+            Token::StaticString(_) => "string",
+            Token::Comment(_) => "comment",
+            Token::Plus
+            | Token::Minus
+            | Token::Star
+            | Token::ForwardSlash
+            | Token::Equal
+            | Token::LessThan
+            | Token::GreaterThan
+            | Token::Ampersand
+            | Token::Bar => "operator",
+            token if RESERVED_WORDS.contains(&token.to_string().as_str()) => "keyword",
+            _ => return None,
+        };
+        return TOKEN_TYPES.iter().position(|t| *t == name);
+    }
+}
+
+/// Walks a program in the order the type checker does, binding every use of a name to its
+/// declaration
+struct Resolver<'a> {
+    tokens: &'a [(Token, Span)],
+    /// Where the `}` closing each `{` ends, by where the `{` starts
+    closing: HashMap<Pos, Pos>,
+    end: Pos,
+    vars: Vec<(String, usize)>,
+    fns: Vec<(String, usize)>,
+    symbols: Vec<Symbol>,
+    references: Vec<(Span, usize)>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(tokens: &'a [(Token, Span)], end: Pos) -> Resolver<'a> {
+        let mut closing = HashMap::new();
+        let mut open = Vec::new();
+        for (token, span) in tokens {
+            match token {
+                Token::Lbrace => open.push(span.start),
+                Token::Rbrace => {
+                    if let Some(start) = open.pop() {
+                        closing.insert(start, span.end);
+                    }
+                }
+                _ => {}
+            }
+        }
+        return Resolver {
+            tokens,
+            closing,
+            end,
+            vars: vec![],
+            fns: vec![],
+            symbols: vec![],
+            references: vec![],
+        };
+    }
+
+    /// The index of the first token starting at or after an offset
+    fn index(&self, pos: Pos) -> usize {
+        return self.tokens.partition_point(|(_, span)| span.start < pos);
+    }
+
+    /// The token starting at an offset
+    fn token_at(&self, pos: Pos) -> Span {
+        match self.tokens.get(self.index(pos)) {
+            Some((_, span)) if span.start == pos => return *span,
+            _ => return Span::at(pos),
+        }
+    }
+
+    /// The first name at or after an offset, as in the declaration starting there
+    fn name_after(&self, pos: Pos) -> Span {
+        return self.tokens[self.index(pos)..]
+            .iter()
+            .find(|(token, _)| matches!(token, Token::Id(_)))
+            .map_or(Span::at(pos), |(_, span)| *span);
+    }
+
+    fn block_span(&self, block: &Block) -> Span {
+        return Span::new(
+            block.pos,
+            self.closing.get(&block.pos).copied().unwrap_or(self.end),
+        );
+    }
+
+    /// Where the statement around an offset ends: after its `;`, or where the block holding it
+    /// closes
+    fn statement_end(&self, pos: Pos) -> Pos {
+        let mut depth = 0;
+        for (token, span) in &self.tokens[self.index(pos)..] {
+            match token {
+                Token::Lbrace | Token::Lparen | Token::Lbracket => depth += 1,
+                Token::Rbrace | Token::Rparen | Token::Rbracket if depth == 0 => return span.start,
+                Token::Rbrace | Token::Rparen | Token::Rbracket => depth -= 1,
+                Token::Semicolon if depth == 0 => return span.end,
+                _ => {}
+            }
+        }
+        return self.end;
+    }
+
+    fn declare(&mut self, name: &str, kind: SymbolKind, span: Span, scope: Span, detail: String) {
+        let index = self.symbols.len();
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            span,
+            scope,
+            detail,
+        });
+        match kind {
+            SymbolKind::Function => self.fns.push((name.to_string(), index)),
+            _ => self.vars.push((name.to_string(), index)),
+        }
+    }
+
+    fn refer(&mut self, name: &str, span: Span, function: bool) {
+        let names = if function { &self.fns } else { &self.vars };
+        if let Some((_, index)) = names.iter().rev().find(|(n, _)| n == name) {
+            self.references.push((span, *index));
+        }
+    }
+
+    /// Resolve the statements of a block and the expression ending it, whose declarations can be
+    /// used up to `end`
+    fn stmts(&mut self, stmts: &[Stmt], result: Option<&Expr>, end: Pos) {
+        let mut i = 0;
+        while i < stmts.len() {
+            // Consecutive function declarations can see each other
+            let group_start = i;
+            while let Some(StmtKind::Fn(decl)) = stmts.get(i).map(|stmt| &stmt.kind) {
+                let span = self.name_after(decl.pos);
+                let scope = Span::new(stmts[group_start].pos, end);
+                self.declare(
+                    &decl.name,
+                    SymbolKind::Function,
+                    span,
+                    scope,
+                    fn_detail(decl),
+                );
+                i += 1;
+            }
+            if i > group_start {
+                for stmt in &stmts[group_start..i] {
+                    if let StmtKind::Fn(decl) = &stmt.kind {
+                        self.function(decl);
+                    }
+                }
+            } else {
+                self.stmt(&stmts[i], end);
+                i += 1;
+            }
+        }
+        if let Some(expr) = result {
+            self.expr(expr);
+        }
+    }
+
+    fn function(&mut self, decl: &FnDecl) {
+        let (vars, fns) = (self.vars.len(), self.fns.len());
+        let scope = self.block_span(&decl.body);
+        for param in &decl.params {
+            let span = self.name_after(param.pos);
+            let detail = var_detail(&param.name, param.mutable, Some(param.ty));
+            self.declare(&param.name, SymbolKind::Parameter, span, scope, detail);
+        }
+        self.block(&decl.body);
+        self.vars.truncate(vars);
+        self.fns.truncate(fns);
+    }
+
+    fn block(&mut self, block: &Block) {
+        let (vars, fns) = (self.vars.len(), self.fns.len());
+        let end = self.block_span(block).end;
+        self.stmts(&block.stmts, block.result.as_deref(), end);
+        self.vars.truncate(vars);
+        self.fns.truncate(fns);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, end: Pos) {
+        match &stmt.kind {
+            StmtKind::Let {
+                name,
+                mutable,
+                ty,
+                init,
+                ..
+            } => {
+                self.expr(init);
+                let span = self.name_after(stmt.pos);
+                let scope = Span::new(self.statement_end(init.pos), end);
+                let detail = var_detail(name, *mutable, ty.or(init.ty.get().copied()));
+                self.declare(name, SymbolKind::Variable, span, scope, detail);
+            }
+            StmtKind::Assign { name, value } => {
+                self.refer(name, self.token_at(stmt.pos), false);
+                self.expr(value);
+            }
+            StmtKind::While { cond, body } => {
+                self.expr(cond);
+                self.block(body);
+            }
+            StmtKind::For {
+                var, lo, hi, body, ..
+            } => {
+                self.expr(lo);
+                self.expr(hi);
+                let vars = self.vars.len();
+                let span = self.name_after(stmt.pos);
+                let scope = self.block_span(body);
+                let detail = var_detail(var, false, Some(Type::Int));
+                self.declare(var, SymbolKind::Variable, span, scope, detail);
+                self.block(body);
+                self.vars.truncate(vars);
+            }
+            StmtKind::Fn(_) => unreachable!("functions are resolved in groups"),
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Error => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        let span = self.token_at(expr.pos);
+        match &expr.kind {
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Str(_)
+            | ExprKind::Error => {}
+            ExprKind::Var(name) => self.refer(name, span, false),
+            ExprKind::Unary(_, operand) => self.expr(operand),
+            ExprKind::Binary(left, _, right) => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::Call(name, args) => {
+                self.refer(name, span, true);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::If {
+                branches,
+                else_block,
+            } => {
+                for (cond, block) in branches {
+                    self.expr(cond);
+                    self.block(block);
+                }
+                if let Some(block) = else_block {
+                    self.block(block);
+                }
+            }
+        }
+    }
+}
+
+fn var_detail(name: &str, mutable: bool, ty: Option<Type>) -> String {
+    let name = if mutable {
+        format!("mut {}", name)
+    } else {
+        name.to_string()
+    };
+    match ty {
+        Some(ty) => return format!("{}: {}", name, ty),
+        None => return name,
+    }
+}
+
+fn fn_detail(decl: &FnDecl) -> String {
+    let params: Vec<String> = decl
+        .params
+        .iter()
+        .map(|param| var_detail(&param.name, param.mutable, Some(param.ty)))
+        .collect();
+    return format!("fn {}({}): {}", decl.name, params.join(", "), decl.result);
+}
+
+/// The type of the first expression positioned at `pos` in statements and the expression ending
+/// them, in the order they are written, if the type checker reached it
+fn expr_type_at(stmts: &[Stmt], result: Option<&Expr>, pos: Pos) -> Option<Type> {
+    let block = |block: &Block| expr_type_at(&block.stmts, block.result.as_deref(), pos);
+    for stmt in stmts {
+        let ty = match &stmt.kind {
+            StmtKind::Let { init: expr, .. }
+            | StmtKind::Assign { value: expr, .. }
+            | StmtKind::Expr(expr) => type_at(expr, pos),
+            StmtKind::While { cond, body } => type_at(cond, pos).or_else(|| block(body)),
+            StmtKind::For { lo, hi, body, .. } => type_at(lo, pos)
+                .or_else(|| type_at(hi, pos))
+                .or_else(|| block(body)),
+            StmtKind::Fn(decl) => block(&decl.body),
+            StmtKind::Error => None,
+        };
+        if ty.is_some() {
+            return ty;
+        }
+    }
+    return result.and_then(|expr| type_at(expr, pos));
+}
+
+/// The type of an expression or of the first one inside it positioned at `pos`
+fn type_at(expr: &Expr, pos: Pos) -> Option<Type> {
+    if expr.pos == pos {
+        if let Some(&ty) = expr.ty.get() {
+            return Some(ty);
+        }
+    }
+    let block = |block: &Block| expr_type_at(&block.stmts, block.result.as_deref(), pos);
+    match &expr.kind {
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Str(_)
+        | ExprKind::Var(_)
+        | ExprKind::Error => return None,
+        ExprKind::Unary(_, operand) => return type_at(operand, pos),
+        ExprKind::Binary(left, _, right) => {
+            return type_at(left, pos).or_else(|| type_at(right, pos))
+        }
+        ExprKind::Call(_, args) => return args.iter().find_map(|arg| type_at(arg, pos)),
+        ExprKind::If {
+            branches,
+            else_block,
+        } => {
+            return branches
+                .iter()
+                .find_map(|(cond, body)| type_at(cond, pos).or_else(|| block(body)))
+                .or_else(|| else_block.as_ref().and_then(block))
+        }
+    }
+}
+
+/// An open document, analysed when it is opened or changed
+struct Document {
+    text: String,
+    /// Where each line starts
+    lines: Vec<Pos>,
+    analysis: Analysis,
+    /// The answer to a request for the semantic tokens
+    semantic_tokens: Json,
+}
+
+impl Document {
+    fn new(text: String) -> Document {
+        let lines = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let analysis = Analysis::new(&text);
+        let mut document = Document {
+            text,
+            lines,
+            analysis,
+            semantic_tokens: Json::Null,
+        };
+        document.semantic_tokens = document.highlight();
+        return document;
+    }
+
+    /// The line and UTF-16 column of an offset
+    fn position(&self, offset: Pos) -> (usize, usize) {
+        let line = self.lines.partition_point(|start| *start <= offset) - 1;
+        let column = self.text[self.lines[line]..offset].encode_utf16().count();
+        return (line, column);
+    }
+
+    /// The offset of a line and UTF-16 column, kept within the line
+    fn offset(&self, line: usize, column: usize) -> Pos {
+        let Some(start) = self.lines.get(line) else {
+            return self.text.len();
+        };
+        let mut units = 0;
+        for (i, c) in self.text[*start..].char_indices() {
+            if units >= column || c == '\n' {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        return self.text.len();
+    }
+
+    fn position_json(&self, offset: Pos) -> Json {
+        let (line, column) = self.position(offset);
+        return Json::object(vec![("line", line.into()), ("character", column.into())]);
+    }
+
+    fn range(&self, span: Span) -> Json {
+        return Json::object(vec![
+            ("start", self.position_json(span.start)),
+            ("end", self.position_json(span.end)),
+        ]);
+    }
+
+    fn location(&self, uri: &str, span: Span) -> Json {
+        return Json::object(vec![("uri", uri.into()), ("range", self.range(span))]);
+    }
+
+    /// A diagnostic as the protocol has it. The secondary labels become related information, and
+    /// the notes and help are added to the message
+    fn diagnostic(&self, uri: &str, diagnostic: &Diagnostic) -> Json {
+        let primary = diagnostic
+            .labels
+            .iter()
+            .find(|label| label.primary)
+            .or(diagnostic.labels.first());
+        let span = primary.map_or(Span::at(0), |label| resolve(&self.text, label.span));
+        let mut message = diagnostic.message.clone();
+        for note in &diagnostic.notes {
+            message.push_str(&format!("\nnote: {}", note));
+        }
+        for help in &diagnostic.help {
+            message.push_str(&format!("\nhelp: {}", help));
+        }
+        let severity: usize = match diagnostic.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+            Severity::Note => 3,
+        };
+        let related = diagnostic
+            .labels
+            .iter()
+            .filter(|label| !label.primary)
+            .map(|label| {
+                let span = resolve(&self.text, label.span);
+                return Json::object(vec![
+                    ("location", self.location(uri, span)),
+                    ("message", label.message.as_str().into()),
+                ]);
+            })
+            .collect::<Vec<_>>();
+        return Json::object(vec![
+            ("range", self.range(span)),
+            ("severity", severity.into()),
+            ("code", diagnostic.code.map_or(Json::Null, Json::from)),
+            ("source", SERVER_NAME.into()),
+            ("message", message.into()),
+            ("relatedInformation", related.into()),
+        ]);
+    }
+
+    /// The highlighted tokens, each as its line and column relative to the one before, its
+    /// length and its index in `TOKEN_TYPES`
+    fn highlight(&self) -> Json {
+        let mut data = Vec::new();
+        let (mut last_line, mut last_column) = (0, 0);
+        let analysis = &self.analysis;
+        for ((_, span), ty) in analysis.tokens.iter().zip(&analysis.token_types) {
+            let Some(ty) = *ty else {
+                continue;
+            };
+            let (line, column) = self.position(span.start);
+            // Tokens may not run across lines, so a string doing so is cut at the first
+            let text = self.text[span.start..span.end].split('\n').next().unwrap();
+            let column_delta = if line == last_line {
+                column - last_column
+            } else {
+                column
+            };
+            for n in [
+                line - last_line,
+                column_delta,
+                text.encode_utf16().count(),
+                ty,
+                0,
+            ] {
+                data.push(Json::from(n));
+            }
+            (last_line, last_column) = (line, column);
+        }
+        return Json::object(vec![("data", data.into())]);
+    }
+}
+
+/// The state of a server: the open documents, by their URI
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    exited: bool,
+}
+
+type RequestResult = Result<Json, (i32, String)>;
+
+impl Server {
+    pub fn new() -> Server {
+        return Server::default();
+    }
+
+    /// Answer one message from the client with the messages to send back
+    pub fn handle(&mut self, text: &str) -> Vec<Json> {
+        let message = match Json::parse(text) {
+            Ok(message) => message,
+            Err(error) => return vec![error_response(&Json::Null, PARSE_ERROR, &error)],
+        };
+        // A message without a method is a response, and the server sends no requests
+        let Some(method) = message.get("method").as_str() else {
+            return vec![];
+        };
+        let id = message.get("id");
+        let params = message.get("params");
+        let result = match method {
+            "initialize" => Ok(capabilities()),
+            "initialized" => return vec![],
+            _ if self.shutdown && method != "exit" => {
+                Err((INVALID_REQUEST, "the server is shutting down".to_string()))
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "exit" => {
+                self.exited = true;
+                return vec![];
+            }
+            "textDocument/didOpen" => {
+                let document = params.get("textDocument");
+                return self.update(document.get("uri"), document.get("text"));
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+                let text = changes.last().map_or(&NULL, |change| change.get("text"));
+                return self.update(params.get("textDocument").get("uri"), text);
+            }
+            "textDocument/didClose" => {
+                let uri = params.get("textDocument").get("uri");
+                if let Some(uri) = uri.as_str() {
+                    self.documents.remove(uri);
+                }
+                return vec![publish_diagnostics(uri, vec![])];
+            }
+            "textDocument/semanticTokens/full" => self
+                .document(params)
+                .map(|(_, document)| document.semantic_tokens.clone()),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        };
+        if *id == Json::Null {
+            return vec![];
+        }
+        match result {
+            Ok(result) => {
+                return vec![Json::object(vec![
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id.clone()),
+                    ("result", result),
+                ])]
+            }
+            Err((code, message)) => return vec![error_response(id, code, &message)],
+        }
+    }
+
+    /// Analyse the new text of a document and publish its diagnostics
+    fn update(&mut self, uri: &Json, text: &Json) -> Vec<Json> {
+        let (Some(name), Some(text)) = (uri.as_str(), text.as_str()) else {
+            return vec![];
+        };
+        let document = Document::new(text.to_string());
+        let diagnostics = document
+            .analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| document.diagnostic(name, diagnostic))
+            .collect();
+        self.documents.insert(name.to_string(), document);
+        return vec![publish_diagnostics(uri, diagnostics)];
+    }
+
+    fn document<'a>(&'a self, params: &'a Json) -> Result<(&'a str, &'a Document), (i32, String)> {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+        match self.documents.get(uri) {
+            Some(document) => return Ok((uri, document)),
+            None => return Err((INVALID_PARAMS, format!("`{}` is not open", uri))),
+        }
+    }
+
+    /// The document of a request and the offset of the position in it
+    fn document_position<'a>(
+        &'a self,
+        params: &'a Json,
+    ) -> Result<(&'a str, &'a Document, Pos), (i32, String)> {
+        let (uri, document) = self.document(params)?;
+        let position = params.get("position");
+        let (Some(line), Some(column)) = (
+            position.get("line").as_usize(),
+            position.get("character").as_usize(),
+        ) else {
+            return Err((INVALID_PARAMS, "expected a position".to_string()));
+        };
+        return Ok((uri, document, document.offset(line, column)));
+    }
+
+    fn definition(&self, params: &Json) -> RequestResult {
+        let (uri, document, offset) = self.document_position(params)?;
+        match document.analysis.symbol_at(offset) {
+            Some(symbol) => return Ok(document.location(uri, symbol.span)),
+            None => return Ok(Json::Null),
+        }
+    }
+
+    fn hover(&self, params: &Json) -> RequestResult {
+        let (_, document, offset) = self.document_position(params)?;
+        match document.analysis.hover(offset) {
+            Some(text) => {
+                let contents =
+                    Json::object(vec![("kind", "plaintext".into()), ("value", text.into())]);
+                return Ok(Json::object(vec![("contents", contents)]));
+            }
+            None => return Ok(Json::Null),
+        }
+    }
+
+    fn completion(&self, params: &Json) -> RequestResult {
+        let (_, document, offset) = self.document_position(params)?;
+        let items = document
+            .analysis
+            .completions(offset)
+            .into_iter()
+            .map(|completion| {
+                return Json::object(vec![
+                    ("label", completion.label.into()),
+                    ("kind", completion.kind.into()),
+                    ("detail", completion.detail.into()),
+                ]);
+            })
+            .collect::<Vec<_>>();
+        return Ok(items.into());
+    }
+}
+
+fn capabilities() -> Json {
+    let token_types: Vec<Json> = TOKEN_TYPES.iter().map(|ty| Json::from(*ty)).collect();
+    let legend = Json::object(vec![
+        ("tokenTypes", token_types.into()),
+        ("tokenModifiers", Json::Array(vec![])),
+    ]);
+    let capabilities = Json::object(vec![
+        ("textDocumentSync", FULL_SYNC.into()),
+        ("hoverProvider", true.into()),
+        ("definitionProvider", true.into()),
+        ("completionProvider", Json::object(vec![])),
+        (
+            "semanticTokensProvider",
+            Json::object(vec![("legend", legend), ("full", true.into())]),
+        ),
+    ]);
+    return Json::object(vec![
+        ("capabilities", capabilities),
+        (
+            "serverInfo",
+            Json::object(vec![("name", SERVER_NAME.into())]),
+        ),
+    ]);
+}
+
+fn publish_diagnostics(uri: &Json, diagnostics: Vec<Json>) -> Json {
+    return Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            Json::object(vec![
+                ("uri", uri.clone()),
+                ("diagnostics", diagnostics.into()),
+            ]),
+        ),
+    ]);
+}
+
+fn error_response(id: &Json, code: i32, message: &str) -> Json {
+    return Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id.clone()),
+        (
+            "error",
+            Json::object(vec![("code", code.into()), ("message", message.into())]),
+        ),
+    ]);
+}
+
+/// Read the body of one message, or `None` at the end of the input
+fn read_message(input: &mut dyn BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a valid Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+}
+
+fn write_message(output: &mut dyn Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    return output.flush();
+}
+
+/// Serve a client until it sends `exit` or its input ends, returning the exit code the protocol
+/// asks for: 0 if the client shut the server down first, 1 if not
+pub fn serve(input: &mut (dyn BufRead + Send), output: &mut (dyn Write + Send)) -> io::Result<i32> {
+    return std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, move || {
+                let mut server = Server::new();
+                while let Some(body) = read_message(input)? {
+                    for message in server.handle(&body) {
+                        write_message(output, &message)?;
+                    }
+                    if server.exited {
+                        break;
+                    }
+                }
+                return Ok(if server.shutdown { 0 } else { 1 });
+            })?;
+        return thread.join().unwrap();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame the messages of a client and read back the messages the server answers with
+    fn session(messages: &[Json]) -> (i32, Vec<Json>) {
+        let input: String = messages
+            .iter()
+            .map(|message| {
+                let body = message.to_string();
+                return format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+            })
+            .collect();
+        let mut output = Vec::new();
+        let code = serve(&mut input.as_bytes(), &mut output).unwrap();
+        let mut output = &output[..];
+        let mut answers = Vec::new();
+        while let Some(body) = read_message(&mut output).unwrap() {
+            answers.push(Json::parse(&body).unwrap());
+        }
+        return (code, answers);
+    }
+
+    fn request(id: usize, method: &str, params: Json) -> Json {
+        return Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ]);
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        return Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ]);
+    }
+
+    fn at(line: usize, character: usize) -> Json {
+        return Json::object(vec![
+            (
+                "textDocument",
+                Json::object(vec![("uri", "file:///a".into())]),
+            ),
+            (
+                "position",
+                Json::object(vec![("line", line.into()), ("character", character.into())]),
+            ),
+        ]);
+    }
+
+    /// The offset of the `n`th occurrence of a piece of the source, counted from 0
+    fn find(source: &str, piece: &str, n: usize) -> Pos {
+        return source.match_indices(piece).nth(n).unwrap().0;
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let text =
+            r#" {"a": [1, -2.5, 1e3, true, null], "b\n\"": "\u00e9\ud83d\ude00/", "c": {}} "#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.get("a").as_array().unwrap()[2], Json::Number(1000.0));
+        assert_eq!(json.get("b\n\"").as_str(), Some("é😀/"));
+        assert_eq!(json.get("missing"), &Json::Null);
+        assert_eq!(
+            json.to_string(),
+            r#"{"a":[1,-2.5,1000,true,null],"b\n\"":"é😀/","c":{}}"#
+        );
+        assert_eq!(Json::parse(&json.to_string()), Ok(json));
+        for bad in ["", "[1,", "{\"a\" 1}", "\"\\q\"", "[1] 2", "tru"] {
+            assert!(Json::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_analysis_binds_names_to_declarations() {
+        let source = "\
+let x = 1.5;
+fn even(n: int): bool { if n == 0 { True } else { odd(n - 1) } }
+fn odd(n: int): bool { if n == 0 { False } else { even(n - 1) } }
+let mut y = x;
+for i = 0 : 3 { let x = i; y = y + 1.0; }
+print(even(4), x);
+";
+        let analysis = Analysis::new(source);
+        // Only the `x` of the loop is never read
+        let warnings: Vec<(Severity, &str)> = analysis
+            .diagnostics
+            .iter()
+            .map(|d| (d.severity, d.message.as_str()))
+            .collect();
+        assert_eq!(warnings, [(Severity::Warning, "unused variable `x`")]);
+        let declared = |offset| analysis.symbol_at(offset).unwrap().span.start;
+        // `odd` is called before it is declared, from the same group
+        assert_eq!(declared(find(source, "odd", 0)), find(source, "odd", 1));
+        assert_eq!(declared(find(source, "n ==", 0)), find(source, "n:", 0));
+        // The `x` inside the loop shadows the first only there
+        assert_eq!(declared(find(source, "x", 1)), find(source, "x", 0));
+        assert_eq!(declared(find(source, "x", 3)), find(source, "x", 0));
+        assert_eq!(declared(find(source, "y = y", 0)), find(source, "y", 0));
+        assert_eq!(
+            analysis.hover(find(source, "even", 2)).unwrap(),
+            "fn even(n: int): bool"
+        );
+        assert_eq!(
+            analysis.hover(find(source, "y =", 0)).unwrap(),
+            "mut y: float"
+        );
+        assert_eq!(analysis.hover(find(source, "i;", 0)).unwrap(), "i: int");
+        assert_eq!(analysis.hover(find(source, "==", 0)).unwrap(), "bool");
+        assert_eq!(analysis.hover(find(source, "1.0", 0)).unwrap(), "float");
+        assert_eq!(analysis.hover(find(source, "{", 0)), None);
+    }
+
+    #[test]
+    fn test_completions_follow_scopes() {
+        let source = "let a = 1;\nfn f(b: int): int {\n  let c = b;\n  c\n}\nlet d = a;\n";
+        let analysis = Analysis::new(source);
+        let names = |offset| {
+            return analysis
+                .completions(offset)
+                .into_iter()
+                .filter(|completion| completion.kind != KEYWORD_ITEM)
+                .map(|completion| completion.label)
+                .collect::<Vec<_>>();
+        };
+        assert_eq!(
+            names(find(source, "  c\n", 0)),
+            ["c", "b", "f", "a", "print"]
+        );
+        // A variable is not in scope in its own initializer
+        assert_eq!(names(find(source, "b;", 0)), ["b", "f", "a", "print"]);
+        assert_eq!(names(source.len()), ["d", "f", "a", "print"]);
+        let completions = analysis.completions(0);
+        assert!(completions
+            .iter()
+            .any(|c| c.label == "while" && c.kind == KEYWORD_ITEM));
+        // The statements around a syntax error still declare their names
+        let source = "let total = 1;\nlet t = ;\nfn f() { let inner = 2; }\nlet u = tot";
+        let broken = Analysis::new(source);
+        assert_eq!(broken.diagnostics.len(), 2);
+        assert_eq!(
+            broken.completions(source.len())[..4]
+                .iter()
+                .map(|c| &c.label)
+                .collect::<Vec<_>>(),
+            ["f", "t", "total", "print"]
+        );
+    }
+
+    #[test]
+    fn test_semantic_tokens() {
+        let document =
+            Document::new("fn f(n: int): int {\n  n * 2 // é\n}\nprint(f(1), \"é\");".into());
+        let data: Vec<usize> = document
+            .semantic_tokens
+            .get("data")
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|n| n.as_usize().unwrap())
+            .collect();
+        let (keyword, ty, function, parameter, number, string, operator, comment) =
+            (0, 1, 2, 4, 5, 6, 7, 8);
+        assert_eq!(
+            data.chunks(5).collect::<Vec<_>>(),
+            [
+                [0, 0, 2, keyword, 0],
+                [0, 3, 1, function, 0],
+                [0, 2, 1, parameter, 0],
+                [0, 3, 3, ty, 0],
+                [0, 6, 3, ty, 0],
+                [1, 2, 1, parameter, 0],
+                [0, 2, 1, operator, 0],
+                [0, 2, 1, number, 0],
+                [0, 2, 4, comment, 0],
+                [2, 0, 5, function, 0],
+                [0, 6, 1, function, 0],
+                [0, 2, 1, number, 0],
+                [0, 4, 3, string, 0],
+            ]
+        );
+        // A document whose lexing fails keeps the tokens before the error
+        let document = Document::new("let s = \"open".into());
+        assert_eq!(document.analysis.tokens.len(), 3);
+        assert_eq!(
+            document.analysis.diagnostics[0].message,
+            "unterminated string literal"
+        );
+    }
+
+    #[test]
+    fn test_scripted_session() {
+        let uri = "file:///a";
+        let open = |text: &str| {
+            let document = Json::object(vec![
+                ("uri", uri.into()),
+                ("languageId", "mcir".into()),
+                ("version", 1.into()),
+                ("text", text.into()),
+            ]);
+            return notification(
+                "textDocument/didOpen",
+                Json::object(vec![("textDocument", document)]),
+            );
+        };
+        let change = notification(
+            "textDocument/didChange",
+            Json::object(vec![
+                ("textDocument", Json::object(vec![("uri", uri.into())])),
+                (
+                    "contentChanges",
+                    vec![Json::object(vec![("text", "let 😀x = 1;\nx + y".into())])].into(),
+                ),
+            ]),
+        );
+        let (code, answers) = session(&[
+            request(1, "initialize", Json::object(vec![])),
+            notification("initialized", Json::object(vec![])),
+            open("let x = 1;\nlet y = x;\n"),
+            request(2, "textDocument/definition", at(1, 8)),
+            request(3, "textDocument/hover", at(1, 4)),
+            change,
+            request(4, "textDocument/completion", at(1, 0)),
+            request(5, "textDocument/frobnicate", Json::object(vec![])),
+            request(6, "shutdown", Json::Null),
+            notification("exit", Json::Null),
+            request(7, "shutdown", Json::Null),
+        ]);
+        assert_eq!(code, 0);
+        assert_eq!(answers.len(), 8);
+        let capabilities = answers[0].get("result").get("capabilities");
+        assert_eq!(capabilities.get("textDocumentSync"), &Json::from(FULL_SYNC));
+        assert_eq!(
+            capabilities
+                .get("semanticTokensProvider")
+                .get("legend")
+                .get("tokenTypes")
+                .as_array()
+                .unwrap()
+                .len(),
+            TOKEN_TYPES.len()
+        );
+        let published = answers[1].get("params");
+        assert_eq!(published.get("uri").as_str(), Some(uri));
+        let warnings = published.get("diagnostics").as_array().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].get("severity"), &Json::from(2usize));
+        assert_eq!(
+            warnings[0].get("message").as_str(),
+            Some("unused variable `y`\nhelp: if this is intentional, name it `_y`")
+        );
+        assert_eq!(
+            answers[2].get("result").to_string(),
+            r#"{"uri":"file:///a","range":{"start":{"line":0,"character":4},"end":{"line":0,"character":5}}}"#
+        );
+        assert_eq!(
+            answers[3]
+                .get("result")
+                .get("contents")
+                .get("value")
+                .as_str(),
+            Some("y: int")
+        );
+        // The emoji is a lexical error, taking two UTF-16 units to the left of where it is
+        let diagnostics = answers[4].get("params").get("diagnostics");
+        assert_eq!(
+            diagnostics.to_string(),
+            format!(
+                "[{{\"range\":{{\"start\":{{\"line\":0,\"character\":4}},\"end\":{{\"line\":0,\"character\":6}}}},\"severity\":1,\"code\":\"E0001\",\"source\":\"{}\",\"message\":\"unexpected character `😀`\",\"relatedInformation\":[]}}]",
+                SERVER_NAME
+            )
+        );
+        let labels: Vec<&str> = answers[5]
+            .get("result")
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item.get("label").as_str().unwrap())
+            .collect();
+        assert_eq!(labels[..3], ["print", "if", "else"]);
+        assert_eq!(
+            answers[6].get("error").get("code"),
+            &Json::from(METHOD_NOT_FOUND)
+        );
+        assert_eq!(answers[7].get("result"), &Json::Null);
+    }
+
+    #[test]
+    fn test_type_errors_are_published_with_related_information() {
+        let (code, answers) = session(&[notification(
+            "textDocument/didOpen",
+            Json::object(vec![(
+                "textDocument",
+                Json::object(vec![
+                    ("uri", "file:///b".into()),
+                    ("text", "let x = 1;\nx = 2;".into()),
+                ]),
+            )]),
+        )]);
+        assert_eq!(code, 1);
+        let diagnostic = &answers[0]
+            .get("params")
+            .get("diagnostics")
+            .as_array()
+            .unwrap()[0];
+        assert_eq!(
+            diagnostic.get("message").as_str(),
+            Some(
+                "cannot assign twice to immutable variable `x`\nhelp: make `x` mutable by declaring it with `mut`"
+            )
+        );
+        assert_eq!(diagnostic.get("code").as_str(), Some("E0002"));
+        assert_eq!(
+            diagnostic.get("range").get("start").to_string(),
+            r#"{"line":1,"character":0}"#
+        );
+        let related = &diagnostic.get("relatedInformation").as_array().unwrap()[0];
+        assert_eq!(
+            related.get("message").as_str(),
+            Some("`x` is declared here")
+        );
+        assert_eq!(
+            related.get("location").get("range").get("end").to_string(),
+            r#"{"line":0,"character":3}"#
+        );
+    }
+}
@@ -1,10 +1,48 @@
+// The chapters favour explicit `return`s
+#![allow(clippy::needless_return)]
+
+mod bytecode;
+mod c;
 mod chapter_1;
+mod chapter_10;
+mod chapter_11;
+mod chapter_12;
 mod chapter_2;
+mod chapter_3;
+mod chapter_4;
+mod chapter_5;
+mod chapter_6;
+mod chapter_7;
+mod chapter_8;
+mod chapter_9;
+mod diagnostics;
+mod driver;
+mod formatter;
+mod grammar;
+mod interpreter;
+mod ir_interpreter;
+mod lalr;
+#[cfg(test)]
+#[rustfmt::skip]
+mod lalr_expr;
+mod linear_scan;
+mod llvm;
+mod lsp;
+mod repl;
+mod riscv;
+mod test_programs;
+mod wasm;
 
-use crate::chapter_1::*;
-use crate::chapter_2::*;
+use std::process::exit;
 
+/// Compile or run a program, see `driver::USAGE`
 fn main() {
-    test_exercise_1();
-    test_exercise_2();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = driver::main(
+        &args,
+        &mut std::io::stdin(),
+        &mut std::io::stdout(),
+        &mut std::io::stderr(),
+    );
+    exit(code);
 }
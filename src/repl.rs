@@ -0,0 +1,378 @@
+/// An interactive read-eval-print loop for the language
+///
+/// Each input is parsed, type checked and run by the reference interpreter as a program of its
+/// own, keeping the `let` bindings and functions of the inputs before it. Positions in an input
+/// start after those of the inputs before it, so an error in a function of an earlier input is
+/// shown in the source of that input. An input continues
+/// over as many lines as it takes to close its brackets, and the value of an input ending in
+/// an expression is shown with its type. Inputs starting with `:` are meta-commands, see `HELP`.
+use std::io::{self, BufRead, Write};
+
+use crate::chapter_2::{tokenize, Token};
+use crate::chapter_3::{parse_at, ParseError};
+use crate::chapter_4::*;
+use crate::chapter_5::TypeChecker;
+use crate::diagnostics::{Diagnostic, Span};
+use crate::driver::{dump_ast, dump_tokens};
+use crate::interpreter::{Session, Value, STACK_SIZE};
+
+const PROMPT: &str = "> ";
+const CONTINUATION: &str = ". ";
+
+pub const HELP: &str = "\
+:tokens <code>  show the tokens of the code
+:ast <code>     show the syntax tree of the code
+:type <code>    show the type of an expression, or of a variable or function
+:help           show this help
+:quit           leave, as does the end of the input
+";
+
+/// The state kept from one input to the next
+#[derive(Default)]
+pub struct Repl {
+    checker: TypeChecker,
+    session: Session,
+    /// The code of every input that was run or checked, with the position of its first byte
+    inputs: Vec<(Pos, String)>,
+}
+
+/// Whether to read another input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Next {
+    Continue,
+    Quit,
+}
+
+/// Run the loop until the input ends or `:quit`, prompting and answering on `out`
+pub fn repl(input: &mut (dyn BufRead + Send), out: &mut (dyn Write + Send)) -> io::Result<()> {
+    return std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, move || {
+                let mut repl = Repl::new();
+                while let Some(text) = read_input(input, out)? {
+                    if repl.eval(&text, out)? == Next::Quit {
+                        break;
+                    }
+                }
+                return Ok(());
+            })?;
+        return thread.join().unwrap();
+    });
+}
+
+/// Read lines until the brackets they open are closed, or `None` at the end of the input
+fn read_input(input: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<Option<String>> {
+    let mut text = String::new();
+    loop {
+        write!(
+            out,
+            "{}",
+            if text.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION
+            }
+        )?;
+        out.flush()?;
+        if input.read_line(&mut text)? == 0 {
+            if text.is_empty() {
+                writeln!(out)?;
+                return Ok(None);
+            }
+            return Ok(Some(text));
+        }
+        if open_brackets(&text) <= 0 {
+            return Ok(Some(text));
+        }
+    }
+}
+
+/// How many more brackets the text opens than it closes
+fn open_brackets(text: &str) -> i32 {
+    // Text that does not lex is complete, for the error to be reported
+    return tokenize(text)
+        .unwrap_or_default()
+        .iter()
+        .map(|token| match token {
+            Token::Lbrace | Token::Lparen | Token::Lbracket => 1,
+            Token::Rbrace | Token::Rparen | Token::Rbracket => -1,
+            _ => 0,
+        })
+        .sum();
+}
+
+/// How the REPL shows a value: strings quoted, so they can be told from the other values
+fn show(value: &Value) -> String {
+    match value {
+        Value::Str(s) => return format!("{:?}", s),
+        value => return value.to_string(),
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        return Repl::default();
+    }
+
+    /// Answer one input, writing what the program prints and any errors to `out`
+    pub fn eval(&mut self, text: &str, out: &mut dyn Write) -> io::Result<Next> {
+        let text = text.trim();
+        let (command, code) = match text.strip_prefix(':') {
+            Some(meta) => meta.split_once(char::is_whitespace).unwrap_or((meta, "")),
+            None => ("", text),
+        };
+        match command {
+            "" if code.is_empty() => {}
+            "" => self.run(code, out)?,
+            "tokens" => match dump_tokens(code) {
+                Ok(tokens) => write!(out, "{}", tokens)?,
+                Err(e) => report(code, e.into(), out)?,
+            },
+            "ast" => match parse_input(code, 0) {
+                Ok(program) => write!(out, "{}", dump_ast(&program))?,
+                Err(e) => report(code, e.into(), out)?,
+            },
+            "type" => self.show_type(code.trim(), out)?,
+            "help" => write!(out, "{}", HELP)?,
+            "quit" => return Ok(Next::Quit),
+            _ => writeln!(out, "unknown command `:{}`, try `:help`", command)?,
+        }
+        return Ok(Next::Continue);
+    }
+
+    /// Check and run code, keeping its bindings if both succeed
+    fn run(&mut self, code: &str, out: &mut dyn Write) -> io::Result<()> {
+        let base = self.add_input(code);
+        let program = match parse_input(code, base) {
+            Ok(program) => program,
+            Err(e) => return self.report(e.into(), out),
+        };
+        let mut checker = self.checker.clone();
+        if let Err(e) = checker.check_program(&program) {
+            return self.report(e.into(), out);
+        }
+        for warning in checker.take_warnings() {
+            self.report(warning.into(), out)?;
+        }
+        match self.session.run(&program, out) {
+            Ok(value) => {
+                self.checker = checker;
+                if let Some(value) = value.filter(|v| *v != Value::Unit) {
+                    let ty = last_expr(&program).unwrap().ty();
+                    writeln!(out, "{} : {}", show(&value), ty)?;
+                }
+                return Ok(());
+            }
+            Err(e) => return self.report(e.into(), out),
+        }
+    }
+
+    /// Show the type of a variable or function in scope, or of what the code declares or
+    /// computes last, without running it
+    fn show_type(&mut self, code: &str, out: &mut dyn Write) -> io::Result<()> {
+        if let Some(ty) = self.checker.var_type(code) {
+            return writeln!(out, "{}", ty);
+        }
+        if let Some(ty) = self.checker.fn_type(code) {
+            return writeln!(out, "{}", ty);
+        }
+        let base = self.add_input(code);
+        let program = match parse_input(code, base) {
+            Ok(program) => program,
+            Err(e) => return self.report(e.into(), out),
+        };
+        let mut checker = self.checker.clone();
+        if let Err(e) = checker.check_program(&program) {
+            return self.report(e.into(), out);
+        }
+        match program.stmts.last().map(|stmt| &stmt.kind) {
+            Some(StmtKind::Expr(expr)) => return writeln!(out, "{}", expr.ty()),
+            Some(StmtKind::Let { name, .. }) => {
+                return writeln!(out, "{}", checker.var_type(name).unwrap())
+            }
+            Some(StmtKind::Fn(decl)) => {
+                return writeln!(out, "{}", checker.fn_type(&decl.name).unwrap())
+            }
+            _ => return writeln!(out, "{}", Type::Unit),
+        }
+    }
+
+    /// Keep the code of an input, returning the position its first byte has. One position is
+    /// left between inputs for the end of each
+    fn add_input(&mut self, code: &str) -> Pos {
+        let base = match self.inputs.last() {
+            Some((base, last)) => base + last.len() + 1,
+            None => 0,
+        };
+        self.inputs.push((base, code.to_string()));
+        return base;
+    }
+
+    /// Show a diagnostic in the input its primary label points into, leaving out the labels that
+    /// point into other inputs
+    fn report(&self, mut diagnostic: Diagnostic, out: &mut dyn Write) -> io::Result<()> {
+        let pos = diagnostic
+            .labels
+            .iter()
+            .find(|label| label.primary)
+            .map_or(0, |label| label.span.start);
+        let Some((base, code)) = self.inputs.iter().rev().find(|(base, _)| *base <= pos) else {
+            return report("", diagnostic, out);
+        };
+        let end = base + code.len();
+        diagnostic
+            .labels
+            .retain(|label| *base <= label.span.start && label.span.end <= end);
+        for label in diagnostic.labels.iter_mut() {
+            label.span = Span::new(label.span.start - base, label.span.end - base);
+        }
+        return report(code, diagnostic, out);
+    }
+}
+
+/// Parse code whose first byte is at `base`, accepting a statement without its `;` at the end of
+/// the input
+fn parse_input(code: &str, base: Pos) -> Result<Program, ParseError> {
+    return parse_at(code, base).or_else(|e| {
+        if e.pos < base + code.len() {
+            return Err(e);
+        }
+        return parse_at(&format!("{};", code), base).map_err(|_| e);
+    });
+}
+
+fn last_expr(program: &Program) -> Option<&Expr> {
+    match program.stmts.last().map(|stmt| &stmt.kind) {
+        Some(StmtKind::Expr(expr)) => return Some(expr),
+        _ => return None,
+    }
+}
+
+fn report(code: &str, diagnostic: Diagnostic, out: &mut dyn Write) -> io::Result<()> {
+    return write!(out, "{}", diagnostic.render("<repl>", code));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed a whole session to the loop and collect what it answers, prompts included
+    fn session(input: &str) -> String {
+        let mut out = Vec::new();
+        repl(&mut input.as_bytes(), &mut out).unwrap();
+        return String::from_utf8(out).unwrap();
+    }
+
+    /// Answer each input in turn, without prompts
+    fn answers(inputs: &[&str]) -> Vec<String> {
+        let mut repl = Repl::new();
+        return inputs
+            .iter()
+            .map(|input| {
+                let mut out = Vec::new();
+                repl.eval(input, &mut out).unwrap();
+                return String::from_utf8(out).unwrap();
+            })
+            .collect();
+    }
+
+    #[test]
+    fn test_repl_keeps_lets_and_fns() {
+        assert_eq!(
+            answers(&[
+                "let mut x = 20",
+                "fn add(n: int): int { n + x }",
+                "x = x + 1;",
+                "add(21)",
+                "print(\"x\", x)",
+                "\"s\" + \"t\"",
+            ]),
+            ["", "", "", "42 : int\n", "x\n21\n", "\"st\" : string\n"]
+        );
+    }
+
+    #[test]
+    fn test_repl_reads_multi_line_blocks() {
+        let input =
+            "fn f(n: int): int {\n  if n < 2 { n }\n  else { f(n - 1) + f(n - 2) }\n}\nf(20)\n";
+        assert_eq!(session(input), "> . . . > 6765 : int\n> \n");
+    }
+
+    #[test]
+    fn test_repl_errors_do_not_exit() {
+        assert_eq!(
+            answers(&[
+                "let x = 1 +;",
+                "let y = True + 1;",
+                "let z = 1 / 0;",
+                "z",
+                "1"
+            ])
+            .iter()
+            .map(|answer| answer.lines().next().unwrap())
+            .collect::<Vec<_>>(),
+            [
+                "error[E0001]: expected expression, found `;`",
+                "error[E0002]: cannot apply `+` to bool and int",
+                "error[E0003]: division by zero",
+                "error[E0002]: undefined variable `z`",
+                "1 : int",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repl_shows_errors_in_the_input_they_come_from() {
+        let answers = answers(&[
+            "fn f(n: int): int {\n  10 / n\n}",
+            "let a = 1; let b = 2; let c = 3;",
+            "let mut y = 0",
+            "y = y + f(0);",
+            "y = y + True;",
+            "fn g() { let z = 1; }",
+        ]);
+        assert_eq!(
+            answers[3],
+            "error[E0003]: division by zero\n --> <repl>:2:6\n  |\n2 |   10 / n\n  |      ^\n"
+        );
+        assert_eq!(
+            answers[4],
+            "error[E0002]: cannot apply `+` to int and bool\n --> <repl>:1:7\n  |\n1 | y = y + True;\n  |       ^\n"
+        );
+        assert!(
+            answers[5].starts_with("warning[W0002]: unused variable `z`\n --> <repl>:1:10\n"),
+            "{}",
+            answers[5]
+        );
+    }
+
+    #[test]
+    fn test_repl_meta_commands() {
+        assert_eq!(
+            answers(&[
+                ":tokens let x",
+                ":ast 1 + 2",
+                "let x = 1.5; fn f(_a: int, _b: bool): string { \"\" }",
+                ":type x",
+                ":type f",
+                ":type x * 2.0",
+                ":type let y = f(1, True)",
+                ":type y",
+                ":frobnicate",
+            ]),
+            [
+                "1:1\tLet\n1:5\tId(\"x\")\n",
+                "binary +\n  int 1\n  int 2\n",
+                "",
+                "float\n",
+                "fn(int, bool): string\n",
+                "float\n",
+                "string\n",
+                "error[E0002]: undefined variable `y`\n --> <repl>:1:1\n  |\n1 | y\n  | ^\n",
+                "unknown command `:frobnicate`, try `:help`\n",
+            ]
+        );
+        assert_eq!(session(":quit\nprint(1)\n"), "> ");
+    }
+}
@@ -0,0 +1,1099 @@
+/// A backend for 64-bit RISC-V with the multiply extension (RV64IM)
+///
+/// Frames follow the standard calling convention: arguments arrive in `a0`-`a7` and then on the
+/// stack, results are returned in `a0` and `s0` is the frame pointer. Temporaries are given
+/// registers by the allocator of Chapter 11.
+use crate::chapter_11::{Allocation, Allocator, Machine};
+use crate::chapter_6::{Access, Frame, Label, Temp};
+use crate::chapter_7::{BinOp, Exp, Frag, RelOp, Stm, PROGRAM_MAIN};
+use crate::chapter_8::canonicalize;
+use crate::chapter_9::Instr;
+use crate::interpreter::MAX_CALL_DEPTH;
+
+/// A frame for RV64 using the standard calling convention
+///
+/// The frame pointer is the stack pointer on entry. The return address and the caller's frame
+/// pointer are saved just below it, locals below them and arguments past the eighth above it.
+#[derive(Debug, Clone)]
+pub struct RiscVFrame {
+    name: Label,
+    formals: Vec<Access>,
+    locals: i64,
+}
+
+impl RiscVFrame {
+    pub const ZERO: Temp = Temp(0);
+    pub const SP: Temp = Temp(2);
+    pub const S0: Temp = Temp(8);
+    pub const A0: Temp = Temp(10);
+
+    /// Registers the first arguments are passed in, in order
+    pub const ARG_REGS: [Temp; 8] = [
+        Temp(10),
+        Temp(11),
+        Temp(12),
+        Temp(13),
+        Temp(14),
+        Temp(15),
+        Temp(16),
+        Temp(17),
+    ];
+
+    /// Registers a call may overwrite: `ra`, `t0`-`t6` and `a0`-`a7`
+    pub const CALLER_SAVES: [Temp; 16] = [
+        Temp(1),
+        Temp(5),
+        Temp(6),
+        Temp(7),
+        Temp(10),
+        Temp(11),
+        Temp(12),
+        Temp(13),
+        Temp(14),
+        Temp(15),
+        Temp(16),
+        Temp(17),
+        Temp(28),
+        Temp(29),
+        Temp(30),
+        Temp(31),
+    ];
+
+    /// Registers a function must give back to its caller unchanged, except `s0`: `s1`-`s11`
+    pub const CALLEE_SAVES: [Temp; 11] = [
+        Temp(9),
+        Temp(18),
+        Temp(19),
+        Temp(20),
+        Temp(21),
+        Temp(22),
+        Temp(23),
+        Temp(24),
+        Temp(25),
+        Temp(26),
+        Temp(27),
+    ];
+
+    /// Registers the allocator may give temporaries: all but `zero`, `sp`, `gp`, `tp` and `s0`.
+    /// The caller-save ones come first, since only those are free around the code that saves the
+    /// others
+    pub const REGISTERS: [Temp; 27] = [
+        Temp(5),
+        Temp(6),
+        Temp(7),
+        Temp(28),
+        Temp(29),
+        Temp(30),
+        Temp(31),
+        Temp(10),
+        Temp(11),
+        Temp(12),
+        Temp(13),
+        Temp(14),
+        Temp(15),
+        Temp(16),
+        Temp(17),
+        Temp(9),
+        Temp(18),
+        Temp(19),
+        Temp(20),
+        Temp(21),
+        Temp(22),
+        Temp(23),
+        Temp(24),
+        Temp(25),
+        Temp(26),
+        Temp(27),
+        Temp(1),
+    ];
+
+    /// Every machine register, as precoloured temporaries
+    pub const PRECOLORED: [Temp; 32] = {
+        let mut registers = [Temp(0); 32];
+        let mut i = 0;
+        while i < registers.len() {
+            registers[i] = Temp(i);
+            i += 1;
+        }
+        registers
+    };
+
+    /// The target description the register allocator works from
+    pub const MACHINE: Machine<'static> = Machine {
+        registers: &RiscVFrame::REGISTERS,
+        precolored: &RiscVFrame::PRECOLORED,
+        codegen,
+    };
+
+    /// Assembly names of the registers, indexed by their temporary
+    pub const REGISTER_NAMES: [&'static str; 32] = [
+        "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+        "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+        "t5", "t6",
+    ];
+
+    /// The assembly name of a temporary that is a machine register
+    pub fn register_name(t: Temp) -> Option<&'static str> {
+        return RiscVFrame::REGISTER_NAMES.get(t.0).copied();
+    }
+
+    /// Bytes below the frame pointer used by the saved registers and the locals allocated so far
+    pub fn locals_size(&self) -> i64 {
+        return (2 + self.locals) * RiscVFrame::WORD_SIZE;
+    }
+}
+
+impl Frame for RiscVFrame {
+    const WORD_SIZE: i64 = 8;
+
+    fn new(name: Label, formals: &[bool]) -> RiscVFrame {
+        let mut frame = RiscVFrame {
+            name,
+            formals: Vec::new(),
+            locals: 0,
+        };
+        for (i, escape) in formals.iter().enumerate() {
+            let access = if i >= RiscVFrame::ARG_REGS.len() {
+                let stack_index = (i - RiscVFrame::ARG_REGS.len()) as i64;
+                Access::InFrame(stack_index * RiscVFrame::WORD_SIZE)
+            } else {
+                frame.alloc_local(*escape)
+            };
+            frame.formals.push(access);
+        }
+        return frame;
+    }
+
+    fn name(&self) -> &Label {
+        return &self.name;
+    }
+
+    fn formals(&self) -> &[Access] {
+        return &self.formals;
+    }
+
+    fn alloc_local(&mut self, escape: bool) -> Access {
+        if escape {
+            self.locals += 1;
+            return Access::InFrame(-self.locals_size());
+        }
+        return Access::InReg(Temp::new());
+    }
+
+    fn fp() -> Temp {
+        return RiscVFrame::S0;
+    }
+
+    fn rv() -> Temp {
+        return RiscVFrame::A0;
+    }
+}
+
+/// The name of a temporary in RISC-V assembly: a register name, or `t` and its number
+pub fn riscv_name(t: Temp) -> String {
+    match RiscVFrame::register_name(t) {
+        Some(name) => return name.to_string(),
+        None => return t.to_string(),
+    }
+}
+
+/// Whether a constant fits the 12-bit signed immediate of I- and S-type instructions
+fn fits_imm(k: i64) -> bool {
+    return (-2048..2048).contains(&k);
+}
+
+/// Select RV64IM instructions for the canonical statements of a function body by maximal munch
+pub fn codegen(stms: &[Stm]) -> Vec<Instr> {
+    let mut codegen = Codegen { instrs: Vec::new() };
+    for stm in stms {
+        codegen.munch_stm(stm);
+    }
+    return codegen.instrs;
+}
+
+struct Codegen {
+    instrs: Vec<Instr>,
+}
+
+impl Codegen {
+    fn emit(&mut self, instr: Instr) {
+        self.instrs.push(instr);
+    }
+
+    fn emit_move(&mut self, dst: Temp, src: Temp) {
+        self.emit(Instr::Move {
+            assem: "mv `d0, `s0".to_string(),
+            dst,
+            src,
+        });
+    }
+
+    fn emit_jump(&mut self, assem: &str, src: Vec<Temp>, jump: Vec<Label>) {
+        self.emit(Instr::Oper {
+            assem: assem.to_string(),
+            dst: Vec::new(),
+            src,
+            jump: Some(jump),
+        });
+    }
+
+    fn munch_stm(&mut self, stm: &Stm) {
+        match stm {
+            Stm::Move(dst, src) => self.munch_move(dst, src),
+            Stm::Exp(e) => match e.as_ref() {
+                Exp::Call(f, args) => self.munch_call(f, args),
+                _ => {
+                    self.munch_exp(e);
+                }
+            },
+            Stm::Jump(target, labels) => match target.as_ref() {
+                Exp::Name(label) => self.emit_jump("j `j0", vec![], vec![label.clone()]),
+                _ => {
+                    let address = self.munch_exp(target);
+                    self.emit_jump("jr `s0", vec![address], labels.clone());
+                }
+            },
+            Stm::CJump(op, a, b, t, f) => {
+                let a = self.munch_exp(a);
+                let b = self.munch_exp(b);
+                // Greater and less-or-equal compare with the operands swapped
+                let (mnemonic, swap) = match op {
+                    RelOp::Eq => ("beq", false),
+                    RelOp::Ne => ("bne", false),
+                    RelOp::Lt => ("blt", false),
+                    RelOp::Ge => ("bge", false),
+                    RelOp::Gt => ("blt", true),
+                    RelOp::Le => ("bge", true),
+                    RelOp::ULt => ("bltu", false),
+                    RelOp::UGe => ("bgeu", false),
+                    RelOp::UGt => ("bltu", true),
+                    RelOp::ULe => ("bgeu", true),
+                };
+                let src = if swap { vec![b, a] } else { vec![a, b] };
+                self.emit_jump(
+                    &format!("{} `s0, `s1, `j0", mnemonic),
+                    src,
+                    vec![t.clone(), f.clone()],
+                );
+            }
+            Stm::Label(label) => self.emit(Instr::Label {
+                assem: format!("{}:", label),
+                label: label.clone(),
+            }),
+            Stm::Seq(..) => panic!("instruction selection needs canonical statements"),
+        }
+    }
+
+    fn munch_move(&mut self, dst: &Exp, src: &Exp) {
+        match dst {
+            Exp::Mem(address) => {
+                let (base, offset) = self.munch_address(address);
+                let value = self.munch_exp(src);
+                self.emit(Instr::oper(
+                    &format!("sd `s0, {}(`s1)", offset),
+                    vec![],
+                    vec![value, base],
+                ));
+            }
+            Exp::Temp(t) => match src {
+                Exp::Call(f, args) => {
+                    self.munch_call(f, args);
+                    self.emit_move(*t, RiscVFrame::A0);
+                }
+                Exp::Const(k) => {
+                    self.emit(Instr::oper(&format!("li `d0, {}", k), vec![*t], vec![]))
+                }
+                Exp::Name(label) => {
+                    self.emit(Instr::oper(&format!("la `d0, {}", label), vec![*t], vec![]))
+                }
+                Exp::Mem(address) => {
+                    let (base, offset) = self.munch_address(address);
+                    self.emit(Instr::oper(
+                        &format!("ld `d0, {}(`s0)", offset),
+                        vec![*t],
+                        vec![base],
+                    ));
+                }
+                _ => {
+                    let value = self.munch_exp(src);
+                    self.emit_move(*t, value);
+                }
+            },
+            _ => panic!("cannot move into {}", dst),
+        }
+    }
+
+    /// Compute an address as a register plus a 12-bit displacement
+    fn munch_address(&mut self, address: &Exp) -> (Temp, i64) {
+        if let Exp::BinOp(op, a, b) = address {
+            match (op, a.as_ref(), b.as_ref()) {
+                (BinOp::Plus, base, Exp::Const(k)) | (BinOp::Plus, Exp::Const(k), base)
+                    if fits_imm(*k) =>
+                {
+                    return (self.munch_exp(base), *k);
+                }
+                (BinOp::Minus, base, Exp::Const(k)) if fits_imm(-*k) => {
+                    return (self.munch_exp(base), -*k);
+                }
+                _ => {}
+            }
+        }
+        return (self.munch_exp(address), 0);
+    }
+
+    /// Emit instructions computing an expression and return the temporary holding its value
+    fn munch_exp(&mut self, exp: &Exp) -> Temp {
+        match exp {
+            Exp::Temp(t) => return *t,
+            Exp::Const(0) => return RiscVFrame::ZERO,
+            Exp::Const(_) | Exp::Name(_) | Exp::Mem(_) | Exp::Call(..) => {
+                let t = Temp::new();
+                self.munch_move(&Exp::Temp(t), exp);
+                return t;
+            }
+            Exp::BinOp(op, a, b) => return self.munch_bin_op(*op, a, b),
+            Exp::Eseq(..) => panic!("instruction selection needs canonical statements"),
+        }
+    }
+
+    fn munch_bin_op(&mut self, op: BinOp, a: &Exp, b: &Exp) -> Temp {
+        let d = Temp::new();
+        let (mnemonic, immediate) = match op {
+            BinOp::Plus => ("add", Some("addi")),
+            BinOp::Minus => ("sub", None),
+            BinOp::Mul => ("mul", None),
+            BinOp::Div => ("div", None),
+            BinOp::And => ("and", Some("andi")),
+            BinOp::Or => ("or", Some("ori")),
+            BinOp::Xor => ("xor", Some("xori")),
+            BinOp::LShift => ("sll", Some("slli")),
+            BinOp::RShift => ("srl", Some("srli")),
+            BinOp::ARShift => ("sra", Some("srai")),
+        };
+        let commutes = matches!(
+            op,
+            BinOp::Plus | BinOp::Mul | BinOp::And | BinOp::Or | BinOp::Xor
+        );
+        let (a, b) = match a {
+            Exp::Const(_) if commutes && !matches!(b, Exp::Const(_)) => (b, a),
+            _ => (a, b),
+        };
+        let is_shift = matches!(op, BinOp::LShift | BinOp::RShift | BinOp::ARShift);
+        let immediate = match (op, b) {
+            (_, Exp::Const(k)) if is_shift => immediate.map(|name| (name, k & 63)),
+            (BinOp::Minus, Exp::Const(k)) if fits_imm(-*k) => Some(("addi", -*k)),
+            (_, Exp::Const(k)) if fits_imm(*k) => immediate.map(|name| (name, *k)),
+            _ => None,
+        };
+        let a = self.munch_exp(a);
+        match immediate {
+            Some((name, k)) => self.emit(Instr::oper(
+                &format!("{} `d0, `s0, {}", name, k),
+                vec![d],
+                vec![a],
+            )),
+            None => {
+                let b = self.munch_exp(b);
+                self.emit(Instr::oper(
+                    &format!("{} `d0, `s0, `s1", mnemonic),
+                    vec![d],
+                    vec![a, b],
+                ));
+            }
+        }
+        return d;
+    }
+
+    /// Emit a call, leaving its result in `a0`
+    fn munch_call(&mut self, f: &Exp, args: &[Exp]) {
+        let target = match f {
+            Exp::Name(_) => None,
+            _ => Some(self.munch_exp(f)),
+        };
+        let values: Vec<Temp> = args.iter().map(|arg| self.munch_exp(arg)).collect();
+        let in_regs = values.len().min(RiscVFrame::ARG_REGS.len());
+
+        // Arguments past the registers go at the bottom of a 16-byte aligned area of the stack
+        let stacked = &values[in_regs..];
+        let area = (stacked.len() as i64 * RiscVFrame::WORD_SIZE + 15) / 16 * 16;
+        if area > 0 {
+            self.emit(Instr::oper(
+                &format!("addi sp, sp, {}", -area),
+                vec![],
+                vec![],
+            ));
+        }
+        for (i, value) in stacked.iter().enumerate() {
+            self.emit(Instr::oper(
+                &format!("sd `s0, {}(sp)", i as i64 * RiscVFrame::WORD_SIZE),
+                vec![],
+                vec![*value],
+            ));
+        }
+        for (reg, value) in RiscVFrame::ARG_REGS.iter().zip(&values) {
+            self.emit_move(*reg, *value);
+        }
+
+        let mut src = RiscVFrame::ARG_REGS[..in_regs].to_vec();
+        let assem = match (f, target) {
+            (Exp::Name(label), _) => format!("call {}", label),
+            (_, Some(target)) => {
+                src.insert(0, target);
+                "jalr `s0".to_string()
+            }
+            _ => unreachable!(),
+        };
+        self.emit(Instr::oper(&assem, RiscVFrame::CALLER_SAVES.to_vec(), src));
+        if area > 0 {
+            self.emit(Instr::oper(
+                &format!("addi sp, sp, {}", area),
+                vec![],
+                vec![],
+            ));
+        }
+    }
+}
+
+/// Assembly for a whole program in GNU assembler syntax
+///
+/// The result is linked with the runtime library, whose `main` calls `PROGRAM_MAIN`.
+pub fn emit_program(frags: Vec<Frag<RiscVFrame>>, allocator: Allocator) -> String {
+    let mut text = String::from("\t.text\n");
+    let mut data = String::new();
+    for frag in frags {
+        match frag {
+            Frag::Proc { body, mut frame } => {
+                let instrs = proc_entry_exit(&frame, codegen(&canonicalize(body)));
+                let allocation = allocator.allocate(instrs, &mut frame, &RiscVFrame::MACHINE);
+                text.push_str(&emit_proc(&frame, &allocation));
+            }
+            Frag::Str(label, s) => {
+                data.push_str(&format!(
+                    "\t.p2align 3\n{}:\n\t.dword {}\n\t.ascii \"{}\"\n",
+                    label,
+                    s.len(),
+                    escape_string(&s)
+                ));
+            }
+        }
+    }
+    text.push_str("\t.data\n\t.p2align 3\ncall_depth:\n\t.dword 0\n");
+    if !data.is_empty() {
+        text.push_str("\t.section .rodata\n");
+        text.push_str(&data);
+    }
+    return text;
+}
+
+/// Bytes as the contents of an `.ascii` directive
+pub fn escape_string(s: &str) -> String {
+    let mut escaped = String::new();
+    for byte in s.bytes() {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    return escaped;
+}
+
+fn move_instr(dst: Temp, src: Temp) -> Instr {
+    return Instr::Move {
+        assem: "mv `d0, `s0".to_string(),
+        dst,
+        src,
+    };
+}
+
+/// Wrap a function body in the moves that take its formals from where they arrive, and keep the
+/// callee-save registers in temporaries the allocator may spill when it needs their registers
+pub fn proc_entry_exit(frame: &RiscVFrame, body: Vec<Instr>) -> Vec<Instr> {
+    let saved: Vec<Temp> = RiscVFrame::CALLEE_SAVES
+        .iter()
+        .map(|_| Temp::new())
+        .collect();
+    let mut instrs: Vec<Instr> = saved
+        .iter()
+        .zip(RiscVFrame::CALLEE_SAVES)
+        .map(|(t, reg)| move_instr(*t, reg))
+        .collect();
+    for (access, reg) in frame.formals().iter().zip(RiscVFrame::ARG_REGS) {
+        match access {
+            Access::InFrame(offset) => instrs.push(Instr::oper(
+                &format!("sd `s0, {}(`s1)", offset),
+                vec![],
+                vec![reg, RiscVFrame::S0],
+            )),
+            Access::InReg(t) => instrs.push(move_instr(*t, reg)),
+        }
+    }
+    instrs.extend(body);
+    for (t, reg) in saved.iter().zip(RiscVFrame::CALLEE_SAVES) {
+        instrs.push(move_instr(reg, *t));
+    }
+    // Emits nothing, but keeps the result and the callee-save registers live to the end
+    let mut live = vec![RiscVFrame::A0, RiscVFrame::SP, RiscVFrame::S0];
+    live.extend(RiscVFrame::CALLEE_SAVES);
+    instrs.push(Instr::oper("", vec![], live));
+    return instrs;
+}
+
+/// Emit a function whose temporaries have been given registers
+///
+/// Every function but the main one counts itself in `call_depth`, stopping the program with a
+/// stack overflow when too many calls are active, as the interpreter does. The counting uses the
+/// temporaries `t3`-`t5`, which hold nothing on entry and exit
+fn emit_proc(frame: &RiscVFrame, allocation: &Allocation) -> String {
+    let size = (frame.locals_size() + 15) / 16 * 16;
+    let name = frame.name();
+    let counted = name.0 != PROGRAM_MAIN;
+    let mut out = String::new();
+    if !counted {
+        out.push_str(&format!("\t.globl {}\n", name));
+    }
+    out.push_str(&format!("\t.p2align 2\n{}:\n", name));
+    out.push_str("\taddi sp, sp, -16\n\tsd ra, 8(sp)\n\tsd s0, 0(sp)\n\taddi s0, sp, 16\n");
+    out.push_str(&adjust_sp(-(size - 16)));
+    if counted {
+        out.push_str(&format!(
+            "\tla t3, call_depth\n\tld t4, 0(t3)\n\tli t5, {}\n\tblt t4, t5, .L{}_enter\n\tcall stack_overflow\n.L{}_enter:\n\taddi t4, t4, 1\n\tsd t4, 0(t3)\n",
+            MAX_CALL_DEPTH, name, name
+        ));
+    }
+    let register = |t: Temp| riscv_name(allocation.colors[&t]);
+    for instr in &allocation.instrs {
+        match instr {
+            Instr::Label { assem, .. } => out.push_str(&format!("{}\n", assem)),
+            Instr::Oper { assem, .. } if assem.is_empty() => {}
+            _ if allocation.is_redundant(instr) => {}
+            _ => out.push_str(&format!("\t{}\n", instr.format(&register))),
+        }
+    }
+    if counted {
+        out.push_str("\tla t3, call_depth\n\tld t4, 0(t3)\n\taddi t4, t4, -1\n\tsd t4, 0(t3)\n");
+    }
+    out.push_str("\taddi sp, s0, -16\n\tld ra, 8(sp)\n\tld s0, 0(sp)\n\taddi sp, sp, 16\n\tret\n");
+    return out;
+}
+
+fn adjust_sp(delta: i64) -> String {
+    if delta == 0 {
+        return String::new();
+    }
+    if fits_imm(delta) {
+        return format!("\taddi sp, sp, {}\n", delta);
+    }
+    return format!("\tli t3, {}\n\tadd sp, sp, t3\n", delta);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter_7::translate;
+    use crate::ir_interpreter::{run_to_string, IrProgram};
+    use crate::test_programs::*;
+    use std::collections::HashMap;
+    use std::process::Command;
+
+    fn temp(t: Temp) -> Box<Exp> {
+        return Box::new(Exp::Temp(t));
+    }
+
+    fn assembly(stms: &[Stm]) -> Vec<String> {
+        return codegen(stms)
+            .iter()
+            .map(|i| i.format(&riscv_name))
+            .collect();
+    }
+
+    fn compile(source: &str) -> String {
+        return emit_program(translate::<RiscVFrame>(&load(source)), Allocator::default());
+    }
+
+    /// Bytes of memory the simulator gives a program
+    const MEMORY_SIZE: usize = 1 << 24;
+
+    /// Where the code is placed, far from any data address
+    const CODE_BASE: i64 = 1 << 40;
+
+    /// The return address `program_main` is called with
+    const EXIT: i64 = -4;
+
+    /// Runs the subset of RV64IM assembly `emit_program` produces, with the runtime library built
+    /// in. Calls to the runtime overwrite the caller-save registers, as real ones may
+    struct Simulator {
+        code: Vec<(String, Vec<String>)>,
+        labels: HashMap<String, i64>,
+        regs: [i64; 32],
+        memory: Vec<u8>,
+        heap: i64,
+        out: String,
+    }
+
+    impl Simulator {
+        fn new(assembly: &str) -> Simulator {
+            let mut sim = Simulator {
+                code: Vec::new(),
+                labels: HashMap::new(),
+                regs: [0; 32],
+                memory: vec![0; MEMORY_SIZE],
+                heap: 0x1000,
+                out: String::new(),
+            };
+            let mut in_text = true;
+            for line in assembly.lines() {
+                let line = line.trim();
+                if let Some(label) = line.strip_suffix(':') {
+                    let address = if in_text {
+                        CODE_BASE + 4 * sim.code.len() as i64
+                    } else {
+                        sim.heap
+                    };
+                    sim.labels.insert(label.to_string(), address);
+                    continue;
+                }
+                let (mnemonic, rest) = line.split_once(' ').unwrap_or((line, ""));
+                match mnemonic {
+                    ".text" => in_text = true,
+                    ".data" | ".section" => in_text = false,
+                    ".globl" => {}
+                    ".p2align" => {
+                        let align = 1 << rest.parse::<i64>().unwrap();
+                        sim.heap = (sim.heap + align - 1) / align * align;
+                    }
+                    ".dword" => {
+                        sim.store(sim.heap, rest.parse().unwrap()).unwrap();
+                        sim.heap += 8;
+                    }
+                    ".ascii" => {
+                        for byte in unescape(&rest[1..rest.len() - 1]) {
+                            sim.memory[sim.heap as usize] = byte;
+                            sim.heap += 1;
+                        }
+                    }
+                    _ => {
+                        let operands = rest
+                            .split(',')
+                            .map(|s| s.trim().to_string())
+                            .filter(|s| !s.is_empty())
+                            .collect();
+                        sim.code.push((mnemonic.to_string(), operands));
+                    }
+                }
+            }
+            sim.heap = (sim.heap + 7) / 8 * 8;
+            return sim;
+        }
+
+        fn reg(&self, name: &str) -> usize {
+            return RiscVFrame::REGISTER_NAMES
+                .iter()
+                .position(|r| *r == name)
+                .unwrap_or_else(|| panic!("unknown register {}", name));
+        }
+
+        fn read(&self, name: &str) -> i64 {
+            return self.regs[self.reg(name)];
+        }
+
+        fn write(&mut self, name: &str, value: i64) {
+            let r = self.reg(name);
+            if r != 0 {
+                self.regs[r] = value;
+            }
+        }
+
+        /// The address of a `k(reg)` operand
+        fn address(&self, operand: &str) -> i64 {
+            let (offset, reg) = operand.trim_end_matches(')').split_once('(').unwrap();
+            return self.read(reg) + offset.parse::<i64>().unwrap();
+        }
+
+        fn check(&self, address: i64) -> Result<usize, String> {
+            if address < 0x1000 || address + 8 > MEMORY_SIZE as i64 {
+                return Err(format!("invalid memory access at address {}", address));
+            }
+            return Ok(address as usize);
+        }
+
+        fn load(&self, address: i64) -> Result<i64, String> {
+            let a = self.check(address)?;
+            return Ok(i64::from_le_bytes(
+                self.memory[a..a + 8].try_into().unwrap(),
+            ));
+        }
+
+        fn store(&mut self, address: i64, value: i64) -> Result<(), String> {
+            let a = self.check(address)?;
+            self.memory[a..a + 8].copy_from_slice(&value.to_le_bytes());
+            return Ok(());
+        }
+
+        fn string(&self, address: i64) -> Result<Vec<u8>, String> {
+            let len = self.load(address)? as usize;
+            let start = self.check(address + 8)?;
+            return Ok(self.memory[start..start + len].to_vec());
+        }
+
+        fn target(&self, label: &str) -> usize {
+            return ((self.labels[label] - CODE_BASE) / 4) as usize;
+        }
+
+        fn run(mut self) -> Result<String, String> {
+            self.write("sp", MEMORY_SIZE as i64);
+            self.write("ra", EXIT);
+            let callee_saves: Vec<i64> = [8, 9]
+                .into_iter()
+                .chain(18..28)
+                .map(|r| self.regs[r])
+                .collect();
+            let mut pc = self.target(PROGRAM_MAIN);
+            loop {
+                let (op, args) = self.code[pc].clone();
+                let arg = |i: usize| args[i].as_str();
+                pc += 1;
+                match op.as_str() {
+                    "li" => self.write(arg(0), arg(1).parse().unwrap()),
+                    "la" => self.write(arg(0), self.labels[arg(1)]),
+                    "ld" => {
+                        let value = self.load(self.address(arg(1)))?;
+                        self.write(arg(0), value);
+                    }
+                    "sd" => self.store(self.address(arg(1)), self.read(arg(0)))?,
+                    "mv" => self.write(arg(0), self.read(arg(1))),
+                    "add" | "sub" | "mul" | "div" | "and" | "or" | "xor" | "sll" | "srl"
+                    | "sra" => {
+                        let value = alu(&op, self.read(arg(1)), self.read(arg(2)));
+                        self.write(arg(0), value);
+                    }
+                    "addi" | "andi" | "ori" | "xori" | "slli" | "srli" | "srai" => {
+                        let name = match op.as_str() {
+                            "slli" => "sll",
+                            _ => op.trim_end_matches('i'),
+                        };
+                        let value = alu(name, self.read(arg(1)), arg(2).parse().unwrap());
+                        self.write(arg(0), value);
+                    }
+                    "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" => {
+                        let (a, b) = (self.read(arg(0)), self.read(arg(1)));
+                        let (ua, ub) = (a as u64, b as u64);
+                        let taken = match op.as_str() {
+                            "beq" => a == b,
+                            "bne" => a != b,
+                            "blt" => a < b,
+                            "bge" => a >= b,
+                            "bltu" => ua < ub,
+                            _ => ua >= ub,
+                        };
+                        if taken {
+                            pc = self.target(arg(2));
+                        }
+                    }
+                    "j" => pc = self.target(arg(0)),
+                    "call" if self.labels.contains_key(arg(0)) => {
+                        self.write("ra", CODE_BASE + 4 * pc as i64);
+                        pc = self.target(arg(0));
+                    }
+                    "call" => self.call_runtime(arg(0))?,
+                    "ret" => {
+                        let ra = self.read("ra");
+                        if ra == EXIT {
+                            break;
+                        }
+                        pc = ((ra - CODE_BASE) / 4) as usize;
+                    }
+                    _ => panic!("unsupported instruction {}", op),
+                }
+            }
+            assert_eq!(
+                self.read("sp"),
+                MEMORY_SIZE as i64,
+                "stack pointer restored"
+            );
+            let now: Vec<i64> = [8, 9]
+                .into_iter()
+                .chain(18..28)
+                .map(|r| self.regs[r])
+                .collect();
+            assert_eq!(now, callee_saves, "callee-save registers preserved");
+            return Ok(self.out);
+        }
+
+        fn call_runtime(&mut self, name: &str) -> Result<(), String> {
+            let (a, b) = (self.read("a0"), self.read("a1"));
+            let float = |bits: i64| f64::from_bits(bits as u64);
+            let bits = |x: f64| x.to_bits() as i64;
+            let result = match name {
+                "print_int" => {
+                    self.out.push_str(&format!("{}\n", a));
+                    0
+                }
+                "print_float" => {
+                    self.out.push_str(&format!("{:.6}\n", float(a)));
+                    0
+                }
+                "print_bool" => {
+                    self.out.push_str(if a != 0 { "True\n" } else { "False\n" });
+                    0
+                }
+                "print_string" => {
+                    let s = String::from_utf8(self.string(a)?).unwrap();
+                    self.out.push_str(&format!("{}\n", s));
+                    0
+                }
+                "string_concat" => {
+                    let mut bytes = self.string(a)?;
+                    bytes.extend(self.string(b)?);
+                    let address = self.heap;
+                    self.store(address, bytes.len() as i64)?;
+                    let start = (address + 8) as usize;
+                    self.memory[start..start + bytes.len()].copy_from_slice(&bytes);
+                    self.heap += (8 + bytes.len() as i64 + 7) / 8 * 8;
+                    address
+                }
+                "string_equal" => (self.string(a)? == self.string(b)?) as i64,
+                "float_add" => bits(float(a) + float(b)),
+                "float_sub" => bits(float(a) - float(b)),
+                "float_mul" => bits(float(a) * float(b)),
+                "float_div" => bits(float(a) / float(b)),
+                "float_neg" => bits(-float(a)),
+                "float_eq" => (float(a) == float(b)) as i64,
+                "float_ne" => (float(a) != float(b)) as i64,
+                "float_lt" => (float(a) < float(b)) as i64,
+                "float_le" => (float(a) <= float(b)) as i64,
+                "float_gt" => (float(a) > float(b)) as i64,
+                "float_ge" => (float(a) >= float(b)) as i64,
+                "div_by_zero" => return Err("division by zero".to_string()),
+                "stack_overflow" => return Err("stack overflow".to_string()),
+                _ => panic!("undefined function {}", name),
+            };
+            for r in RiscVFrame::CALLER_SAVES {
+                self.regs[r.0] = 0x5eed_5eed;
+            }
+            self.write("a0", result);
+            return Ok(());
+        }
+    }
+
+    fn alu(op: &str, a: i64, b: i64) -> i64 {
+        match op {
+            "add" => return a.wrapping_add(b),
+            "sub" => return a.wrapping_sub(b),
+            "mul" => return a.wrapping_mul(b),
+            "div" if b == 0 => return -1,
+            "div" => return a.wrapping_div(b),
+            "and" => return a & b,
+            "or" => return a | b,
+            "xor" => return a ^ b,
+            "sll" => return a.wrapping_shl(b as u32),
+            "srl" => return (a as u64).wrapping_shr(b as u32) as i64,
+            "sra" => return a.wrapping_shr(b as u32),
+            _ => panic!("unknown operation {}", op),
+        }
+    }
+
+    fn unescape(s: &str) -> Vec<u8> {
+        let bytes = s.as_bytes();
+        let mut result = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] != b'\\' {
+                result.push(bytes[i]);
+                i += 1;
+            } else if bytes[i + 1].is_ascii_digit() {
+                let octal = std::str::from_utf8(&bytes[i + 1..i + 4]).unwrap();
+                result.push(u8::from_str_radix(octal, 8).unwrap());
+                i += 4;
+            } else {
+                result.push(bytes[i + 1]);
+                i += 2;
+            }
+        }
+        return result;
+    }
+
+    #[test]
+    fn test_riscv_frame_formals() {
+        let mut escapes = vec![false; 10];
+        escapes[1] = true;
+        let frame = RiscVFrame::new(Label::named("f"), &escapes);
+        let formals = frame.formals();
+        assert!(matches!(formals[0], Access::InReg(_)));
+        assert_eq!(formals[1], Access::InFrame(-24));
+        assert_eq!(formals[8], Access::InFrame(0));
+        assert_eq!(formals[9], Access::InFrame(8));
+        assert_eq!(frame.locals_size(), 24);
+    }
+
+    #[test]
+    fn test_riscv_munch_frame_accesses() {
+        let t = Temp(300);
+        let slot = Box::new(Exp::Mem(Box::new(Exp::BinOp(
+            BinOp::Plus,
+            temp(RiscVFrame::S0),
+            Box::new(Exp::Const(-24)),
+        ))));
+        let code = assembly(&[
+            Stm::Move(slot.clone(), Box::new(Exp::Const(0))),
+            Stm::Move(temp(t), slot),
+        ]);
+        assert_eq!(code, vec!["sd zero, -24(s0)", "ld t300, -24(s0)"]);
+    }
+
+    #[test]
+    fn test_riscv_munch_immediates() {
+        let (a, b) = (Temp(310), Temp(311));
+        let diff = Exp::BinOp(BinOp::Minus, temp(a), Box::new(Exp::Const(5)));
+        let code = assembly(&[Stm::Move(temp(b), Box::new(diff))]);
+        assert!(code[0].starts_with("addi t") && code[0].ends_with(", t310, -5"));
+        let product = Exp::BinOp(BinOp::Mul, temp(a), Box::new(Exp::Const(5000)));
+        let code = assembly(&[Stm::Move(temp(b), Box::new(product))]);
+        assert!(code[0].ends_with(", 5000") && code[0].starts_with("li "));
+        assert!(code[1].starts_with("mul "));
+    }
+
+    #[test]
+    fn test_riscv_munch_branches_swap_for_greater() {
+        let (a, b) = (Temp(320), Temp(321));
+        let code = assembly(&[Stm::CJump(
+            RelOp::Gt,
+            temp(a),
+            temp(b),
+            Label::named("yes"),
+            Label::named("no"),
+        )]);
+        assert_eq!(code, vec!["blt t321, t320, yes"]);
+    }
+
+    #[test]
+    fn test_riscv_frames_in_ir_interpreter() {
+        for (name, source) in PROGRAMS {
+            let frags = translate::<RiscVFrame>(&load(source));
+            assert_eq!(
+                run_to_string(&IrProgram::new(frags)),
+                Ok(expected_output(source)),
+                "program {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_riscv_programs_run_on_simulator() {
+        for (name, source) in PROGRAMS {
+            assert_eq!(
+                Simulator::new(&compile(source)).run(),
+                Ok(expected_output(source)),
+                "program {}",
+                name
+            );
+        }
+        for (name, source) in FAILING_PROGRAMS {
+            assert_eq!(
+                Simulator::new(&compile(source)).run(),
+                Err(expected_error(source)),
+                "program {}",
+                name
+            );
+        }
+    }
+
+    /// More values live at once than there are registers, so some must be spilled
+    #[test]
+    fn test_riscv_spilling_program_runs_on_simulator() {
+        for allocator in [Allocator::GraphColoring, Allocator::LinearScan] {
+            for count in [10, 40] {
+                let source = pressure_program(count);
+                let assembly = emit_program(translate::<RiscVFrame>(&load(&source)), allocator);
+                assert_eq!(
+                    Simulator::new(&assembly).run(),
+                    Ok(expected_output(&source)),
+                    "{:?} with {} values",
+                    allocator,
+                    count
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_riscv_linear_scan_programs_run_on_simulator() {
+        for (name, source) in PROGRAMS {
+            let frags = translate::<RiscVFrame>(&load(source));
+            assert_eq!(
+                Simulator::new(&emit_program(frags, Allocator::LinearScan)).run(),
+                Ok(expected_output(source)),
+                "program {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_riscv_string_escapes() {
+        assert_eq!(escape_string("a\"b\\c\n"), "a\\\"b\\\\c\\012");
+        assert_eq!(unescape(&escape_string("a\"b\\c\n")), b"a\"b\\c\n");
+    }
+
+    /// The emitted assembly is accepted by a real RISC-V assembler
+    #[test]
+    #[ignore = "needs `llvm-mc`, run with `cargo test -- --ignored`"]
+    fn test_riscv_assembles_with_llvm_mc() {
+        require_tool("llvm-mc");
+        let dir = std::env::temp_dir().join(format!("riscv_asm_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in PROGRAMS {
+            let path = dir.join(format!("{}.s", name));
+            std::fs::write(&path, compile(source)).unwrap();
+            let output = Command::new("llvm-mc")
+                .args(["-triple=riscv64", "-mattr=+m", "-filetype=obj", "-o"])
+                .arg(dir.join(format!("{}.o", name)))
+                .arg(&path)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "program {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Programs linked with the runtime library run under user-mode emulation
+    #[test]
+    #[ignore = "needs `riscv64-linux-gnu-gcc` and `qemu-riscv64`, run with `cargo test -- --ignored`"]
+    fn test_riscv_programs_run_under_qemu() {
+        let gcc = "riscv64-linux-gnu-gcc";
+        require_tool(gcc);
+        require_tool("qemu-riscv64");
+        let dir = std::env::temp_dir().join(format!("riscv_run_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let runtime = concat!(env!("CARGO_MANIFEST_DIR"), "/runtime/runtime.c");
+        for (name, source) in PROGRAMS {
+            let path = dir.join(format!("{}.s", name));
+            let exe = dir.join(name);
+            std::fs::write(&path, compile(source)).unwrap();
+            let status = Command::new(gcc)
+                .args(["-static", "-march=rv64imafd", "-o"])
+                .arg(&exe)
+                .arg(&path)
+                .arg(runtime)
+                .arg("-lm")
+                .status()
+                .unwrap();
+            assert!(status.success(), "program {} did not link", name);
+            let output = Command::new("qemu-riscv64").arg(&exe).output().unwrap();
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                expected_output(source),
+                "program {}",
+                name
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+/// Example programs shared by the tests of every stage that runs code, and by the benchmark of
+/// the register allocators
+///
+/// Each stage checks that running a program gives exactly the output of the reference
+/// interpreter, so a program only needs its source here.
+use crate::chapter_3::parse;
+use crate::chapter_4::Program;
+use crate::chapter_5::check;
+#[cfg(test)]
+use crate::interpreter::interpret_to_string;
+
+/// Programs that run to completion, by name
+pub const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "arithmetic",
+        "print(1 + 2 * 3, (1 + 2) * 3, 7 / 2, -7 / 2, 7 - 10);",
+    ),
+    (
+        "wrapping",
+        "print(2000000000 * 2000000000 * 3, 2147483647 * 2147483647 * 2 + 2147483647 * 9);",
+    ),
+    (
+        "comparisons",
+        "print(1 < 2, 2 <= 1, 3 == 3, 3 <> 3, 4 > 4, 4 >= 4, True == False);",
+    ),
+    (
+        "logic",
+        "let t = True; let f = False;
+         print(t & f, t | f, f | f & t, (1 < 2) & (2 < 3));",
+    ),
+    (
+        "short_circuit",
+        "print(False & 1 / 0 == 0, True | 1 / 0 == 0);",
+    ),
+    (
+        "floats",
+        "let x = 1.5;
+         print(x * 2.0, 1.0 / 4.0, x - 0.25, -x, x < 2.0, x == 1.5, x >= 1.6);",
+    ),
+    (
+        "strings",
+        r#"let s = "ab" + "cd";
+           print(s, s == "abcd", s <> "abcd", "" + s + "!", "x" == "y");"#,
+    ),
+    (
+        "if_expression",
+        "fn sign(x: int): int { if x < 0 { -1 } elseif x == 0 { 0 } else { 1 } }
+         let big = if sign(5) > 0 { 100 } else { 0 };
+         if big == 100 { print(big); }
+         print(sign(-5), sign(0), sign(5));",
+    ),
+    (
+        "loops",
+        "let mut total = 0;
+         for i = 0 : 5 { total = total + i; }
+         let mut n = 0;
+         while n < 3 { n = n + 1; }
+         for i = 3 : 1 { print(i); }
+         print(total, n);",
+    ),
+    (
+        "recursion",
+        "fn fact(n: int): int { if n == 0 { 1 } else { n * fact(n - 1) } }
+         fn fib(n: int): int { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }
+         print(fact(10), fib(15));",
+    ),
+    (
+        "mutual_recursion",
+        "fn even(n: int): bool { if n == 0 { True } else { odd(n - 1) } }
+         fn odd(n: int): bool { if n == 0 { False } else { even(n - 1) } }
+         print(even(10), odd(7), even(7));",
+    ),
+    (
+        "nested_functions",
+        "fn counter(): int {
+             let mut count = 0;
+             fn bump(by: int) { count = count + by; }
+             bump(2);
+             bump(3);
+             count
+         }
+         print(counter());",
+    ),
+    (
+        "deep_nesting",
+        "let mut x = 1;
+         fn a(p: int): int {
+             let y = p * 10;
+             fn b(): int {
+                 fn c(): int { x = x + 1; x + y + p }
+                 c() + c()
+             }
+             b()
+         }
+         print(a(2), x);",
+    ),
+    (
+        "static_scoping",
+        "let x = 1;
+         fn show() { print(x); }
+         let x = 2;
+         show();
+         print(x);",
+    ),
+    (
+        "many_arguments",
+        "fn sum(a: int, b: int, c: int, d: int, e: int, f: int, g: int, h: int): int {
+             a + 2 * b + 3 * c + 4 * d + 5 * e + 6 * f + 7 * g + 8 * h
+         }
+         fn weigh(mut a: int, b: float, c: bool, d: string, e: int, f: int, g: float): float {
+             a = a + e + f;
+             if c { print(d); }
+             b * g
+         }
+         print(sum(1, 2, 3, 4, 5, 6, 7, 8), weigh(1, 2.5, True, \"hi\", 5, 6, 4.0));",
+    ),
+    (
+        "escaping_parameters",
+        "fn outer(a: int, mut b: int): int {
+             fn inner(): int { b = b + a; b }
+             inner();
+             inner()
+         }
+         print(outer(3, 4));",
+    ),
+    (
+        "nested_calls",
+        "fn add(a: int, b: int): int { a + b }
+         fn twice(a: int): int { add(a, a) }
+         print(add(twice(add(1, 2)), add(twice(3), twice(4))));",
+    ),
+    (
+        "collatz",
+        "fn steps(n: int): int {
+             let mut n = n;
+             let mut count = 0;
+             while n <> 1 {
+                 if n / 2 * 2 == n { n = n / 2; } else { n = 3 * n + 1; }
+                 count = count + 1;
+             }
+             count
+         }
+         let mut best = 0;
+         let mut arg = 0;
+         for i = 1 : 30 {
+             let s = steps(i);
+             if s > best { best = s; arg = i; }
+         }
+         print(arg, best);",
+    ),
+    (
+        "string_building",
+        r#"fn repeat(s: string, n: int): string {
+               let mut out = "";
+               for i = 0 : n { out = out + s; }
+               out
+           }
+           print(repeat("ab", 3), repeat("x", 0) == "");"#,
+    ),
+];
+
+/// Programs stopped by a runtime error, by name
+#[cfg(test)]
+pub const FAILING_PROGRAMS: &[(&str, &str)] = &[
+    ("divide_by_zero", "print(1); let zero = 0; print(1 / zero);"),
+    (
+        "deep_recursion",
+        "fn down(n: int): int { if n == 0 { 0 } else { 1 + down(n - 1) } } print(down(100000));",
+    ),
+];
+
+/// A program keeping `count` values live at once, across calls and around a loop, so that
+/// register allocation has to spill some of them
+pub fn pressure_program(count: usize) -> String {
+    let mut source = String::from("fn id(x: int): int { x } fn f(k: int): int { ");
+    for i in 0..count {
+        source.push_str(&format!("let v{} = k * {}; ", i, i + 1));
+    }
+    let sum: Vec<String> = (0..count)
+        .map(|i| format!("v{} * {}", i, count - i))
+        .collect();
+    source.push_str(&format!(
+        "let mut total = 0; for i = 0 : 3 {{ total = total + id(i) * ({}); }} total }} ",
+        sum.join(" + ")
+    ));
+    source.push_str("print(f(3), f(-2));");
+    return source;
+}
+
+/// Parse and type check one of the programs
+pub fn load(source: &str) -> Program {
+    let program = parse(source).unwrap();
+    check(&program).unwrap();
+    return program;
+}
+
+/// What the reference interpreter prints for a program that runs to completion
+#[cfg(test)]
+pub fn expected_output(source: &str) -> String {
+    match interpret_to_string(&load(source)) {
+        Ok(output) => return output,
+        Err(error) => panic!("reference interpreter failed: {}", error),
+    }
+}
+
+/// The message of the error the reference interpreter stops a program with
+#[cfg(test)]
+pub fn expected_error(source: &str) -> String {
+    match interpret_to_string(&load(source)) {
+        Ok(output) => panic!("reference interpreter succeeded with {:?}", output),
+        Err(error) => return error.message,
+    }
+}
+
+/// Fail the test unless `name` is installed, for the tests that are only run on request
+#[cfg(test)]
+pub fn require_tool(name: &str) {
+    let found = std::process::Command::new(name)
+        .arg("--version")
+        .output()
+        .is_ok();
+    assert!(found, "`{}` is not installed", name);
+}
@@ -0,0 +1,1479 @@
+/// A backend lowering type checked programs to WebAssembly, as a text or a binary module
+///
+/// WebAssembly only has structured control flow, so this works from the syntax tree rather than
+/// the intermediate representation:
+///
+/// * `int` is `i64`, `float` is `f64`, and `bool` and `string` are `i32`. A string is the address
+///   of a 32-bit length followed by the bytes.
+/// * Every function of the program is a WebAssembly function. Nested functions take a static
+///   link as their first parameter.
+/// * Variables captured by nested functions live in a frame on a stack in linear memory, the rest
+///   in WebAssembly locals. Each frame starts with its static link.
+/// * `print` calls one host function per type, imported from `env` along with the functions
+///   reporting runtime errors. The program is the export `main`.
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::chapter_4::*;
+use crate::chapter_6::{find_escape, Escapes};
+use crate::interpreter::MAX_CALL_DEPTH;
+
+/// Bytes of a slot in a frame
+const SLOT_SIZE: u32 = 8;
+
+const PAGE_SIZE: u32 = 65536;
+
+/// A WebAssembly value type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F64,
+}
+
+/// An instruction of a function body
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    /// An instruction without immediates, such as `i64.add`
+    Op(&'static str),
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    Call(u32),
+    /// Load a value of a type from the address on the stack plus an offset
+    Load(ValType, u32),
+    Store(ValType, u32),
+    Load8U(u32),
+    Block(Option<ValType>),
+    Loop(Option<ValType>),
+    If(Option<ValType>),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+}
+
+/// The signature of a function
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub ty: FuncType,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub ty: FuncType,
+    /// Locals after the parameters
+    pub locals: Vec<ValType>,
+    pub body: Vec<Instr>,
+}
+
+/// A mutable `i32` global
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub name: String,
+    pub init: i32,
+}
+
+/// A WebAssembly module. Imported functions are numbered before the defined ones
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub imports: Vec<Import>,
+    pub functions: Vec<Function>,
+    pub globals: Vec<Global>,
+    pub memory_pages: u32,
+    /// Bytes placed in memory at an address when the module starts
+    pub data: Vec<(u32, Vec<u8>)>,
+    /// Exported functions, by name
+    pub exports: Vec<(String, u32)>,
+}
+
+// Imported functions
+const PRINT_INT: u32 = 0;
+const PRINT_FLOAT: u32 = 1;
+const PRINT_BOOL: u32 = 2;
+const PRINT_STRING: u32 = 3;
+const DIV_BY_ZERO: u32 = 4;
+const STACK_OVERFLOW: u32 = 5;
+
+// Functions every module defines, numbered after the imports
+const ALLOC: u32 = 6;
+const DIV: u32 = 7;
+const STRING_CONCAT: u32 = 8;
+const STRING_EQUAL: u32 = 9;
+
+// Globals
+const SP: u32 = 0;
+const HEAP: u32 = 1;
+const DEPTH: u32 = 2;
+
+fn val_type(ty: Type) -> Option<ValType> {
+    match ty {
+        Type::Int => return Some(ValType::I64),
+        Type::Float => return Some(ValType::F64),
+        Type::Bool | Type::String => return Some(ValType::I32),
+        Type::Unit => return None,
+    }
+}
+
+fn func_type(params: &[ValType], results: &[ValType]) -> FuncType {
+    return FuncType {
+        params: params.to_vec(),
+        results: results.to_vec(),
+    };
+}
+
+/// Lower a type checked program to a WebAssembly module
+pub fn compile(program: &Program) -> Module {
+    let mut compiler = Compiler {
+        escapes: find_escape(program),
+        env: Vec::new(),
+        fns: Vec::new(),
+        functions: runtime_functions(),
+        data: Vec::new(),
+        strings: HashMap::new(),
+        max_frame_size: 0,
+    };
+    let (main, main_frame_size) = compiler.compile_main(program);
+    compiler.functions.push(Some(main));
+
+    let stack_base = align(DATA_BASE + compiler.data.len() as u32, SLOT_SIZE);
+    let stack_size = main_frame_size + compiler.max_frame_size * MAX_CALL_DEPTH as u32;
+    let heap_base = align(stack_base + stack_size, SLOT_SIZE);
+    let imports = [
+        ("print_int", vec![ValType::I64]),
+        ("print_float", vec![ValType::F64]),
+        ("print_bool", vec![ValType::I32]),
+        ("print_string", vec![ValType::I32]),
+        ("div_by_zero", vec![]),
+        ("stack_overflow", vec![]),
+    ];
+    let functions: Vec<Function> = compiler.functions.into_iter().map(Option::unwrap).collect();
+    let main_index = (imports.len() + functions.len() - 1) as u32;
+    return Module {
+        imports: imports
+            .into_iter()
+            .map(|(name, params)| Import {
+                module: "env".to_string(),
+                name: name.to_string(),
+                ty: func_type(&params, &[]),
+            })
+            .collect(),
+        functions,
+        globals: vec![
+            Global {
+                name: "sp".to_string(),
+                init: stack_base as i32,
+            },
+            Global {
+                name: "heap".to_string(),
+                init: heap_base as i32,
+            },
+            Global {
+                name: "depth".to_string(),
+                init: 0,
+            },
+        ],
+        memory_pages: heap_base / PAGE_SIZE + 1,
+        data: vec![(DATA_BASE, compiler.data)],
+        exports: vec![("main".to_string(), main_index)],
+    };
+}
+
+/// Where string literals start. Address zero is never used
+const DATA_BASE: u32 = 8;
+
+fn align(n: u32, to: u32) -> u32 {
+    return n.div_ceil(to) * to;
+}
+
+/// The functions of the runtime written in WebAssembly itself, at their fixed indices
+fn runtime_functions() -> Vec<Option<Function>> {
+    use Instr::*;
+    use ValType::*;
+    let alloc = Function {
+        name: "alloc".to_string(),
+        ty: func_type(&[I32], &[I32]),
+        locals: vec![I32],
+        // Bump allocate 8-byte aligned blocks, growing memory when the heap runs out
+        body: vec![
+            GlobalGet(HEAP),
+            LocalSet(1),
+            LocalGet(1),
+            LocalGet(0),
+            I32Const(7),
+            Op("i32.add"),
+            I32Const(-8),
+            Op("i32.and"),
+            Op("i32.add"),
+            GlobalSet(HEAP),
+            GlobalGet(HEAP),
+            Op("memory.size"),
+            I32Const(16),
+            Op("i32.shl"),
+            Op("i32.gt_u"),
+            If(None),
+            GlobalGet(HEAP),
+            Op("memory.size"),
+            I32Const(16),
+            Op("i32.shl"),
+            Op("i32.sub"),
+            I32Const(PAGE_SIZE as i32 - 1),
+            Op("i32.add"),
+            I32Const(16),
+            Op("i32.shr_u"),
+            Op("memory.grow"),
+            I32Const(-1),
+            Op("i32.eq"),
+            If(None),
+            Op("unreachable"),
+            End,
+            End,
+            LocalGet(1),
+        ],
+    };
+    let div = Function {
+        name: "div".to_string(),
+        ty: func_type(&[I64, I64], &[I64]),
+        locals: vec![],
+        // Division by zero is an error and the one overflowing division wraps
+        body: vec![
+            LocalGet(1),
+            Op("i64.eqz"),
+            If(None),
+            Call(DIV_BY_ZERO),
+            Op("unreachable"),
+            End,
+            LocalGet(1),
+            I64Const(-1),
+            Op("i64.eq"),
+            If(Some(I64)),
+            I64Const(0),
+            LocalGet(0),
+            Op("i64.sub"),
+            Else,
+            LocalGet(0),
+            LocalGet(1),
+            Op("i64.div_s"),
+            End,
+        ],
+    };
+    let string_concat = Function {
+        name: "string_concat".to_string(),
+        ty: func_type(&[I32, I32], &[I32]),
+        locals: vec![I32, I32, I32],
+        body: vec![
+            // Locals 2 and 3 hold the lengths and 4 the new string
+            LocalGet(0),
+            Load(I32, 0),
+            LocalSet(2),
+            LocalGet(1),
+            Load(I32, 0),
+            LocalSet(3),
+            LocalGet(2),
+            LocalGet(3),
+            Op("i32.add"),
+            I32Const(4),
+            Op("i32.add"),
+            Call(ALLOC),
+            LocalTee(4),
+            LocalGet(2),
+            LocalGet(3),
+            Op("i32.add"),
+            Store(I32, 0),
+            LocalGet(4),
+            I32Const(4),
+            Op("i32.add"),
+            LocalGet(0),
+            I32Const(4),
+            Op("i32.add"),
+            LocalGet(2),
+            Op("memory.copy"),
+            LocalGet(4),
+            I32Const(4),
+            Op("i32.add"),
+            LocalGet(2),
+            Op("i32.add"),
+            LocalGet(1),
+            I32Const(4),
+            Op("i32.add"),
+            LocalGet(3),
+            Op("memory.copy"),
+            LocalGet(4),
+        ],
+    };
+    let string_equal = Function {
+        name: "string_equal".to_string(),
+        ty: func_type(&[I32, I32], &[I32]),
+        locals: vec![I32, I32],
+        body: vec![
+            // Local 2 holds the length and 3 the index of the next byte to compare
+            LocalGet(0),
+            Load(I32, 0),
+            LocalTee(2),
+            LocalGet(1),
+            Load(I32, 0),
+            Op("i32.ne"),
+            If(None),
+            I32Const(0),
+            Op("return"),
+            End,
+            Block(None),
+            Loop(None),
+            LocalGet(3),
+            LocalGet(2),
+            Op("i32.ge_u"),
+            BrIf(1),
+            LocalGet(0),
+            LocalGet(3),
+            Op("i32.add"),
+            Load8U(4),
+            LocalGet(1),
+            LocalGet(3),
+            Op("i32.add"),
+            Load8U(4),
+            Op("i32.ne"),
+            If(None),
+            I32Const(0),
+            Op("return"),
+            End,
+            LocalGet(3),
+            I32Const(1),
+            Op("i32.add"),
+            LocalSet(3),
+            Br(0),
+            End,
+            End,
+            I32Const(1),
+        ],
+    };
+    return vec![
+        Some(alloc),
+        Some(div),
+        Some(string_concat),
+        Some(string_equal),
+    ];
+}
+
+/// Where a name of the program lives in the module: a Wasm local of the function being compiled,
+/// a slot of a frame in linear memory, or a function index
+#[derive(Clone, Copy)]
+enum Binding {
+    Local(u32),
+    /// A slot of the frame of the function at a nesting level
+    Slot {
+        level: usize,
+        offset: u32,
+        ty: ValType,
+    },
+    /// A function with the index it is called by, declared in the function at a nesting level
+    Fn {
+        index: u32,
+        level: usize,
+    },
+}
+
+/// A function being compiled
+struct FnContext {
+    level: usize,
+    /// Types of the parameters followed by the locals
+    locals: Vec<ValType>,
+    params: usize,
+    frame_size: u32,
+    /// The local holding the address of the frame
+    fp: u32,
+}
+
+struct Compiler {
+    /// The declarations that escape, found in Chapter 6
+    escapes: Escapes,
+    env: Vec<(String, Binding)>,
+    fns: Vec<FnContext>,
+    /// Functions by index after the imports, filled in as they are finished
+    functions: Vec<Option<Function>>,
+    data: Vec<u8>,
+    strings: HashMap<String, u32>,
+    /// Largest frame of any function but the main one
+    max_frame_size: u32,
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut FnContext {
+        return self.fns.last_mut().unwrap();
+    }
+
+    fn new_local(&mut self, ty: ValType) -> u32 {
+        let context = self.current();
+        context.locals.push(ty);
+        return context.locals.len() as u32 - 1;
+    }
+
+    /// Give a variable a Wasm local, or a slot of the frame in linear memory if a nested function
+    /// reaches it through the static link
+    fn declare(&mut self, name: &str, ty: ValType, escape: bool) -> Binding {
+        let binding = if escape {
+            let context = self.current();
+            let offset = context.frame_size;
+            context.frame_size += SLOT_SIZE;
+            Binding::Slot {
+                level: context.level,
+                offset,
+                ty,
+            }
+        } else {
+            Binding::Local(self.new_local(ty))
+        };
+        self.env.push((name.to_string(), binding));
+        return binding;
+    }
+
+    fn lookup(&self, name: &str) -> Binding {
+        for (n, binding) in self.env.iter().rev() {
+            if n == name {
+                return *binding;
+            }
+        }
+        panic!("`{}` is not declared", name);
+    }
+
+    /// The address of the frame of the function at `level`, found by following static links
+    fn frame_address(&self, level: usize, code: &mut Vec<Instr>) {
+        let context = self.fns.last().unwrap();
+        code.push(Instr::LocalGet(context.fp));
+        for _ in level..context.level {
+            code.push(Instr::Load(ValType::I32, 0));
+        }
+    }
+
+    fn string_address(&mut self, s: &str) -> u32 {
+        if let Some(address) = self.strings.get(s) {
+            return *address;
+        }
+        let address = DATA_BASE + self.data.len() as u32;
+        self.data.extend_from_slice(&(s.len() as i32).to_le_bytes());
+        self.data.extend_from_slice(s.as_bytes());
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        self.strings.insert(s.to_string(), address);
+        return address;
+    }
+
+    /// Compile the implicit main function, returning it with the size of its frame
+    fn compile_main(&mut self, program: &Program) -> (Function, u32) {
+        self.fns.push(FnContext {
+            level: 0,
+            locals: vec![ValType::I32],
+            params: 0,
+            frame_size: SLOT_SIZE,
+            fp: 0,
+        });
+        let mut body = Vec::new();
+        self.compile_stmts(&program.stmts, &mut body);
+        let context = self.fns.pop().unwrap();
+        let mut code = vec![
+            Instr::GlobalGet(SP),
+            Instr::LocalTee(0),
+            Instr::I32Const(context.frame_size as i32),
+            Instr::Op("i32.add"),
+            Instr::GlobalSet(SP),
+        ];
+        code.extend(body);
+        let main = Function {
+            name: "program_main".to_string(),
+            ty: func_type(&[], &[]),
+            locals: context.locals,
+            body: code,
+        };
+        return (main, context.frame_size);
+    }
+
+    /// Compile consecutive function declarations, reserving all their function indices first so
+    /// that each body can call any of them
+    fn compile_fn_group(&mut self, decls: &[&FnDecl]) {
+        let level = self.fns.last().unwrap().level;
+        let mut indices = Vec::new();
+        for decl in decls {
+            let index = (STACK_OVERFLOW + 1) + self.functions.len() as u32;
+            self.functions.push(None);
+            self.env
+                .push((decl.name.clone(), Binding::Fn { index, level }));
+            indices.push(index);
+        }
+        for (decl, index) in decls.iter().zip(indices) {
+            let function = self.compile_fn(decl, level + 1, index);
+            self.functions[(index - STACK_OVERFLOW - 1) as usize] = Some(function);
+        }
+    }
+
+    fn compile_fn(&mut self, decl: &FnDecl, level: usize, index: u32) -> Function {
+        let mut params = vec![ValType::I32];
+        for param in &decl.params {
+            params.push(val_type(param.ty).expect("parameters have values"));
+        }
+        let result: Vec<ValType> = val_type(decl.result).into_iter().collect();
+        self.fns.push(FnContext {
+            level,
+            locals: params.clone(),
+            params: params.len(),
+            frame_size: SLOT_SIZE,
+            fp: 0,
+        });
+        let fp = self.new_local(ValType::I32);
+        self.current().fp = fp;
+
+        let scope = self.env.len();
+        let mut prologue = Vec::new();
+        for (i, param) in decl.params.iter().enumerate() {
+            let ty = params[i + 1];
+            if self.escapes.escapes(param.pos) {
+                let binding = self.declare(&param.name, ty, true);
+                if let Binding::Slot { offset, .. } = binding {
+                    prologue.push(Instr::LocalGet(fp));
+                    prologue.push(Instr::LocalGet(i as u32 + 1));
+                    prologue.push(Instr::Store(ty, offset));
+                }
+            } else {
+                // Parameters that stay in locals are used where they arrive
+                self.env
+                    .push((param.name.clone(), Binding::Local(i as u32 + 1)));
+            }
+        }
+        let mut body = Vec::new();
+        self.compile_block(&decl.body, !result.is_empty(), &mut body);
+        self.env.truncate(scope);
+        let result_local = result.first().map(|ty| self.new_local(*ty));
+        let context = self.fns.pop().unwrap();
+        self.max_frame_size = self.max_frame_size.max(context.frame_size);
+
+        use Instr::*;
+        let mut code = vec![
+            // Stop runaway recursion like the reference interpreter does
+            GlobalGet(DEPTH),
+            I32Const(MAX_CALL_DEPTH as i32),
+            Op("i32.ge_s"),
+            If(None),
+            Call(STACK_OVERFLOW),
+            Op("unreachable"),
+            End,
+            GlobalGet(DEPTH),
+            I32Const(1),
+            Op("i32.add"),
+            GlobalSet(DEPTH),
+            // Push the frame and store the static link at its start
+            GlobalGet(SP),
+            LocalTee(fp),
+            I32Const(context.frame_size as i32),
+            Op("i32.add"),
+            GlobalSet(SP),
+            LocalGet(fp),
+            LocalGet(0),
+            Store(ValType::I32, 0),
+        ];
+        code.extend(prologue);
+        code.extend(body);
+        if let Some(local) = result_local {
+            code.push(LocalSet(local));
+        }
+        code.extend([
+            LocalGet(fp),
+            GlobalSet(SP),
+            GlobalGet(DEPTH),
+            I32Const(1),
+            Op("i32.sub"),
+            GlobalSet(DEPTH),
+        ]);
+        if let Some(local) = result_local {
+            code.push(LocalGet(local));
+        }
+        return Function {
+            name: format!("{}_{}", decl.name, index),
+            ty: FuncType {
+                params,
+                results: result,
+            },
+            locals: context.locals[context.params..].to_vec(),
+            body: code,
+        };
+    }
+
+    fn compile_stmts(&mut self, stmts: &[Stmt], code: &mut Vec<Instr>) {
+        let mut i = 0;
+        while i < stmts.len() {
+            let mut decls = Vec::new();
+            while let Some(Stmt {
+                kind: StmtKind::Fn(decl),
+                ..
+            }) = stmts.get(i)
+            {
+                decls.push(decl);
+                i += 1;
+            }
+            if !decls.is_empty() {
+                self.compile_fn_group(&decls);
+            } else {
+                self.compile_stmt(&stmts[i], code);
+                i += 1;
+            }
+        }
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt, code: &mut Vec<Instr>) {
+        match &stmt.kind {
+            StmtKind::Let { name, init, .. } => {
+                let ty = val_type(init.ty()).expect("variables have values");
+                let binding = self.declare_later(name, ty, self.escapes.escapes(stmt.pos));
+                self.store(binding, init, code);
+                self.env.push((name.clone(), binding));
+            }
+            StmtKind::Assign { name, value } => {
+                let binding = self.lookup(name);
+                self.store(binding, value, code);
+            }
+            StmtKind::While { cond, body } => {
+                code.extend([Instr::Block(None), Instr::Loop(None)]);
+                self.compile_expr(cond, code);
+                code.extend([Instr::Op("i32.eqz"), Instr::BrIf(1)]);
+                self.compile_block(body, false, code);
+                code.extend([Instr::Br(0), Instr::End, Instr::End]);
+            }
+            StmtKind::For { var, lo, hi, body } => {
+                let binding = self.declare_later(var, ValType::I64, self.escapes.escapes(stmt.pos));
+                self.store(binding, lo, code);
+                let limit = self.new_local(ValType::I64);
+                self.compile_expr(hi, code);
+                code.push(Instr::LocalSet(limit));
+                let scope = self.env.len();
+                self.env.push((var.clone(), binding));
+                code.extend([Instr::Block(None), Instr::Loop(None)]);
+                self.load(binding, code);
+                code.extend([
+                    Instr::LocalGet(limit),
+                    Instr::Op("i64.ge_s"),
+                    Instr::BrIf(1),
+                ]);
+                self.compile_block(body, false, code);
+                self.frame_address_of(binding, code);
+                self.load(binding, code);
+                code.extend([Instr::I64Const(1), Instr::Op("i64.add")]);
+                self.store_top(binding, code);
+                code.extend([Instr::Br(0), Instr::End, Instr::End]);
+                self.env.truncate(scope);
+            }
+            StmtKind::Fn(_) => unreachable!("function declarations are compiled in groups"),
+            StmtKind::Error => unreachable!("the type checker rejects syntax errors"),
+            StmtKind::Expr(expr) => {
+                self.compile_expr(expr, code);
+                if expr.ty() != Type::Unit {
+                    code.push(Instr::Op("drop"));
+                }
+            }
+        }
+    }
+
+    /// Allocate the place of a variable without making it visible, for a declaration whose
+    /// initializer may still refer to an outer variable of the same name
+    fn declare_later(&mut self, name: &str, ty: ValType, escape: bool) -> Binding {
+        let binding = self.declare(name, ty, escape);
+        self.env.pop();
+        return binding;
+    }
+
+    /// Push the frame address a store to a slot needs, before the value is computed
+    fn frame_address_of(&self, binding: Binding, code: &mut Vec<Instr>) {
+        if let Binding::Slot { level, .. } = binding {
+            self.frame_address(level, code);
+        }
+    }
+
+    /// Store the value on top of the stack, after `frame_address_of`
+    fn store_top(&self, binding: Binding, code: &mut Vec<Instr>) {
+        match binding {
+            Binding::Local(local) => code.push(Instr::LocalSet(local)),
+            Binding::Slot { offset, ty, .. } => code.push(Instr::Store(ty, offset)),
+            Binding::Fn { .. } => panic!("cannot assign to a function"),
+        }
+    }
+
+    fn store(&mut self, binding: Binding, value: &Expr, code: &mut Vec<Instr>) {
+        self.frame_address_of(binding, code);
+        self.compile_expr(value, code);
+        self.store_top(binding, code);
+    }
+
+    fn load(&self, binding: Binding, code: &mut Vec<Instr>) {
+        match binding {
+            Binding::Local(local) => code.push(Instr::LocalGet(local)),
+            Binding::Slot { level, offset, ty } => {
+                self.frame_address(level, code);
+                code.push(Instr::Load(ty, offset));
+            }
+            Binding::Fn { .. } => panic!("a function is not a value"),
+        }
+    }
+
+    /// Compile a block, leaving its value on the stack when `value` is set
+    fn compile_block(&mut self, block: &Block, value: bool, code: &mut Vec<Instr>) {
+        let scope = self.env.len();
+        self.compile_stmts(&block.stmts, code);
+        if let Some(result) = &block.result {
+            self.compile_expr(result, code);
+            if !value && result.ty() != Type::Unit {
+                code.push(Instr::Op("drop"));
+            }
+        }
+        self.env.truncate(scope);
+    }
+
+    fn compile_expr(&mut self, expr: &Expr, code: &mut Vec<Instr>) {
+        match &expr.kind {
+            ExprKind::Int(i) => code.push(Instr::I64Const(*i)),
+            ExprKind::Float(x) => code.push(Instr::F64Const(*x)),
+            ExprKind::Bool(b) => code.push(Instr::I32Const(*b as i32)),
+            ExprKind::Str(s) => {
+                let address = self.string_address(s);
+                code.push(Instr::I32Const(address as i32));
+            }
+            ExprKind::Var(name) => {
+                let binding = self.lookup(name);
+                self.load(binding, code);
+            }
+            ExprKind::Unary(UnOp::Neg, operand) => {
+                if operand.ty() == Type::Float {
+                    self.compile_expr(operand, code);
+                    code.push(Instr::Op("f64.neg"));
+                } else {
+                    code.push(Instr::I64Const(0));
+                    self.compile_expr(operand, code);
+                    code.push(Instr::Op("i64.sub"));
+                }
+            }
+            ExprKind::Binary(left, op, right) => self.compile_binary(left, *op, right, code),
+            ExprKind::Error => unreachable!("the type checker rejects syntax errors"),
+            ExprKind::Call(name, args) => self.compile_call(name, args, code),
+            ExprKind::If {
+                branches,
+                else_block,
+            } => self.compile_if(branches, else_block.as_ref(), expr.ty(), code),
+        }
+    }
+
+    fn compile_binary(&mut self, left: &Expr, op: BinOp, right: &Expr, code: &mut Vec<Instr>) {
+        match op {
+            BinOp::And => {
+                self.compile_expr(left, code);
+                code.push(Instr::If(Some(ValType::I32)));
+                self.compile_expr(right, code);
+                code.extend([Instr::Else, Instr::I32Const(0), Instr::End]);
+                return;
+            }
+            BinOp::Or => {
+                self.compile_expr(left, code);
+                code.extend([
+                    Instr::If(Some(ValType::I32)),
+                    Instr::I32Const(1),
+                    Instr::Else,
+                ]);
+                self.compile_expr(right, code);
+                code.push(Instr::End);
+                return;
+            }
+            _ => {}
+        }
+        self.compile_expr(left, code);
+        self.compile_expr(right, code);
+        let instr = match (left.ty(), op) {
+            (Type::Int, BinOp::Div) => Instr::Call(DIV),
+            (Type::String, BinOp::Add) => Instr::Call(STRING_CONCAT),
+            (Type::String, BinOp::Eq) => Instr::Call(STRING_EQUAL),
+            (Type::String, BinOp::Neq) => {
+                code.push(Instr::Call(STRING_EQUAL));
+                Instr::Op("i32.eqz")
+            }
+            (Type::Int, _) => Instr::Op(match op {
+                BinOp::Add => "i64.add",
+                BinOp::Sub => "i64.sub",
+                BinOp::Mul => "i64.mul",
+                BinOp::Eq => "i64.eq",
+                BinOp::Neq => "i64.ne",
+                BinOp::Lt => "i64.lt_s",
+                BinOp::Le => "i64.le_s",
+                BinOp::Gt => "i64.gt_s",
+                BinOp::Ge => "i64.ge_s",
+                _ => unreachable!(),
+            }),
+            (Type::Float, _) => Instr::Op(match op {
+                BinOp::Add => "f64.add",
+                BinOp::Sub => "f64.sub",
+                BinOp::Mul => "f64.mul",
+                BinOp::Div => "f64.div",
+                BinOp::Eq => "f64.eq",
+                BinOp::Neq => "f64.ne",
+                BinOp::Lt => "f64.lt",
+                BinOp::Le => "f64.le",
+                BinOp::Gt => "f64.gt",
+                BinOp::Ge => "f64.ge",
+                _ => unreachable!(),
+            }),
+            (Type::Bool, BinOp::Eq) => Instr::Op("i32.eq"),
+            (Type::Bool, BinOp::Neq) => Instr::Op("i32.ne"),
+            (ty, _) => panic!("cannot apply `{}` to {}", op, ty),
+        };
+        code.push(instr);
+    }
+
+    fn compile_call(&mut self, name: &str, args: &[Expr], code: &mut Vec<Instr>) {
+        let binding = self
+            .env
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, b)| *b);
+        if let Some(Binding::Fn { index, level }) = binding {
+            self.frame_address(level, code);
+            for arg in args {
+                self.compile_expr(arg, code);
+            }
+            code.push(Instr::Call(index));
+            return;
+        }
+        // `print` computes every argument before printing any of them
+        let mut locals = Vec::new();
+        for arg in args {
+            self.compile_expr(arg, code);
+            let local = self.new_local(val_type(arg.ty()).expect("cannot print ()"));
+            code.push(Instr::LocalSet(local));
+            locals.push(local);
+        }
+        for (arg, local) in args.iter().zip(locals) {
+            let print = match arg.ty() {
+                Type::Int => PRINT_INT,
+                Type::Float => PRINT_FLOAT,
+                Type::Bool => PRINT_BOOL,
+                Type::String => PRINT_STRING,
+                Type::Unit => unreachable!(),
+            };
+            code.extend([Instr::LocalGet(local), Instr::Call(print)]);
+        }
+    }
+
+    fn compile_if(
+        &mut self,
+        branches: &[(Expr, Block)],
+        else_block: Option<&Block>,
+        ty: Type,
+        code: &mut Vec<Instr>,
+    ) {
+        let (cond, then) = &branches[0];
+        let value = ty != Type::Unit;
+        self.compile_expr(cond, code);
+        code.push(Instr::If(val_type(ty)));
+        self.compile_block(then, value, code);
+        if branches.len() > 1 {
+            code.push(Instr::Else);
+            self.compile_if(&branches[1..], else_block, ty, code);
+        } else if let Some(block) = else_block {
+            code.push(Instr::Else);
+            self.compile_block(block, value, code);
+        }
+        code.push(Instr::End);
+    }
+}
+
+impl ValType {
+    fn name(self) -> &'static str {
+        match self {
+            ValType::I32 => return "i32",
+            ValType::I64 => return "i64",
+            ValType::F64 => return "f64",
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            ValType::I32 => return 0x7F,
+            ValType::I64 => return 0x7E,
+            ValType::F64 => return 0x7C,
+        }
+    }
+
+    /// The natural alignment of a value in memory, as a power of two
+    fn align(self) -> u32 {
+        match self {
+            ValType::I32 => return 2,
+            ValType::I64 | ValType::F64 => return 3,
+        }
+    }
+}
+
+/// The encoding of an instruction without immediates
+fn opcode(op: &str) -> &'static [u8] {
+    match op {
+        "unreachable" => return &[0x00],
+        "return" => return &[0x0F],
+        "drop" => return &[0x1A],
+        "memory.size" => return &[0x3F, 0x00],
+        "memory.grow" => return &[0x40, 0x00],
+        "i32.eqz" => return &[0x45],
+        "i32.eq" => return &[0x46],
+        "i32.ne" => return &[0x47],
+        "i32.gt_u" => return &[0x4B],
+        "i32.ge_s" => return &[0x4E],
+        "i32.ge_u" => return &[0x4F],
+        "i64.eqz" => return &[0x50],
+        "i64.eq" => return &[0x51],
+        "i64.ne" => return &[0x52],
+        "i64.lt_s" => return &[0x53],
+        "i64.gt_s" => return &[0x55],
+        "i64.le_s" => return &[0x57],
+        "i64.ge_s" => return &[0x59],
+        "f64.eq" => return &[0x61],
+        "f64.ne" => return &[0x62],
+        "f64.lt" => return &[0x63],
+        "f64.gt" => return &[0x64],
+        "f64.le" => return &[0x65],
+        "f64.ge" => return &[0x66],
+        "i32.add" => return &[0x6A],
+        "i32.sub" => return &[0x6B],
+        "i32.and" => return &[0x71],
+        "i32.shl" => return &[0x74],
+        "i32.shr_u" => return &[0x76],
+        "i64.add" => return &[0x7C],
+        "i64.sub" => return &[0x7D],
+        "i64.mul" => return &[0x7E],
+        "i64.div_s" => return &[0x7F],
+        "f64.neg" => return &[0x9A],
+        "f64.add" => return &[0xA0],
+        "f64.sub" => return &[0xA1],
+        "f64.mul" => return &[0xA2],
+        "f64.div" => return &[0xA3],
+        "memory.copy" => return &[0xFC, 0x0A, 0x00, 0x00],
+        _ => panic!("no encoding for `{}`", op),
+    }
+}
+
+fn block_type(ty: Option<ValType>) -> String {
+    match ty {
+        Some(ty) => return format!(" (result {})", ty.name()),
+        None => return String::new(),
+    }
+}
+
+fn wat_float(x: f64) -> String {
+    if x.is_nan() {
+        return "nan".to_string();
+    }
+    return format!("{:?}", x);
+}
+
+/// Quote bytes as a WebAssembly text string
+fn wat_string(bytes: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for &b in bytes {
+        if b.is_ascii_graphic() && b != b'"' && b != b'\\' || b == b' ' {
+            s.push(b as char);
+        } else {
+            write!(s, "\\{:02x}", b).unwrap();
+        }
+    }
+    s.push('"');
+    return s;
+}
+
+fn uleb(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, s: &str) {
+    uleb(out, s.len() as u64);
+    out.extend(s.as_bytes());
+}
+
+fn section(out: &mut Vec<u8>, id: u8, contents: Vec<u8>) {
+    out.push(id);
+    uleb(out, contents.len() as u64);
+    out.extend(contents);
+}
+
+impl Module {
+    fn function_name(&self, index: u32) -> &str {
+        let index = index as usize;
+        if index < self.imports.len() {
+            return &self.imports[index].name;
+        }
+        return &self.functions[index - self.imports.len()].name;
+    }
+
+    /// The module in the WebAssembly text format
+    pub fn to_wat(&self) -> String {
+        let mut out = String::from("(module\n");
+        for import in &self.imports {
+            writeln!(
+                out,
+                "  (import \"{}\" \"{}\" (func ${}{}))",
+                import.module,
+                import.name,
+                import.name,
+                signature(&import.ty)
+            )
+            .unwrap();
+        }
+        writeln!(out, "  (memory (export \"memory\") {})", self.memory_pages).unwrap();
+        for global in &self.globals {
+            writeln!(
+                out,
+                "  (global ${} (mut i32) (i32.const {}))",
+                global.name, global.init
+            )
+            .unwrap();
+        }
+        for (name, index) in &self.exports {
+            writeln!(
+                out,
+                "  (export \"{}\" (func ${}))",
+                name,
+                self.function_name(*index)
+            )
+            .unwrap();
+        }
+        for function in &self.functions {
+            write!(out, "  (func ${}{}", function.name, signature(&function.ty)).unwrap();
+            if !function.locals.is_empty() {
+                out.push_str(" (local");
+                for ty in &function.locals {
+                    write!(out, " {}", ty.name()).unwrap();
+                }
+                out.push(')');
+            }
+            out.push('\n');
+            let mut depth = 2;
+            for instr in &function.body {
+                if matches!(instr, Instr::Else | Instr::End) {
+                    depth -= 1;
+                }
+                writeln!(out, "{}{}", "  ".repeat(depth), self.instr_wat(instr)).unwrap();
+                if matches!(
+                    instr,
+                    Instr::Block(_) | Instr::Loop(_) | Instr::If(_) | Instr::Else
+                ) {
+                    depth += 1;
+                }
+            }
+            out.push_str("  )\n");
+        }
+        for (address, bytes) in &self.data {
+            writeln!(
+                out,
+                "  (data (i32.const {}) {})",
+                address,
+                wat_string(bytes)
+            )
+            .unwrap();
+        }
+        out.push_str(")\n");
+        return out;
+    }
+
+    fn instr_wat(&self, instr: &Instr) -> String {
+        match instr {
+            Instr::Op(op) => return op.to_string(),
+            Instr::I32Const(n) => return format!("i32.const {}", n),
+            Instr::I64Const(n) => return format!("i64.const {}", n),
+            Instr::F64Const(x) => return format!("f64.const {}", wat_float(*x)),
+            Instr::LocalGet(i) => return format!("local.get {}", i),
+            Instr::LocalSet(i) => return format!("local.set {}", i),
+            Instr::LocalTee(i) => return format!("local.tee {}", i),
+            Instr::GlobalGet(i) => {
+                return format!("global.get ${}", self.globals[*i as usize].name)
+            }
+            Instr::GlobalSet(i) => {
+                return format!("global.set ${}", self.globals[*i as usize].name)
+            }
+            Instr::Call(f) => return format!("call ${}", self.function_name(*f)),
+            Instr::Load(ty, offset) => return format!("{}.load offset={}", ty.name(), offset),
+            Instr::Store(ty, offset) => return format!("{}.store offset={}", ty.name(), offset),
+            Instr::Load8U(offset) => return format!("i32.load8_u offset={}", offset),
+            Instr::Block(ty) => return format!("block{}", block_type(*ty)),
+            Instr::Loop(ty) => return format!("loop{}", block_type(*ty)),
+            Instr::If(ty) => return format!("if{}", block_type(*ty)),
+            Instr::Else => return "else".to_string(),
+            Instr::End => return "end".to_string(),
+            Instr::Br(depth) => return format!("br {}", depth),
+            Instr::BrIf(depth) => return format!("br_if {}", depth),
+        }
+    }
+
+    /// The module in the WebAssembly binary format
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut types: Vec<&FuncType> = Vec::new();
+        let mut type_index = |ty| {
+            if let Some(i) = types.iter().position(|t| *t == ty) {
+                return i as u64;
+            }
+            types.push(ty);
+            return types.len() as u64 - 1;
+        };
+        let import_types: Vec<u64> = self.imports.iter().map(|i| type_index(&i.ty)).collect();
+        let function_types: Vec<u64> = self.functions.iter().map(|f| type_index(&f.ty)).collect();
+
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+
+        let mut contents = Vec::new();
+        uleb(&mut contents, types.len() as u64);
+        for ty in &types {
+            contents.push(0x60);
+            for list in [&ty.params, &ty.results] {
+                uleb(&mut contents, list.len() as u64);
+                contents.extend(list.iter().map(|t| t.code()));
+            }
+        }
+        section(&mut out, 1, contents);
+
+        let mut contents = Vec::new();
+        uleb(&mut contents, self.imports.len() as u64);
+        for (import, ty) in self.imports.iter().zip(import_types) {
+            name(&mut contents, &import.module);
+            name(&mut contents, &import.name);
+            contents.push(0x00);
+            uleb(&mut contents, ty);
+        }
+        section(&mut out, 2, contents);
+
+        let mut contents = Vec::new();
+        uleb(&mut contents, function_types.len() as u64);
+        for ty in function_types {
+            uleb(&mut contents, ty);
+        }
+        section(&mut out, 3, contents);
+
+        let mut contents = vec![1, 0x00];
+        uleb(&mut contents, self.memory_pages as u64);
+        section(&mut out, 5, contents);
+
+        let mut contents = Vec::new();
+        uleb(&mut contents, self.globals.len() as u64);
+        for global in &self.globals {
+            contents.extend([ValType::I32.code(), 0x01, 0x41]);
+            sleb(&mut contents, global.init as i64);
+            contents.push(0x0B);
+        }
+        section(&mut out, 6, contents);
+
+        let mut contents = Vec::new();
+        uleb(&mut contents, self.exports.len() as u64 + 1);
+        name(&mut contents, "memory");
+        contents.extend([0x02, 0x00]);
+        for (export, index) in &self.exports {
+            name(&mut contents, export);
+            contents.push(0x00);
+            uleb(&mut contents, *index as u64);
+        }
+        section(&mut out, 7, contents);
+
+        let mut contents = Vec::new();
+        uleb(&mut contents, self.functions.len() as u64);
+        for function in &self.functions {
+            let body = encode_body(function);
+            uleb(&mut contents, body.len() as u64);
+            contents.extend(body);
+        }
+        section(&mut out, 10, contents);
+
+        let mut contents = Vec::new();
+        uleb(&mut contents, self.data.len() as u64);
+        for (address, bytes) in &self.data {
+            contents.extend([0x00, 0x41]);
+            sleb(&mut contents, *address as i64);
+            contents.push(0x0B);
+            uleb(&mut contents, bytes.len() as u64);
+            contents.extend(bytes);
+        }
+        section(&mut out, 11, contents);
+        return out;
+    }
+}
+
+fn signature(ty: &FuncType) -> String {
+    let mut s = String::new();
+    if !ty.params.is_empty() {
+        s.push_str(" (param");
+        for param in &ty.params {
+            write!(s, " {}", param.name()).unwrap();
+        }
+        s.push(')');
+    }
+    for result in &ty.results {
+        write!(s, " (result {})", result.name()).unwrap();
+    }
+    return s;
+}
+
+fn encode_body(function: &Function) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut groups: Vec<(u64, ValType)> = Vec::new();
+    for ty in &function.locals {
+        match groups.last_mut() {
+            Some((count, last)) if last == ty => *count += 1,
+            _ => groups.push((1, *ty)),
+        }
+    }
+    uleb(&mut out, groups.len() as u64);
+    for (count, ty) in groups {
+        uleb(&mut out, count);
+        out.push(ty.code());
+    }
+    for instr in &function.body {
+        match instr {
+            Instr::Op(op) => out.extend(opcode(op)),
+            Instr::I32Const(n) => {
+                out.push(0x41);
+                sleb(&mut out, *n as i64);
+            }
+            Instr::I64Const(n) => {
+                out.push(0x42);
+                sleb(&mut out, *n);
+            }
+            Instr::F64Const(x) => {
+                out.push(0x44);
+                out.extend(x.to_le_bytes());
+            }
+            Instr::LocalGet(i) | Instr::LocalSet(i) | Instr::LocalTee(i) => {
+                out.push(match instr {
+                    Instr::LocalGet(_) => 0x20,
+                    Instr::LocalSet(_) => 0x21,
+                    _ => 0x22,
+                });
+                uleb(&mut out, *i as u64);
+            }
+            Instr::GlobalGet(i) => {
+                out.push(0x23);
+                uleb(&mut out, *i as u64);
+            }
+            Instr::GlobalSet(i) => {
+                out.push(0x24);
+                uleb(&mut out, *i as u64);
+            }
+            Instr::Call(f) => {
+                out.push(0x10);
+                uleb(&mut out, *f as u64);
+            }
+            Instr::Load(ty, offset) | Instr::Store(ty, offset) => {
+                let load = matches!(instr, Instr::Load(..));
+                out.push(match (ty, load) {
+                    (ValType::I32, true) => 0x28,
+                    (ValType::I64, true) => 0x29,
+                    (ValType::F64, true) => 0x2B,
+                    (ValType::I32, false) => 0x36,
+                    (ValType::I64, false) => 0x37,
+                    (ValType::F64, false) => 0x39,
+                });
+                uleb(&mut out, ty.align() as u64);
+                uleb(&mut out, *offset as u64);
+            }
+            Instr::Load8U(offset) => {
+                out.extend([0x2D, 0x00]);
+                uleb(&mut out, *offset as u64);
+            }
+            Instr::Block(ty) | Instr::Loop(ty) | Instr::If(ty) => {
+                out.push(match instr {
+                    Instr::Block(_) => 0x02,
+                    Instr::Loop(_) => 0x03,
+                    _ => 0x04,
+                });
+                out.push(ty.map_or(0x40, ValType::code));
+            }
+            Instr::Else => out.push(0x05),
+            Instr::End => out.push(0x0B),
+            Instr::Br(depth) => {
+                out.push(0x0C);
+                uleb(&mut out, *depth as u64);
+            }
+            Instr::BrIf(depth) => {
+                out.push(0x0D);
+                uleb(&mut out, *depth as u64);
+            }
+        }
+    }
+    out.push(0x0B);
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_programs::*;
+    use std::process::{Command, Output};
+
+    /// Run a program under the Node.js host, from a directory of its own
+    fn run(dir: &std::path::Path, name: &str, source: &str) -> Output {
+        let path = dir.join(format!("{}.wasm", name));
+        std::fs::write(&path, compile(&load(source)).to_binary()).unwrap();
+        return Command::new("node")
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/runtime/wasm_host.js"))
+            .arg(&path)
+            .output()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_leb128() {
+        let mut out = Vec::new();
+        uleb(&mut out, 624485);
+        assert_eq!(out, [0xE5, 0x8E, 0x26]);
+        out.clear();
+        sleb(&mut out, -123456);
+        assert_eq!(out, [0xC0, 0xBB, 0x78]);
+        out.clear();
+        sleb(&mut out, 64);
+        assert_eq!(out, [0xC0, 0x00]);
+        out.clear();
+        sleb(&mut out, -1);
+        assert_eq!(out, [0x7F]);
+    }
+
+    #[test]
+    fn test_wasm_types() {
+        let module = compile(&load(
+            "fn f(x: int, y: float, b: bool, s: string): float { y } print(f(1, 2.0, True, \"s\"));",
+        ));
+        let f = module
+            .functions
+            .iter()
+            .find(|f| f.name.starts_with("f_"))
+            .unwrap();
+        assert_eq!(
+            f.ty,
+            func_type(
+                &[
+                    ValType::I32,
+                    ValType::I64,
+                    ValType::F64,
+                    ValType::I32,
+                    ValType::I32
+                ],
+                &[ValType::F64]
+            )
+        );
+        // Six imports and four runtime functions come before `f` and then `main`
+        assert_eq!(module.exports, [("main".to_string(), 11)]);
+    }
+
+    #[test]
+    fn test_wasm_escaping_variables_live_in_frame() {
+        let module = compile(&load(
+            "fn f(): int { let x = 1; let y = 2; fn g(): int { x } g() + y } print(f());",
+        ));
+        let f = module
+            .functions
+            .iter()
+            .find(|f| f.name.starts_with("f_"))
+            .unwrap();
+        // `x` is the only slot after the static link, and `y` a local
+        assert!(f.body.contains(&Instr::I32Const(16)));
+        assert!(f.body.contains(&Instr::Store(ValType::I64, 8)));
+        assert!(f.locals.contains(&ValType::I64));
+    }
+
+    #[test]
+    fn test_wat() {
+        let wat = compile(&load("let s = \"a b!\"; print(s, 1 + 2);")).to_wat();
+        assert!(wat.starts_with("(module\n"));
+        assert!(wat.contains("(import \"env\" \"print_int\" (func $print_int (param i64)))"));
+        assert!(wat.contains("(export \"main\" (func $program_main))"));
+        assert!(wat.contains("(func $program_main (local i32 i32 i32 i64)"));
+        assert!(wat.contains("\n    call $print_string\n"));
+        assert!(wat.contains("(data (i32.const 8) \"\\04\\00\\00\\00a b!\")"));
+    }
+
+    #[test]
+    #[ignore = "needs `node`, run with `cargo test -- --ignored`"]
+    fn test_wasm_programs_run_under_node() {
+        require_tool("node");
+        let dir = std::env::temp_dir().join(format!("wasm_run_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in PROGRAMS {
+            let output = run(&dir, name, source);
+            assert!(
+                output.status.success(),
+                "program {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                expected_output(source),
+                "program {}",
+                name
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs `node`, run with `cargo test -- --ignored`"]
+    fn test_wasm_runtime_errors_under_node() {
+        require_tool("node");
+        let dir = std::env::temp_dir().join(format!("wasm_fail_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in FAILING_PROGRAMS {
+            let output = run(&dir, name, source);
+            assert_eq!(output.status.code(), Some(1), "program {}", name);
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap().trim_end(),
+                expected_error(source),
+                "program {}",
+                name
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs `node`, run with `cargo test -- --ignored`"]
+    fn test_wasm_float_formatting_under_node() {
+        require_tool("node");
+        let dir = std::env::temp_dir().join(format!("wasm_float_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source =
+            "print(1.0 / 128.0, 0.0 - 2.5, 1.0 / 0.0, 0.1 + 0.2, 1.0 / 3.0 * 3000000000.0);";
+        let output = run(&dir, "floats", source);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected_output(source)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}